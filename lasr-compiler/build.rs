@@ -1,6 +1,30 @@
 use std::{env, fs, path::PathBuf, process::Command};
 
 fn main() {
+    println!("cargo:rerun-if-env-changed=LASR_RUNTIME_WASM");
+    println!("cargo:rerun-if-env-changed=LASR_RUNTIME_PROFILE");
+    println!("cargo:rerun-if-env-changed=LASR_RUNTIME_FEATURES");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("missing OUT_DIR"));
+    let staged_wasm = out_dir.join("lasr_runtime.wasm");
+
+    if let Some(prebuilt_wasm) = env::var_os("LASR_RUNTIME_WASM") {
+        let prebuilt_wasm = PathBuf::from(prebuilt_wasm);
+        println!("cargo:rerun-if-changed={}", prebuilt_wasm.display());
+        fs::copy(&prebuilt_wasm, &staged_wasm).unwrap_or_else(|err| {
+            panic!(
+                "failed to copy LASR_RUNTIME_WASM ({}) into OUT_DIR: {err}",
+                prebuilt_wasm.display()
+            )
+        });
+        println!("cargo:rustc-env=LASR_RUNTIME_BUILD_PROFILE=prebuilt");
+        println!("cargo:rustc-env=LASR_RUNTIME_BUILD_FEATURES=");
+        return;
+    }
+
+    let profile = env::var("LASR_RUNTIME_PROFILE").unwrap_or_else(|_| "release".to_owned());
+    let features = env::var("LASR_RUNTIME_FEATURES").unwrap_or_default();
+
     println!("cargo:rerun-if-changed=../lasr-runtime/Cargo.toml");
     for file in runtime_source_files() {
         println!("cargo:rerun-if-changed={}", file.display());
@@ -14,31 +38,66 @@ fn main() {
     let target_dir = workspace_dir.join("target").join("lasr-buildrs");
 
     let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
-    let status = Command::new(cargo)
+    let mut command = Command::new(cargo);
+    command
         .current_dir(workspace_dir)
         .env("CARGO_TARGET_DIR", &target_dir)
-        .args([
-            "build",
-            "-p",
-            "lasr-runtime",
-            "--release",
-            "--target",
-            "wasm32-wasip1",
-        ])
+        .args(["build", "-p", "lasr-runtime", "--target", "wasm32-wasip1"]);
+
+    match profile.as_str() {
+        "release" => {
+            command.arg("--release");
+        }
+        "debug" => {}
+        _ => panic!("LASR_RUNTIME_PROFILE must be either debug or release, not {profile}"),
+    }
+
+    if !features.is_empty() {
+        command.args(["--features", &features]);
+    }
+
+    let status = command
         .status()
         .expect("failed to run cargo build for lasr-runtime");
 
-    assert!(status.success(), "failed to build lasr-runtime wasm");
+    if !status.success() {
+        if !wasm_target_installed() {
+            panic!(
+                "failed to build lasr-runtime: the wasm32-wasip1 target is not installed. Either \
+                 install it with `rustup target add wasm32-wasip1` or point the \
+                 LASR_RUNTIME_WASM environment variable at a prebuilt lasr_runtime.wasm"
+            );
+        }
+        panic!(
+            "failed to build lasr-runtime wasm (set LASR_RUNTIME_WASM to use a prebuilt \
+             lasr_runtime.wasm instead)"
+        );
+    }
 
     let built_wasm = target_dir
         .join("wasm32-wasip1")
-        .join("release")
+        .join(&profile)
         .join("lasr_runtime.wasm");
 
-    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("missing OUT_DIR"));
-    let staged_wasm = out_dir.join("lasr_runtime.wasm");
-
     fs::copy(&built_wasm, &staged_wasm).expect("failed to stage lasr_runtime.wasm into OUT_DIR");
+
+    println!("cargo:rustc-env=LASR_RUNTIME_BUILD_PROFILE={profile}");
+    println!("cargo:rustc-env=LASR_RUNTIME_BUILD_FEATURES={features}");
+}
+
+fn wasm_target_installed() -> bool {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let Ok(output) = Command::new(rustc).args(["--print", "sysroot"]).output() else {
+        // We can't tell, so don't claim that it's missing.
+        return true;
+    };
+    let sysroot = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    !output.status.success()
+        || sysroot
+            .join("lib")
+            .join("rustlib")
+            .join("wasm32-wasip1")
+            .is_dir()
 }
 
 fn runtime_source_files() -> Vec<PathBuf> {
@@ -0,0 +1,135 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail, ensure};
+
+use crate::diagnostics::MessageFormat;
+
+pub const DEFAULT_EXPORT: &str = "lasr_script";
+const USAGE: &str = "\
+usage: lasr-compiler [options] <script.lua> [out.wasm]
+       lasr-compiler build-dir [options] [--recursive] <dir> [--out <outdir>]
+
+options:
+    --runtime <runtime.wasm>          inject into a custom runtime module
+    --config <config.toml>            provide a TOML file to the script as the CONFIG global
+    --export-name <name>              export providing the script (default: lasr_script)
+    --message-format <human|json>     format of diagnostics
+    --optimize                        drop custom sections that aren't needed at runtime
+    --wasm-opt <path>                 additionally optimize the output with wasm-opt
+    --pack-data                       place the script in unused memory before the heap if possible";
+
+pub enum Command {
+    Compile {
+        script_lua: PathBuf,
+        output_wasm: PathBuf,
+    },
+    BuildDir {
+        dir: PathBuf,
+        out_dir: PathBuf,
+        recursive: bool,
+    },
+}
+
+pub struct Args {
+    pub command: Command,
+    pub runtime_wasm: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub export_name: String,
+    pub message_format: MessageFormat,
+    pub optimize: bool,
+    pub wasm_opt: Option<PathBuf>,
+    pub pack_data: bool,
+}
+
+impl Args {
+    pub fn parse(args: Vec<String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let build_dir = args.next_if(|arg| arg == "build-dir").is_some();
+
+        let mut positional = Vec::new();
+        let mut runtime_wasm = None;
+        let mut config = None;
+        let mut export_name = None;
+        let mut message_format = MessageFormat::Human;
+        let mut out_dir = None;
+        let mut recursive = false;
+        let mut optimize = false;
+        let mut wasm_opt = None;
+        let mut pack_data = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--runtime" => {
+                    let path = args.next().context("--runtime expects a path")?;
+                    runtime_wasm = Some(PathBuf::from(path));
+                }
+                "--config" => {
+                    let path = args.next().context("--config expects a path")?;
+                    config = Some(PathBuf::from(path));
+                }
+                "--export-name" => {
+                    export_name = Some(args.next().context("--export-name expects a name")?);
+                }
+                "--message-format" => {
+                    message_format = match args.next().as_deref() {
+                        Some("human") => MessageFormat::Human,
+                        Some("json") => MessageFormat::Json,
+                        _ => bail!("--message-format expects either human or json"),
+                    };
+                }
+                "--optimize" => optimize = true,
+                "--wasm-opt" => {
+                    let path = args.next().context("--wasm-opt expects a path")?;
+                    wasm_opt = Some(PathBuf::from(path));
+                }
+                "--pack-data" => pack_data = true,
+                "--out" if build_dir => {
+                    let path = args.next().context("--out expects a directory")?;
+                    out_dir = Some(PathBuf::from(path));
+                }
+                "--recursive" if build_dir => recursive = true,
+                _ if arg.starts_with("--") => bail!("unknown option {arg}\n{USAGE}"),
+                _ => positional.push(arg),
+            }
+        }
+
+        let command = if build_dir {
+            ensure!(positional.len() == 1, USAGE);
+
+            let dir = PathBuf::from(&positional[0]);
+            let out_dir = out_dir.unwrap_or_else(|| dir.clone());
+
+            Command::BuildDir {
+                dir,
+                out_dir,
+                recursive,
+            }
+        } else {
+            ensure!(!positional.is_empty(), USAGE);
+            ensure!(positional.len() <= 2, USAGE);
+
+            let script_lua = PathBuf::from(&positional[0]);
+
+            let output_wasm = positional
+                .get(1)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| script_lua.with_extension("wasm"));
+
+            Command::Compile {
+                script_lua,
+                output_wasm,
+            }
+        };
+
+        Ok(Self {
+            command,
+            runtime_wasm,
+            config,
+            export_name: export_name.unwrap_or_else(|| DEFAULT_EXPORT.to_owned()),
+            message_format,
+            optimize,
+            wasm_opt,
+            pack_data,
+        })
+    }
+}
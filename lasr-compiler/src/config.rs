@@ -0,0 +1,888 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+};
+
+pub struct ConfigError {
+    pub message: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+type Table = BTreeMap<String, Value>;
+
+/// Turns a TOML config into a Lua chunk assigning it to the `CONFIG` global,
+/// to be placed in front of the script. The chunk is a single line, so the
+/// script's line numbers stay the same. Keys are sorted, so the same config
+/// always results in the same chunk.
+///
+/// Only tables, arrays, strings, numbers and booleans are supported, as Lua
+/// has nothing to represent TOML's date and time values with.
+pub fn config_chunk(toml: &str) -> Result<String, ConfigError> {
+    let config = Parser {
+        src: toml,
+        pos: 0,
+        headers: HashSet::new(),
+        dotted: HashSet::new(),
+        inline: HashSet::new(),
+    }
+    .parse()?;
+
+    let mut chunk = "CONFIG = ".to_owned();
+    write_table(&mut chunk, &config);
+    chunk.push_str("; ");
+    Ok(chunk)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    /// The tables defined by a `[header]`, which may only appear once.
+    headers: HashSet<Vec<String>>,
+    /// The tables created by dotted keys, which can't get a header later.
+    dotted: HashSet<Vec<String>>,
+    /// The inline tables and arrays, which can't be extended afterwards.
+    inline: HashSet<Vec<String>>,
+}
+
+impl Parser<'_> {
+    fn parse(mut self) -> Result<Table, ConfigError> {
+        let mut root = Table::new();
+        // The header the key/value pairs currently go into.
+        let mut current = Vec::new();
+
+        loop {
+            self.skip_trivia(true);
+            let Some(c) = self.peek() else {
+                break;
+            };
+
+            if c == '[' {
+                self.pos += 1;
+                let array = self.eat('[');
+                self.skip_trivia(false);
+                let start = self.pos;
+                let path = self.parse_key()?;
+                self.skip_trivia(false);
+                self.expect(']')?;
+                if array {
+                    self.expect(']')?;
+                }
+
+                self.check_not_inline(&path, start)?;
+                let (last, parents) = path.split_last().unwrap();
+                let parent = self.table_at(&mut root, parents, start)?;
+                if array {
+                    let entry = parent
+                        .entry(last.clone())
+                        .or_insert_with(|| Value::Array(Vec::new()));
+                    let Value::Array(tables) = entry else {
+                        return Err(
+                            self.error_at(start, format!("{last} is not an array of tables"))
+                        );
+                    };
+                    if !tables.iter().all(|value| matches!(value, Value::Table(_))) {
+                        return Err(
+                            self.error_at(start, format!("{last} is not an array of tables"))
+                        );
+                    }
+                    tables.push(Value::Table(Table::new()));
+                    // Whatever was defined below the previous table of the
+                    // array may be defined again for the new one.
+                    for set in [&mut self.headers, &mut self.dotted, &mut self.inline] {
+                        set.retain(|defined| !defined.starts_with(&path));
+                    }
+                } else {
+                    if self.headers.contains(&path) || self.dotted.contains(&path) {
+                        return Err(self.error_at(
+                            start,
+                            format!("table {} is defined more than once", path.join(".")),
+                        ));
+                    }
+                    self.table_at(&mut root, &path, start)?;
+                    self.headers.insert(path.clone());
+                }
+                current = path;
+            } else {
+                let table = self.table_at(&mut root, &current, self.pos)?;
+                self.parse_key_value(table, Some(&current))?;
+            }
+
+            self.skip_trivia(false);
+            match self.peek() {
+                None | Some('\n') => {}
+                Some('\r') if self.src[self.pos..].starts_with("\r\n") => {}
+                Some(_) => return Err(self.error("expected the end of the line")),
+            }
+        }
+
+        Ok(root)
+    }
+
+    /// The table at `path`, creating the tables that don't exist yet. An
+    /// array of tables along the way stands for its last table.
+    fn table_at<'t>(
+        &self,
+        mut table: &'t mut Table,
+        path: &[String],
+        pos: usize,
+    ) -> Result<&'t mut Table, ConfigError> {
+        for key in path {
+            let value = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            table = match value {
+                Value::Table(table) => table,
+                Value::Array(values) => match values.last_mut() {
+                    Some(Value::Table(table)) => table,
+                    _ => return Err(self.error_at(pos, format!("{key} is not a table"))),
+                },
+                _ => return Err(self.error_at(pos, format!("{key} is not a table"))),
+            };
+        }
+        Ok(table)
+    }
+
+    /// Errors if any table along `path` is an inline table or array.
+    fn check_not_inline(&self, path: &[String], pos: usize) -> Result<(), ConfigError> {
+        for len in 1..=path.len() {
+            if self.inline.contains(&path[..len]) {
+                return Err(self.error_at(
+                    pos,
+                    format!(
+                        "{} is an inline table or array, which can't be extended",
+                        path[..len].join(".")
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// A `key = value` pair. `base` is the path of `table`, unless it is an
+    /// inline table, which is built in one go and needs no bookkeeping.
+    fn parse_key_value(
+        &mut self,
+        table: &mut Table,
+        base: Option<&[String]>,
+    ) -> Result<(), ConfigError> {
+        let start = self.pos;
+        let path = self.parse_key()?;
+        self.skip_trivia(false);
+        self.expect('=')?;
+        self.skip_trivia(false);
+        let value = self.parse_value()?;
+
+        let (last, parents) = path.split_last().unwrap();
+        if let Some(base) = base {
+            let mut absolute = base.to_vec();
+            for parent in parents {
+                absolute.push(parent.clone());
+                if self.headers.contains(&absolute) {
+                    return Err(self.error_at(
+                        start,
+                        format!(
+                            "table {} is already defined by a header",
+                            absolute.join(".")
+                        ),
+                    ));
+                }
+                self.check_not_inline(&absolute, start)?;
+                self.dotted.insert(absolute.clone());
+            }
+            if let Value::Array(_) | Value::Table(_) = value {
+                absolute.push(last.clone());
+                self.inline.insert(absolute);
+            }
+        }
+
+        let table = self.table_at(table, parents, start)?;
+        if table.contains_key(last) {
+            return Err(self.error_at(start, format!("{last} is defined more than once")));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+
+    /// A key, split at its dots.
+    fn parse_key(&mut self) -> Result<Vec<String>, ConfigError> {
+        let mut path = Vec::new();
+        loop {
+            let key = match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    self.parse_basic_string()?
+                }
+                Some('\'') => {
+                    self.pos += 1;
+                    self.parse_literal_string()?
+                }
+                _ => {
+                    let len = self.src[self.pos..]
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                        .unwrap_or(self.src.len() - self.pos);
+                    if len == 0 {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.src[self.pos..self.pos + len].to_owned();
+                    self.pos += len;
+                    key
+                }
+            };
+            path.push(key);
+
+            self.skip_trivia(false);
+            if !self.eat('.') {
+                return Ok(path);
+            }
+            self.skip_trivia(false);
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ConfigError> {
+        let rest = &self.src[self.pos..];
+        if rest.starts_with("\"\"\"") {
+            self.pos += 3;
+            return self.parse_multiline_string('"').map(Value::String);
+        }
+        if rest.starts_with("'''") {
+            self.pos += 3;
+            return self.parse_multiline_string('\'').map(Value::String);
+        }
+
+        match self.peek() {
+            Some('"') => {
+                self.pos += 1;
+                self.parse_basic_string().map(Value::String)
+            }
+            Some('\'') => {
+                self.pos += 1;
+                self.parse_literal_string().map(Value::String)
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                loop {
+                    self.skip_trivia(true);
+                    if self.eat(']') {
+                        break;
+                    }
+                    values.push(self.parse_value()?);
+                    self.skip_trivia(true);
+                    if self.eat(']') {
+                        break;
+                    }
+                    self.expect(',')?;
+                }
+                Ok(Value::Array(values))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut table = Table::new();
+                self.skip_trivia(false);
+                if !self.eat('}') {
+                    loop {
+                        self.skip_trivia(false);
+                        self.parse_key_value(&mut table, None)?;
+                        self.skip_trivia(false);
+                        if self.eat('}') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Value::Table(table))
+            }
+            Some(_) => self.parse_scalar(),
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    /// Booleans and numbers, and date and time values to reject.
+    fn parse_scalar(&mut self) -> Result<Value, ConfigError> {
+        let start = self.pos;
+        let len = self.src[start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_+-.:".contains(c)))
+            .unwrap_or(self.src.len() - start);
+        let token = &self.src[start..start + len];
+        self.pos += len;
+
+        let bytes = token.as_bytes();
+        let is_date = bytes.len() >= 10
+            && bytes[..4].iter().all(u8::is_ascii_digit)
+            && bytes[4] == b'-'
+            && bytes[7] == b'-';
+        if is_date || (token.contains(':') && !token.starts_with(['+', '-'])) {
+            return Err(self.error_at(
+                start,
+                "date and time values are not supported, use a string instead".to_owned(),
+            ));
+        }
+
+        let invalid = || self.error_at(start, format!("invalid value {token}"));
+        Ok(match token {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            "inf" | "+inf" => Value::Float(f64::INFINITY),
+            "-inf" => Value::Float(f64::NEG_INFINITY),
+            "nan" | "+nan" | "-nan" => Value::Float(f64::NAN),
+            _ => {
+                if token.is_empty()
+                    || token.starts_with('_')
+                    || token.ends_with('_')
+                    || token.contains("__")
+                {
+                    return Err(invalid());
+                }
+                let digits = token.replace('_', "");
+
+                let radix = match digits.get(..2) {
+                    Some("0x") => Some(16),
+                    Some("0o") => Some(8),
+                    Some("0b") => Some(2),
+                    _ => None,
+                };
+                if let Some(radix) = radix {
+                    if !is_digits(&token[2..], radix) {
+                        return Err(invalid());
+                    }
+                    let value = u64::from_str_radix(&digits[2..], radix)
+                        .ok()
+                        .and_then(|value| i64::try_from(value).ok())
+                        .ok_or_else(invalid)?;
+                    Value::Integer(value)
+                } else if token.contains(['.', 'e', 'E']) {
+                    if !is_float(token) {
+                        return Err(invalid());
+                    }
+                    Value::Float(digits.parse().map_err(|_| invalid())?)
+                } else {
+                    let unsigned = token.strip_prefix(['+', '-']).unwrap_or(token);
+                    if unsigned.len() > 1 && unsigned.starts_with('0') {
+                        return Err(self.error_at(
+                            start,
+                            format!("invalid value {token}, integers can't have leading zeros"),
+                        ));
+                    }
+                    if !is_decimal(unsigned) {
+                        return Err(invalid());
+                    }
+                    Value::Integer(digits.parse().map_err(|_| invalid())?)
+                }
+            }
+        })
+    }
+
+    /// A `"` string, after the opening quote.
+    fn parse_basic_string(&mut self) -> Result<String, ConfigError> {
+        let start = self.pos - 1;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(self.parse_escape()?),
+                Some('\n') | None => {
+                    return Err(self.error_at(start, "unterminated string".to_owned()));
+                }
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// A `'` string, after the opening quote.
+    fn parse_literal_string(&mut self) -> Result<String, ConfigError> {
+        let len = self.src[self.pos..]
+            .find(['\'', '\n'])
+            .filter(|&len| self.src[self.pos + len..].starts_with('\''))
+            .ok_or_else(|| self.error_at(self.pos - 1, "unterminated string".to_owned()))?;
+        let string = self.src[self.pos..self.pos + len].to_owned();
+        self.pos += len + 1;
+        Ok(string)
+    }
+
+    /// A `"""` or `'''` string, after the opening quotes.
+    fn parse_multiline_string(&mut self, quote: char) -> Result<String, ConfigError> {
+        let start = self.pos - 3;
+        // A newline right after the opening quotes isn't part of the string.
+        if !self.eat('\n') && self.src[self.pos..].starts_with("\r\n") {
+            self.pos += 2;
+        }
+
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some(c) if c == quote => {
+                    let mut quotes = 1;
+                    while quotes < 5 && self.eat(quote) {
+                        quotes += 1;
+                    }
+                    if quotes >= 3 {
+                        // Up to two quotes may end the string right before
+                        // the closing ones.
+                        string.extend(std::iter::repeat_n(quote, quotes - 3));
+                        return Ok(string);
+                    }
+                    string.extend(std::iter::repeat_n(quote, quotes));
+                }
+                Some('\\') if quote == '"' => {
+                    // A backslash at the end of a line trims the whitespace
+                    // up to the next non-whitespace character.
+                    let rest = &self.src[self.pos..];
+                    let trimmed = rest.trim_start_matches([' ', '\t']);
+                    if trimmed.starts_with(['\n', '\r']) {
+                        self.pos += rest.len() - trimmed.len();
+                        self.skip_trivia_whitespace();
+                    } else {
+                        string.push(self.parse_escape()?);
+                    }
+                }
+                Some(c) => string.push(c),
+                None => return Err(self.error_at(start, "unterminated string".to_owned())),
+            }
+        }
+    }
+
+    fn skip_trivia_whitespace(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// An escape sequence, after the backslash.
+    fn parse_escape(&mut self) -> Result<char, ConfigError> {
+        let start = self.pos;
+        let digits = match self.next() {
+            Some('b') => return Ok('\u{8}'),
+            Some('t') => return Ok('\t'),
+            Some('n') => return Ok('\n'),
+            Some('f') => return Ok('\u{c}'),
+            Some('r') => return Ok('\r'),
+            Some('e') => return Ok('\u{1b}'),
+            Some('"') => return Ok('"'),
+            Some('\\') => return Ok('\\'),
+            Some('x') => 2,
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => return Err(self.error_at(start, "invalid escape sequence".to_owned())),
+        };
+
+        let c = self
+            .src
+            .get(self.pos..self.pos + digits)
+            .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error_at(start, "invalid escape sequence".to_owned()))?;
+        self.pos += digits;
+        Ok(c)
+    }
+
+    /// Skips whitespace and comments, and newlines if `newlines` is set.
+    fn skip_trivia(&mut self, newlines: bool) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t') => self.pos += 1,
+                Some('\n') if newlines => self.pos += 1,
+                Some('\r') if newlines && self.src[self.pos..].starts_with("\r\n") => self.pos += 2,
+                Some('#') => {
+                    self.pos += self.src[self.pos..]
+                        .find('\n')
+                        .unwrap_or(self.src.len() - self.pos);
+                    if self.src[..self.pos].ends_with('\r') {
+                        self.pos -= 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let eaten = self.peek() == Some(c);
+        if eaten {
+            self.pos += c.len_utf8();
+        }
+        eaten
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ConfigError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {c}")))
+        }
+    }
+
+    fn error(&self, message: &str) -> ConfigError {
+        self.error_at(self.pos, message.to_owned())
+    }
+
+    fn error_at(&self, pos: usize, message: String) -> ConfigError {
+        let before = &self.src[..pos];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+        let column = before[line_start..].chars().count() + 1;
+
+        ConfigError {
+            message,
+            line: line.try_into().unwrap_or(u32::MAX),
+            column: column.try_into().unwrap_or(u32::MAX),
+        }
+    }
+}
+
+/// Whether `token` is digits of the `radix`, with single underscores only
+/// between them.
+fn is_digits(token: &str, radix: u32) -> bool {
+    !token.is_empty()
+        && token
+            .split('_')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_digit(radix)))
+}
+
+/// Whether `token` is an unsigned decimal integer without leading zeros.
+fn is_decimal(token: &str) -> bool {
+    is_digits(token, 10) && (token == "0" || !token.starts_with('0'))
+}
+
+/// Whether `token` is a float, which needs digits on both sides of its `.`,
+/// unlike in Lua.
+fn is_float(token: &str) -> bool {
+    let token = token.strip_prefix(['+', '-']).unwrap_or(token);
+    let (mantissa, exponent) = match token.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (token, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+
+    is_decimal(integer)
+        && fraction.is_none_or(|fraction| is_digits(fraction, 10))
+        && exponent.is_none_or(|exponent| {
+            is_digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent), 10)
+        })
+}
+
+fn write_table(out: &mut String, table: &Table) {
+    out.push('{');
+    for (i, (key, value)) in table.iter().enumerate() {
+        if i != 0 {
+            out.push_str(", ");
+        }
+        out.push('[');
+        write_string(out, key);
+        out.push_str("] = ");
+        write_value(out, value);
+    }
+    out.push('}');
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::String(string) => write_string(out, string),
+        // Lua reads the literal as a float, as its absolute value doesn't fit.
+        Value::Integer(i64::MIN) => out.push_str("math.mininteger"),
+        Value::Integer(value) => {
+            let _ = write!(out, "{value}");
+        }
+        Value::Float(value) if value.is_nan() => out.push_str("(0/0)"),
+        Value::Float(value) if value.is_infinite() => out.push_str(if *value > 0.0 {
+            "math.huge"
+        } else {
+            "-math.huge"
+        }),
+        // `Debug` always includes a `.` or an exponent, so Lua reads a float.
+        Value::Float(value) => {
+            let _ = write!(out, "{value:?}");
+        }
+        Value::Boolean(value) => {
+            let _ = write!(out, "{value}");
+        }
+        Value::Array(values) => {
+            out.push('{');
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, value);
+            }
+            out.push('}');
+        }
+        Value::Table(table) => write_table(out, table),
+    }
+}
+
+fn write_string(out: &mut String, string: &str) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_ascii_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(toml: &str) -> String {
+        match config_chunk(toml) {
+            Ok(chunk) => chunk,
+            Err(err) => panic!("{}:{}: {}", err.line, err.column, err.message),
+        }
+    }
+
+    fn error(toml: &str) -> (u32, u32, String) {
+        match config_chunk(toml) {
+            Ok(chunk) => panic!("expected an error, got {chunk}"),
+            Err(err) => (err.line, err.column, err.message),
+        }
+    }
+
+    #[test]
+    fn generates_sorted_single_line_chunk() {
+        let toml = "\
+title = 'Any%'
+splits = ['Intro', \"Boss\"]
+
+[options]
+start_on_load = true
+delay = 1.5
+count = 3
+";
+        assert_eq!(
+            chunk(toml),
+            "CONFIG = {[\"options\"] = {[\"count\"] = 3, [\"delay\"] = 1.5, \
+             [\"start_on_load\"] = true}, [\"splits\"] = {\"Intro\", \"Boss\"}, \
+             [\"title\"] = \"Any%\"}; "
+        );
+        assert!(!chunk(toml).contains('\n'));
+        assert_eq!(chunk(""), "CONFIG = {}; ");
+    }
+
+    #[test]
+    fn strings_and_escapes() {
+        assert_eq!(
+            chunk(r#"s = "tab\tquote\"back\\slash\u00e9\U0001F600\x41\e""#),
+            "CONFIG = {[\"s\"] = \"tab\\tquote\\\"back\\\\slashé😀A\\x1b\"}; "
+        );
+        assert_eq!(
+            chunk(r"s = 'C:\no\escapes'"),
+            "CONFIG = {[\"s\"] = \"C:\\\\no\\\\escapes\"}; "
+        );
+        assert_eq!(
+            chunk("s = \"\"\"\nline 1\nline \\\n    2\"\"\""),
+            "CONFIG = {[\"s\"] = \"line 1\\nline 2\"}; "
+        );
+        assert_eq!(
+            chunk("s = '''\n'quoted'\n'''''"),
+            "CONFIG = {[\"s\"] = \"'quoted'\\n''\"}; "
+        );
+        assert_eq!(
+            chunk("\"quoted key\" = 1\n'literal.key' = 2"),
+            "CONFIG = {[\"literal.key\"] = 2, [\"quoted key\"] = 1}; "
+        );
+
+        assert_eq!(
+            error("s = \"\\q\""),
+            (1, 7, "invalid escape sequence".to_owned())
+        );
+        assert_eq!(
+            error("s = \"\\uD800\""),
+            (1, 7, "invalid escape sequence".to_owned())
+        );
+        assert_eq!(
+            error("s = \"open\nt = 1"),
+            (1, 5, "unterminated string".to_owned())
+        );
+        assert_eq!(error("s = 'open"), (1, 5, "unterminated string".to_owned()));
+    }
+
+    #[test]
+    fn arrays_and_inline_tables() {
+        assert_eq!(
+            chunk("a = [\n  1, # one\n  [2.0, 'x'],\n  { y = true },\n]"),
+            "CONFIG = {[\"a\"] = {1, {2.0, \"x\"}, {[\"y\"] = true}}}; "
+        );
+        assert_eq!(
+            chunk("point = { x = 1, y.z = 2 }\nempty = {}"),
+            "CONFIG = {[\"empty\"] = {}, [\"point\"] = {[\"x\"] = 1, [\"y\"] = {[\"z\"] = 2}}}; "
+        );
+        assert_eq!(
+            chunk(
+                "[[split]]\nname = 'a'\n[split.extra]\nx = 1\n[[split]]\nname = 'b'\n[split.extra]\nx = 2"
+            ),
+            "CONFIG = {[\"split\"] = {{[\"extra\"] = {[\"x\"] = 1}, [\"name\"] = \"a\"}, \
+             {[\"extra\"] = {[\"x\"] = 2}, [\"name\"] = \"b\"}}}; "
+        );
+
+        assert_eq!(
+            error("point = { x = 1 }\n[point]"),
+            (
+                2,
+                2,
+                "point is an inline table or array, which can't be extended".to_owned()
+            )
+        );
+        assert_eq!(
+            error("point = { x = 1 }\npoint.y = 2"),
+            (
+                2,
+                1,
+                "point is an inline table or array, which can't be extended".to_owned()
+            )
+        );
+        assert_eq!(
+            error("split = [{ x = 1 }]\n[[split]]"),
+            (
+                2,
+                3,
+                "split is an inline table or array, which can't be extended".to_owned()
+            )
+        );
+        assert_eq!(error("a = [1 2]"), (1, 8, "expected ,".to_owned()));
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        assert_eq!(
+            error("a = 1\na = 2"),
+            (2, 1, "a is defined more than once".to_owned())
+        );
+        assert_eq!(
+            error("t = { a = 1, a = 2 }"),
+            (1, 14, "a is defined more than once".to_owned())
+        );
+        assert_eq!(
+            error("[a]\nx = 1\n[b]\n[a]"),
+            (4, 2, "table a is defined more than once".to_owned())
+        );
+        assert_eq!(
+            error("[a.b]\n[a]\n[a.b]"),
+            (3, 2, "table a.b is defined more than once".to_owned())
+        );
+        assert_eq!(
+            error("[a]\nb.c = 1\n[a.b]"),
+            (3, 2, "table a.b is defined more than once".to_owned())
+        );
+        assert_eq!(
+            error("[a.b]\n[a]\nb.c = 1"),
+            (3, 1, "table a.b is already defined by a header".to_owned())
+        );
+        assert_eq!(error("a = 1\n[a]"), (2, 2, "a is not a table".to_owned()));
+        assert_eq!(
+            error("[a]\n[[a]]"),
+            (2, 3, "a is not an array of tables".to_owned())
+        );
+
+        // Defining a super-table after its sub-table is fine.
+        assert_eq!(
+            chunk("[a.b]\nx = 1\n[a]\ny = 2"),
+            "CONFIG = {[\"a\"] = {[\"b\"] = {[\"x\"] = 1}, [\"y\"] = 2}}; "
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            chunk("a = 1_000\nb = -0\nc = 0xff\nd = 0o17\ne = 0b101\nf = +7"),
+            "CONFIG = {[\"a\"] = 1000, [\"b\"] = 0, [\"c\"] = 255, [\"d\"] = 15, \
+             [\"e\"] = 5, [\"f\"] = 7}; "
+        );
+        assert_eq!(
+            chunk("a = 1.0\nb = -3.5e2\nc = 1e3\nd = 6.626E-34\ne = 1_0.2_5"),
+            "CONFIG = {[\"a\"] = 1.0, [\"b\"] = -350.0, [\"c\"] = 1000.0, \
+             [\"d\"] = 6.626e-34, [\"e\"] = 10.25}; "
+        );
+        assert_eq!(
+            chunk("a = inf\nb = -inf\nc = nan\nd = -9223372036854775808"),
+            "CONFIG = {[\"a\"] = math.huge, [\"b\"] = -math.huge, [\"c\"] = (0/0), \
+             [\"d\"] = math.mininteger}; "
+        );
+
+        for invalid in [
+            ".5",
+            "-.5",
+            "5.",
+            "1.e5",
+            "1e",
+            "1e+",
+            "1._5",
+            "1_.5",
+            "1__0",
+            "_1",
+            "1_",
+            "01.5",
+            "0x",
+            "0x_1",
+            "0xg",
+            "1.5.5",
+            "1e5e5",
+            "9223372036854775808",
+            "--1",
+            "+-1",
+            "one",
+        ] {
+            assert_eq!(
+                error(&format!("x = {invalid}")),
+                (1, 5, format!("invalid value {invalid}")),
+            );
+        }
+        assert_eq!(
+            error("x = 007"),
+            (
+                1,
+                5,
+                "invalid value 007, integers can't have leading zeros".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_dates_and_times() {
+        for value in ["1979-05-27", "1979-05-27T07:32:00Z", "07:32:00"] {
+            assert_eq!(
+                error(&format!("x = {value}")).2,
+                "date and time values are not supported, use a string instead"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_trailing_garbage() {
+        assert_eq!(
+            error("a = 1 b = 2"),
+            (1, 7, "expected the end of the line".to_owned())
+        );
+        assert_eq!(chunk("a = 1 # comment\r\nb = 2\r\n"), chunk("a = 1\nb = 2"));
+    }
+}
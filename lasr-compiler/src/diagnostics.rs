@@ -0,0 +1,311 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// These are part of the JSON output, so existing codes must never be renamed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    ReadFailed,
+    WriteFailed,
+    InjectionFailed,
+    WasmOptFailed,
+    InvalidScript,
+    InvalidConfig,
+}
+
+impl Code {
+    fn as_str(self) -> &'static str {
+        match self {
+            Code::ReadFailed => "read-failed",
+            Code::WriteFailed => "write-failed",
+            Code::InjectionFailed => "injection-failed",
+            Code::WasmOptFailed => "wasm-opt-failed",
+            Code::InvalidScript => "invalid-script",
+            Code::InvalidConfig => "invalid-config",
+        }
+    }
+}
+
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn warning(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message)
+        }
+    }
+
+    pub fn with_file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_owned());
+        self
+    }
+
+    pub fn with_position(mut self, line: u32, column: u32) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = String::from("{\"reason\":\"diagnostic\",\"severity\":");
+        push_json_str(self.severity.as_str(), &mut out);
+        out.push_str(",\"code\":");
+        push_json_str(self.code.as_str(), &mut out);
+        out.push_str(",\"message\":");
+        push_json_str(&self.message, &mut out);
+        out.push_str(",\"file\":");
+        push_json_opt_path(self.file.as_deref(), &mut out);
+        out.push_str(",\"line\":");
+        push_json_opt_u32(self.line, &mut out);
+        out.push_str(",\"column\":");
+        push_json_opt_u32(self.column, &mut out);
+        out.push('}');
+        out
+    }
+}
+
+pub struct Reporter {
+    format: MessageFormat,
+}
+
+impl Reporter {
+    pub fn new(format: MessageFormat) -> Self {
+        Self { format }
+    }
+
+    pub fn report(&self, diagnostic: &Diagnostic) {
+        match self.format {
+            MessageFormat::Human => {
+                let mut out = String::new();
+                if let Some(file) = &diagnostic.file {
+                    let _ = write!(out, "{}:", file.display());
+                    if let Some(line) = diagnostic.line {
+                        let _ = write!(out, "{line}:");
+                        if let Some(column) = diagnostic.column {
+                            let _ = write!(out, "{column}:");
+                        }
+                    }
+                    out.push(' ');
+                }
+                let _ = write!(
+                    out,
+                    "{}[{}]: {}",
+                    diagnostic.severity.as_str(),
+                    diagnostic.code.as_str(),
+                    diagnostic.message
+                );
+                eprintln!("{out}");
+            }
+            MessageFormat::Json => println!("{}", diagnostic.to_json()),
+        }
+    }
+
+    pub fn script_finished(&self, script: &Path, output: Option<&Path>) {
+        if let MessageFormat::Json = self.format {
+            let mut out = String::from("{\"reason\":\"script-finished\",\"script\":");
+            push_json_str(&script.to_string_lossy(), &mut out);
+            out.push_str(",\"success\":");
+            out.push_str(if output.is_some() { "true" } else { "false" });
+            out.push_str(",\"output\":");
+            push_json_opt_path(output, &mut out);
+            out.push('}');
+            println!("{out}");
+        }
+    }
+
+    pub fn summary(&self, results: &[(PathBuf, bool)]) {
+        if let MessageFormat::Human = self.format {
+            let failed = results.iter().filter(|(_, success)| !success).count();
+            eprintln!();
+            for (script, success) in results {
+                let status = if *success { "ok" } else { "FAILED" };
+                eprintln!("{status:<8}{}", script.display());
+            }
+            eprintln!(
+                "\n{} scripts compiled, {failed} failed",
+                results.len() - failed
+            );
+        }
+    }
+
+    pub fn finish(&self, output: Option<&Path>) {
+        if let MessageFormat::Json = self.format {
+            let mut out = String::from("{\"reason\":\"build-finished\",\"success\":");
+            out.push_str(if output.is_some() { "true" } else { "false" });
+            out.push_str(",\"output\":");
+            push_json_opt_path(output, &mut out);
+            out.push('}');
+            println!("{out}");
+        }
+    }
+}
+
+fn push_json_opt_u32(value: Option<u32>, out: &mut String) {
+    match value {
+        Some(value) => {
+            let _ = write!(out, "{value}");
+        }
+        None => out.push_str("null"),
+    }
+}
+
+fn push_json_opt_path(value: Option<&Path>, out: &mut String) {
+    match value {
+        Some(value) => push_json_str(&value.to_string_lossy(), out),
+        None => out.push_str("null"),
+    }
+}
+
+fn push_json_str(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a flat JSON object of strings, integers and `null`s, strictly
+    /// enough that a missing escape breaks it.
+    fn parse_object(json: &str) -> Vec<(String, Option<String>)> {
+        let mut chars = json.chars().peekable();
+        let mut fields = Vec::new();
+        assert_eq!(chars.next(), Some('{'));
+        loop {
+            let key = parse_str(&mut chars);
+            assert_eq!(chars.next(), Some(':'));
+            let value = match chars.peek() {
+                Some('"') => Some(parse_str(&mut chars)),
+                Some('n') => {
+                    assert_eq!(chars.by_ref().take(4).collect::<String>(), "null");
+                    None
+                }
+                _ => {
+                    let mut number = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        number.push(c);
+                    }
+                    assert!(!number.is_empty(), "{json}");
+                    Some(number)
+                }
+            };
+            fields.push((key, value));
+            match chars.next() {
+                Some(',') => {}
+                Some('}') => break,
+                c => panic!("unexpected {c:?} in {json}"),
+            }
+        }
+        assert_eq!(chars.next(), None, "{json}");
+        fields
+    }
+
+    fn parse_str(chars: &mut impl Iterator<Item = char>) -> String {
+        assert_eq!(chars.next(), Some('"'));
+        let mut value = String::new();
+        loop {
+            match chars.next().expect("unterminated string") {
+                '"' => return value,
+                '\\' => value.push(match chars.next().expect("unterminated escape") {
+                    '"' => '"',
+                    '\\' => '\\',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let hex = chars.take(4).collect::<String>();
+                        char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap()
+                    }
+                    c => panic!("invalid escape \\{c}"),
+                }),
+                c => {
+                    assert!(c >= ' ', "unescaped control character {c:?}");
+                    value.push(c);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        let text = "say \"hi\" C:\\dir\\ a\nb\r\tc\u{0}\u{1b}\u{1f} é 😀";
+        let diagnostic = Diagnostic::error(Code::InvalidScript, text)
+            .with_file(Path::new(text))
+            .with_position(3, 14);
+
+        let fields = parse_object(&diagnostic.to_json());
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .unwrap_or_else(|| panic!("missing {name}"))
+                .1
+                .as_deref()
+        };
+        assert_eq!(field("reason"), Some("diagnostic"));
+        assert_eq!(field("severity"), Some("error"));
+        assert_eq!(field("code"), Some("invalid-script"));
+        assert_eq!(field("message"), Some(text));
+        assert_eq!(field("file"), Some(text));
+        assert_eq!(field("line"), Some("3"));
+        assert_eq!(field("column"), Some("14"));
+
+        let fields = parse_object(&Diagnostic::warning(Code::ReadFailed, "").to_json());
+        assert!(fields.contains(&("file".to_owned(), None)));
+        assert!(fields.contains(&("message".to_owned(), Some(String::new()))));
+    }
+}
@@ -0,0 +1,1528 @@
+use std::{borrow::Cow, fmt::Write, ops::Range};
+
+use anyhow::{Context, Result, bail, ensure};
+use wasm_encoder::{CustomSection, Module, RawSection, SectionId};
+use wasmparser::{
+    BinaryReader, DataKind, DataSectionReader, Export, ExportSectionReader, ExternalKind, FuncType,
+    FunctionBody, FunctionSectionReader, GlobalSectionReader, ImportSectionReader,
+    MemorySectionReader, MemoryType, Operator, Parser, Payload, TypeRef, TypeSectionReader,
+    ValType,
+};
+
+pub const METADATA_SECTION: &str = "lasr.metadata";
+
+/// Custom section in which the runtime describes itself with `key=value`
+/// lines, such as its `version` and `api-level`.
+pub const RUNTIME_INFO_SECTION: &str = "lasr.runtime";
+
+pub fn inject_script(wasm: &[u8], script: &[u8], export_name: &str) -> Result<Vec<u8>> {
+    RuntimeTemplate::parse(wasm, export_name)?.inject(script, &InjectOptions::default())
+}
+
+#[derive(Default)]
+pub struct InjectOptions {
+    /// Drops all custom sections, none of which are needed to run the module.
+    pub optimize: bool,
+    /// Places the script between the end of the existing data and the heap
+    /// base if it fits there, instead of always growing the memory.
+    pub pack_data: bool,
+    /// Key / value pairs stored as `key=value` lines in the
+    /// [`METADATA_SECTION`] custom section, which is kept by `optimize`.
+    pub metadata: Vec<(String, String)>,
+}
+
+pub struct RuntimeTemplate<'a> {
+    wasm: &'a [u8],
+    section_order: Vec<SectionItem<'a>>,
+    code_body_ranges: Vec<Range<usize>>,
+    data_segments: Vec<(u32, i32, Vec<u8>)>,
+    memory_limits: MemoryLimits,
+    slack: Option<Range<u64>>,
+    patch_index: usize,
+    wrapper_index: usize,
+    abi: ScriptAbi,
+    export_section: Vec<u8>,
+    runtime_info: Vec<(String, String)>,
+}
+
+impl<'a> RuntimeTemplate<'a> {
+    pub fn parse(wasm: &'a [u8], export_name: &str) -> Result<Self> {
+        #[cfg(test)]
+        tests::PARSES.with(|parses| parses.set(parses.get() + 1));
+
+        let mut section_order: Vec<SectionItem<'_>> = Vec::new();
+        let mut code_body_ranges: Vec<Range<usize>> = Vec::new();
+        let mut data_segments: Vec<(u32, i32, Vec<u8>)> = Vec::new();
+        let mut types: Vec<FuncType> = Vec::new();
+        let mut func_type_indices: Vec<u32> = Vec::new();
+        let mut globals: Vec<(bool, Option<i32>)> = Vec::new();
+        let mut imports = ImportCounts::default();
+        let mut export_func_index: Option<u32> = None;
+        let mut export_entries: Vec<ExportEntry> = Vec::new();
+        let mut has_code_section = false;
+        let mut has_data_section = false;
+        let mut memory_limits: Option<MemoryLimits> = None;
+        let mut runtime_info: Vec<(String, String)> = Vec::new();
+
+        for payload in Parser::new(0).parse_all(wasm) {
+            let payload = payload?;
+            let raw_section = raw_section_from_payload(&payload, wasm)?;
+            match payload {
+                Payload::Version { .. } => {}
+                Payload::TypeSection(reader) => {
+                    types = read_types(reader)?;
+                    if let Some(section) = raw_section {
+                        section_order.push(SectionItem::Raw(section));
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    imports = count_imports(reader)?;
+                    if let Some(section) = raw_section {
+                        section_order.push(SectionItem::Raw(section));
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    func_type_indices = read_function_types(reader)?;
+                    if let Some(section) = raw_section {
+                        section_order.push(SectionItem::Raw(section));
+                    }
+                }
+                Payload::GlobalSection(reader) => {
+                    globals = read_global_values(reader)?;
+                    if let Some(section) = raw_section {
+                        section_order.push(SectionItem::Raw(section));
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    let (entries, export_index) = read_exports(reader, export_name)?;
+                    export_entries = entries;
+                    export_func_index = export_index;
+                    section_order.push(SectionItem::Export);
+                }
+                Payload::MemorySection(reader) => {
+                    memory_limits = Some(read_memory_section(reader)?);
+                    section_order.push(SectionItem::Memory);
+                }
+                Payload::DataCountSection { .. } => {
+                    section_order.push(SectionItem::DataCount);
+                }
+                Payload::CodeSectionStart { .. } => {
+                    has_code_section = true;
+                    section_order.push(SectionItem::Code);
+                }
+                Payload::CodeSectionEntry(body) => {
+                    code_body_ranges.push(body.range());
+                }
+                Payload::DataSection(reader) => {
+                    data_segments = read_data_segments(reader)?;
+                    has_data_section = true;
+                    section_order.push(SectionItem::Data);
+                }
+                Payload::CustomSection(reader) => {
+                    if reader.name() == RUNTIME_INFO_SECTION {
+                        runtime_info = parse_key_values(reader.data())
+                            .context("runtime info section is not valid UTF-8")?;
+                    }
+                    if let Some(section) = raw_section {
+                        section_order.push(SectionItem::Raw(section));
+                    }
+                }
+                Payload::End(_) => {
+                    if let Payload::End(_) = payload {
+                        break;
+                    }
+                }
+                _ => {
+                    if let Some(section) = raw_section {
+                        section_order.push(SectionItem::Raw(section));
+                    }
+                }
+            }
+        }
+
+        let export_func_index = export_func_index.with_context(|| {
+            format!("module does not export a function named {export_name} (use --export-name to select a different export)")
+        })?;
+
+        let export_code_index = export_func_index
+            .checked_sub(imports.funcs)
+            .with_context(|| format!("export {export_name} refers to an imported function"))?;
+
+        ensure!(
+            imports.memories == 0,
+            "module imports its linear memory, but the script can only be injected into a module \
+             that defines its own memory (link the runtime without --import-memory)"
+        );
+        let memory_limits = memory_limits.context("module has no memory")?;
+
+        ensure!(has_code_section, "module has no code section");
+
+        ensure!(
+            (export_code_index as usize) < code_body_ranges.len(),
+            "export function index out of range"
+        );
+        ensure!(
+            func_type_indices.len() == code_body_ranges.len(),
+            "function section declares {} functions but the code section has {} bodies",
+            func_type_indices.len(),
+            code_body_ranges.len()
+        );
+
+        let export_type = function_type(&types, &func_type_indices, export_code_index as usize)?;
+        let abi = ScriptAbi::from_signature(export_type).with_context(|| {
+            format!(
+                "export {export_name} has signature {}, but the script can only be patched into a \
+                 function with signature (i32) -> () (an `extern \"C\"` function returning a \
+                 pointer/length struct through a return pointer) or () -> (i32, i32) (the same \
+                 function compiled with multivalue returns)",
+                format_signature(export_type)
+            )
+        })?;
+
+        if !has_data_section {
+            // The data section has to directly follow the code section, with
+            // only custom sections allowed after it.
+            let code_position = section_order
+                .iter()
+                .position(|item| matches!(item, SectionItem::Code))
+                .context("module has no code section")?;
+            section_order.insert(code_position + 1, SectionItem::Data);
+        }
+
+        let patch_index = resolve_patch_index(
+            wasm,
+            &code_body_ranges,
+            export_code_index as usize,
+            imports.funcs,
+            |index| {
+                function_type(&types, &func_type_indices, index).is_ok_and(|ty| ty == export_type)
+            },
+        )?;
+
+        let exported_global = |name: &str| {
+            let entry = export_entries
+                .iter()
+                .find(|entry| entry.name == name && entry.kind == ExternalKind::Global)?;
+            let index = entry.index.checked_sub(imports.globals)?;
+            globals.get(index as usize)?.1
+        };
+        // wasm-ld doesn't export `__stack_pointer`, but always makes it the
+        // first global, unless it's imported.
+        let stack_pointer = exported_global("__stack_pointer").or_else(|| match globals.first() {
+            Some(&(true, init)) if imports.globals == 0 => init,
+            _ => None,
+        });
+        let slack = find_slack(
+            &data_segments,
+            exported_global("__data_end"),
+            exported_global("__heap_base"),
+            stack_pointer,
+        );
+
+        Ok(Self {
+            wasm,
+            section_order,
+            code_body_ranges,
+            data_segments,
+            memory_limits,
+            slack,
+            patch_index,
+            wrapper_index: export_code_index as usize,
+            abi,
+            export_section: build_export_section(&export_entries),
+            runtime_info,
+        })
+    }
+
+    /// The `key=value` pairs of the runtime's [`RUNTIME_INFO_SECTION`], if it
+    /// has one.
+    pub fn runtime_info(&self) -> &[(String, String)] {
+        &self.runtime_info
+    }
+
+    pub fn inject(&self, script: &[u8], options: &InjectOptions) -> Result<Vec<u8>> {
+        let mut data_segments = self.data_segments.clone();
+        let (data_offset, script_len, new_initial_pages) = append_script_data(
+            &mut data_segments,
+            script,
+            &self.memory_limits,
+            self.slack.as_ref().filter(|_| options.pack_data),
+        )?;
+
+        let code_section = build_code_section(
+            self.wasm,
+            &self.code_body_ranges,
+            self.patch_index,
+            self.wrapper_index,
+            self.abi,
+            data_offset,
+            script_len,
+        )?;
+        let data_section = build_data_section(&data_segments);
+        let data_count_section = build_data_count_section(data_segments.len());
+        let memory_section = build_memory_section(
+            new_initial_pages,
+            &self.memory_limits,
+            &self.wasm[self.memory_limits.others.clone()],
+        )?;
+
+        let mut module = Module::new();
+        for item in &self.section_order {
+            match item {
+                SectionItem::Raw(section) => {
+                    if options.optimize && section.id == SectionId::Custom as u8 {
+                        continue;
+                    }
+                    module.section(section);
+                }
+                SectionItem::Code => {
+                    let section = RawSection {
+                        id: SectionId::Code as u8,
+                        data: &code_section,
+                    };
+                    module.section(&section);
+                }
+                SectionItem::Export => {
+                    let section = RawSection {
+                        id: SectionId::Export as u8,
+                        data: &self.export_section,
+                    };
+                    module.section(&section);
+                }
+                SectionItem::Memory => {
+                    let section = RawSection {
+                        id: SectionId::Memory as u8,
+                        data: &memory_section,
+                    };
+                    module.section(&section);
+                }
+                SectionItem::DataCount => {
+                    let section = RawSection {
+                        id: SectionId::DataCount as u8,
+                        data: &data_count_section,
+                    };
+                    module.section(&section);
+                }
+                SectionItem::Data => {
+                    let section = RawSection {
+                        id: SectionId::Data as u8,
+                        data: &data_section,
+                    };
+                    module.section(&section);
+                }
+            }
+        }
+
+        if !options.metadata.is_empty() {
+            let mut data = String::new();
+            for (key, value) in &options.metadata {
+                let _ = writeln!(data, "{key}={value}");
+            }
+            module.section(&CustomSection {
+                name: Cow::Borrowed(METADATA_SECTION),
+                data: Cow::Borrowed(data.as_bytes()),
+            });
+        }
+
+        Ok(module.finish())
+    }
+}
+
+fn parse_key_values(data: &[u8]) -> Result<Vec<(String, String)>> {
+    let text = std::str::from_utf8(data)?;
+    Ok(text
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect())
+}
+
+fn read_types(reader: TypeSectionReader) -> Result<Vec<FuncType>> {
+    let mut types = Vec::new();
+    for ty in reader.into_iter_err_on_gc_types() {
+        types.push(ty.context("GC types are not supported")?);
+    }
+    Ok(types)
+}
+
+fn read_function_types(reader: FunctionSectionReader) -> Result<Vec<u32>> {
+    let mut indices = Vec::new();
+    for index in reader {
+        indices.push(index?);
+    }
+    Ok(indices)
+}
+
+fn function_type<'a>(
+    types: &'a [FuncType],
+    func_type_indices: &[u32],
+    code_index: usize,
+) -> Result<&'a FuncType> {
+    let type_index = *func_type_indices
+        .get(code_index)
+        .context("function has no type declaration")?;
+    types
+        .get(type_index as usize)
+        .with_context(|| format!("function type index {type_index} out of range"))
+}
+
+impl ScriptAbi {
+    fn from_signature(ty: &FuncType) -> Option<Self> {
+        match (ty.params(), ty.results()) {
+            ([ValType::I32], []) => Some(Self::Sret),
+            ([], [ValType::I32, ValType::I32]) => Some(Self::Multivalue),
+            _ => None,
+        }
+    }
+}
+
+fn format_signature(ty: &FuncType) -> String {
+    let format_list = |types: &[ValType]| {
+        types
+            .iter()
+            .map(|ty| ty.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!(
+        "({}) -> ({})",
+        format_list(ty.params()),
+        format_list(ty.results())
+    )
+}
+
+fn count_imports(reader: ImportSectionReader) -> Result<ImportCounts> {
+    let mut counts = ImportCounts::default();
+    for import in reader.into_imports() {
+        let import = import?;
+        match import.ty {
+            TypeRef::Func(_) | TypeRef::FuncExact(_) => counts.funcs += 1,
+            TypeRef::Memory(_) => counts.memories += 1,
+            TypeRef::Global(_) => counts.globals += 1,
+            _ => {}
+        }
+    }
+    Ok(counts)
+}
+
+fn read_exports(
+    reader: ExportSectionReader,
+    export_name: &str,
+) -> Result<(Vec<ExportEntry>, Option<u32>)> {
+    let mut entries = Vec::new();
+    let mut export_index = None;
+    for export in reader {
+        let export: Export = export?;
+        if export.name == export_name
+            && let ExternalKind::Func = export.kind
+        {
+            export_index = Some(export.index);
+            continue;
+        }
+        entries.push(ExportEntry {
+            name: export.name.to_owned(),
+            kind: export.kind,
+            index: export.index,
+        });
+    }
+    Ok((entries, export_index))
+}
+
+fn resolve_patch_index(
+    wasm: &[u8],
+    bodies: &[Range<usize>],
+    export_index: usize,
+    func_imports: u32,
+    is_compatible: impl Fn(usize) -> bool,
+) -> Result<usize> {
+    let range = bodies
+        .get(export_index)
+        .context("export function body missing")?;
+    let reader = BinaryReader::new(&wasm[range.clone()], range.start);
+    let body = FunctionBody::new(reader);
+    let mut reader = body.get_operators_reader()?;
+    while !reader.eof() {
+        if let Operator::Call { function_index } = reader.read()?
+            && function_index >= func_imports
+        {
+            let index = function_index - func_imports;
+            let index = index.try_into().context("call target index overflow")?;
+            if index < bodies.len() && is_compatible(index) {
+                return Ok(index);
+            }
+        }
+    }
+    Ok(export_index)
+}
+
+/// Reads the limits of memory 0, which the script goes into. Any further
+/// memories are passed through untouched, as the bytes following memory 0.
+fn read_memory_section(reader: MemorySectionReader) -> Result<MemoryLimits> {
+    let section_end = reader.range().end;
+    let count = reader.count();
+    let mut memories = reader.into_iter_with_offsets();
+    let (_, memory) = memories.next().context("memory section is empty")??;
+    let others = match memories.next() {
+        Some(other) => other?.0..section_end,
+        None => section_end..section_end,
+    };
+
+    ensure!(
+        !memory.shared,
+        "memory 0 is shared (flags {:#04x}), but the script can only be injected into a \
+         non-shared memory (shared memory comes from the `atomics` target feature, as in \
+         `-C target-feature=+atomics`, so build the runtime without it)",
+        memory_flags(&memory)
+    );
+    ensure!(
+        !memory.memory64,
+        "memory 0 is 64-bit (flags {:#04x}), but the script can only be injected into a 32-bit \
+         memory (build the runtime for wasm32 rather than wasm64)",
+        memory_flags(&memory)
+    );
+    let initial = memory
+        .initial
+        .try_into()
+        .context("memory initial too large")?;
+    let maximum = match memory.maximum {
+        Some(value) => Some(value.try_into().context("memory maximum too large")?),
+        None => None,
+    };
+    Ok(MemoryLimits {
+        initial,
+        maximum,
+        page_size_log2: memory.page_size_log2,
+        count,
+        others,
+    })
+}
+
+/// The flags byte a memory type is encoded with.
+fn memory_flags(memory: &MemoryType) -> u8 {
+    u8::from(memory.maximum.is_some())
+        | u8::from(memory.shared) << 1
+        | u8::from(memory.memory64) << 2
+        | u8::from(memory.page_size_log2.is_some()) << 3
+}
+
+fn read_data_segments(reader: DataSectionReader) -> Result<Vec<(u32, i32, Vec<u8>)>> {
+    let mut segments = Vec::new();
+    for (index, segment) in reader.into_iter().enumerate() {
+        let segment = segment?;
+        match segment.kind {
+            DataKind::Active {
+                memory_index,
+                offset_expr,
+            } => {
+                let offset = parse_i32_const(offset_expr).with_context(|| {
+                    format!("data segment {index} is not placed at a constant address")
+                })?;
+                segments.push((memory_index, offset, segment.data.to_vec()));
+            }
+            DataKind::Passive => {
+                bail!(
+                    "data segment {index} is passive, but only active segments with i32.const \
+                     offsets are supported"
+                );
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn parse_i32_const(expr: wasmparser::ConstExpr) -> Result<i32> {
+    let mut reader = expr.get_operators_reader();
+    let op = reader.read()?;
+    let offset = match op {
+        wasmparser::Operator::I32Const { value } => value,
+        op => bail!(
+            "unsupported offset expression {op:?} (only i32.const is supported, position \
+             independent modules are not)"
+        ),
+    };
+    let end = reader.read()?;
+    ensure!(
+        matches!(end, wasmparser::Operator::End),
+        "malformed data offset expression"
+    );
+    Ok(offset)
+}
+
+/// Whether each global is mutable, along with its initial value if that's an
+/// `i32.const`.
+fn read_global_values(reader: GlobalSectionReader) -> Result<Vec<(bool, Option<i32>)>> {
+    let mut values = Vec::new();
+    for global in reader {
+        let global = global?;
+        values.push((global.ty.mutable, parse_i32_const(global.init_expr).ok()));
+    }
+    Ok(values)
+}
+
+/// Finds the unused memory between the end of the data segments and the heap
+/// base. Without an exported heap base there's no telling where the
+/// allocator starts, and without the initial stack pointer there's no telling
+/// where the stack is, so nothing is considered free.
+///
+/// The stack grows down from the initial stack pointer. With `--stack-first`,
+/// like Rust links, it's below the data. Otherwise wasm-ld places it between
+/// the data and the heap, which leaves no room there.
+fn find_slack(
+    segments: &[(u32, i32, Vec<u8>)],
+    data_end: Option<i32>,
+    heap_base: Option<i32>,
+    stack_pointer: Option<i32>,
+) -> Option<Range<u64>> {
+    let heap_base = u64::from(heap_base? as u32);
+    let stack_end = u64::from(stack_pointer? as u32);
+    let used_end = segments
+        .iter()
+        .filter(|(mem, _, _)| *mem == 0)
+        .map(|(_, offset, bytes)| u64::from(*offset as u32) + bytes.len() as u64)
+        .chain(data_end.map(|end| u64::from(end as u32)))
+        .fold(stack_end, u64::max);
+    (used_end < heap_base).then_some(used_end..heap_base)
+}
+
+fn append_script_data(
+    segments: &mut Vec<(u32, i32, Vec<u8>)>,
+    script: &[u8],
+    memory: &MemoryLimits,
+    slack: Option<&Range<u64>>,
+) -> Result<(i32, i32, u32)> {
+    let len: i32 = script.len().try_into().context("script too large")?;
+    let page_size = memory.page_size();
+    let initial_size = u64::from(memory.initial) * page_size;
+
+    if let Some(slack) = slack {
+        let aligned = (slack.start + 15) & !15;
+        let end_offset = aligned + len as u64;
+        if end_offset <= slack.end
+            && end_offset <= initial_size
+            && let Ok(offset_i32) = aligned.try_into()
+        {
+            segments.push((0, offset_i32, script.to_vec()));
+            return Ok((offset_i32, len, memory.initial));
+        }
+    }
+
+    let base_offset = u64::from(memory.initial)
+        .checked_mul(page_size)
+        .context("memory size overflow")?;
+    let aligned = (base_offset + 15) & !15;
+    let end_offset = aligned
+        .checked_add(len as u64)
+        .context("script offset overflow")?;
+
+    let required_pages: u32 = end_offset
+        .div_ceil(page_size)
+        .try_into()
+        .context("required pages overflow")?;
+    let new_initial = required_pages.max(memory.initial);
+    ensure!(
+        memory.maximum.is_none_or(|max| new_initial <= max),
+        "script does not fit within maximum memory size"
+    );
+
+    let offset_i32 = aligned.try_into().context("script offset too large")?;
+    segments.push((0, offset_i32, script.to_vec()));
+    Ok((offset_i32, len, new_initial))
+}
+
+fn build_code_section(
+    wasm: &[u8],
+    bodies: &[Range<usize>],
+    replace_index: usize,
+    wrapper_index: usize,
+    abi: ScriptAbi,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    push_u32_leb(bodies.len() as u32, &mut data);
+
+    // The wrapper is only checked to share the patched function's signature,
+    // so it gets the same body in case anything still calls it.
+    for (index, range) in bodies.iter().enumerate() {
+        let body = if index == replace_index || index == wrapper_index {
+            match abi {
+                ScriptAbi::Sret => build_script_body_sret(ptr, len),
+                ScriptAbi::Multivalue => build_script_body_multivalue(ptr, len),
+            }
+        } else {
+            wasm[range.clone()].to_vec()
+        };
+        push_u32_leb(body.len() as u32, &mut data);
+        data.extend_from_slice(&body);
+    }
+
+    Ok(data)
+}
+
+fn build_script_body_sret(ptr: i32, len: i32) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_u32_leb(0, &mut body);
+    body.push(0x20);
+    body.push(0x00);
+    body.push(0x41);
+    push_i32_leb(len, &mut body);
+    body.push(0x36);
+    body.push(0x02);
+    body.push(0x04);
+    body.push(0x20);
+    body.push(0x00);
+    body.push(0x41);
+    push_i32_leb(ptr, &mut body);
+    body.push(0x36);
+    body.push(0x02);
+    body.push(0x00);
+    body.push(0x0b);
+    body
+}
+
+fn build_script_body_multivalue(ptr: i32, len: i32) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_u32_leb(0, &mut body);
+    body.push(0x41);
+    push_i32_leb(ptr, &mut body);
+    body.push(0x41);
+    push_i32_leb(len, &mut body);
+    body.push(0x0b);
+    body
+}
+
+fn build_data_section(segments: &[(u32, i32, Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_u32_leb(segments.len() as u32, &mut data);
+    for (mem, offset, bytes) in segments {
+        if *mem == 0 {
+            data.push(0x00);
+        } else {
+            data.push(0x02);
+            push_u32_leb(*mem, &mut data);
+        }
+        data.push(0x41);
+        push_i32_leb(*offset, &mut data);
+        data.push(0x0b);
+        push_u32_leb(bytes.len() as u32, &mut data);
+        data.extend_from_slice(bytes);
+    }
+    data
+}
+
+fn build_data_count_section(count: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_u32_leb(count as u32, &mut data);
+    data
+}
+
+fn build_export_section(entries: &[ExportEntry]) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_u32_leb(entries.len() as u32, &mut data);
+    for entry in entries {
+        push_name(&entry.name, &mut data);
+        data.push(export_kind_byte(entry.kind));
+        push_u32_leb(entry.index, &mut data);
+    }
+    data
+}
+
+fn export_kind_byte(kind: ExternalKind) -> u8 {
+    match kind {
+        ExternalKind::Func => 0x00,
+        ExternalKind::Table => 0x01,
+        ExternalKind::Memory => 0x02,
+        ExternalKind::Global => 0x03,
+        ExternalKind::Tag => 0x04,
+        ExternalKind::FuncExact => unreachable!("FuncExact is not valid in exports"),
+    }
+}
+
+/// Re-encodes memory 0 with its new initial size, followed by the `others`
+/// as they were.
+fn build_memory_section(initial: u32, memory: &MemoryLimits, others: &[u8]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    push_u32_leb(memory.count, &mut data);
+    data.push(u8::from(memory.maximum.is_some()) | u8::from(memory.page_size_log2.is_some()) << 3);
+    push_u32_leb(initial, &mut data);
+    if let Some(max) = memory.maximum {
+        push_u32_leb(max, &mut data);
+    }
+    if let Some(page_size_log2) = memory.page_size_log2 {
+        push_u32_leb(page_size_log2, &mut data);
+    }
+    data.extend_from_slice(others);
+    Ok(data)
+}
+
+fn push_u32_leb(mut value: u32, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn push_name(value: &str, out: &mut Vec<u8>) {
+    push_u32_leb(value.len() as u32, out);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn push_i32_leb(mut value: i32, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        let sign_bit = (byte & 0x40) != 0;
+        value >>= 7;
+        let done = (value == 0 && !sign_bit) || (value == -1 && sign_bit);
+        if done {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn raw_section_from_payload<'a>(
+    payload: &Payload<'a>,
+    wasm: &'a [u8],
+) -> Result<Option<RawSection<'a>>> {
+    let Some((id, range)) = payload.as_section() else {
+        return Ok(None);
+    };
+    Ok(Some(RawSection {
+        id,
+        data: &wasm[range],
+    }))
+}
+
+enum SectionItem<'a> {
+    Raw(RawSection<'a>),
+    Code,
+    Export,
+    Memory,
+    DataCount,
+    Data,
+}
+
+/// How the patched function hands the script's pointer and length back.
+#[derive(Clone, Copy)]
+enum ScriptAbi {
+    /// `(i32) -> ()`: the pointer is stored at offset 0 and the length at
+    /// offset 4 of the return pointer passed as the only parameter.
+    Sret,
+    /// `() -> (i32, i32)`: the pointer and length are returned directly.
+    Multivalue,
+}
+
+#[derive(Default)]
+struct ImportCounts {
+    funcs: u32,
+    memories: u32,
+    globals: u32,
+}
+
+struct MemoryLimits {
+    initial: u32,
+    maximum: Option<u32>,
+    /// The custom page size of memory 0, which has to survive re-encoding.
+    page_size_log2: Option<u32>,
+    /// How many memories the module defines, including memory 0.
+    count: u32,
+    /// Where the encoded memories after memory 0 are in the module.
+    others: Range<usize>,
+}
+
+impl MemoryLimits {
+    fn page_size(&self) -> u64 {
+        1 << self.page_size_log2.unwrap_or(16)
+    }
+}
+
+struct ExportEntry {
+    name: String,
+    kind: ExternalKind,
+    index: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_encoder::{
+        CodeSection, ConstExpr, DataCountSection, DataSection, ExportKind, ExportSection, Function,
+        FunctionSection, GlobalSection, GlobalType, Instruction, MemorySection, TypeSection,
+    };
+    use wasmparser::{Validator, WasmFeatures};
+
+    use super::*;
+
+    thread_local! {
+        /// How often [`RuntimeTemplate::parse`] ran on this thread.
+        pub(super) static PARSES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    const SCRIPT: &[u8] = b"timer.start()";
+
+    /// A minimal runtime module: an exported `script` function that forwards
+    /// to an inner function of the same signature, like the bundled runtime's
+    /// sret wrapper does.
+    struct Fixture {
+        params: Vec<wasm_encoder::ValType>,
+        results: Vec<wasm_encoder::ValType>,
+        calls_inner: bool,
+        memories: Vec<wasm_encoder::MemoryType>,
+        data: Vec<(u32, i32, &'static [u8])>,
+        data_count: bool,
+        /// `__data_end` and `__heap_base`.
+        globals: (Option<i32>, Option<i32>),
+        /// The initial `__stack_pointer`, the first global, which isn't
+        /// exported, as wasm-ld links it.
+        stack_pointer: Option<i32>,
+        custom_sections: Vec<&'static str>,
+    }
+
+    impl Default for Fixture {
+        fn default() -> Self {
+            Self {
+                params: vec![wasm_encoder::ValType::I32],
+                results: vec![],
+                calls_inner: true,
+                memories: vec![memory(2, None)],
+                data: vec![(0, 1024, b"runtime data")],
+                data_count: false,
+                globals: (None, None),
+                stack_pointer: None,
+                custom_sections: vec![],
+            }
+        }
+    }
+
+    impl Fixture {
+        fn build(&self) -> Vec<u8> {
+            let mut module = wasm_encoder::Module::new();
+
+            let mut types = TypeSection::new();
+            types
+                .ty()
+                .function(self.params.iter().copied(), self.results.iter().copied());
+            module.section(&types);
+
+            let mut functions = FunctionSection::new();
+            functions.function(0);
+            if self.calls_inner {
+                functions.function(0);
+            }
+            module.section(&functions);
+
+            let mut memories = MemorySection::new();
+            for memory in &self.memories {
+                memories.memory(*memory);
+            }
+            module.section(&memories);
+
+            let mut globals = GlobalSection::new();
+            if let Some(stack_pointer) = self.stack_pointer {
+                let global_type = GlobalType {
+                    val_type: wasm_encoder::ValType::I32,
+                    mutable: true,
+                    shared: false,
+                };
+                globals.global(global_type, &ConstExpr::i32_const(stack_pointer));
+            }
+            let mut exports = ExportSection::new();
+            exports.export("script", ExportKind::Func, 0);
+            exports.export("memory", ExportKind::Memory, 0);
+            for (name, value) in [
+                ("__data_end", self.globals.0),
+                ("__heap_base", self.globals.1),
+            ] {
+                if let Some(value) = value {
+                    let global_type = GlobalType {
+                        val_type: wasm_encoder::ValType::I32,
+                        mutable: false,
+                        shared: false,
+                    };
+                    exports.export(name, ExportKind::Global, globals.len());
+                    globals.global(global_type, &ConstExpr::i32_const(value));
+                }
+            }
+            if !globals.is_empty() {
+                module.section(&globals);
+            }
+            module.section(&exports);
+
+            if self.data_count {
+                module.section(&DataCountSection {
+                    count: self.data.len() as u32,
+                });
+            }
+
+            let mut code = CodeSection::new();
+            let mut placeholder = Function::new([]);
+            for _ in &self.results {
+                placeholder.instruction(&Instruction::I32Const(0));
+            }
+            placeholder.instruction(&Instruction::End);
+            if self.calls_inner {
+                let mut wrapper = Function::new([]);
+                for index in 0..self.params.len() as u32 {
+                    wrapper.instruction(&Instruction::LocalGet(index));
+                }
+                wrapper.instruction(&Instruction::Call(1));
+                wrapper.instruction(&Instruction::End);
+                code.function(&wrapper);
+            }
+            code.function(&placeholder);
+            module.section(&code);
+
+            if !self.data.is_empty() {
+                let mut data = DataSection::new();
+                for &(memory, offset, bytes) in &self.data {
+                    data.active(memory, &ConstExpr::i32_const(offset), bytes.iter().copied());
+                }
+                module.section(&data);
+            }
+
+            for name in &self.custom_sections {
+                module.section(&wasm_encoder::CustomSection {
+                    name: Cow::Borrowed(name),
+                    data: Cow::Borrowed(b"custom"),
+                });
+            }
+
+            module.finish()
+        }
+
+        fn inject(&self, options: &InjectOptions) -> Result<Vec<u8>> {
+            RuntimeTemplate::parse(&self.build(), "script")?.inject(SCRIPT, options)
+        }
+    }
+
+    fn memory(minimum: u64, maximum: Option<u64>) -> wasm_encoder::MemoryType {
+        wasm_encoder::MemoryType {
+            minimum,
+            maximum,
+            memory64: false,
+            shared: false,
+            page_size_log2: None,
+        }
+    }
+
+    /// What the tests look at in an injected module.
+    #[derive(Default)]
+    struct Output {
+        section_ids: Vec<u8>,
+        custom_sections: Vec<String>,
+        memories: Vec<MemoryType>,
+        data_count: Option<u32>,
+        data: Vec<(u32, i32, Vec<u8>)>,
+        /// The constants the patched function returns, as pointer and length.
+        script: (i32, i32),
+    }
+
+    fn read_output(wasm: &[u8]) -> Output {
+        Validator::new_with_features(WasmFeatures::all())
+            .validate_all(wasm)
+            .expect("injected module is invalid");
+
+        let mut output = Output::default();
+        let mut patched_body = None;
+        for payload in Parser::new(0).parse_all(wasm) {
+            let payload = payload.unwrap();
+            if let Some((id, _)) = payload.as_section() {
+                output.section_ids.push(id);
+            }
+            match payload {
+                Payload::CustomSection(reader) => {
+                    output.custom_sections.push(reader.name().to_owned());
+                }
+                Payload::MemorySection(reader) => {
+                    output.memories = reader.into_iter().map(Result::unwrap).collect();
+                }
+                Payload::DataCountSection { count, .. } => output.data_count = Some(count),
+                Payload::DataSection(reader) => output.data = read_data_segments(reader).unwrap(),
+                Payload::CodeSectionEntry(body) if patched_body.is_none() => {
+                    patched_body = Some(body);
+                }
+                _ => {}
+            }
+        }
+
+        let mut reader = patched_body.unwrap().get_operators_reader().unwrap();
+        let mut sret = false;
+        let mut constants = Vec::new();
+        while !reader.eof() {
+            match reader.read().unwrap() {
+                Operator::LocalGet { .. } => sret = true,
+                Operator::I32Const { value } => constants.push(value),
+                _ => {}
+            }
+        }
+        output.script = match (sret, constants.as_slice()) {
+            (true, &[len, ptr]) => (ptr, len),
+            (false, &[ptr, len]) => (ptr, len),
+            _ => panic!("unexpected patched body"),
+        };
+        output
+    }
+
+    impl Output {
+        /// The bytes the patched function points at, checking that they are
+        /// within the initial size of memory 0.
+        fn script(&self) -> &[u8] {
+            let (ptr, len) = self.script;
+            let memory = &self.memories[0];
+            let page_size = 1u64 << memory.page_size_log2.unwrap_or(16);
+            assert!(u64::from(ptr as u32) + len as u64 <= memory.initial * page_size);
+            let (_, _, bytes) = self
+                .data
+                .iter()
+                .find(|(memory, offset, _)| *memory == 0 && *offset == ptr)
+                .expect("no data segment at the script's address");
+            assert_eq!(bytes.len(), len as usize);
+            bytes
+        }
+    }
+
+    #[test]
+    fn passes_through_further_memories() {
+        let mut shared = memory(1, Some(1));
+        shared.shared = true;
+        let fixture = Fixture {
+            memories: vec![memory(2, Some(8)), memory(1, None), shared],
+            data: vec![(0, 1024, b"runtime data"), (1, 16, b"other memory")],
+            ..Fixture::default()
+        };
+
+        let output = read_output(&fixture.inject(&InjectOptions::default()).unwrap());
+        assert_eq!(output.script(), SCRIPT);
+
+        let memories = &output.memories;
+        assert_eq!(memories.len(), 3);
+        assert_eq!((memories[0].initial, memories[0].maximum), (3, Some(8)));
+        assert_eq!((memories[1].initial, memories[1].maximum), (1, None));
+        assert!(!memories[1].shared);
+        assert_eq!((memories[2].initial, memories[2].maximum), (1, Some(1)));
+        assert!(memories[2].shared);
+        assert!(output.data.contains(&(1, 16, b"other memory".to_vec())));
+    }
+
+    #[test]
+    fn keeps_custom_page_size() {
+        let mut tiny_pages = memory(4096, None);
+        tiny_pages.page_size_log2 = Some(0);
+        let fixture = Fixture {
+            memories: vec![tiny_pages],
+            ..Fixture::default()
+        };
+
+        let output = read_output(&fixture.inject(&InjectOptions::default()).unwrap());
+        assert_eq!(output.memories[0].page_size_log2, Some(0));
+        assert_eq!(output.script.0, 4096);
+        assert_eq!(output.memories[0].initial, 4096 + SCRIPT.len() as u64);
+        assert_eq!(output.script(), SCRIPT);
+    }
+
+    #[test]
+    fn rejects_shared_memory_0() {
+        let mut shared = memory(2, Some(16));
+        shared.shared = true;
+        let fixture = Fixture {
+            memories: vec![shared],
+            ..Fixture::default()
+        };
+
+        let error = fixture.inject(&InjectOptions::default()).unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.starts_with("memory 0 is shared (flags 0x03)"),
+            "{message}"
+        );
+        assert!(message.contains("-C target-feature=+atomics"), "{message}");
+    }
+
+    const CODE: u8 = SectionId::Code as u8;
+    const DATA: u8 = SectionId::Data as u8;
+    const DATA_COUNT: u8 = SectionId::DataCount as u8;
+    const CUSTOM: u8 = SectionId::Custom as u8;
+
+    #[test]
+    fn synthesizes_data_section_after_code() {
+        let fixture = Fixture {
+            data: vec![],
+            custom_sections: vec!["name", "producers"],
+            ..Fixture::default()
+        };
+
+        let output = read_output(&fixture.inject(&InjectOptions::default()).unwrap());
+        assert!(output.section_ids.ends_with(&[CODE, DATA, CUSTOM, CUSTOM]));
+        assert_eq!(output.custom_sections, ["name", "producers"]);
+        assert_eq!(output.data.len(), 1);
+        assert_eq!(output.script(), SCRIPT);
+    }
+
+    #[test]
+    fn keeps_trailing_custom_sections_after_data() {
+        let fixture = Fixture {
+            custom_sections: vec!["name", "target_features", "producers"],
+            ..Fixture::default()
+        };
+
+        let output = read_output(&fixture.inject(&InjectOptions::default()).unwrap());
+        assert!(
+            output
+                .section_ids
+                .ends_with(&[CODE, DATA, CUSTOM, CUSTOM, CUSTOM])
+        );
+        assert_eq!(
+            output.custom_sections,
+            ["name", "target_features", "producers"]
+        );
+        assert_eq!(output.data.len(), 2);
+        assert_eq!(output.script(), SCRIPT);
+    }
+
+    #[test]
+    fn updates_data_count() {
+        for data in [vec![], vec![(0, 1024, &b"runtime data"[..])]] {
+            let fixture = Fixture {
+                data,
+                data_count: true,
+                custom_sections: vec!["name"],
+                ..Fixture::default()
+            };
+
+            let output = read_output(&fixture.inject(&InjectOptions::default()).unwrap());
+            let position = |id| output.section_ids.iter().position(|&other| other == id);
+            assert!(position(DATA_COUNT) < position(CODE));
+            assert!(output.section_ids.ends_with(&[CODE, DATA, CUSTOM]));
+            assert_eq!(output.data_count, Some(output.data.len() as u32));
+            assert_eq!(output.script(), SCRIPT);
+        }
+    }
+
+    #[test]
+    fn optimize_drops_custom_sections() {
+        let fixture = Fixture {
+            custom_sections: vec!["name", "producers"],
+            ..Fixture::default()
+        };
+        let options = InjectOptions {
+            optimize: true,
+            metadata: vec![("name".to_owned(), "Game".to_owned())],
+            ..InjectOptions::default()
+        };
+
+        let output = read_output(&fixture.inject(&options).unwrap());
+        assert_eq!(output.custom_sections, [METADATA_SECTION]);
+    }
+
+    fn limits(initial: u32, maximum: Option<u32>) -> MemoryLimits {
+        MemoryLimits {
+            initial,
+            maximum,
+            page_size_log2: None,
+            count: 1,
+            others: 0..0,
+        }
+    }
+
+    /// Checks that no two segments in memory 0 share a byte and that none of
+    /// them reach into the heap.
+    fn assert_disjoint(segments: &[(u32, i32, Vec<u8>)], heap_base: Option<i32>) {
+        let mut ranges = segments
+            .iter()
+            .filter(|(memory, _, _)| *memory == 0)
+            .map(|(_, offset, bytes)| *offset as u64..*offset as u64 + bytes.len() as u64)
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.start);
+        for pair in ranges.windows(2) {
+            assert!(pair[0].end <= pair[1].start, "{ranges:?} overlap");
+        }
+        if let Some(heap_base) = heap_base.map(|base| base as u64) {
+            for range in &ranges {
+                assert!(
+                    range.end <= heap_base || range.start >= heap_base,
+                    "{range:?} overlaps the heap"
+                );
+            }
+        }
+    }
+
+    /// The initial stack pointer of a module linked with `--stack-first`, whose
+    /// stack ends where the data starts.
+    const STACK_FIRST: Option<i32> = Some(1024);
+
+    #[test]
+    fn slack_needs_heap_base() {
+        let segments = vec![(0, 1024, vec![0; 100])];
+        assert_eq!(find_slack(&segments, None, None, STACK_FIRST), None);
+        assert_eq!(find_slack(&segments, Some(2000), None, STACK_FIRST), None);
+        assert_eq!(
+            find_slack(&segments, None, Some(4096), STACK_FIRST),
+            Some(1124..4096)
+        );
+        assert_eq!(
+            find_slack(&segments, Some(2000), Some(4096), STACK_FIRST),
+            Some(2000..4096)
+        );
+        // `__data_end` below the segments doesn't hide them.
+        assert_eq!(
+            find_slack(&segments, Some(512), Some(4096), STACK_FIRST),
+            Some(1124..4096)
+        );
+        // Segments in other memories don't take up memory 0.
+        let segments = vec![(0, 1024, vec![0; 100]), (1, 3000, vec![0; 100])];
+        assert_eq!(
+            find_slack(&segments, None, Some(4096), STACK_FIRST),
+            Some(1124..4096)
+        );
+        // Nothing is free if the data reaches the heap base.
+        assert_eq!(
+            find_slack(&segments, Some(4096), Some(4096), STACK_FIRST),
+            None
+        );
+        assert_eq!(find_slack(&segments, None, Some(1000), STACK_FIRST), None);
+    }
+
+    #[test]
+    fn slack_excludes_the_stack() {
+        let segments = vec![(0, 1024, vec![0; 100])];
+        // Without the stack pointer, the stack may be anywhere.
+        assert_eq!(find_slack(&segments, None, Some(4096), None), None);
+        // wasm-ld's default layout puts the stack between the data and the
+        // heap, all the way up to the heap base.
+        assert_eq!(
+            find_slack(&segments, Some(1124), Some(4096), Some(4096)),
+            None
+        );
+        // Only what's above the stack is free.
+        assert_eq!(
+            find_slack(&segments, Some(1124), Some(4096), Some(3000)),
+            Some(3000..4096)
+        );
+        // A stack ending below the data doesn't take anything from it.
+        assert_eq!(
+            find_slack(&segments, Some(1124), Some(4096), Some(512)),
+            Some(1124..4096)
+        );
+    }
+
+    #[test]
+    fn packs_script_into_slack() {
+        let heap_base = 4096;
+        let mut segments = vec![(0, 1024, vec![0; 101])];
+        let slack = find_slack(&segments, None, Some(heap_base), STACK_FIRST).unwrap();
+
+        let (offset, len, pages) =
+            append_script_data(&mut segments, SCRIPT, &limits(1, None), Some(&slack)).unwrap();
+        assert_eq!((offset, len, pages), (1136, SCRIPT.len() as i32, 1));
+        assert_disjoint(&segments, Some(heap_base));
+    }
+
+    #[test]
+    fn grows_memory_when_slack_is_too_small() {
+        let heap_base = 1200;
+        let script = vec![b'-'; 200];
+        let mut segments = vec![(0, 1024, vec![0; 101])];
+        let slack = find_slack(&segments, Some(1125), Some(heap_base), STACK_FIRST).unwrap();
+
+        let (offset, _, pages) =
+            append_script_data(&mut segments, &script, &limits(1, None), Some(&slack)).unwrap();
+        assert_eq!((offset, pages), (65536, 2));
+        assert_disjoint(&segments, Some(heap_base));
+    }
+
+    #[test]
+    fn does_not_pack_past_initial_memory() {
+        // A heap base beyond the initial memory isn't backed by any pages.
+        let mut segments = vec![(0, 65530, vec![0; 4])];
+        let slack = find_slack(&segments, None, Some(1 << 20), STACK_FIRST).unwrap();
+
+        let (offset, _, pages) =
+            append_script_data(&mut segments, SCRIPT, &limits(1, None), Some(&slack)).unwrap();
+        assert_eq!((offset, pages), (65536, 2));
+        assert_disjoint(&segments, None);
+    }
+
+    #[test]
+    fn grows_memory_without_pack_data() {
+        let mut segments = vec![(0, 1024, vec![0; 101])];
+        let (offset, _, pages) =
+            append_script_data(&mut segments, SCRIPT, &limits(3, None), None).unwrap();
+        assert_eq!((offset, pages), (3 * 65536, 4));
+        assert_disjoint(&segments, None);
+
+        let error =
+            append_script_data(&mut segments, SCRIPT, &limits(3, Some(3)), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "script does not fit within maximum memory size"
+        );
+    }
+
+    #[test]
+    fn pack_data_uses_exported_globals() {
+        let pack_data = InjectOptions {
+            pack_data: true,
+            ..InjectOptions::default()
+        };
+        for (stack_pointer, data_end, heap_base, expected) in [
+            (STACK_FIRST, None, None, 2 * 65536),
+            (STACK_FIRST, None, Some(8192), 1040),
+            (STACK_FIRST, Some(4000), Some(8192), 4000),
+            (STACK_FIRST, Some(4000), None, 2 * 65536),
+            // Where the stack is is unknown.
+            (None, Some(4000), Some(8192), 2 * 65536),
+        ] {
+            let fixture = Fixture {
+                globals: (data_end, heap_base),
+                stack_pointer,
+                ..Fixture::default()
+            };
+
+            let output = read_output(&fixture.inject(&pack_data).unwrap());
+            assert_eq!(output.script.0, expected);
+            assert_eq!(output.script(), SCRIPT);
+            assert_disjoint(&output.data, heap_base);
+
+            let unpacked = read_output(&fixture.inject(&InjectOptions::default()).unwrap());
+            assert_eq!(unpacked.script.0, 2 * 65536);
+        }
+    }
+
+    #[test]
+    fn pack_data_keeps_off_a_stack_after_the_data() {
+        let pack_data = InjectOptions {
+            pack_data: true,
+            ..InjectOptions::default()
+        };
+        // wasm-ld's layout without `--stack-first`: the data, a stack
+        // growing down from 70000, then the heap.
+        for (heap_base, expected) in [(70000, 2 * 65536), (80000, 70000)] {
+            let fixture = Fixture {
+                globals: (Some(1036), Some(heap_base)),
+                stack_pointer: Some(70000),
+                ..Fixture::default()
+            };
+
+            let output = read_output(&fixture.inject(&pack_data).unwrap());
+            assert_eq!(output.script.0, expected);
+            assert_eq!(output.script(), SCRIPT);
+            assert!(output.script.0 >= 70000, "the script is on the stack");
+        }
+    }
+
+    #[test]
+    fn parses_runtime_once_for_many_scripts() {
+        let wasm = Fixture::default().build();
+        let parses = PARSES.with(|parses| parses.get());
+
+        let template = RuntimeTemplate::parse(&wasm, "script").unwrap();
+        for index in 0..50 {
+            let script = format!("timer.setVariable('script', {index})").repeat(index + 1);
+            let output = read_output(
+                &template
+                    .inject(script.as_bytes(), &InjectOptions::default())
+                    .unwrap(),
+            );
+            assert_eq!(output.script(), script.as_bytes());
+        }
+
+        assert_eq!(PARSES.with(|parses| parses.get()) - parses, 1);
+    }
+
+    #[test]
+    fn patches_both_abis() {
+        use wasm_encoder::ValType::I32;
+
+        for (params, results) in [(vec![I32], vec![]), (vec![], vec![I32, I32])] {
+            for calls_inner in [true, false] {
+                let fixture = Fixture {
+                    params: params.clone(),
+                    results: results.clone(),
+                    calls_inner,
+                    ..Fixture::default()
+                };
+                let wasm = fixture.build();
+
+                let template = RuntimeTemplate::parse(&wasm, "script").unwrap();
+                assert_eq!(template.patch_index, usize::from(calls_inner));
+                assert_eq!(template.wrapper_index, 0);
+
+                let output = template.inject(SCRIPT, &InjectOptions::default()).unwrap();
+                assert_eq!(read_output(&output).script(), SCRIPT);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_signatures() {
+        use wasm_encoder::ValType::{I32, I64};
+
+        for (params, results, signature) in [
+            (vec![I64], vec![], "(i64) -> ()"),
+            (vec![], vec![I32], "() -> (i32)"),
+            (vec![I32, I32], vec![], "(i32, i32) -> ()"),
+            (vec![I32], vec![I32, I32], "(i32) -> (i32, i32)"),
+        ] {
+            let fixture = Fixture {
+                params,
+                results,
+                ..Fixture::default()
+            };
+
+            let error = fixture.inject(&InjectOptions::default()).unwrap_err();
+            let message = error.to_string();
+            assert!(
+                message.starts_with(&format!("export script has signature {signature}, ")),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_missing_export() {
+        let error = RuntimeTemplate::parse(&Fixture::default().build(), "my_script")
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .starts_with("module does not export a function named my_script"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_export_without_compatible_call() {
+        let wasm = Fixture::default().build();
+        let mut bodies = Vec::new();
+        for payload in Parser::new(0).parse_all(&wasm) {
+            if let Payload::CodeSectionEntry(body) = payload.unwrap() {
+                bodies.push(body.range());
+            }
+        }
+
+        assert_eq!(
+            resolve_patch_index(&wasm, &bodies, 0, 0, |_| true).unwrap(),
+            1
+        );
+        assert_eq!(
+            resolve_patch_index(&wasm, &bodies, 0, 0, |_| false).unwrap(),
+            0
+        );
+        // A call into an import is never patched.
+        assert_eq!(
+            resolve_patch_index(&wasm, &bodies, 0, 2, |_| true).unwrap(),
+            0
+        );
+        // The callee has no body, so neither is it.
+        assert_eq!(
+            resolve_patch_index(&wasm, &bodies[..1], 0, 0, |_| true).unwrap(),
+            0
+        );
+    }
+}
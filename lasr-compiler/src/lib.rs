@@ -0,0 +1,18 @@
+mod config;
+mod inject;
+mod script;
+
+pub use config::{ConfigError, config_chunk};
+pub use inject::{
+    InjectOptions, METADATA_SECTION, RUNTIME_INFO_SECTION, RuntimeTemplate, inject_script,
+};
+pub use script::{ScriptError, script_hash, validate_script};
+
+pub const LASR_RUNTIME_WASM: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/lasr_runtime.wasm"));
+
+/// The profile [`LASR_RUNTIME_WASM`] was built with: `release`, `debug` or
+/// `prebuilt` if it was supplied through `LASR_RUNTIME_WASM`.
+pub const LASR_RUNTIME_PROFILE: &str = env!("LASR_RUNTIME_BUILD_PROFILE");
+
+/// The comma separated cargo features [`LASR_RUNTIME_WASM`] was built with.
+pub const LASR_RUNTIME_FEATURES: &str = env!("LASR_RUNTIME_BUILD_FEATURES");
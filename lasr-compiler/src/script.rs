@@ -0,0 +1,162 @@
+use std::{fmt::Write, str};
+
+const HEXDUMP_CONTEXT: usize = 16;
+
+pub struct ScriptError {
+    pub message: String,
+    pub offset: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Checks that the script can be loaded by the runtime, which requires it to
+/// be valid UTF-8. NUL bytes are rejected as well, as they practically only
+/// show up in corrupted files.
+pub fn validate_script(script: &[u8]) -> Result<(), ScriptError> {
+    let nul = script.iter().position(|&b| b == 0);
+    let invalid_utf8 = str::from_utf8(script).err();
+
+    let (offset, problem) = match (nul, invalid_utf8) {
+        (Some(nul), Some(err)) if err.valid_up_to() < nul => (err.valid_up_to(), utf8_problem(err)),
+        (Some(nul), _) => (
+            nul,
+            "script contains a NUL byte, which usually means the file is corrupted (e.g. binary \
+             data was appended to it)"
+                .to_owned(),
+        ),
+        (None, Some(err)) => (err.valid_up_to(), utf8_problem(err)),
+        (None, None) => return Ok(()),
+    };
+
+    let before = &script[..offset];
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let column = offset - line_start + 1;
+
+    let mut message = format!("{problem} at byte offset {offset}:\n");
+    hexdump(script, offset, &mut message);
+
+    Err(ScriptError {
+        message,
+        offset,
+        line: line.try_into().unwrap_or(u32::MAX),
+        column: column.try_into().unwrap_or(u32::MAX),
+    })
+}
+
+/// A short hash of the script, recorded as `script-hash` in the metadata. The
+/// runtime prints the same hash when it starts, so a log tells which build of
+/// an auto splitter is running. It's 64-bit FNV-1a, like the runtime uses.
+pub fn script_hash(script: &[u8]) -> String {
+    let hash = script
+        .iter()
+        .fold(0xCBF2_9CE4_8422_2325_u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+        });
+    format!("{hash:016x}")
+}
+
+fn utf8_problem(err: str::Utf8Error) -> String {
+    match err.error_len() {
+        Some(_) => "script is not valid UTF-8".to_owned(),
+        None => "script is not valid UTF-8 (it ends in the middle of a character)".to_owned(),
+    }
+}
+
+fn hexdump(bytes: &[u8], offset: usize, out: &mut String) {
+    let start = offset.saturating_sub(HEXDUMP_CONTEXT) & !0xF;
+    let end = (offset + HEXDUMP_CONTEXT + 1).min(bytes.len());
+
+    for (row, chunk) in bytes[start..end].chunks(16).enumerate() {
+        let row_offset = start + row * 16;
+        let _ = write!(out, "  {row_offset:08x} ");
+        for (i, byte) in chunk.iter().enumerate() {
+            if row_offset + i == offset {
+                let _ = write!(out, "[{byte:02x}]");
+            } else if row_offset + i == offset + 1 {
+                let _ = write!(out, "{byte:02x}");
+            } else {
+                let _ = write!(out, " {byte:02x}");
+            }
+        }
+        for _ in chunk.len()..16 {
+            out.push_str("   ");
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+    out.pop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(script: &[u8]) -> ScriptError {
+        match validate_script(script) {
+            Ok(()) => panic!("expected {script:?} to be rejected"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn accepts_valid_scripts() {
+        assert!(validate_script(b"").is_ok());
+        assert!(
+            validate_script(b"function update()\n    -- \xC3\xA9\xF0\x9F\x98\x80\nend\n").is_ok()
+        );
+    }
+
+    #[test]
+    fn locates_invalid_utf8() {
+        let err = error(b"local a = 1\nlocal b = 2\nlocal c = \"\xFF\"\n");
+        assert_eq!((err.offset, err.line, err.column), (35, 3, 12));
+        assert_eq!(
+            err.message,
+            "script is not valid UTF-8 at byte offset 35:\n  \
+             00000010  6c 20 62 20 3d 20 32 0a 6c 6f 63 61 6c 20 63 20  |l b = 2.local c |\n  \
+             00000020  3d 20 22[ff]22 0a                                |= \".\".|"
+        );
+
+        let err = error(b"print()\n\xE2\x82");
+        assert_eq!((err.offset, err.line, err.column), (8, 2, 1));
+        assert!(
+            err.message
+                .starts_with("script is not valid UTF-8 (it ends in the middle of a character)"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn calls_out_nul_bytes() {
+        let err = error(b"print()\nprint()\0\x7FELF");
+        assert_eq!((err.offset, err.line, err.column), (15, 2, 8));
+        assert!(
+            err.message.starts_with(
+                "script contains a NUL byte, which usually means the file is corrupted"
+            ),
+            "{}",
+            err.message
+        );
+
+        // Whichever problem comes first is reported.
+        assert_eq!(error(b"\xFF\0").offset, 0);
+        assert_eq!(error(b"\0\xFF").offset, 0);
+        assert!(
+            error(b"\0\xFF")
+                .message
+                .starts_with("script contains a NUL byte")
+        );
+    }
+}
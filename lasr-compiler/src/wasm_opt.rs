@@ -0,0 +1,68 @@
+use std::{
+    env, fs,
+    path::Path,
+    process::{self, Command},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use anyhow::{Context, Result, ensure};
+use wasmparser::{Validator, WasmFeatures};
+
+// Matches the features the runtime is compiled with, see .cargo/config.toml.
+// They need to be passed explicitly, as wasm-opt defaults to the MVP feature
+// set.
+const FEATURE_FLAGS: &[&str] = &[
+    "--enable-bulk-memory",
+    "--enable-mutable-globals",
+    "--enable-nontrapping-float-to-int",
+    "--enable-sign-ext",
+    "--enable-simd",
+    "--enable-relaxed-simd",
+    "--enable-tail-call",
+    "--enable-multivalue",
+    "--enable-reference-types",
+];
+
+pub fn run(wasm_opt: &Path, module: &[u8]) -> Result<Vec<u8>> {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let base = env::temp_dir().join(format!("lasr-compiler-{}-{id}", process::id()));
+    let input = base.with_extension("in.wasm");
+    let output = base.with_extension("out.wasm");
+
+    let result = optimize(wasm_opt, module, &input, &output);
+
+    let _ = fs::remove_file(&input);
+    let _ = fs::remove_file(&output);
+
+    result
+}
+
+fn optimize(wasm_opt: &Path, module: &[u8], input: &Path, output: &Path) -> Result<Vec<u8>> {
+    fs::write(input, module).with_context(|| format!("failed to write {}", input.display()))?;
+
+    let result = Command::new(wasm_opt)
+        .arg(input)
+        .arg("-O2")
+        .args(FEATURE_FLAGS)
+        .arg("-o")
+        .arg(output)
+        .output()
+        .context("failed to run wasm-opt")?;
+
+    ensure!(
+        result.status.success(),
+        "wasm-opt failed ({}): {}",
+        result.status,
+        String::from_utf8_lossy(&result.stderr).trim()
+    );
+
+    let optimized = fs::read(output).context("failed to read the output of wasm-opt")?;
+
+    Validator::new_with_features(WasmFeatures::all())
+        .validate_all(&optimized)
+        .context("wasm-opt produced an invalid module")?;
+
+    Ok(optimized)
+}
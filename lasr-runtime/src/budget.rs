@@ -0,0 +1,388 @@
+//! Keeps a callback stuck in an endless loop from hanging the auto splitter.
+//! Tsuki has no instruction count hook, so the script is instrumented instead:
+//! every loop iteration, `goto` and function call first calls a native that
+//! counts down the budget of whatever is running and raises an error once it
+//! runs out. Only the script's own work counts, waiting in an async native
+//! like `sig_scan` doesn't.
+
+use std::{cell::Cell, fmt};
+
+use tsuki::context::{Args, Context, Ret};
+
+use crate::state::{Result, State};
+
+/// How many loop iterations and function calls a callback may take in a
+/// single tick by default, a few hundred million instructions.
+pub const DEFAULT_LIMIT: u64 = 20_000_000;
+
+/// The local the instrumented script calls the budget check through. An
+/// upvalue is quicker to get at than a global.
+const CHECK: &str = "__lasr_budget";
+
+/// What the budget is currently counted for.
+#[derive(Clone, Copy)]
+pub enum Running {
+    /// The script's main chunk.
+    Script,
+    /// One of the callbacks, by its global name.
+    Callback(&'static str),
+    /// A task started with `spawn`.
+    Task,
+}
+
+impl fmt::Display for Running {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Script => f.write_str("the script"),
+            Self::Callback(name) => write!(f, "callback '{name}'"),
+            Self::Task => f.write_str("a task"),
+        }
+    }
+}
+
+thread_local! {
+    static LIMIT: Cell<u64> = const { Cell::new(DEFAULT_LIMIT) };
+    static LEFT: Cell<u64> = const { Cell::new(DEFAULT_LIMIT) };
+    static RUNNING: Cell<Running> = const { Cell::new(Running::Script) };
+}
+
+/// Sets the budget each callback starts with, `0` turns the checks off. The
+/// script sets it through `executionBudget` in `startup`.
+pub fn set_limit(limit: u64) {
+    LIMIT.set(limit);
+}
+
+/// Gives `running` a full budget, right before it's called.
+pub fn start(running: Running) {
+    RUNNING.set(running);
+    restart();
+}
+
+/// Refills the budget of whatever is running, like for the next task.
+pub fn restart() {
+    LEFT.set(LIMIT.get());
+}
+
+/// The check the instrumented script calls. The budget stays used up once
+/// it ran out, so a `pcall` in the loop can't catch its way past it.
+pub fn check(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    match LEFT.get() {
+        0 if LIMIT.get() != 0 => Err(format!("{} exceeded execution budget", RUNNING.get()).into()),
+        0 => Ok(cx.into()),
+        left => {
+            LEFT.set(left - 1);
+            Ok(cx.into())
+        }
+    }
+}
+
+/// Adds a budget check to the start of every loop body and function, and in
+/// front of every `goto`. The check is passed to the chunk when it's called.
+/// Nothing is inserted on a line of its own, so errors still point at the
+/// right lines.
+pub fn instrument(source: &str) -> String {
+    let bytes = source.as_bytes();
+    let mut out = String::with_capacity(source.len() + source.len() / 8);
+    out.push_str("local ");
+    out.push_str(CHECK);
+    out.push_str(" = ...; ");
+
+    let mut copied = 0;
+    let mut insert = |out: &mut String, at: usize| {
+        out.push_str(&source[copied..at]);
+        out.push(' ');
+        // The `;` keeps a body starting with `(` from calling the check's
+        // result instead.
+        out.push_str(CHECK);
+        out.push_str("(); ");
+        copied = at;
+    };
+
+    // Whether a `function` keyword is waiting for its parameter list, and
+    // whether that list is open.
+    let mut in_function = false;
+    let mut in_params = false;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = match long_bracket(bytes, i + 2) {
+                    Some(end) => end,
+                    None => bytes[i..]
+                        .iter()
+                        .position(|&b| b == b'\n')
+                        .map_or(bytes.len(), |len| i + len),
+                };
+            }
+            b'[' => i = long_bracket(bytes, i).unwrap_or(i + 1),
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while let Some(&b) = bytes.get(i) {
+                    i += match b {
+                        b'\\' => 2,
+                        b'\n' => break,
+                        _ if b == quote => {
+                            i += 1;
+                            break;
+                        }
+                        _ => 1,
+                    };
+                }
+            }
+            b'.' if !bytes.get(i + 1).is_some_and(u8::is_ascii_digit) => i += 1,
+            b'0'..=b'9' | b'.' => {
+                // Good enough to not mistake an exponent or a hex digit for
+                // a name, the parser checks the rest.
+                while bytes
+                    .get(i)
+                    .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'.')
+                {
+                    i += 1;
+                }
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                let start = i;
+                while bytes
+                    .get(i)
+                    .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_')
+                {
+                    i += 1;
+                }
+                match &source[start..i] {
+                    "do" | "repeat" => insert(&mut out, i),
+                    "goto" => insert(&mut out, start),
+                    "function" => in_function = true,
+                    _ => {}
+                }
+            }
+            b'(' if in_function => {
+                in_function = false;
+                in_params = true;
+                i += 1;
+            }
+            b')' if in_params => {
+                in_params = false;
+                i += 1;
+                insert(&mut out, i);
+            }
+            _ => i += 1,
+        }
+    }
+
+    out.push_str(&source[copied..]);
+    out
+}
+
+/// Skips a long bracket like `[==[ ... ]==]` starting at `start`, returning
+/// where it ends. `None` if there's no long bracket there.
+fn long_bracket(bytes: &[u8], start: usize) -> Option<usize> {
+    let level = bytes.get(start + 1..)?.iter().position(|&b| b != b'=')?;
+    if bytes[start] != b'[' || bytes[start + 1 + level] != b'[' {
+        return None;
+    }
+
+    let body = start + level + 2;
+    let end = bytes[body..]
+        .windows(level + 2)
+        .position(|window| {
+            window[0] == b']'
+                && window[level + 1] == b']'
+                && window[1..=level].iter().all(|&b| b == b'=')
+        })
+        .map_or(bytes.len(), |pos| body + pos + level + 2);
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use tsuki::{Value, fp};
+
+    use super::{Running, check, instrument, set_limit, start};
+    use crate::{
+        callbacks::lookup,
+        testing::{block_on, lua, run},
+        utils::{call_logged, resume},
+    };
+
+    #[test]
+    fn instruments_loops_and_functions() {
+        assert_eq!(
+            instrument("while x do y() end"),
+            "local __lasr_budget = ...; while x do __lasr_budget();  y() end"
+        );
+        assert_eq!(
+            instrument("local function f(a, b) return a end"),
+            "local __lasr_budget = ...; local function f(a, b) __lasr_budget();  return a end"
+        );
+        assert_eq!(
+            instrument("::top:: repeat until x goto top"),
+            "local __lasr_budget = ...; ::top:: repeat __lasr_budget();  until x  __lasr_budget(); goto top"
+        );
+        assert_eq!(
+            instrument("do (cb)() end"),
+            "local __lasr_budget = ...; do __lasr_budget();  (cb)() end"
+        );
+
+        // Bodies starting with `(` call what's in the parentheses, not the
+        // check's result.
+        let lua = lua();
+        let chunk = lua
+            .load(
+                "test.lua",
+                instrument(
+                    r#"
+                    local calls = 0
+                    local function cb() calls = calls + 1 end
+                    do (cb)() end
+                    local function f() (cb or nil)() end
+                    f()
+                    for _ = 1, 2 do (cb)() end
+                    assert(calls == 4)
+                    "#,
+                ),
+            )
+            .unwrap();
+        let () = lua.create_thread().call(&*chunk, fp!(check)).unwrap();
+
+        // Keywords in strings, comments and names are left alone.
+        let untouched = r#"
+            local s = "while x do \" do" .. 'repeat' .. [==[ do ]] function() ]==]
+            -- for i = 1, 10 do
+            --[[ do
+            ]] local done, doit, _do = 0x1E, 1e-5, .5
+            t.redo = s:find("goto")
+        "#;
+        assert_eq!(
+            instrument(untouched),
+            format!("local __lasr_budget = ...; {untouched}")
+        );
+    }
+
+    #[test]
+    fn keeps_running_after_an_endless_callback() {
+        let lua = lua();
+        let chunk = lua
+            .load(
+                "script.lua",
+                instrument(
+                    r#"
+                    updates = 0
+                    function update()
+                        updates = updates + 1
+                        while true do end
+                    end
+                    function escape()
+                        while true do
+                            pcall(function() end)
+                        end
+                    end
+                    function recurse()
+                        return recurse()
+                    end
+                    function yielding()
+                        -- 600 iterations, but only 100 per tick.
+                        for i = 1, 600 do
+                            if i % 100 == 0 then
+                                coroutine.yield()
+                            end
+                        end
+                        return true
+                    end
+                    "#,
+                ),
+            )
+            .unwrap();
+        let td = lua.create_thread();
+        let () = td.call(&*chunk, fp!(check)).unwrap();
+
+        let driver = lua
+            .load("callbacks.lua", include_str!("callbacks.lua"))
+            .unwrap();
+        let Value::LuaFn(driver) = td.call(&*driver, fp!(resume as async)).unwrap() else {
+            unreachable!("callbacks.lua returns the driver function");
+        };
+
+        set_limit(200);
+        block_on(async {
+            for name in ["update", "update", "escape", "recurse"] {
+                let callback = lookup(&lua, name).unwrap();
+                assert!(matches!(
+                    call_logged(&td, &driver, &callback).await,
+                    Value::Nil
+                ));
+            }
+            run(&lua, "assert(updates == 2)");
+
+            let yielding = lookup(&lua, "yielding").unwrap();
+            for _ in 0..6 {
+                assert!(matches!(
+                    call_logged(&td, &driver, &yielding).await,
+                    Value::Nil
+                ));
+            }
+            assert!(matches!(
+                call_logged(&td, &driver, &yielding).await,
+                Value::True
+            ));
+        });
+    }
+
+    #[test]
+    fn stops_endless_tasks() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            logged = {}
+            function capture(message)
+                logged[#logged + 1] = message
+            end
+            "#,
+        );
+        let td = lua.create_thread();
+        let run_tasks = lua.load("tasks.lua", include_str!("tasks.lua")).unwrap();
+        let capture = lookup(&lua, "capture").unwrap();
+        let Value::LuaFn(run_tasks) = td
+            .call(&*run_tasks, (fp!(resume as async), &*capture.func))
+            .unwrap()
+        else {
+            unreachable!("tasks.lua returns the task runner");
+        };
+
+        let chunk = lua
+            .load(
+                "script.lua",
+                instrument(
+                    r#"
+                    ticks = 0
+                    spawn(function()
+                        while true do end
+                    end)
+                    spawn(function()
+                        while true do
+                            ticks = ticks + 1
+                            nextTick()
+                        end
+                    end)
+                    "#,
+                ),
+            )
+            .unwrap();
+        let () = td.call(&*chunk, fp!(check)).unwrap();
+
+        set_limit(200);
+        for elapsed_ms in 0..3 {
+            start(Running::Task);
+            let () = block_on(td.async_call(&run_tasks, elapsed_ms)).unwrap();
+        }
+        run(
+            &lua,
+            r#"
+            assert(ticks == 3)
+            assert(#logged == 1)
+            assert(logged[1]:find("a task exceeded execution budget", 1, true))
+            "#,
+        );
+    }
+}
@@ -0,0 +1,27 @@
+-- Drives the per-tick callbacks. Every callback runs as a coroutine, so it can
+-- call `coroutine.yield()` to continue right where it left off on the next
+-- tick. Returns whether the callback ran without an error, followed by the
+-- error or what it returned.
+
+-- Resumes like `coroutine.resume`, but lets the callback call async natives
+-- like `sig_scan`.
+local resume = ...
+local create, status = coroutine.create, coroutine.status
+
+-- The suspended coroutine of each callback that yielded on a previous tick.
+local suspended = {}
+
+return function(callback)
+    local co = suspended[callback] or create(callback)
+    local ok, result = resume(co)
+
+    if status(co) == "suspended" then
+        -- Yielding means there's no decision this tick.
+        suspended[callback] = co
+        return true, nil
+    end
+
+    -- After an error, the callback starts over on the next tick.
+    suspended[callback] = nil
+    return ok, result
+end
@@ -0,0 +1,157 @@
+use tsuki::{Lua, LuaFn, Ref, Thread, Value};
+
+use crate::{
+    log,
+    state::State,
+    utils::{PairsBatch, TablePairs, type_name},
+};
+
+/// Every callback the runtime calls, by its global name.
+const CALLBACK_NAMES: [&str; 13] = [
+    "startup",
+    "onTickStart",
+    "onTickEnd",
+    "onAttach",
+    "state",
+    "update",
+    "start",
+    "split",
+    "isLoading",
+    "reset",
+    "gameTime",
+    "onExit",
+    "onSettingsChanged",
+];
+
+/// The globals `startup` may set to configure the runtime, along with the
+/// type they need to have.
+const CONFIG_GLOBALS: [(&str, &str); 17] = [
+    ("refreshRate", "number"),
+    ("idleTickRate", "number"),
+    ("useGameTime", "boolean"),
+    ("mapsCacheCycles", "number"),
+    ("eagerMaps", "boolean"),
+    ("freshVmPerAttach", "boolean"),
+    ("resetOnGameExit", "boolean"),
+    ("resetOnGameStart", "boolean"),
+    ("gameTimeUnit", "string"),
+    ("maxMessageLength", "number"),
+    ("readBudget", "number"),
+    ("executionBudget", "number"),
+    ("recordTicks", "number"),
+    ("recordRawReads", "boolean"),
+    ("gameTimeWhenEnded", "boolean"),
+    ("isLoadingWhenEnded", "boolean"),
+    ("resetWhenEnded", "boolean"),
+];
+
+/// A callback along with its global name, which tells what ran out of
+/// budget.
+pub struct Callback<'a> {
+    pub name: &'static str,
+    pub func: Ref<'a, LuaFn<State>>,
+}
+
+/// The script's callbacks, looked up once instead of on every tick. Scripts
+/// that reassign a callback need to call `rebindCallbacks()` afterwards.
+pub struct Callbacks<'a> {
+    pub on_attach: Option<Callback<'a>>,
+    pub state: Option<Callback<'a>>,
+    pub update: Option<Callback<'a>>,
+    pub start: Option<Callback<'a>>,
+    pub split: Option<Callback<'a>>,
+    pub is_loading: Option<Callback<'a>>,
+    pub reset: Option<Callback<'a>>,
+    pub game_time: Option<Callback<'a>>,
+    pub on_exit: Option<Callback<'a>>,
+    pub on_settings_changed: Option<Callback<'a>>,
+    pub on_tick_start: Option<Callback<'a>>,
+    pub on_tick_end: Option<Callback<'a>>,
+}
+
+impl<'a> Callbacks<'a> {
+    pub fn bind(lua: &'a Lua<State>) -> Self {
+        Self {
+            on_attach: lookup(lua, "onAttach"),
+            state: lookup(lua, "state"),
+            update: lookup(lua, "update"),
+            start: lookup(lua, "start"),
+            split: lookup(lua, "split"),
+            is_loading: lookup(lua, "isLoading"),
+            reset: lookup(lua, "reset"),
+            game_time: lookup(lua, "gameTime"),
+            on_exit: lookup(lua, "onExit"),
+            on_settings_changed: lookup(lua, "onSettingsChanged"),
+            on_tick_start: lookup(lua, "onTickStart"),
+            on_tick_end: lookup(lua, "onTickEnd"),
+        }
+    }
+}
+
+pub fn lookup<'a>(lua: &'a Lua<State>, name: &'static str) -> Option<Callback<'a>> {
+    match lua.global().get_str_key(name) {
+        Value::LuaFn(func) => Some(Callback { name, func }),
+        _ => None,
+    }
+}
+
+/// Prints which callbacks the script defines, and warns about globals that
+/// look like a misspelled callback or setting, or have the wrong type.
+pub fn report(lua: &Lua<State>, td: &Ref<'_, Thread<State>>) {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for name in CALLBACK_NAMES {
+        match lua.global().get_str_key(name) {
+            Value::LuaFn(_) => found.push(name),
+            Value::Nil => missing.push(name),
+            value => log::warn(&format!(
+                "[startup] {name} is a {}, not a function, so it's never called",
+                type_name(&value)
+            )),
+        }
+    }
+    log::print_message(&format!(
+        "[startup] Callbacks found: {}; not defined: {}",
+        list(&found),
+        list(&missing)
+    ));
+
+    for (name, expected) in CONFIG_GLOBALS {
+        let value = lua.global().get_str_key(name);
+        if !matches!(value, Value::Nil) && type_name(&value) != expected {
+            log::warn(&format!(
+                "[startup] {name} needs to be a {expected}, not a {}, so it's ignored",
+                type_name(&value)
+            ));
+        }
+    }
+
+    let globals = lua.global();
+    let batch = lua.registry::<PairsBatch>().unwrap();
+    for pair in TablePairs::new(td, &batch, globals) {
+        let Ok((Value::Str(key), _)) = pair else {
+            continue;
+        };
+        let Some(key) = key.as_utf8() else {
+            continue;
+        };
+        let known = CALLBACK_NAMES
+            .into_iter()
+            .chain(CONFIG_GLOBALS.into_iter().map(|(name, _)| name));
+        for name in known {
+            if key != name && key.eq_ignore_ascii_case(name) {
+                log::warn(&format!(
+                    "[startup] {key} is never used, did you mean {name}?"
+                ));
+            }
+        }
+    }
+}
+
+fn list(names: &[&str]) -> String {
+    if names.is_empty() {
+        "none".to_owned()
+    } else {
+        names.join(", ")
+    }
+}
@@ -0,0 +1,20 @@
+//! Native functions a fork of the runtime adds for its game, built with the
+//! `custom-api` feature. Keeping them in this file alone keeps merging
+//! upstream changes painless. Functions are written like the ones in
+//! `lua_api` and registered here like `register_api` registers those:
+//!
+//! ```ignore
+//! lua.global()
+//!     .set_str_key("readPlayer", traced!("readPlayer", read_player));
+//! ```
+//!
+//! Scripts should check for them with `hasApi`, as the API level only covers
+//! upstream functions.
+
+use tsuki::Lua;
+
+use crate::state::State;
+
+/// Called after all other functions are registered, so these may replace
+/// them.
+pub fn register(_lua: &Lua<State>) {}
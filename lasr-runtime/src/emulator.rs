@@ -0,0 +1,184 @@
+//! The `emulator` module, exposing asr's emulator support, which finds the
+//! emulated system's RAM inside of the emulator process.
+
+use asr::emulator::{gba, gcn, genesis, ps1, ps2, snes};
+use tsuki::{
+    Float, Lua, Module, Ref, Table, Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use crate::state::{Result, State};
+
+pub struct EmulatorLib;
+
+impl Module<State> for EmulatorLib {
+    const NAME: &str = "emulator";
+
+    type Inst<'a>
+        = Ref<'a, Table<State>>
+    where
+        State: 'a;
+
+    fn open(self, lua: &Lua<State>) -> Result<Self::Inst<'_>> {
+        let m = lua.create_table();
+
+        m.set_str_key("attach", fp!(attach));
+        m.set_str_key("rebase", fp!(rebase));
+        m.set_str_key("ramBase", fp!(ram_base));
+
+        Ok(m)
+    }
+}
+
+pub enum Emulator {
+    Ps1(ps1::Emulator),
+    Ps2(ps2::Emulator),
+    Gcn(gcn::Emulator),
+    Gba(gba::Emulator),
+    Snes(snes::Emulator),
+    Genesis(genesis::Emulator),
+}
+
+macro_rules! dispatch {
+    ($emulator:expr, $emu:ident => $body:expr) => {
+        match $emulator {
+            Emulator::Ps1($emu) => $body,
+            Emulator::Ps2($emu) => $body,
+            Emulator::Gcn($emu) => $body,
+            Emulator::Gba($emu) => $body,
+            Emulator::Snes($emu) => $body,
+            Emulator::Genesis($emu) => $body,
+        }
+    };
+}
+
+impl Emulator {
+    fn attach(system: &str) -> Option<Option<Self>> {
+        Some(match system {
+            "ps1" => ps1::Emulator::attach().map(Self::Ps1),
+            "ps2" => ps2::Emulator::attach().map(Self::Ps2),
+            "gcn" => gcn::Emulator::attach().map(Self::Gcn),
+            "gba" => gba::Emulator::attach().map(Self::Gba),
+            "snes" => snes::Emulator::attach().map(Self::Snes),
+            "genesis" => genesis::Emulator::attach().map(Self::Genesis),
+            _ => return None,
+        })
+    }
+
+    /// Finds the RAM again, which can move when the emulator loads a game.
+    fn rebase(&mut self) -> bool {
+        dispatch!(self, emu => emu.update())
+    }
+
+    fn read_value(&self, ty: &str, address: u32) -> Option<Value<'static, State>> {
+        macro_rules! read {
+            ($ty:ty) => {
+                dispatch!(self, emu => emu.read::<$ty>(address).ok())
+            };
+        }
+
+        Some(match ty {
+            "sbyte" => Value::Int(read!(i8)?.into()),
+            "byte" => Value::Int(read!(u8)?.into()),
+            "short" => Value::Int(read!(i16)?.into()),
+            "ushort" => Value::Int(read!(u16)?.into()),
+            "int" => Value::Int(read!(i32)?.into()),
+            "uint" => Value::Int(read!(u32)?.into()),
+            "long" => Value::Int(read!(i64)?),
+            "ulong" => Value::Int(read!(u64)? as i64),
+            "float" => Value::Float(Float(read!(f32)?.into())),
+            "double" => Value::Float(Float(read!(f64)?)),
+            "bool" => {
+                if read!(u8)? == 0 {
+                    Value::False
+                } else {
+                    Value::True
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+fn attach(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let arg = cx.arg(1);
+    let system = arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("system is not valid UTF-8"))?;
+
+    let emulator = Emulator::attach(system).ok_or_else(|| {
+        arg.error("unsupported system, use one of ps1, ps2, gcn, gba, snes or genesis")
+    })?;
+
+    let found = emulator.is_some();
+    *cx.associated_data().emulator.borrow_mut() = emulator;
+
+    cx.push(found)?;
+    Ok(cx.into())
+}
+
+fn rebase(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let found = cx
+        .associated_data()
+        .emulator
+        .borrow_mut()
+        .as_mut()
+        .ok_or("emulator.attach needs to be called first")?
+        .rebase();
+
+    cx.push(found)?;
+    Ok(cx.into())
+}
+
+fn ram_base(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    // FIXME: asr keeps the RAM base of its emulators private, so there's no
+    // way to hand it to the script yet.
+    cx.push(Value::Nil)?;
+    cx.push(Value::Str(cx.create_str(
+        "ramBase is not supported yet, use emuReadAddress instead",
+    )))?;
+    Ok(cx.into())
+}
+
+pub fn emu_read_address(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let ty_arg = cx.arg(1);
+    let ty = ty_arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| ty_arg.error("type is not valid UTF-8"))?;
+    if !matches!(
+        ty,
+        "sbyte"
+            | "byte"
+            | "short"
+            | "ushort"
+            | "int"
+            | "uint"
+            | "long"
+            | "ulong"
+            | "float"
+            | "double"
+            | "bool"
+    ) {
+        return Err(ty_arg.error("unsupported type").into());
+    }
+    let address = cx.arg(2).to_int()? as u32;
+
+    let mut emulator = cx.associated_data().emulator.borrow_mut();
+    let emulator = emulator
+        .as_mut()
+        .ok_or("emulator.attach needs to be called first")?;
+
+    // A failing read usually means the RAM moved, so look for it again before
+    // giving up.
+    let value = match emulator.read_value(ty, address) {
+        Some(value) => value,
+        None if emulator.rebase() => emulator.read_value(ty, address).unwrap_or(Value::Nil),
+        None => Value::Nil,
+    };
+
+    cx.push(value)?;
+    Ok(cx.into())
+}
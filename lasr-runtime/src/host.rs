@@ -0,0 +1,255 @@
+//! Everything the runtime asks of the host besides process memory: the timer,
+//! printing and attaching. Goes through a [`Host`], so the main loop can run
+//! against a fake game in tests. Like the log, it's kept per thread instead
+//! of in the [`State`](crate::state::State), as messages are printed from
+//! places that have no state at hand.
+
+use std::{cell::RefCell, rc::Rc};
+
+use asr::{
+    Address, Process,
+    time::Duration,
+    timer::{self, TimerState},
+};
+
+use crate::process_memory::ProcessMemory;
+
+/// The subset of asr's host functions the runtime uses, other than the ones
+/// behind [`ProcessMemory`].
+pub trait Host {
+    fn timer_state(&self) -> TimerState;
+
+    fn start(&self);
+
+    fn split(&self);
+
+    fn reset(&self);
+
+    fn set_game_time(&self, game_time: Duration);
+
+    fn pause_game_time(&self);
+
+    fn resume_game_time(&self);
+
+    fn set_variable(&self, key: &str, value: &str);
+
+    fn print_message(&self, message: &str);
+
+    fn set_tick_rate(&self, ticks_per_second: f64);
+
+    /// Attaches to `name` if it's running, along with its main module's
+    /// address.
+    fn attach(&self, name: &str) -> Option<(Rc<dyn ProcessMemory>, Option<Address>)>;
+}
+
+/// The host the auto splitter runs in, through asr.
+#[cfg_attr(test, allow(dead_code))]
+pub struct Asr;
+
+impl Host for Asr {
+    fn timer_state(&self) -> TimerState {
+        timer::state()
+    }
+
+    fn start(&self) {
+        timer::start();
+    }
+
+    fn split(&self) {
+        timer::split();
+    }
+
+    fn reset(&self) {
+        timer::reset();
+    }
+
+    fn set_game_time(&self, game_time: Duration) {
+        timer::set_game_time(game_time);
+    }
+
+    fn pause_game_time(&self) {
+        timer::pause_game_time();
+    }
+
+    fn resume_game_time(&self) {
+        timer::resume_game_time();
+    }
+
+    fn set_variable(&self, key: &str, value: &str) {
+        timer::set_variable(key, value);
+    }
+
+    fn print_message(&self, message: &str) {
+        asr::print_message(message);
+    }
+
+    fn set_tick_rate(&self, ticks_per_second: f64) {
+        asr::set_tick_rate(ticks_per_second);
+    }
+
+    fn attach(&self, name: &str) -> Option<(Rc<dyn ProcessMemory>, Option<Address>)> {
+        let process = Process::attach(name)?;
+        let base_address = process.get_module_address(name).ok();
+        Some((Rc::new(process), base_address))
+    }
+}
+
+thread_local! {
+    static HOST: RefCell<Rc<dyn Host>> = RefCell::new(default_host());
+}
+
+#[cfg(not(test))]
+fn default_host() -> Rc<dyn Host> {
+    Rc::new(Asr)
+}
+
+/// Tests never talk to a real host, unless they set one.
+#[cfg(test)]
+fn default_host() -> Rc<dyn Host> {
+    Rc::new(mock::MockHost::new())
+}
+
+/// The host the runtime currently talks to.
+pub fn get() -> Rc<dyn Host> {
+    HOST.with_borrow(Rc::clone)
+}
+
+/// Talks to `host` from now on.
+#[cfg(test)]
+pub fn set(host: Rc<dyn Host>) {
+    HOST.set(host);
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashMap,
+        rc::Rc,
+    };
+
+    use asr::{Address, time::Duration, timer::TimerState};
+
+    use super::Host;
+    use crate::process_memory::{ProcessMemory, mock::MockProcess};
+
+    /// What the runtime did to the timer.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Event {
+        Start,
+        Split,
+        Reset,
+        SetGameTime(Duration),
+        PauseGameTime,
+        ResumeGameTime,
+    }
+
+    /// A LiveSplit with `segments` splits, which records what the runtime
+    /// does to it and has `processes` running.
+    pub struct MockHost {
+        segments: usize,
+        state: Cell<TimerState>,
+        split_index: Cell<usize>,
+        processes: RefCell<HashMap<String, Rc<MockProcess>>>,
+        pub events: RefCell<Vec<Event>>,
+        /// Every update of a variable, in order.
+        pub variables: RefCell<Vec<(String, String)>>,
+        pub messages: RefCell<Vec<String>>,
+        pub tick_rate: Cell<Option<f64>>,
+    }
+
+    impl MockHost {
+        pub fn new() -> Self {
+            Self {
+                segments: 1,
+                state: Cell::new(TimerState::NotRunning),
+                split_index: Cell::new(0),
+                processes: RefCell::default(),
+                events: RefCell::default(),
+                variables: RefCell::default(),
+                messages: RefCell::default(),
+                tick_rate: Cell::new(None),
+            }
+        }
+
+        /// Sets how many splits the run has, the last one ends it.
+        pub fn segments(mut self, segments: usize) -> Self {
+            self.segments = segments;
+            self
+        }
+
+        /// Starts the game, so it can be attached to as `name`.
+        pub fn launch(&self, name: &str, process: Rc<MockProcess>) {
+            self.processes.borrow_mut().insert(name.to_owned(), process);
+        }
+
+        fn record(&self, event: Event) {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
+    impl Host for MockHost {
+        fn timer_state(&self) -> TimerState {
+            self.state.get()
+        }
+
+        fn start(&self) {
+            if self.state.get() == TimerState::NotRunning {
+                self.state.set(TimerState::Running);
+                self.split_index.set(0);
+            }
+            self.record(Event::Start);
+        }
+
+        fn split(&self) {
+            if self.state.get() == TimerState::Running {
+                let split_index = self.split_index.get() + 1;
+                self.split_index.set(split_index);
+                if split_index == self.segments {
+                    self.state.set(TimerState::Ended);
+                }
+            }
+            self.record(Event::Split);
+        }
+
+        fn reset(&self) {
+            self.state.set(TimerState::NotRunning);
+            self.record(Event::Reset);
+        }
+
+        fn set_game_time(&self, game_time: Duration) {
+            self.record(Event::SetGameTime(game_time));
+        }
+
+        fn pause_game_time(&self) {
+            self.record(Event::PauseGameTime);
+        }
+
+        fn resume_game_time(&self) {
+            self.record(Event::ResumeGameTime);
+        }
+
+        fn set_variable(&self, key: &str, value: &str) {
+            self.variables
+                .borrow_mut()
+                .push((key.to_owned(), value.to_owned()));
+        }
+
+        fn print_message(&self, message: &str) {
+            self.messages.borrow_mut().push(message.to_owned());
+        }
+
+        fn set_tick_rate(&self, ticks_per_second: f64) {
+            self.tick_rate.set(Some(ticks_per_second));
+        }
+
+        fn attach(&self, name: &str) -> Option<(Rc<dyn ProcessMemory>, Option<Address>)> {
+            let process = self.processes.borrow().get(name)?.clone();
+            if !process.is_open() {
+                return None;
+            }
+            let base_address = process.get_module_address(name).ok();
+            Some((process, base_address))
+        }
+    }
+}
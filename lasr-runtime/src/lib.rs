@@ -1,89 +1,189 @@
-#![allow(clippy::await_holding_refcell_ref)]
-
 use std::{
-    cell::{Cell, RefCell},
-    pin::Pin,
+    future::poll_fn,
+    pin::{Pin, pin},
     rc::Rc,
+    task::Poll,
+    time::Instant,
 };
 
-use asr::{
-    Address,
-    future::next_tick,
-    time::Duration,
-    timer::{self, TimerState},
-};
+use asr::{future::next_tick, timer::TimerState};
 use tsuki::{
-    Float, Lua, Ref, Thread, Value,
+    Float, Lua, LuaFn, Ref, Thread, Value,
     builtin::{BaseLib, CoroLib, IoLib, MathLib, OsLib, StrLib, TableLib, Utf8Lib},
     fp,
 };
 
+mod budget;
+mod callbacks;
+#[cfg(feature = "custom-api")]
+mod custom_api;
+#[cfg(feature = "emulator")]
+mod emulator;
+mod heap;
+mod host;
+mod log;
 mod lua_api;
 mod luajit_bitlib;
+mod process_memory;
+mod record;
 mod script;
+mod settings;
+mod sigscan;
+mod splits;
 mod state;
+#[cfg(test)]
+mod testing;
+mod timer_policy;
+mod trace;
+#[cfg(feature = "unity")]
+mod unity;
+#[cfg(feature = "unreal")]
+mod unreal;
 mod utils;
+mod variables;
 
+use budget::Running;
+use callbacks::{Callback, Callbacks};
 use lua_api::{
-    get_base_address, get_maps, get_module_size, get_pid, print, print_tbl, process, read_address,
-    set_variable, shallow_copy_tbl, sig_scan, size_of,
+    Capability, apply_game_time_pause, autosplit, check_autosplits, clear_variable, create_buffer,
+    create_frame_counter, create_watcher, detect_version, fastpairs, find_pattern,
+    find_pattern_all, format_address, format_time, get_arch, get_base_address, get_maps,
+    get_memory_info, get_module_size, get_os, get_perf_stats, get_pid, get_symbol_address,
+    get_symbols, get_timer_state, has_api, is_64_bit, is_game_time_paused, map_ranges, maps_ready,
+    mem_stats, pause_game_time, print, print_tbl, process, process_info, read_address,
+    read_into_buffer, read_pointer_path, rebind_callbacks, register_buffer, register_scan,
+    request_burst, rescan_stale, reset_split_once, resume_game_time, runtime_uses, scanned,
+    set_pointer_size, set_split_cooldown, set_start_cooldown, set_time_variable, set_variable,
+    set_variable_throttled, set_variable_with_ttl, shallow_copy_tbl, sig_scan, size_of, snapshot,
+    split_once, try_attach, try_get_base_address, try_get_module_size, update_watchers,
+    wait_for_module, warm_maps,
 };
 use luajit_bitlib::LuaJitBitLib;
 use script::script_str;
-use state::State;
-use utils::{call_maybe, call_maybe_bool};
+use settings::SettingsLib;
+use splits::SplitsLib;
+use state::{GameTimeUnit, State};
+use timer_policy::{TickResults, TimerAction};
+use trace::traced;
+use utils::{
+    Globals, call_logged, call_maybe, call_maybe_bool, error_message, fnv1a, register_pairs_batch,
+    resume,
+};
 
 asr::async_main!(stable);
 
+/// Bumped whenever the Lua visible API changes, so scripts can check
+/// `LASR_API_LEVEL` to degrade gracefully on older runtimes.
+macro_rules! api_level {
+    () => {
+        59
+    };
+}
+
+/// [`api_level!`] for the modules declared before it.
+const API_LEVEL: i64 = api_level!();
+
+const RUNTIME_INFO: &str = concat!(
+    "version=",
+    env!("CARGO_PKG_VERSION"),
+    "\napi-level=",
+    api_level!(),
+    "\n",
+);
+
+// Read by the compiler to record the runtime's version and API level in the
+// metadata of every compiled auto splitter.
+#[cfg_attr(target_family = "wasm", unsafe(link_section = "lasr.runtime"))]
+#[used]
+static RUNTIME_INFO_SECTION: [u8; RUNTIME_INFO.len()] = str_to_array(RUNTIME_INFO);
+
+const fn str_to_array<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut out = [0; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
 // FIXME: asr should pretend to call async main on non-wasm targets, so we don't
 // have to silence the unused warning (we force it through no_mangle so unused
-// warnings inside are still detected).
-#[cfg_attr(not(target_family = "wasm"), unsafe(no_mangle))]
+// warnings inside are still detected). Tests bring their own `main`.
+#[cfg_attr(not(any(target_family = "wasm", test)), unsafe(no_mangle))]
+#[cfg_attr(test, allow(dead_code))]
 async fn main() {
+    log::init();
     loop {
-        let lua = Lua::new(State {
-            process: RefCell::new(None),
-            base_address: Cell::new(Address::NULL),
-            process_name: RefCell::new(None),
-            maps_cache: RefCell::new(None),
-            maps_cache_cycles: Cell::new(1),
-            maps_cache_cycles_value: Cell::new(1),
-        });
+        run(script_str()).await;
+    }
+}
+
+/// Runs `script` until it needs a fresh VM, like when the game closed and it
+/// doesn't attach to it again. Polled once per tick.
+async fn run(script: &str) {
+    let lua = Lua::new(State::new());
+
+    register_api(&lua);
+
+    let td = lua.create_thread();
+
+    let driver = lua
+        .load("callbacks.lua", include_str!("callbacks.lua"))
+        .unwrap();
+    let Value::LuaFn(driver) = td.async_call(&driver, fp!(resume as async)).await.unwrap() else {
+        unreachable!("callbacks.lua returns the driver function");
+    };
+
+    let run_tasks = lua.load("tasks.lua", include_str!("tasks.lua")).unwrap();
+    let Value::LuaFn(run_tasks) = td
+        .async_call(&run_tasks, (fp!(resume as async), fp!(log::log_error)))
+        .await
+        .unwrap()
+    else {
+        unreachable!("tasks.lua returns the task runner");
+    };
+
+    let chunk = lua.load("script.lua", budget::instrument(script)).unwrap();
+    budget::start(Running::Script);
+    () = td.async_call(&chunk, fp!(budget::check)).await.unwrap();
+    print_banner(&lua, script);
+
+    // `startup` may wait for the game through `process`, keep the tasks
+    // running and the refresh rate applied in the meantime.
+    let started = Instant::now();
+    let startup_td = lua.create_thread();
+    let use_game_time = {
+        let mut startup = pin!(startup(&lua, &startup_td));
+        loop {
+            budget::start(Running::Callback("startup"));
+            if let Poll::Ready(use_game_time) =
+                poll_fn(|cx| Poll::Ready(startup.as_mut().poll(cx))).await
+            {
+                break use_game_time;
+            }
+
+            let on_tick_start = callbacks::lookup(&lua, "onTickStart");
+            call_tick_hook(&lua, &td, &driver, on_tick_start.as_ref()).await;
+
+            apply_refresh_rate(&lua);
+
+            let elapsed_ms = started.elapsed().as_millis() as i64;
+            budget::start(Running::Task);
+            () = td.async_call(&run_tasks, elapsed_ms).await.unwrap();
 
-        lua.use_module(None, true, BaseLib).unwrap();
-        lua.use_module(None, true, CoroLib).unwrap();
-        lua.use_module(None, true, IoLib).unwrap();
-        lua.use_module(None, true, MathLib).unwrap();
-        lua.use_module(None, true, LuaJitBitLib).unwrap();
-        lua.use_module(None, true, OsLib).unwrap();
-        lua.use_module(None, true, StrLib).unwrap();
-        lua.use_module(None, true, TableLib).unwrap();
-        lua.use_module(None, true, Utf8Lib).unwrap();
-
-        lua.global().set_str_key("process", fp!(process as async));
-        lua.global().set_str_key("readAddress", fp!(read_address));
-        lua.global().set_str_key("getPID", fp!(get_pid));
-        lua.global().set_str_key("print", fp!(print));
-        lua.global().set_str_key("sig_scan", fp!(sig_scan as async));
-        lua.global()
-            .set_str_key("getBaseAddress", fp!(get_base_address));
-        lua.global().set_str_key("sizeOf", fp!(size_of));
-        lua.global()
-            .set_str_key("getModuleSize", fp!(get_module_size));
-        lua.global().set_str_key("getMaps", fp!(get_maps));
-        lua.global().set_str_key("print_tbl", fp!(print_tbl));
-        lua.global()
-            .set_str_key("shallow_copy_tbl", fp!(shallow_copy_tbl));
-
-        lua.global().set_str_key("setVariable", fp!(set_variable));
-
-        let td = lua.create_thread();
-
-        let chunk = lua.load("script.lua", script_str()).unwrap();
-        () = td.async_call(&chunk, ()).await.unwrap();
-
-        let use_game_time = startup(&lua, &td).await;
+            let on_tick_end = callbacks::lookup(&lua, "onTickEnd");
+            end_tick_hooks(&lua, &td, &driver, on_tick_end.as_ref()).await;
 
+            next_tick().await;
+        }
+    };
+    callbacks::report(&lua, &td);
+    lua.associated_data().startup_finished.set(true);
+    let mut callbacks = Callbacks::bind(&lua);
+
+    loop {
         while lua
             .associated_data()
             .process
@@ -91,47 +191,165 @@ async fn main() {
             .as_ref()
             .is_some_and(|p| p.is_open())
         {
-            call_maybe(&lua, &td, "state").await;
-            call_maybe(&lua, &td, "update").await;
-
-            let timer_state = timer::state();
-
-            if use_game_time && let TimerState::Running | TimerState::Paused = timer_state {
-                let game_time_fn = lua.global().get_str_key("gameTime");
-                if let Value::LuaFn(func) = game_time_fn {
-                    match td.async_call(&func, ()).await.unwrap() {
-                        Value::Int(millis) => timer::set_game_time(Duration::milliseconds(millis)),
-                        Value::Float(Float(millis)) => {
-                            timer::set_game_time(Duration::seconds_f64(millis * 0.001))
-                        }
-                        _ => {}
+            trace::tick();
+            *lua.associated_data().settings_map.borrow_mut() = None;
+
+            if lua.associated_data().rebind_callbacks.take() {
+                callbacks = Callbacks::bind(&lua);
+            }
+
+            call_tick_hook(&lua, &td, &driver, callbacks.on_tick_start.as_ref()).await;
+
+            if lua.associated_data().attach_pending.take() {
+                on_attach(&lua, &td, callbacks.on_attach.as_ref()).await;
+            }
+
+            update_watchers(lua.associated_data());
+
+            call_maybe(&td, &driver, callbacks.state.as_ref()).await;
+            call_maybe(&td, &driver, callbacks.update.as_ref()).await;
+
+            for _ in 0..lua.associated_data().burst.take() {
+                call_maybe(&td, &driver, callbacks.state.as_ref()).await;
+                call_maybe(&td, &driver, callbacks.update.as_ref()).await;
+            }
+
+            // Whatever got read after the game closed is garbage, so don't
+            // base any timer actions on it.
+            if process_died(&lua) {
+                end_tick_hooks(&lua, &td, &driver, callbacks.on_tick_end.as_ref()).await;
+                break;
+            }
+
+            if let Some(callback) = &callbacks.on_settings_changed {
+                for (key, old, new) in settings::changes(lua.associated_data()) {
+                    let key = Value::Str(lua.create_str(key.as_str()));
+                    let old = old.to_value(|value| Value::Str(lua.create_str(value)));
+                    let new = new.to_value(|value| Value::Str(lua.create_str(value)));
+                    // A mistake in it isn't worth stopping the auto
+                    // splitter for.
+                    budget::start(Running::Callback(callback.name));
+                    let args = (&key, &old, &new);
+                    if let Err(err) = td.async_call::<()>(&callback.func, args).await {
+                        log::warn(&format!("[onSettingsChanged] {}", error_message(&*err)));
                     }
                 }
             }
 
-            if let TimerState::NotRunning = timer_state
-                && let Some(true) = call_maybe_bool(&lua, &td, "start").await
-            {
-                timer::start();
+            let timer_state = host::get().timer_state();
+
+            if let TimerState::NotRunning = timer_state {
+                lua.associated_data().splits_fired.borrow_mut().clear();
+                lua.associated_data().split_once_fired.borrow_mut().clear();
+                // Game time can't be paused without a run.
+                lua.associated_data().game_time_paused.set(false);
+                log::set_game_time(None);
             }
 
-            if let TimerState::Running | TimerState::Paused = timer_state
-                && let Some(true) = call_maybe_bool(&lua, &td, "split").await
+            // The callbacks may change the policy's settings, so it can't
+            // stay borrowed while they run.
+            let (calls_game_time, calls_start, calls_split, calls_is_loading, calls_reset) = {
+                let policy = lua.associated_data().timer_policy.borrow();
+                (
+                    policy.calls_game_time(timer_state),
+                    policy.calls_start(timer_state),
+                    policy.calls_split(timer_state),
+                    policy.calls_is_loading(timer_state),
+                    policy.calls_reset(timer_state),
+                )
+            };
+
+            let mut results = TickResults::default();
+
+            if use_game_time
+                && calls_game_time
+                && let Some(func) = &callbacks.game_time
             {
-                timer::split();
+                let unit = lua.associated_data().game_time_unit.get();
+                results.game_time = match call_logged(&td, &driver, func).await {
+                    Value::Int(time) => Some(unit.duration(time as f64)),
+                    Value::Float(Float(time)) => Some(unit.duration(time)),
+                    _ => None,
+                };
+            }
+
+            if calls_start {
+                results.start = call_maybe_bool(&td, &driver, callbacks.start.as_ref()).await;
             }
 
-            match call_maybe_bool(&lua, &td, "isLoading").await {
-                Some(true) => timer::pause_game_time(),
-                Some(false) => timer::resume_game_time(),
-                None => {}
+            if calls_split {
+                results.split = if check_autosplits(lua.associated_data()) {
+                    Some(true)
+                } else {
+                    call_maybe_bool(&td, &driver, callbacks.split.as_ref()).await
+                };
             }
 
-            // I feel like this should also check the timer state.
-            if let Some(true) = call_maybe_bool(&lua, &td, "reset").await {
-                timer::reset();
+            if calls_is_loading {
+                results.is_loading =
+                    call_maybe_bool(&td, &driver, callbacks.is_loading.as_ref()).await;
             }
 
+            if calls_reset {
+                results.reset = call_maybe_bool(&td, &driver, callbacks.reset.as_ref()).await;
+            }
+
+            // Whatever got read after the game closed is garbage, so don't
+            // base any timer actions on it.
+            if process_died(&lua) {
+                end_tick_hooks(&lua, &td, &driver, callbacks.on_tick_end.as_ref()).await;
+                break;
+            }
+
+            record::results(&results);
+            let actions = lua.associated_data().timer_policy.borrow_mut().decide(
+                timer_state,
+                Instant::now(),
+                results,
+            );
+            for action in actions {
+                match action {
+                    TimerAction::SetGameTime(game_time) => {
+                        host::get().set_game_time(game_time);
+                        log::set_game_time(Some(game_time));
+                    }
+                    TimerAction::Start => {
+                        host::get().start();
+                        lua.associated_data().split_once_fired.borrow_mut().clear();
+                        variables::flush(lua.associated_data(), true);
+                        // The host ignores pauses while no run is going,
+                        // like one from `pauseGameTime` in `startup`.
+                        apply_game_time_pause(lua.associated_data(), true);
+                    }
+                    TimerAction::Split => {
+                        host::get().split();
+                        variables::flush(lua.associated_data(), true);
+                    }
+                    TimerAction::SetLoading(loading) => {
+                        lua.associated_data().loading.set(loading);
+                        apply_game_time_pause(lua.associated_data(), true);
+                    }
+                    TimerAction::Reset => {
+                        host::get().reset();
+                        lua.associated_data().split_once_fired.borrow_mut().clear();
+                        variables::flush(lua.associated_data(), true);
+                        lua.associated_data().game_time_paused.set(false);
+                    }
+                }
+            }
+
+            apply_idle_tick_rate(&lua);
+
+            let elapsed_ms = started.elapsed().as_millis() as i64;
+            budget::start(Running::Task);
+            () = td.async_call(&run_tasks, elapsed_ms).await.unwrap();
+
+            variables::flush(lua.associated_data(), false);
+            variables::expire(lua.associated_data());
+            rescan_stale(lua.associated_data()).await;
+            lua.associated_data().read_stats.end_tick();
+            record::end_tick();
+
             let next_cycles = lua.associated_data().maps_cache_cycles_value.get() - 1;
 
             lua.associated_data()
@@ -145,8 +363,409 @@ async fn main() {
                     .set(lua.associated_data().maps_cache_cycles.get());
             }
 
+            end_tick_hooks(&lua, &td, &driver, callbacks.on_tick_end.as_ref()).await;
+
+            next_tick().await;
+        }
+
+        // The process exited, so drop it before anything else can hold on
+        // to it.
+        let process_name = lua.associated_data().process_name.borrow_mut().take();
+        *lua.associated_data().process.borrow_mut() = None;
+        *lua.associated_data().maps_cache.borrow_mut() = None;
+
+        if lua.associated_data().reset_on_game_exit.get()
+            && let TimerState::Running | TimerState::Paused = host::get().timer_state()
+        {
+            log::print_message("[resetOnGameExit] The game closed, resetting the timer");
+            reset_timer(lua.associated_data());
+        }
+
+        call_maybe(&td, &driver, callbacks.on_exit.as_ref()).await;
+
+        if let Value::True = lua.global().get_str_key("freshVmPerAttach") {
+            break;
+        }
+        let Some(process_name) = process_name else {
+            break;
+        };
+        // Keep ticking while the game is closed, so the tick hooks and
+        // the tasks don't stall until it starts again.
+        let reattached = loop {
+            match try_attach(lua.associated_data(), &process_name) {
+                Ok(true) => break true,
+                Ok(false) => {}
+                Err(_) => break false,
+            }
+
+            call_tick_hook(&lua, &td, &driver, callbacks.on_tick_start.as_ref()).await;
+
+            let elapsed_ms = started.elapsed().as_millis() as i64;
+            budget::start(Running::Task);
+            () = td.async_call(&run_tasks, elapsed_ms).await.unwrap();
+
+            end_tick_hooks(&lua, &td, &driver, callbacks.on_tick_end.as_ref()).await;
+
             next_tick().await;
+        };
+        if !reattached {
+            break;
+        }
+        if let Value::True = lua.global().get_str_key("eagerMaps") {
+            warm_maps(lua.associated_data()).await;
+        }
+    }
+}
+
+/// Makes the Lua API available to the script: the standard library, the
+/// runtime's functions and, with the `custom-api` feature, the functions a
+/// fork adds in `custom_api.rs`.
+fn register_api(lua: &Lua<State>) {
+    lua.set_registry::<Globals>(lua.global());
+    lua.use_module(None, true, BaseLib).unwrap();
+    lua.use_module(None, true, CoroLib).unwrap();
+    lua.use_module(None, true, IoLib).unwrap();
+    lua.use_module(None, true, MathLib).unwrap();
+    lua.use_module(None, true, LuaJitBitLib).unwrap();
+    lua.use_module(None, true, OsLib).unwrap();
+    lua.use_module(None, true, StrLib).unwrap();
+    lua.use_module(None, true, TableLib).unwrap();
+    lua.use_module(None, true, Utf8Lib).unwrap();
+    lua.use_module(None, true, SettingsLib).unwrap();
+    lua.use_module(None, true, SplitsLib).unwrap();
+    #[cfg(feature = "unity")]
+    lua.use_module(None, true, unity::UnityLib).unwrap();
+    #[cfg(feature = "unreal")]
+    lua.use_module(None, true, unreal::UnrealLib).unwrap();
+    #[cfg(feature = "emulator")]
+    lua.use_module(None, true, emulator::EmulatorLib).unwrap();
+    // Needs `table.unpack`.
+    register_pairs_batch(lua);
+    register_buffer(lua);
+
+    lua.global()
+        .set_str_key("process", traced!("process", process as async));
+    lua.global()
+        .set_str_key("readAddress", traced!("readAddress", read_address));
+    lua.global().set_str_key(
+        "readPointerPath",
+        traced!("readPointerPath", read_pointer_path),
+    );
+    lua.global()
+        .set_str_key("getPID", traced!("getPID", get_pid));
+    lua.global()
+        .set_str_key("is64Bit", traced!("is64Bit", is_64_bit));
+    lua.global().set_str_key("print", traced!("print", print));
+    lua.global()
+        .set_str_key("sig_scan", traced!("sig_scan", sig_scan as async));
+    lua.global().set_str_key(
+        "detectVersion",
+        traced!("detectVersion", detect_version as async),
+    );
+    lua.global().set_str_key(
+        "registerScan",
+        traced!("registerScan", register_scan as async),
+    );
+    lua.global()
+        .set_str_key("scanned", traced!("scanned", scanned));
+    lua.global()
+        .set_str_key("findPattern", traced!("findPattern", find_pattern));
+    lua.global().set_str_key(
+        "findPatternAll",
+        traced!("findPatternAll", find_pattern_all),
+    );
+    lua.global().set_str_key(
+        "getBaseAddress",
+        traced!("getBaseAddress", get_base_address),
+    );
+    lua.global().set_str_key(
+        "tryGetBaseAddress",
+        traced!("tryGetBaseAddress", try_get_base_address),
+    );
+    lua.global()
+        .set_str_key("sizeOf", traced!("sizeOf", size_of));
+    lua.global().set_str_key(
+        "setPointerSize",
+        traced!("setPointerSize", set_pointer_size),
+    );
+    lua.global()
+        .set_str_key("snapshot", traced!("snapshot", snapshot));
+    lua.global()
+        .set_str_key("createBuffer", traced!("createBuffer", create_buffer));
+    lua.global().set_str_key(
+        "readIntoBuffer",
+        traced!("readIntoBuffer", read_into_buffer),
+    );
+    lua.global()
+        .set_str_key("formatAddress", traced!("formatAddress", format_address));
+    lua.global()
+        .set_str_key("splitOnce", traced!("splitOnce", split_once));
+    lua.global().set_str_key(
+        "resetSplitOnce",
+        traced!("resetSplitOnce", reset_split_once),
+    );
+    lua.global()
+        .set_str_key("dumpTrace", traced!("dumpTrace", record::dump_trace));
+    lua.global()
+        .set_str_key("getPerfStats", traced!("getPerfStats", get_perf_stats));
+    lua.global().set_str_key(
+        "createFrameCounter",
+        traced!("createFrameCounter", create_frame_counter),
+    );
+    lua.global()
+        .set_str_key("createWatcher", traced!("createWatcher", create_watcher));
+    lua.global()
+        .set_str_key("autosplit", traced!("autosplit", autosplit));
+    lua.global()
+        .set_str_key("getModuleSize", traced!("getModuleSize", get_module_size));
+    lua.global().set_str_key(
+        "tryGetModuleSize",
+        traced!("tryGetModuleSize", try_get_module_size),
+    );
+    lua.global()
+        .set_str_key("getMaps", traced!("getMaps", get_maps));
+    lua.global()
+        .set_str_key("mapRanges", traced!("mapRanges", map_ranges));
+    lua.global()
+        .set_str_key("mapsReady", traced!("mapsReady", maps_ready));
+    lua.global()
+        .set_str_key("getMemoryInfo", traced!("getMemoryInfo", get_memory_info));
+    lua.global()
+        .set_str_key("print_tbl", traced!("print_tbl", print_tbl));
+    lua.global().set_str_key(
+        "shallow_copy_tbl",
+        traced!("shallow_copy_tbl", shallow_copy_tbl),
+    );
+    lua.global()
+        .set_str_key("fastpairs", traced!("fastpairs", fastpairs));
+
+    lua.global()
+        .set_str_key("setVariable", traced!("setVariable", set_variable));
+    lua.global().set_str_key(
+        "setVariableThrottled",
+        traced!("setVariableThrottled", set_variable_throttled),
+    );
+    lua.global().set_str_key(
+        "setVariableWithTTL",
+        traced!("setVariableWithTTL", set_variable_with_ttl),
+    );
+    lua.global()
+        .set_str_key("clearVariable", traced!("clearVariable", clear_variable));
+    lua.global()
+        .set_str_key("formatTime", traced!("formatTime", format_time));
+    lua.global().set_str_key(
+        "setTimeVariable",
+        traced!("setTimeVariable", set_time_variable),
+    );
+    lua.global()
+        .set_str_key("getTimerState", traced!("getTimerState", get_timer_state));
+    lua.global().set_str_key(
+        "isGameTimePaused",
+        traced!("isGameTimePaused", is_game_time_paused),
+    );
+    lua.global()
+        .set_str_key("pauseGameTime", traced!("pauseGameTime", pause_game_time));
+    lua.global().set_str_key(
+        "resumeGameTime",
+        traced!("resumeGameTime", resume_game_time),
+    );
+    lua.global().set_str_key(
+        "setStartCooldown",
+        traced!("setStartCooldown", set_start_cooldown),
+    );
+    lua.global().set_str_key(
+        "setSplitCooldown",
+        traced!("setSplitCooldown", set_split_cooldown),
+    );
+    lua.global().set_str_key(
+        "setLogTimestamps",
+        traced!("setLogTimestamps", log::set_log_timestamps),
+    );
+    lua.global()
+        .set_str_key("hasApi", traced!("hasApi", has_api));
+    lua.global()
+        .set_str_key("runtimeUses", traced!("runtimeUses", runtime_uses));
+    lua.global().set_str_key("getOS", traced!("getOS", get_os));
+    lua.global()
+        .set_str_key("getArch", traced!("getArch", get_arch));
+    lua.global()
+        .set_str_key("memStats", traced!("memStats", mem_stats));
+    lua.global().set_str_key(
+        "rebindCallbacks",
+        traced!("rebindCallbacks", rebind_callbacks),
+    );
+    lua.global()
+        .set_str_key("requestBurst", traced!("requestBurst", request_burst));
+    lua.global().set_str_key(
+        "waitForModule",
+        traced!("waitForModule", wait_for_module as async),
+    );
+    lua.global().set_str_key(
+        "getSymbolAddress",
+        traced!("getSymbolAddress", get_symbol_address),
+    );
+    lua.global()
+        .set_str_key("getSymbols", traced!("getSymbols", get_symbols));
+    #[cfg(feature = "emulator")]
+    lua.global().set_str_key(
+        "emuReadAddress",
+        traced!("emuReadAddress", emulator::emu_read_address),
+    );
+
+    lua.global().set_str_key(
+        "LASR_VERSION",
+        Value::Str(lua.create_str(env!("CARGO_PKG_VERSION"))),
+    );
+    lua.global()
+        .set_str_key("LASR_API_LEVEL", Value::Int(api_level!()));
+
+    #[cfg(feature = "custom-api")]
+    custom_api::register(lua);
+}
+
+/// Calls `onTickStart` or `onTickEnd`, if the script defines it, timing it
+/// for `getPerfStats`.
+async fn call_tick_hook(
+    lua: &Lua<State>,
+    td: &Ref<'_, Thread<State>>,
+    driver: &Ref<'_, LuaFn<State>>,
+    hook: Option<&Callback<'_>>,
+) {
+    let Some(hook) = hook else {
+        return;
+    };
+    let started = Instant::now();
+    call_maybe(td, driver, Some(hook)).await;
+    lua.associated_data()
+        .read_stats
+        .count_tick_hook(started.elapsed());
+}
+
+/// Calls `onTickEnd`, the last thing in every tick.
+async fn end_tick_hooks(
+    lua: &Lua<State>,
+    td: &Ref<'_, Thread<State>>,
+    driver: &Ref<'_, LuaFn<State>>,
+    on_tick_end: Option<&Callback<'_>>,
+) {
+    call_tick_hook(lua, td, driver, on_tick_end).await;
+    lua.associated_data().read_stats.end_tick_hooks();
+}
+
+/// Whether a read in the current tick noticed that the game closed.
+fn process_died(lua: &Lua<State>) -> bool {
+    lua.associated_data().process_died.get()
+}
+
+/// Resets the timer along with everything the runtime tracks for the run.
+fn reset_timer(state: &State) {
+    host::get().reset();
+    state.split_once_fired.borrow_mut().clear();
+    state
+        .timer_policy
+        .borrow_mut()
+        .started_or_reset(Instant::now());
+    variables::flush(state, true);
+    state.game_time_paused.set(false);
+}
+
+/// Calls `onAttach` with what's known about the newly attached process. An
+/// error is only logged, the script keeps running without whatever it failed
+/// to set up.
+async fn on_attach(lua: &Lua<State>, td: &Ref<'_, Thread<State>>, func: Option<&Callback<'_>>) {
+    let Some(func) = func else {
+        return;
+    };
+
+    let state = lua.associated_data();
+    let name = match &*state.process_name.borrow() {
+        Some(name) => Value::Str(lua.create_str(name.as_str())),
+        None => Value::Nil,
+    };
+    let info = lua.create_table();
+    process_info(state, &info, name);
+
+    budget::start(Running::Callback(func.name));
+    let result: Result<Value<State>, _> = td.async_call(&func.func, info).await;
+    if let Err(err) = result {
+        log::warn(&format!(
+            "[onAttach] Failed, continuing without it: {}",
+            error_message(&*err)
+        ));
+    }
+}
+
+/// Prints what's running, so a log tells which build of which auto splitter
+/// was loaded. The hash matches the `script-hash` the compiler records.
+fn print_banner(lua: &Lua<State>, script: &str) {
+    let mut banner = format!(
+        "[lasr] Runtime {} (API level {}), script of {} bytes with hash {:016x}",
+        env!("CARGO_PKG_VERSION"),
+        api_level!(),
+        script.len(),
+        fnv1a(script.as_bytes()),
+    );
+    for (global, prefix) in [("SCRIPT_NAME", ": "), ("SCRIPT_VERSION", " ")] {
+        match lua.global().get_str_key(global) {
+            Value::Str(value) => {
+                if let Some(value) = value.as_utf8() {
+                    banner.push_str(prefix);
+                    banner.push_str(value);
+                }
+            }
+            Value::Int(value) => banner.push_str(&format!("{prefix}{value}")),
+            Value::Float(value) => banner.push_str(&format!("{prefix}{}", value.0)),
+            _ => {}
+        }
+    }
+    log::print_message(&banner);
+}
+
+fn apply_refresh_rate(lua: &Lua<State>) {
+    match lua.global().get_str_key("refreshRate") {
+        Value::Int(refresh_rate) => set_tick_rate(lua, "refreshRate", refresh_rate as _),
+        Value::Float(refresh_rate) => set_tick_rate(lua, "refreshRate", refresh_rate.0),
+        _ => {}
+    }
+}
+
+/// Sets the host's tick rate, unless the runtime doesn't use that.
+fn set_tick_rate(lua: &Lua<State>, setting: &str, ticks_per_second: f64) {
+    if lua
+        .associated_data()
+        .host_features
+        .check(Capability::TickRate, setting)
+    {
+        host::get().set_tick_rate(ticks_per_second);
+    }
+}
+
+/// The tick rate the host uses unless told otherwise.
+const DEFAULT_TICK_RATE: f64 = 120.0;
+
+/// Drops to `idleTickRate` while no run is going, as `start` didn't start
+/// one this tick, and goes back to `refreshRate` once one starts.
+fn apply_idle_tick_rate(lua: &Lua<State>) {
+    let Some(idle_tick_rate) = lua.associated_data().idle_tick_rate.get() else {
+        return;
+    };
+
+    let idle = matches!(host::get().timer_state(), TimerState::NotRunning);
+    if lua.associated_data().idling.replace(idle) == idle {
+        return;
+    }
+
+    if idle {
+        set_tick_rate(lua, "idleTickRate", idle_tick_rate);
+        log::print_message(&format!(
+            "[idleTickRate] No run is going, ticking {idle_tick_rate} times per second"
+        ));
+    } else {
+        match lua.global().get_str_key("refreshRate") {
+            Value::Int(_) | Value::Float(_) => apply_refresh_rate(lua),
+            _ => set_tick_rate(lua, "idleTickRate", DEFAULT_TICK_RATE),
         }
+        log::print_message("[idleTickRate] A run started, ticking at the refresh rate again");
     }
 }
 
@@ -157,16 +776,91 @@ async fn startup(lua: &Pin<Rc<Lua<State>>>, td: &Ref<'_, Thread<State>>) -> bool
     if let Value::LuaFn(func) = startup_fn {
         () = td.async_call(&func, ()).await.unwrap();
 
-        match lua.global().get_str_key("refreshRate") {
-            Value::Int(refresh_rate) => asr::set_tick_rate(refresh_rate as _),
-            Value::Float(refresh_rate) => asr::set_tick_rate(refresh_rate.0),
-            _ => {}
-        }
+        apply_refresh_rate(lua);
 
         if let Value::True = lua.global().get_str_key("useGameTime") {
             use_game_time = true;
         }
 
+        if let Value::Int(len) = lua.global().get_str_key("maxMessageLength") {
+            lua.associated_data()
+                .max_message_len
+                .set(len.max(0) as usize);
+        }
+
+        if let Value::Int(ticks) = lua.global().get_str_key("recordTicks")
+            && ticks > 0
+        {
+            let raw = matches!(lua.global().get_str_key("recordRawReads"), Value::True);
+            record::enable(ticks as usize, raw);
+        }
+
+        budget::set_limit(match lua.global().get_str_key("executionBudget") {
+            Value::Int(limit) => limit.max(0) as u64,
+            Value::Float(limit) => limit.0.max(0.0) as u64,
+            _ => budget::DEFAULT_LIMIT,
+        });
+
+        if let Value::Int(budget) = lua.global().get_str_key("readBudget") {
+            lua.associated_data()
+                .read_stats
+                .budget
+                .set(budget.clamp(0, u32::MAX as i64) as u32);
+        }
+
+        if let Value::Str(unit) = lua.global().get_str_key("gameTimeUnit") {
+            let unit = match unit.as_utf8() {
+                Some("ms") => GameTimeUnit::Milliseconds,
+                Some("us") => GameTimeUnit::Microseconds,
+                Some("s") => GameTimeUnit::Seconds,
+                _ => {
+                    log::warn("[startup] gameTimeUnit must be 'ms', 'us' or 's', using 'ms'");
+                    GameTimeUnit::Milliseconds
+                }
+            };
+            lua.associated_data().game_time_unit.set(unit);
+        }
+
+        if let Value::True = lua.global().get_str_key("gameTimeWhenEnded") {
+            lua.associated_data()
+                .timer_policy
+                .borrow_mut()
+                .game_time_when_ended = true;
+        }
+
+        if let Value::True = lua.global().get_str_key("isLoadingWhenEnded") {
+            lua.associated_data()
+                .timer_policy
+                .borrow_mut()
+                .is_loading_when_ended = true;
+        }
+
+        if let Value::False = lua.global().get_str_key("resetWhenEnded") {
+            lua.associated_data()
+                .timer_policy
+                .borrow_mut()
+                .reset_when_ended = false;
+        }
+
+        if let Value::True = lua.global().get_str_key("resetOnGameExit") {
+            lua.associated_data().reset_on_game_exit.set(true);
+        }
+
+        if let Value::True = lua.global().get_str_key("resetOnGameStart") {
+            lua.associated_data().reset_on_game_start.set(true);
+        }
+
+        match lua.global().get_str_key("idleTickRate") {
+            Value::Int(rate) if rate > 0 => {
+                lua.associated_data().idle_tick_rate.set(Some(rate as f64));
+            }
+            Value::Float(Float(rate)) if rate > 0.0 => {
+                lua.associated_data().idle_tick_rate.set(Some(rate));
+            }
+            Value::Nil => {}
+            _ => log::warn("[startup] idleTickRate must be a positive number, ignoring it"),
+        }
+
         match lua.global().get_str_key("mapsCacheCycles") {
             Value::Int(cycles) => {
                 let cycles = cycles.max(0);
@@ -184,3 +878,257 @@ async fn startup(lua: &Pin<Rc<Lua<State>>>, td: &Ref<'_, Thread<State>>) -> bool
 
     use_game_time
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        rc::Rc,
+        task::{Context, Waker},
+    };
+
+    use asr::time::Duration;
+
+    use super::run;
+    use crate::{
+        host::{
+            self,
+            mock::{Event, MockHost},
+        },
+        process_memory::mock::MockProcess,
+    };
+
+    /// A game whose level, loading flag and in-game time sit right after a
+    /// `STATS` marker, which the script finds through `sig_scan`.
+    fn game() -> Rc<MockProcess> {
+        let mut module = vec![0; 0x1000];
+        module[0x400..0x405].copy_from_slice(b"STATS");
+        Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x1000)
+                .map(0x10000, module),
+        )
+    }
+
+    /// Shows the game `level`, `loading` or not, at `igt` milliseconds.
+    fn play(game: &MockProcess, level: i32, loading: bool, igt: i32) {
+        game.write(0x10405, &level.to_le_bytes());
+        game.write(0x10409, &[loading as u8]);
+        game.write(0x1040D, &igt.to_le_bytes());
+    }
+
+    const SCRIPT: &str = r#"
+        useGameTime = true
+
+        local stats, old, current
+
+        function startup()
+            process("game.exe")
+            stats = sig_scan("53 54 41 54 53", 5)
+            print("found the stats at " .. stats)
+        end
+
+        function state()
+            old = current
+            current = {
+                level = readAddress("int", stats) or 0,
+                loading = readAddress("bool", stats + 4),
+                igt = readAddress("int", stats + 8),
+            }
+        end
+
+        function update()
+            setVariable("Level", tostring(current.level))
+            setTimeVariable("IGT", current.igt or 0)
+        end
+
+        function start()
+            return old ~= nil and old.level == 0 and current.level == 1
+        end
+
+        function split()
+            return current.level > old.level
+        end
+
+        function isLoading()
+            return current.loading
+        end
+
+        function gameTime()
+            return current.igt
+        end
+
+        function reset()
+            return current.level == 0
+        end
+    "#;
+
+    #[test]
+    fn runs_a_script_against_a_game() {
+        let host = Rc::new(MockHost::new().segments(3));
+        host::set(host.clone());
+        let game = game();
+        play(&game, 0, false, 0);
+        host.launch("game.exe", game.clone());
+
+        let mut run = pin!(run(SCRIPT));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut tick = || {
+            assert!(run.as_mut().poll(&mut cx).is_pending());
+            host.events.take()
+        };
+        let igt = |ms| Event::SetGameTime(Duration::milliseconds(ms));
+
+        // Attaching, scanning and the first tick, where `reset` can't reset
+        // as there's no run. Only `isLoading` gets through.
+        assert_eq!(tick(), [Event::ResumeGameTime]);
+        assert!(
+            host.messages
+                .borrow()
+                .iter()
+                .any(|m| m == "found the stats at 1029")
+        );
+
+        play(&game, 1, false, 100);
+        assert_eq!(
+            tick(),
+            [Event::Start, Event::ResumeGameTime, Event::ResumeGameTime]
+        );
+        play(&game, 1, true, 100);
+        assert_eq!(tick(), [igt(100), Event::PauseGameTime]);
+        play(&game, 2, false, 300);
+        assert_eq!(tick(), [igt(300), Event::Split, Event::ResumeGameTime]);
+
+        // The run goes on while the game is closed and after it started again.
+        game.close();
+        assert_eq!(tick(), []);
+        let game = self::game();
+        play(&game, 2, false, 400);
+        host.launch("game.exe", game.clone());
+        assert_eq!(tick(), [igt(400), Event::ResumeGameTime]);
+
+        play(&game, 3, false, 500);
+        assert_eq!(tick(), [igt(500), Event::Split, Event::ResumeGameTime]);
+        play(&game, 4, false, 600);
+        assert_eq!(tick(), [igt(600), Event::Split, Event::ResumeGameTime]);
+        // The last split ended the run, which still gets reset.
+        play(&game, 0, false, 0);
+        assert_eq!(tick(), [Event::Reset]);
+
+        let variables = host.variables.take();
+        let updates = |key| {
+            variables
+                .iter()
+                .filter(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(updates("Level"), ["0", "1", "1", "2", "2", "3", "4", "0"]);
+        // The time only goes to the host when it changed.
+        assert_eq!(
+            updates("IGT"),
+            [
+                "0:00.000", "0:00.100", "0:00.300", "0:00.400", "0:00.500", "0:00.600", "0:00.000"
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_the_execution_budget_from_startup() {
+        let host = Rc::new(MockHost::new());
+        host::set(host.clone());
+        host.launch("game.exe", game());
+
+        let mut run = pin!(run(r#"
+            updates, spins = 0, 0
+
+            function startup()
+                executionBudget = 1000
+                process("game.exe")
+            end
+
+            function update()
+                updates = updates + 1
+                if updates == 2 then
+                    print("spun " .. spins .. " times")
+                end
+                while updates == 1 do
+                    spins = spins + 1
+                end
+            end
+        "#));
+        let mut cx = Context::from_waker(Waker::noop());
+        for _ in 0..2 {
+            assert!(run.as_mut().poll(&mut cx).is_pending());
+        }
+
+        let messages = host.messages.take();
+        assert!(messages.iter().any(|message| message.starts_with("ERROR ")
+            && message.contains("callback 'update' exceeded execution budget")));
+        // Calling `update` took one of the 1000 checks, the loop the rest.
+        assert!(messages.iter().any(|message| message == "spun 999 times"));
+    }
+
+    #[test]
+    fn keeps_the_maps_cache_cycles_after_reattaching() {
+        let host = Rc::new(MockHost::new());
+        host::set(host.clone());
+        let first = game();
+        host.launch("game.exe", first.clone());
+
+        let mut run = pin!(run(r#"
+            function startup()
+                mapsCacheCycles = 3
+                process("game.exe")
+            end
+
+            function update()
+                getMaps()
+            end
+        "#));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut tick = || assert!(run.as_mut().poll(&mut cx).is_pending());
+
+        for _ in 0..6 {
+            tick();
+        }
+        assert_eq!(first.range_queries.get(), 2);
+
+        first.close();
+        tick();
+        let second = game();
+        host.launch("game.exe", second.clone());
+        for _ in 0..6 {
+            tick();
+        }
+        assert_eq!(second.range_queries.get(), 2);
+    }
+
+    #[test]
+    fn reports_where_on_attach_failed() {
+        let host = Rc::new(MockHost::new());
+        host::set(host.clone());
+        host.launch("game.exe", game());
+
+        let mut run = pin!(run(r#"
+            function startup()
+                process("game.exe")
+            end
+
+            function onAttach(info)
+                error("no " .. info.name)
+            end
+        "#));
+        let mut cx = Context::from_waker(Waker::noop());
+        for _ in 0..2 {
+            assert!(run.as_mut().poll(&mut cx).is_pending());
+        }
+
+        let messages = host.messages.borrow();
+        assert!(
+            messages.iter().any(|message| message
+                == "WARNING [onAttach] Failed, continuing without it: script.lua:7: no game.exe"),
+            "{messages:?}"
+        );
+    }
+}
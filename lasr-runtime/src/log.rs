@@ -0,0 +1,133 @@
+//! Every message the runtime prints goes through here, so they can all be
+//! prefixed with a timestamp through `setLogTimestamps` and marked with their
+//! severity.
+
+use std::{cell::Cell, fmt::Write, time::Instant};
+
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::{
+    host,
+    state::{Result, State},
+};
+
+/// How important a message is. asr doesn't let hosts tell messages apart, so
+/// warnings get a prefix they can look for instead.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Output meant for debugging the runtime, like the API call traces.
+    Debug,
+    /// Regular output, like the script's own `print` calls.
+    Info,
+    /// Problems the user needs to know about, like a module that can't be
+    /// found or a misconfigured script.
+    Warning,
+    /// Something in the script broke, like a task that failed.
+    Error,
+}
+
+impl Severity {
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Debug | Self::Info => "",
+            Self::Warning => "WARNING ",
+            Self::Error => "ERROR ",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Timestamps {
+    Off,
+    /// The time since the runtime started.
+    Elapsed,
+    /// The game time last set on the timer, falling back to the time since
+    /// the runtime started when there is none.
+    GameTime,
+}
+
+thread_local! {
+    static TIMESTAMPS: Cell<Timestamps> = const { Cell::new(Timestamps::Off) };
+    static STARTED: Instant = Instant::now();
+    static GAME_TIME: Cell<Option<asr::time::Duration>> = const { Cell::new(None) };
+}
+
+/// Starts the clock the timestamps are relative to.
+pub fn init() {
+    STARTED.with(|_| {});
+}
+
+/// Remembers the game time the runtime set, for `setLogTimestamps("gameTime")`.
+/// `None` once there's no run anymore.
+pub fn set_game_time(game_time: Option<asr::time::Duration>) {
+    GAME_TIME.set(game_time);
+}
+
+pub fn print_message(message: &str) {
+    print(Severity::Info, message);
+}
+
+pub fn warn(message: &str) {
+    print(Severity::Warning, message);
+}
+
+pub fn error(message: &str) {
+    print(Severity::Error, message);
+}
+
+pub fn debug(message: &str) {
+    print(Severity::Debug, message);
+}
+
+pub fn print(severity: Severity, message: &str) {
+    let prefix = severity.prefix();
+    let millis = match TIMESTAMPS.get() {
+        Timestamps::Off if prefix.is_empty() => {
+            host::get().print_message(message);
+            return;
+        }
+        Timestamps::Off => {
+            host::get().print_message(&format!("{prefix}{message}"));
+            return;
+        }
+        Timestamps::GameTime if let Some(game_time) = GAME_TIME.get() => {
+            game_time.whole_milliseconds().max(0) as u128
+        }
+        _ => STARTED.with(|started| started.elapsed().as_millis()),
+    };
+
+    let (secs, millis) = (millis / 1000, millis % 1000);
+    let (mins, secs) = (secs / 60, secs % 60);
+    let (hours, mins) = (mins / 60, mins % 60);
+
+    let mut line = String::with_capacity(message.len() + 24);
+    let _ = write!(
+        line,
+        "[{hours:02}:{mins:02}:{secs:02}.{millis:03}] {prefix}{message}"
+    );
+    host::get().print_message(&line);
+}
+
+/// `logError(message)` for the Lua side of the runtime.
+pub fn log_error(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let message = cx.arg(1).to_str()?;
+    error(&String::from_utf8_lossy(message.as_bytes()));
+    Ok(cx.into())
+}
+
+pub fn set_log_timestamps(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let timestamps = match cx.arg(1).get() {
+        Some(Value::True) => Timestamps::Elapsed,
+        Some(Value::False | Value::Nil) | None => Timestamps::Off,
+        Some(Value::Str(mode)) if mode.as_bytes() == b"gameTime" => Timestamps::GameTime,
+        _ => {
+            return Err(cx.arg(1).error("expected true, false or 'gameTime'"));
+        }
+    };
+
+    TIMESTAMPS.set(timestamps);
+    Ok(cx.into())
+}
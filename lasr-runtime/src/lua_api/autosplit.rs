@@ -0,0 +1,103 @@
+use tsuki::{
+    Table, Value,
+    context::{Args, Context, Ret},
+};
+
+use super::watcher::{Condition, Expected, check_watcher, watcher_id};
+use crate::{
+    splits,
+    state::{Result, State},
+};
+
+/// A split condition registered through `autosplit`.
+pub struct AutoSplit {
+    watcher: usize,
+    condition: Condition,
+    split: String,
+}
+
+pub fn autosplit(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let list = cx.arg(1).get_table()?;
+
+    let mut autosplits = Vec::new();
+    for i in 1.. {
+        match list.get(Value::Int(i)) {
+            Value::Table(entry) => autosplits.push(
+                parse_entry(cx.associated_data(), &entry)
+                    .map_err(|msg| format!("autosplit condition {i}: {msg}"))?,
+            ),
+            Value::Nil => break,
+            _ => {
+                return Err(cx.arg(1).error("every condition needs to be a table"));
+            }
+        }
+    }
+
+    cx.associated_data()
+        .autosplits
+        .borrow_mut()
+        .extend(autosplits);
+
+    Ok(cx.into())
+}
+
+fn parse_entry(state: &State, entry: &Table<State>) -> Result<AutoSplit, String> {
+    let watcher = watcher_id(state, &entry.get_str_key("watch"))
+        .ok_or("watch needs to be a watcher returned by createWatcher")?;
+
+    let Value::Str(when) = entry.get_str_key("when") else {
+        return Err("when needs to be a string".to_owned());
+    };
+    let value = || {
+        Expected::from_value(&entry.get_str_key("value"))
+            .ok_or("value needs to be a number, boolean or string")
+    };
+    let condition = match when.as_utf8() {
+        Some("changed") => Condition::Changed,
+        Some("increased") => Condition::Increased,
+        Some("decreased") => Condition::Decreased,
+        Some("changedTo") => Condition::ChangedTo(value()?),
+        Some("changedFrom") => Condition::ChangedFrom(value()?),
+        _ => {
+            return Err(
+                "when needs to be changed, increased, decreased, changedTo or changedFrom"
+                    .to_owned(),
+            );
+        }
+    };
+
+    let Value::Str(split) = entry.get_str_key("split") else {
+        return Err("split needs to be a string".to_owned());
+    };
+    let split = split
+        .as_utf8()
+        .ok_or("split is not valid UTF-8")?
+        .to_owned();
+
+    Ok(AutoSplit {
+        watcher,
+        condition,
+        split,
+    })
+}
+
+/// Whether one of the `autosplit` conditions calls for a split this tick.
+/// Called after the watchers were updated, before the `split` callback, which
+/// is only called if this returns `false`.
+///
+/// A split defined through `splits.define` only happens if its setting is
+/// enabled, any other id works like `splitOnce`. Either way, each split
+/// happens at most once per run.
+pub fn check_autosplits(state: &State) -> bool {
+    state.autosplits.borrow().iter().any(|autosplit| {
+        check_watcher(state, autosplit.watcher, &autosplit.condition)
+            && if state.split_ids.borrow().contains(&autosplit.split) {
+                splits::fire(state, &autosplit.split)
+            } else {
+                state
+                    .split_once_fired
+                    .borrow_mut()
+                    .insert(autosplit.split.clone())
+            }
+    })
+}
@@ -0,0 +1,295 @@
+use std::cell::RefCell;
+
+use tsuki::{
+    Lua, UserData, Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use super::{
+    process::note_no_process,
+    read_address::{report_read_failure, resolve_address},
+    scans::record_read,
+    value_type::MAX_READ_LEN,
+};
+use crate::state::{Result, State};
+
+/// The bytes of a buffer returned by `createBuffer`. The buffer owns them, so
+/// they're freed along with it once the script no longer uses it.
+pub struct Buffer(RefCell<Vec<u8>>);
+
+/// Gives buffers their methods.
+pub fn register_buffer(lua: &Lua<State>) {
+    let methods = lua.create_table();
+    methods.set_str_key("get", fp!(get));
+    methods.set_str_key("slice", fp!(slice));
+    methods.set_str_key("len", fp!(len));
+    methods.set_str_key("resize", fp!(resize));
+
+    let metatable = lua.create_table();
+    metatable.set_str_key("__index", &*methods);
+    lua.register_metatable::<Buffer>(&metatable);
+}
+
+/// `createBuffer(length)` returns a buffer of `length` bytes that
+/// `readIntoBuffer` reads into. Its bytes live on the Rust side, so reading a
+/// large region every tick doesn't create a new Lua string every tick.
+pub fn create_buffer(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let length = length_arg(&cx, 1)?;
+    let buffer = cx.create_ud(Buffer(RefCell::new(vec![0; length])));
+
+    cx.push(&*buffer)?;
+    Ok(cx.into())
+}
+
+/// The buffer passed as argument `n`.
+fn buffer_arg<'a>(cx: &'a Context<State, Args>, n: usize) -> Result<&'a Buffer> {
+    cx.arg(n)
+        .get_ud::<Buffer>()
+        .map(UserData::value)
+        .map_err(|_| {
+            cx.arg(n)
+                .error("expected a buffer returned by createBuffer")
+        })
+}
+
+/// The length passed as argument `n`, which is capped, so a typo can't make
+/// the runtime run out of memory.
+fn length_arg(cx: &Context<State, Args>, n: usize) -> Result<usize> {
+    let arg = cx.arg(n);
+    match arg.to_int()? {
+        length if length >= 1 && length as u64 <= MAX_READ_LEN as u64 => Ok(length as usize),
+        _ => Err(arg.error(format!("length must be between 1 and {MAX_READ_LEN}"))),
+    }
+}
+
+/// `readIntoBuffer(buf, module_or_addr, offsets..., [{len = n}])` reads the
+/// first `n` bytes of the buffer, all of them by default, from the process
+/// and returns how many bytes were read, or `nil` if the read failed. The rest
+/// of the buffer is left as it was.
+pub fn read_into_buffer(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    if cx.args() < 2 {
+        return Err("readIntoBuffer expects a buffer and an address".into());
+    }
+
+    let buffer = buffer_arg(&cx, 1)?;
+    let capacity = buffer.0.borrow().len();
+
+    let mut last = cx.args();
+    let mut length = capacity;
+    if last > 2
+        && let Some(options) = cx.arg(last).as_table()
+    {
+        match options.get_str_key("len") {
+            Value::Int(len) if len >= 1 && len as usize <= capacity => length = len as usize,
+            Value::Nil => {}
+            _ => {
+                return Err(cx
+                    .arg(last)
+                    .error("len must be between 1 and the buffer's length"));
+            }
+        }
+        last -= 1;
+    }
+
+    let mut scan_name = None;
+
+    let read = 'read: {
+        let process = &*cx.associated_data().process.borrow();
+        let Some(process) = process.as_ref() else {
+            note_no_process(cx.associated_data(), "readIntoBuffer");
+            break 'read false;
+        };
+
+        if cx.associated_data().process_died.get() {
+            break 'read false;
+        }
+
+        let (address, scan) = resolve_address(&cx, &**process, "readIntoBuffer", 2, last)?;
+        scan_name = scan;
+        let Some(address) = address else {
+            break 'read false;
+        };
+
+        cx.associated_data().read_stats.count_read();
+        if process
+            .read_into_buf(address, &mut buffer.0.borrow_mut()[..length])
+            .is_err()
+        {
+            report_read_failure(cx.associated_data(), &**process, "readIntoBuffer", address);
+            break 'read false;
+        }
+
+        true
+    };
+
+    if let Some(name) = scan_name {
+        record_read(cx.associated_data(), &name, read);
+    }
+
+    cx.push(if read {
+        Value::Int(length as i64)
+    } else {
+        Value::Nil
+    })?;
+    Ok(cx.into())
+}
+
+/// `buf:get(i)`, the byte at the 1-based index `i`, or `nil` outside of the
+/// buffer.
+fn get(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let buffer = buffer_arg(&cx, 1)?;
+    let index = cx.arg(2).to_int()?;
+
+    let byte = usize::try_from(index)
+        .ok()
+        .and_then(|index| index.checked_sub(1))
+        .and_then(|index| buffer.0.borrow().get(index).copied());
+
+    cx.push(byte.map_or(Value::Nil, |byte| Value::Int(byte as i64)))?;
+    Ok(cx.into())
+}
+
+/// `buf:slice(from, len)`, a string of `len` bytes starting at the 1-based
+/// index `from`, or `nil` if any of them is outside of the buffer. Only this
+/// copies the bytes into Lua.
+fn slice(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let buffer = buffer_arg(&cx, 1)?;
+    let from = cx.arg(2).to_int()?;
+    let len = cx.arg(3).to_int()?;
+
+    let bytes = {
+        let bytes = buffer.0.borrow();
+        usize::try_from(from)
+            .ok()
+            .and_then(|from| from.checked_sub(1))
+            .zip(usize::try_from(len).ok())
+            .and_then(|(from, len)| bytes.get(from..from.checked_add(len)?))
+            .map(<[u8]>::to_vec)
+    };
+
+    cx.push(match bytes {
+        Some(bytes) => Value::Str(cx.create_bytes(bytes)),
+        None => Value::Nil,
+    })?;
+    Ok(cx.into())
+}
+
+/// `buf:len()`, the buffer's length.
+fn len(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let len = buffer_arg(&cx, 1)?.0.borrow().len();
+
+    cx.push(Value::Int(len as i64))?;
+    Ok(cx.into())
+}
+
+/// `buf:resize(length)` changes the buffer's length. The bytes that fit are
+/// kept, new ones are zero.
+fn resize(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let buffer = buffer_arg(&cx, 1)?;
+    let length = length_arg(&cx, 2)?;
+
+    buffer.0.borrow_mut().resize(length, 0);
+    Ok(cx.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
+    };
+
+    fn game() -> Rc<MockProcess> {
+        Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x10000, (0..=255).collect::<Vec<u8>>()),
+        )
+    }
+
+    #[test]
+    fn reads_into_buffers() {
+        let lua = lua();
+        attach(&lua, &game(), "game.exe");
+        run(
+            &lua,
+            r#"
+            local buf = createBuffer(8)
+            assert(buf:len() == 8 and buf:get(1) == 0 and buf:get(8) == 0)
+            assert(readIntoBuffer(buf, 0x10) == 8)
+            assert(buf:get(1) == 0x10 and buf:get(8) == 0x17)
+
+            -- Only the first bytes, the rest stays as it was.
+            assert(readIntoBuffer(buf, 0x40, { len = 2 }) == 2)
+            assert(buf:get(1) == 0x40 and buf:get(2) == 0x41 and buf:get(3) == 0x12)
+            assert(not pcall(readIntoBuffer, buf, 0x40, { len = 9 }))
+            assert(not pcall(readIntoBuffer, buf, 0x40, { len = 0 }))
+
+            -- Running past the end of the memory fails as a whole.
+            assert(readIntoBuffer(buf, 0xFC) == nil)
+            assert(buf:get(1) == 0x40)
+            assert(readIntoBuffer(buf, 0xFC, { len = 4 }) == 4)
+            assert(buf:get(4) == 0xFF and buf:get(5) == 0x14)
+
+            assert(not pcall(readIntoBuffer, {}, 0x10))
+            assert(not pcall(readIntoBuffer, { id = 100 }, 0x10))
+            assert(not pcall(buf.get, { id = 100 }, 1))
+            -- Buffers can't be changed to point somewhere else.
+            assert(not pcall(function() buf.id = 1 end))
+            "#,
+        );
+    }
+
+    #[test]
+    fn keeps_slices_in_bounds() {
+        let lua = lua();
+        attach(&lua, &game(), "game.exe");
+        run(
+            &lua,
+            r#"
+            local buf = createBuffer(4)
+            readIntoBuffer(buf, 0x61)
+            assert(buf:slice(1, 4) == "abcd")
+            assert(buf:slice(2, 2) == "bc")
+            assert(buf:slice(4, 1) == "d" and buf:slice(5, 0) == "")
+            assert(buf:slice(4, 2) == nil and buf:slice(5, 1) == nil)
+            assert(buf:slice(0, 1) == nil and buf:slice(-1, 1) == nil)
+            assert(buf:slice(1, -1) == nil)
+            assert(buf:slice(2, math.maxinteger) == nil)
+            assert(buf:get(0) == nil and buf:get(5) == nil and buf:get(-1) == nil)
+            "#,
+        );
+    }
+
+    #[test]
+    fn resizes_buffers() {
+        let lua = lua();
+        attach(&lua, &game(), "game.exe");
+        run(
+            &lua,
+            r#"
+            local buf = createBuffer(4)
+            readIntoBuffer(buf, 0x61)
+            buf:resize(6)
+            assert(buf:len() == 6 and buf:slice(1, 6) == "abcd\0\0")
+            assert(readIntoBuffer(buf, 0x61) == 6 and buf:slice(5, 2) == "ef")
+
+            buf:resize(2)
+            assert(buf:len() == 2 and buf:slice(1, 2) == "ab" and buf:get(3) == nil)
+            assert(readIntoBuffer(buf, 0x70) == 2)
+            assert(not pcall(readIntoBuffer, buf, 0x70, { len = 3 }))
+
+            assert(not pcall(buf.resize, buf, 0))
+            assert(not pcall(createBuffer, 0))
+
+            -- A typo in a length can't take all of the memory.
+            assert(not pcall(createBuffer, 0x7FFFFFFF))
+            assert(not pcall(buf.resize, buf, 0x7FFFFFFF))
+            assert(buf:len() == 2)
+            "#,
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use tsuki::context::{Args, Context, Ret};
+
+use crate::state::{Result, State};
+
+pub fn set_start_cooldown(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let window = Duration::from_millis(cx.arg(1).to_int()?.max(0) as u64);
+    cx.associated_data()
+        .timer_policy
+        .borrow_mut()
+        .start_cooldown = window;
+    Ok(cx.into())
+}
+
+pub fn set_split_cooldown(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let window = Duration::from_millis(cx.arg(1).to_int()?.max(0) as u64);
+    cx.associated_data()
+        .timer_policy
+        .borrow_mut()
+        .split_cooldown = window;
+    Ok(cx.into())
+}
@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use super::{module_name::resolve_module, process::note_no_process};
+use crate::{
+    sigscan::{parse_signature, scan_signatures},
+    state::{Result, State},
+    utils::{PairsBatch, TablePairs},
+};
+
+pub async fn detect_version<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, State, Ret>> {
+    let arg = cx.arg(1);
+    let table = arg.get_table()?;
+
+    // Sorted, so the cache key doesn't depend on the table's order.
+    let mut patterns = Vec::new();
+    {
+        let td = cx.create_thread();
+        let batch = cx.registry::<PairsBatch>().unwrap();
+        for pair in TablePairs::new(&td, &batch, table) {
+            let (Value::Str(key), Value::Str(pattern)) = pair? else {
+                return Err(arg.error("versions must map names to signatures"));
+            };
+            let key = key
+                .as_utf8()
+                .ok_or_else(|| arg.error("version name is not valid UTF-8"))?;
+            let pattern = pattern
+                .as_utf8()
+                .ok_or_else(|| arg.error("signature is not valid UTF-8"))?;
+            patterns.push((key.to_owned(), pattern.to_owned()));
+        }
+    }
+    patterns.sort();
+
+    let module_arg = cx.arg(2);
+    let module = module_arg
+        .to_nilable_str(false)?
+        .map(|module| {
+            module
+                .as_utf8()
+                .map(str::to_owned)
+                .ok_or_else(|| module_arg.error("module name is not valid UTF-8"))
+        })
+        .transpose()?;
+
+    let mut cache_key = module.clone().unwrap_or_default();
+    for (key, pattern) in &patterns {
+        let _ = write!(cache_key, "\0{key}\0{pattern}");
+    }
+
+    let cached = cx
+        .associated_data()
+        .detected_versions
+        .borrow()
+        .get(&cache_key)
+        .cloned();
+    let matches = match cached {
+        Some(matches) => matches,
+        None => {
+            let signatures = patterns
+                .iter()
+                .map(|(_, pattern)| parse_signature(pattern).map_err(|msg| arg.error(msg)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let found = 'scan: {
+                let state = cx.associated_data();
+                let process = state.process.borrow().clone();
+                let Some(process) = process else {
+                    note_no_process(state, "detectVersion");
+                    break 'scan None;
+                };
+
+                let range = match &module {
+                    Some(module) => {
+                        let Some((module, base)) = resolve_module(state, &*process, module) else {
+                            break 'scan None;
+                        };
+                        let Ok(size) = process.get_module_size(&module) else {
+                            break 'scan None;
+                        };
+                        Some((base, size))
+                    }
+                    None => None,
+                };
+
+                Some(scan_signatures(&*process, &state.read_stats, &signatures, range).await?)
+            };
+            let Some(found) = found else {
+                // Nothing is cached, so the next call tries again once the
+                // process or module is there.
+                cx.push(Value::Nil)?;
+                return Ok(cx.into());
+            };
+
+            // The match closest to the start of memory comes first.
+            let mut matches: Vec<_> = patterns
+                .iter()
+                .zip(found)
+                .filter_map(|((key, _), address)| Some((address?, key.clone())))
+                .collect();
+            matches.sort();
+            let matches: Vec<String> = matches.into_iter().map(|(_, key)| key).collect();
+
+            cx.associated_data()
+                .detected_versions
+                .borrow_mut()
+                .insert(cache_key, matches.clone());
+            matches
+        }
+    };
+
+    match matches.first() {
+        Some(key) => cx.push(Value::Str(cx.create_str(key.as_str())))?,
+        None => cx.push(Value::Nil)?,
+    }
+    if matches.len() > 1 {
+        let all = cx.create_table();
+        for (i, key) in matches.iter().enumerate() {
+            all.set(
+                Value::Int(i as i64 + 1),
+                Value::Str(cx.create_str(key.as_str())),
+            )?;
+        }
+        cx.push(Value::Table(all))?;
+    }
+
+    Ok(cx.into())
+}
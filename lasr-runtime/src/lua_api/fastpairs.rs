@@ -0,0 +1,103 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use crate::{
+    state::{Result, State},
+    utils::next_pair,
+};
+
+/// `fastpairs(t)`, like `pairs(t)` but without looking for a `__pairs`
+/// metamethod. It walks the table with the same native as the runtime's own
+/// table walking. That takes one call per pair just like `pairs`, as Tsuki
+/// can't iterate a table from Rust. The order is unspecified and the table
+/// must not be changed while iterating.
+pub fn fastpairs(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let table = cx.arg(1).get_table()?;
+
+    cx.push(fp!(next_pair))?;
+    cx.push(table)?;
+    cx.push(Value::Nil)?;
+    Ok(cx.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::testing::{lua, run};
+
+    #[test]
+    fn walks_every_pair() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            local t = setmetatable({ 1, 2, 3, a = 4, [true] = 5 }, {
+                __pairs = function() error("fastpairs doesn't use __pairs") end,
+            })
+            local sum, count = 0, 0
+            for k, v in fastpairs(t) do
+                assert(t[k] == v)
+                sum, count = sum + v, count + 1
+            end
+            assert(sum == 15 and count == 5)
+
+            for _ in fastpairs({}) do
+                error("an empty table has no pairs")
+            end
+            assert(not pcall(fastpairs, nil))
+            "#,
+        );
+    }
+
+    /// Not a real benchmark, but it shows when `fastpairs` falls behind
+    /// `pairs`. Wall clock timings are too noisy to fail the regular test run
+    /// on.
+    #[test]
+    #[ignore = "timing dependent, run with --ignored on an idle machine"]
+    fn keeps_up_with_pairs() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            T = {}
+            for i = 1, 10000 do
+                T["key" .. i] = i
+            end
+            "#,
+        );
+
+        let time = |iter: &str| {
+            let source = format!(
+                r#"
+                for _ = 1, 10 do
+                    local sum, count = 0, 0
+                    for _, v in {iter}(T) do
+                        sum, count = sum + v, count + 1
+                    end
+                    assert(count == 10000 and sum == 50005000)
+                end
+                "#
+            );
+            // The fastest of a few runs, to filter out noise.
+            (0..3)
+                .map(|_| {
+                    let started = Instant::now();
+                    run(&lua, &source);
+                    started.elapsed()
+                })
+                .min()
+                .unwrap_or(Duration::ZERO)
+        };
+
+        let pairs = time("pairs");
+        let fastpairs = time("fastpairs");
+        assert!(
+            fastpairs < pairs * 2,
+            "fastpairs took {fastpairs:?}, pairs {pairs:?}"
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use asr::Address;
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use super::module_name::containing_module;
+use crate::{
+    process_memory::ProcessMemory,
+    state::{Result, State},
+};
+
+/// Formats `address` relative to the known module it lies in, like
+/// `game.exe+0x4A1B2C`. Failing that, relative to the start of the cached
+/// memory map it lies in, as maps have no names, and as plain hex as a last
+/// resort.
+pub fn describe_address(
+    state: &State,
+    process: Option<&dyn ProcessMemory>,
+    address: Address,
+) -> String {
+    if let Some(process) = process
+        && let Some((module, base)) = containing_module(state, process, address)
+    {
+        return format!("{module}+0x{:X}", address.value() - base.value());
+    }
+
+    let map = state.maps_cache.borrow().as_ref().and_then(|maps| {
+        maps.iter()
+            .find(|map| (map.start..map.end).contains(&address.value()))
+            .map(|map| map.start)
+    });
+    match map {
+        Some(start) => format!("0x{start:X}+0x{:X}", address.value() - start),
+        None => format!("0x{:X}", address.value()),
+    }
+}
+
+pub fn format_address(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let address = Address::new(cx.arg(1).to_int()? as u64);
+
+    let formatted = {
+        let process = cx.associated_data().process.borrow();
+        describe_address(cx.associated_data(), process.as_deref(), address)
+    };
+
+    cx.push(Value::Str(cx.create_str(formatted)))?;
+    Ok(cx.into())
+}
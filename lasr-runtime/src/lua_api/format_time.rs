@@ -0,0 +1,165 @@
+use std::fmt::Write;
+
+use tsuki::{
+    Float, Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::{
+    host,
+    state::{Result, State},
+};
+
+#[derive(Clone, Copy)]
+enum TimeFormat {
+    /// `1:23:45.678`, leaving out the hours when they are zero.
+    Auto,
+    /// `5025678`
+    Milliseconds,
+    /// `5025.678`
+    Seconds,
+    /// `1:23:45.678`
+    HoursMinutesSecondsMillis,
+    /// `1:23:45`
+    HoursMinutesSeconds,
+}
+
+fn millis_arg(cx: &Context<State, Args>, index: usize) -> Result<i64> {
+    // Rounding to whole milliseconds first means 59999.5 turns into `1:00.000`
+    // rather than `0:60.000`.
+    Ok(match cx.arg(index).get() {
+        Some(Value::Int(millis)) => millis,
+        Some(Value::Float(Float(millis))) => millis.round() as i64,
+        _ => cx.arg(index).to_int()?,
+    })
+}
+
+fn format_millis(millis: i64, format: TimeFormat) -> String {
+    let mut out = String::new();
+    if millis < 0 {
+        out.push('-');
+    }
+    let millis = millis.unsigned_abs();
+
+    let (secs, frac) = (millis / 1000, millis % 1000);
+    let (mins, secs) = (secs / 60, secs % 60);
+    let (hours, mins) = (mins / 60, mins % 60);
+
+    let _ = match format {
+        TimeFormat::Milliseconds => write!(out, "{millis}"),
+        TimeFormat::Seconds => write!(out, "{}.{frac:03}", millis / 1000),
+        TimeFormat::Auto if hours == 0 => write!(out, "{mins}:{secs:02}.{frac:03}"),
+        TimeFormat::Auto | TimeFormat::HoursMinutesSecondsMillis => {
+            write!(out, "{hours}:{mins:02}:{secs:02}.{frac:03}")
+        }
+        TimeFormat::HoursMinutesSeconds => write!(out, "{hours}:{mins:02}:{secs:02}"),
+    };
+    out
+}
+
+pub fn format_time(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let millis = millis_arg(&cx, 1)?;
+
+    let format_arg = cx.arg(2);
+    let format = match format_arg.to_nilable_str(false)? {
+        None => TimeFormat::Auto,
+        Some(format) => match format.as_utf8() {
+            Some("ms") => TimeFormat::Milliseconds,
+            Some("s") => TimeFormat::Seconds,
+            Some("hmsms") => TimeFormat::HoursMinutesSecondsMillis,
+            Some("hms") => TimeFormat::HoursMinutesSeconds,
+            _ => {
+                return Err(
+                    format_arg.error("format needs to be one of 'ms', 's', 'hmsms' or 'hms'")
+                );
+            }
+        },
+    };
+
+    cx.push(Value::Str(cx.create_str(format_millis(millis, format))))?;
+    Ok(cx.into())
+}
+
+pub fn set_time_variable(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let key = cx.arg(1);
+    let key = key
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| key.error("key is not valid UTF-8"))?;
+    let millis = millis_arg(&cx, 2)?;
+
+    let text = format_millis(millis, TimeFormat::Auto);
+
+    // Most ticks don't change the displayed text, so skip the host call then.
+    {
+        let mut time_variables = cx.associated_data().time_variables.borrow_mut();
+        if time_variables.get(key) != Some(&text) {
+            host::get().set_variable(key, &text);
+            time_variables.insert(key.to_owned(), text);
+        }
+    }
+
+    Ok(cx.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{lua, run};
+
+    #[test]
+    fn rounds_to_whole_milliseconds() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(formatTime(59999.4) == "0:59.999")
+            assert(formatTime(59999.5) == "1:00.000")
+            assert(formatTime(59999.5, "s") == "60.000")
+            assert(formatTime(59999.5, "hms") == "0:01:00")
+            assert(formatTime(3599999.5) == "1:00:00.000")
+            assert(formatTime(0.4) == "0:00.000")
+            assert(formatTime(1.5, "ms") == "2")
+            "#,
+        );
+    }
+
+    #[test]
+    fn formats_negative_durations() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(formatTime(-1) == "-0:00.001")
+            assert(formatTime(-59999.5) == "-1:00.000")
+            assert(formatTime(-3723004) == "-1:02:03.004")
+            assert(formatTime(-3723004, "hmsms") == "-1:02:03.004")
+            assert(formatTime(-3723004, "hms") == "-1:02:03")
+            assert(formatTime(-3723004, "s") == "-3723.004")
+            assert(formatTime(-3723004, "ms") == "-3723004")
+            assert(formatTime(-5, "s") == "-0.005")
+            -- Rounds to zero, which has no sign.
+            assert(formatTime(-0.4) == "0:00.000")
+            assert(formatTime(math.mininteger, "ms") == "-9223372036854775808")
+            "#,
+        );
+    }
+
+    #[test]
+    fn formats_every_format() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(formatTime(5025678) == "1:23:45.678")
+            assert(formatTime(83678) == "1:23.678")
+            assert(formatTime(83678, "hmsms") == "0:01:23.678")
+            assert(formatTime(5025678, "hms") == "1:23:45")
+            assert(formatTime(5025678, "s") == "5025.678")
+            assert(formatTime(5025678, "ms") == "5025678")
+            assert(formatTime(360000000) == "100:00:00.000")
+            assert(not pcall(formatTime, 1, "minutes"))
+            assert(not pcall(formatTime, "soon"))
+            "#,
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use super::{
+    process::note_no_process,
+    read_address::{AddressPath, report_read_failure},
+    scans::record_read,
+    value_type::ValueType,
+};
+use crate::state::{Result, State};
+
+/// How far a counter may go past its wrap between two `delta` calls by
+/// default, anything further back is taken as the game resetting it.
+const DEFAULT_MAX_DELTA: u64 = 600;
+
+/// A frame counter created through `createFrameCounter`.
+pub struct FrameCounter {
+    ty: ValueType,
+    path: AddressPath,
+    /// The counter wraps to 0 here, the integer type's range by default.
+    wrap: u128,
+    max_delta: u64,
+    /// The value read by the last `delta` call, if it could be read.
+    last: Option<u64>,
+}
+
+impl FrameCounter {
+    /// How many frames passed to get from the last value to `value`.
+    fn advance(&mut self, value: u64) -> u64 {
+        let value = (value as u128 % self.wrap) as u64;
+        let Some(last) = self.last.replace(value) else {
+            return 0;
+        };
+
+        if value >= last {
+            value - last
+        } else {
+            // Going backwards is either a wrap, if it only went a little past
+            // the end, or the game resetting the counter.
+            let wrapped = self.wrap - last as u128 + value as u128;
+            if wrapped <= self.max_delta as u128 {
+                wrapped as u64
+            } else {
+                0
+            }
+        }
+    }
+}
+
+pub fn create_frame_counter(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let ty_arg = cx.arg(1);
+    let ty = ValueType::parse(
+        ty_arg
+            .to_str()?
+            .as_utf8()
+            .ok_or_else(|| ty_arg.error("type is not valid UTF-8"))?,
+    )
+    .map_err(|msg| ty_arg.error(msg))?;
+    if !matches!(
+        ty,
+        ValueType::SByte
+            | ValueType::Byte
+            | ValueType::Short
+            | ValueType::UShort
+            | ValueType::Int
+            | ValueType::UInt
+            | ValueType::Long
+            | ValueType::ULong
+    ) {
+        return Err(ty_arg.error("frame counters must be integers"));
+    }
+
+    let mut wrap = 1u128 << (ty.size().unwrap() * 8);
+    let mut max_delta = DEFAULT_MAX_DELTA;
+
+    // A table after the address is the options, a table in its place is a
+    // handle from `scanned`.
+    let mut last = cx.args();
+    if last > 2
+        && let Some(options) = cx.arg(last).as_table()
+    {
+        match options.get_str_key("wrap") {
+            Value::Int(value) if value >= 2 => wrap = wrap.min(value as u128),
+            Value::Nil => {}
+            _ => return Err(cx.arg(last).error("wrap must be at least 2")),
+        }
+        match options.get_str_key("maxDelta") {
+            Value::Int(value) if value >= 0 => max_delta = value as u64,
+            Value::Nil => {}
+            _ => return Err(cx.arg(last).error("maxDelta must not be negative")),
+        }
+        last -= 1;
+    }
+
+    let Some(path) = AddressPath::parse(&cx, "createFrameCounter", 2, last)? else {
+        cx.push(Value::Nil)?;
+        return Ok(cx.into());
+    };
+
+    let id = {
+        let mut counters = cx.associated_data().frame_counters.borrow_mut();
+        counters.push(FrameCounter {
+            ty,
+            path,
+            wrap,
+            max_delta,
+            last: None,
+        });
+        counters.len() - 1
+    };
+
+    let counter = cx.create_table();
+    counter.set_str_key("id", Value::Int(id as i64));
+    counter.set_str_key("delta", fp!(delta));
+
+    cx.push(Value::Table(counter))?;
+    Ok(cx.into())
+}
+
+/// `counter:delta()`, how many frames passed since the last call. The first
+/// call, and the first one after the counter couldn't be read, returns 0.
+fn delta(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let Value::Int(id) = cx.arg(1).get_table()?.get_str_key("id") else {
+        return Err(cx
+            .arg(1)
+            .error("expected a counter returned by createFrameCounter"));
+    };
+
+    let frames = {
+        let state = cx.associated_data();
+        let process = &*state.process.borrow();
+
+        let mut counters = state.frame_counters.borrow_mut();
+        let counter = counters
+            .get_mut(id as usize)
+            .ok_or("the counter doesn't exist")?;
+
+        let value = match process {
+            None => {
+                note_no_process(state, "createFrameCounter");
+                None
+            }
+            Some(_) if state.process_died.get() => None,
+            Some(process) => counter
+                .path
+                .follow(state, &**process, "createFrameCounter")
+                .and_then(|address| {
+                    let mut buf = [0; 8];
+                    state.read_stats.count_read();
+                    match process.read_into_buf(address, &mut buf[..counter.ty.size().unwrap()]) {
+                        Ok(()) => Some(u64::from_le_bytes(buf)),
+                        Err(_) => {
+                            report_read_failure(state, &**process, "createFrameCounter", address);
+                            None
+                        }
+                    }
+                }),
+        };
+
+        if let Some(name) = counter.path.scan_name() {
+            record_read(state, name, value.is_some());
+        }
+
+        match value {
+            Some(value) => counter.advance(value),
+            None => {
+                // Frames that passed while the counter couldn't be read are
+                // lost, counting from whatever it reads next could be way off.
+                counter.last = None;
+                0
+            }
+        }
+    };
+
+    cx.push(Value::Int(frames as i64))?;
+    Ok(cx.into())
+}
+
+/// Forgets the counters' last values, as they're meaningless in a newly
+/// attached process.
+pub fn reset_frame_counters(state: &State) {
+    for counter in state.frame_counters.borrow_mut().iter_mut() {
+        counter.last = None;
+    }
+}
@@ -0,0 +1,42 @@
+use tsuki::context::{Args, Context, Ret};
+
+use crate::{
+    host,
+    state::{Result, State},
+};
+
+/// Pauses game time while either `isLoading` says the game is loading or the
+/// script paused it through `pauseGameTime`. `force` sends the decision to the
+/// host even if it didn't change.
+pub fn apply_game_time_pause(state: &State, force: bool) {
+    let paused = state.game_time_paused_manually.get() || state.loading.get();
+    if !force && paused == state.game_time_paused.get() {
+        return;
+    }
+
+    if paused {
+        host::get().pause_game_time();
+    } else {
+        host::get().resume_game_time();
+    }
+    state.game_time_paused.set(paused);
+}
+
+pub fn is_game_time_paused(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    cx.push(cx.associated_data().game_time_paused.get())?;
+    Ok(cx.into())
+}
+
+pub fn pause_game_time(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    cx.associated_data().game_time_paused_manually.set(true);
+    apply_game_time_pause(cx.associated_data(), false);
+    Ok(cx.into())
+}
+
+pub fn resume_game_time(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    // This only releases the manual pause, `isLoading` still gets the final
+    // say.
+    cx.associated_data().game_time_paused_manually.set(false);
+    apply_game_time_pause(cx.associated_data(), false);
+    Ok(cx.into())
+}
@@ -0,0 +1,31 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::state::{Result, State};
+
+pub fn get_arch(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    if cx.args() != 0 {
+        return Err("getArch takes no arguments".into());
+    }
+
+    let arch = cx.associated_data().arch.get().unwrap_or_else(host_arch);
+
+    cx.push(Value::Str(cx.create_str(arch)))?;
+    Ok(cx.into())
+}
+
+/// The architecture LiveSplit runs on, which is all there is to report
+/// before attaching.
+pub fn host_arch() -> &'static str {
+    let Ok(arch) = asr::get_arch() else {
+        return "unknown";
+    };
+    match arch.as_str() {
+        "x86" => "x86",
+        "x86_64" => "x86_64",
+        "aarch64" => "arm64",
+        _ => "unknown",
+    }
+}
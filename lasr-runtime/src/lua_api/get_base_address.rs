@@ -1,26 +1,73 @@
-use tsuki::{Value, context::{Args, Context, Ret}};
+use asr::Address;
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
 
+use super::{module_name::resolve_module, process::note_no_process};
 use crate::state::{Result, State};
 
 pub fn get_base_address(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
-    let module_arg = cx.arg(1);
+    match base_address(&cx, "getBaseAddress")? {
+        Some(Ok(address)) => cx.push(Value::Int(address.value() as i64))?,
+        Some(Err(message)) => return Err(cx.arg(1).error(message)),
+        None => cx.push(Value::Nil)?,
+    }
+    Ok(cx.into())
+}
 
-    let address = {
-        let process_ref = cx.associated_data().process.borrow();
-        let process = process_ref.as_ref().ok_or("no process attached")?;
-
-        if let Some(module) = module_arg.to_nilable_str(false)? {
-            let module = module
-                .as_utf8()
-                .ok_or_else(|| module_arg.error("module name is not valid UTF-8"))?;
-            process
-                .get_module_address(module)
-                .map_err(|_| module_arg.error("module not found"))?
-        } else {
-            cx.associated_data().base_address.get()
+/// `getBaseAddress`, returning `nil` and a message instead of raising an error
+/// if the module isn't loaded.
+pub fn try_get_base_address(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    match base_address(&cx, "tryGetBaseAddress")? {
+        Some(Ok(address)) => cx.push(Value::Int(address.value() as i64))?,
+        Some(Err(message)) => {
+            cx.push(Value::Nil)?;
+            cx.push(Value::Str(cx.create_str(message)))?;
         }
+        None => cx.push(Value::Nil)?,
+    }
+    Ok(cx.into())
+}
+
+/// The address of the module given as the first argument, or of the main
+/// module without one. `None` if no process is attached. The inner error is a
+/// message for the script.
+fn base_address(
+    cx: &Context<State, Args>,
+    api: &'static str,
+) -> Result<Option<Result<Address, &'static str>>> {
+    let module_arg = cx.arg(1);
+
+    let process_ref = cx.associated_data().process.borrow();
+    let Some(process) = process_ref.as_ref() else {
+        note_no_process(cx.associated_data(), api);
+        return Ok(None);
     };
 
-    cx.push(Value::Int(address.value() as i64))?;
-    Ok(cx.into())
+    let Some(module) = module_arg.to_nilable_str(false)? else {
+        return Ok(Some(Ok(cx.associated_data().base_address.get())));
+    };
+    let module = module
+        .as_utf8()
+        .ok_or_else(|| module_arg.error("module name is not valid UTF-8"))?;
+
+    let cached = cx
+        .associated_data()
+        .module_addresses
+        .borrow()
+        .get(module)
+        .copied();
+    if let Some(address) = cached {
+        return Ok(Some(Ok(address)));
+    }
+
+    let Some((_, address)) = resolve_module(cx.associated_data(), &**process, module) else {
+        return Ok(Some(Err("module not found")));
+    };
+    cx.associated_data()
+        .module_addresses
+        .borrow_mut()
+        .insert(module.to_owned(), address);
+    Ok(Some(Ok(address)))
 }
@@ -1,8 +1,11 @@
+use asr::{Address, future::next_tick};
 use tsuki::{
     Value,
     context::{Args, Context, Ret},
+    fp,
 };
 
+use super::process::{main_module_size, note_no_process};
 use crate::state::{MapRange, Result, State};
 
 pub fn get_maps(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
@@ -11,43 +14,247 @@ pub fn get_maps(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
         return Ok(cx.into());
     }
 
-    if cx.associated_data().maps_cache.borrow().is_none() {
-        let mut maps = Vec::new();
-        {
-            let process_ref = cx.associated_data().process.borrow();
-            let process = process_ref.as_ref().ok_or("no process attached")?;
-
-            for range in process.memory_ranges() {
-                let (base, size) = match range.range() {
-                    Ok(v) => v,
-                    Err(_) => continue,
-                };
-
-                maps.push(MapRange {
-                    start: base.value(),
-                    end: base.value() + size,
-                    size,
-                });
-            }
-        }
-
-        *cx.associated_data().maps_cache.borrow_mut() = Some(maps);
+    if !cache_maps(cx.associated_data(), "getMaps") {
+        cx.push(Value::Nil)?;
+        return Ok(cx.into());
     }
 
     let table = cx.create_table();
     if let Some(maps) = cx.associated_data().maps_cache.borrow().as_ref() {
         for (i, map) in maps.iter().enumerate() {
-            let entry = cx.create_table();
-            // FIXME: name is unavailable in asr.
-            entry.set_str_key("name", cx.create_str(""));
-            entry.set_str_key("start", map.start as i64);
-            entry.set_str_key("end", map.end as i64);
-            entry.set_str_key("size", map.size as i64);
-
-            table.set((i + 1) as i64, entry).unwrap();
+            table.set((i + 1) as i64, map_table(&cx, map)).unwrap();
         }
     }
 
     cx.push(Value::Table(table))?;
     Ok(cx.into())
 }
+
+/// `for map in mapRanges() do`, walks the maps like `getMaps` returns them,
+/// but creates each map's table only when the loop gets to it.
+pub fn map_ranges(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    cache_maps(cx.associated_data(), "mapRanges");
+
+    cx.push(fp!(next_map))?;
+    Ok(cx.into())
+}
+
+/// The iterator function of `mapRanges`. The loop keeps no state besides the
+/// previous map's table, so breaking out of it leaves nothing behind. The
+/// next map is the first one starting after the previous one, which keeps
+/// the loop going in address order if the cache is refreshed in between.
+fn next_map(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let after = match cx.arg(2).get() {
+        None | Some(Value::Nil) => None,
+        Some(Value::Table(previous)) => match previous.get_str_key("start") {
+            Value::Int(start) => Some(start as u64),
+            _ => return Err("mapRanges got a map without a start".into()),
+        },
+        _ => return Err("mapRanges needs to be used in a for-in loop".into()),
+    };
+
+    cache_maps(cx.associated_data(), "mapRanges");
+
+    let map = {
+        let cache = cx.associated_data().maps_cache.borrow();
+        let maps = cache.as_deref().unwrap_or_default();
+        let next = match after {
+            Some(after) => maps.partition_point(|map| map.start <= after),
+            None => 0,
+        };
+        maps.get(next).map(|map| map_table(&cx, map))
+    };
+
+    cx.push(map.unwrap_or(Value::Nil))?;
+    Ok(cx.into())
+}
+
+/// `getMemoryInfo()`, how many memory maps there are, how many bytes they
+/// span in total and the size of the largest one, along with the main
+/// module's size, for judging how long a scan of all of the memory takes.
+/// Reuses the maps cached for `getMaps`, so it only queries the process if
+/// they aren't cached.
+pub fn get_memory_info(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    if !cache_maps(cx.associated_data(), "getMemoryInfo") {
+        cx.push(Value::Nil)?;
+        return Ok(cx.into());
+    }
+
+    let info = cx.create_table();
+    if let Some(maps) = cx.associated_data().maps_cache.borrow().as_ref() {
+        let total_bytes: u64 = maps.iter().map(|map| map.size).sum();
+        let largest_range = maps.iter().map(|map| map.size).max().unwrap_or(0);
+        info.set_str_key("rangeCount", Value::Int(maps.len() as i64));
+        info.set_str_key("totalBytes", Value::Int(total_bytes as i64));
+        info.set_str_key("largestRange", Value::Int(largest_range as i64));
+    }
+    if let Some(size) = main_module_size(cx.associated_data()) {
+        info.set_str_key("mainModuleSize", Value::Int(size as i64));
+    }
+
+    cx.push(Value::Table(info))?;
+    Ok(cx.into())
+}
+
+/// `mapsReady()`, whether the memory maps are cached, so `getMaps` and the
+/// like don't have to query the process.
+pub fn maps_ready(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let ready = cx.associated_data().maps_cache.borrow().is_some();
+    cx.push(ready)?;
+    Ok(cx.into())
+}
+
+/// How many memory maps `eagerMaps` queries per tick.
+const EAGER_MAPS_PER_TICK: usize = 256;
+
+/// Queries and caches the memory maps of the newly attached process for
+/// `eagerMaps`, a few at a time per tick so a process with many maps doesn't
+/// stall a single tick.
+pub async fn warm_maps(state: &State) {
+    let process = state.process.borrow().clone();
+    let Some(process) = process else {
+        return;
+    };
+
+    let mut maps = Vec::new();
+    for (i, range) in process.memory_ranges().enumerate() {
+        if i > 0 && i % EAGER_MAPS_PER_TICK == 0 {
+            next_tick().await;
+        }
+        if let Ok((base, size)) = range {
+            maps.push(map_range(base, size));
+        }
+    }
+    maps.sort_by_key(|map| map.start);
+
+    *state.maps_cache.borrow_mut() = Some(maps);
+}
+
+/// Queries the memory maps, unless they're cached already. `false` if there's
+/// no process to query.
+fn cache_maps(state: &State, api: &'static str) -> bool {
+    if state.maps_cache.borrow().is_some() {
+        return true;
+    }
+
+    let process_ref = state.process.borrow();
+    let Some(process) = process_ref.as_ref() else {
+        note_no_process(state, api);
+        return false;
+    };
+
+    let mut maps = Vec::new();
+    for range in process.memory_ranges() {
+        let (base, size) = match range {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        maps.push(map_range(base, size));
+    }
+    // `mapRanges` relies on the order to find where it left off.
+    maps.sort_by_key(|map| map.start);
+
+    *state.maps_cache.borrow_mut() = Some(maps);
+    true
+}
+
+fn map_range(base: Address, size: u64) -> MapRange {
+    MapRange {
+        start: base.value(),
+        end: base.value() + size,
+        size,
+    }
+}
+
+fn map_table<'a>(cx: &Context<'a, State, Args>, map: &MapRange) -> Value<'a, State> {
+    let entry = cx.create_table();
+    // FIXME: name is unavailable in asr.
+    entry.set_str_key("name", cx.create_str(""));
+    entry.set_str_key("start", map.start as i64);
+    entry.set_str_key("end", map.end as i64);
+    entry.set_str_key("size", map.size as i64);
+    Value::Table(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
+    };
+
+    fn game() -> Rc<MockProcess> {
+        Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x30000, vec![0; 0x300])
+                .map(0x10000, vec![0; 0x100])
+                .map(0x20000, vec![0; 0x200]),
+        )
+    }
+
+    #[test]
+    fn queries_the_maps_once() {
+        let game = game();
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            assert(not mapsReady())
+            local maps = getMaps()
+            assert(#maps == 3 and mapsReady())
+            assert(maps[1].start == 0x10000 and maps[1]["end"] == 0x10100 and maps[1].size == 0x100)
+            assert(maps[3].start == 0x30000 and maps[3].size == 0x300)
+            assert(#getMaps() == 3)
+
+            local info = getMemoryInfo()
+            assert(info.rangeCount == 3 and info.totalBytes == 0x600)
+            assert(info.largestRange == 0x300)
+
+            local starts = {}
+            for map in mapRanges() do
+                starts[#starts + 1] = map.start
+            end
+            assert(#starts == 3 and starts[1] == 0x10000 and starts[3] == 0x30000)
+            "#,
+        );
+        assert_eq!(game.range_queries.get(), 1);
+    }
+
+    #[test]
+    fn queries_again_after_attaching() {
+        let game = game();
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(&lua, "assert(#getMaps() == 3)");
+
+        let other = Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x10000, vec![0; 0x100]),
+        );
+        attach(&lua, &other, "game.exe");
+        run(&lua, "assert(#getMaps() == 1)");
+
+        assert_eq!(game.range_queries.get(), 1);
+        assert_eq!(other.range_queries.get(), 1);
+    }
+
+    #[test]
+    fn needs_a_process() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(getMaps() == nil and getMemoryInfo() == nil)
+            for _ in mapRanges() do
+                error("there are no maps without a process")
+            end
+            "#,
+        );
+    }
+}
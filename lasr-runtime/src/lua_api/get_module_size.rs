@@ -1,30 +1,68 @@
-use tsuki::{Value, context::{Args, Context, Ret}};
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
 
+use super::{module_name::resolve_module, process::note_no_process};
 use crate::state::{Result, State};
 
 pub fn get_module_size(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    match module_size(&cx, "getModuleSize")? {
+        Some(Ok(size)) => cx.push(Value::Int(size as i64))?,
+        Some(Err(message)) => return Err(cx.arg(1).error(message)),
+        None => cx.push(Value::Nil)?,
+    }
+    Ok(cx.into())
+}
+
+/// `getModuleSize`, returning `nil` and a message instead of raising an error
+/// if the module isn't loaded.
+pub fn try_get_module_size(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    match module_size(&cx, "tryGetModuleSize")? {
+        Some(Ok(size)) => cx.push(Value::Int(size as i64))?,
+        Some(Err(message)) => {
+            cx.push(Value::Nil)?;
+            cx.push(Value::Str(cx.create_str(message)))?;
+        }
+        None => cx.push(Value::Nil)?,
+    }
+    Ok(cx.into())
+}
+
+/// The size of the module given as the first argument, or of the main module
+/// without one. `None` if no process is attached. The inner error is a
+/// message for the script.
+fn module_size(
+    cx: &Context<State, Args>,
+    api: &'static str,
+) -> Result<Option<Result<u64, &'static str>>> {
     let module_arg = cx.arg(1);
 
-    let size = {
-        let process_ref = cx.associated_data().process.borrow();
-        let process = process_ref.as_ref().ok_or("no process attached")?;
+    let process_ref = cx.associated_data().process.borrow();
+    let Some(process) = process_ref.as_ref() else {
+        note_no_process(cx.associated_data(), api);
+        return Ok(None);
+    };
 
-        if let Some(module) = module_arg.to_nilable_str(false)? {
+    let module = match module_arg.to_nilable_str(false)? {
+        Some(module) => {
             let module = module
                 .as_utf8()
                 .ok_or_else(|| module_arg.error("module name is not valid UTF-8"))?;
-            process
-                .get_module_size(module)
-                .map_err(|_| module_arg.error("module not found"))?
-        } else {
+            match resolve_module(cx.associated_data(), &**process, module) {
+                Some((module, _)) => module,
+                None => return Ok(Some(Err("module not found"))),
+            }
+        }
+        None => {
             let name_ref = cx.associated_data().process_name.borrow();
-            let name = name_ref.as_ref().ok_or("no process name available")?;
-            process
-                .get_module_size(name)
-                .map_err(|_| module_arg.error("module not found"))?
+            name_ref.clone().ok_or("no process name available")?
         }
     };
 
-    cx.push(Value::Int(size as i64))?;
-    Ok(cx.into())
+    Ok(Some(
+        process
+            .get_module_size(&module)
+            .map_err(|_| "module not found"),
+    ))
 }
@@ -0,0 +1,31 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::state::{Result, State};
+
+pub fn get_os(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    if cx.args() != 0 {
+        return Err("getOS takes no arguments".into());
+    }
+
+    let os = cx.associated_data().os.get().unwrap_or_else(host_os);
+
+    cx.push(Value::Str(cx.create_str(os)))?;
+    Ok(cx.into())
+}
+
+/// The OS LiveSplit runs on, which is all there is to report before
+/// attaching.
+pub fn host_os() -> &'static str {
+    let Ok(os) = asr::get_os() else {
+        return "unknown";
+    };
+    match os.as_str() {
+        "windows" => "windows",
+        "linux" => "linux",
+        "macos" => "macos",
+        _ => "unknown",
+    }
+}
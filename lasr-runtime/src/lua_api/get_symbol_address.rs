@@ -0,0 +1,145 @@
+use asr::{
+    Address, Process,
+    file_format::{elf, pe},
+};
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use super::{module_name::resolve_module, process::note_no_process};
+use crate::state::{Result, State};
+
+pub fn get_symbol_address(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let module = module_arg(&cx)?;
+    let symbol_arg = cx.arg(2);
+    let symbol = symbol_arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| symbol_arg.error("symbol name is not valid UTF-8"))?;
+
+    let address = with_symbols(&cx, &module, |symbols| {
+        symbols
+            .iter()
+            .find(|(name, _)| name == symbol)
+            .map(|&(_, address)| address)
+    })?;
+
+    match address {
+        Ok(Some(address)) => cx.push(Value::Int(address.value() as i64))?,
+        Ok(None) => {
+            cx.push(Value::Nil)?;
+            cx.push(Value::Str(
+                cx.create_str(format!("{module} doesn't export {symbol}")),
+            ))?;
+        }
+        Err(message) => {
+            cx.push(Value::Nil)?;
+            cx.push(Value::Str(cx.create_str(message)))?;
+        }
+    }
+    Ok(cx.into())
+}
+
+pub fn get_symbols(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let module = module_arg(&cx)?;
+
+    let names = with_symbols(&cx, &module, |symbols| {
+        symbols
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>()
+    })?;
+
+    match names {
+        Ok(names) => {
+            let table = cx.create_table();
+            for (i, name) in names.into_iter().enumerate() {
+                table.set(Value::Int(i as i64 + 1), Value::Str(cx.create_str(name)))?;
+            }
+            cx.push(Value::Table(table))?;
+        }
+        Err(message) => {
+            cx.push(Value::Nil)?;
+            cx.push(Value::Str(cx.create_str(message)))?;
+        }
+    }
+    Ok(cx.into())
+}
+
+fn module_arg(cx: &Context<State, Args>) -> Result<String> {
+    let arg = cx.arg(1);
+    Ok(arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("module name is not valid UTF-8"))?
+        .to_owned())
+}
+
+/// Runs `f` on the module's exports, which get parsed once per module as
+/// reading them through the process is slow. The inner error is a message
+/// for the script.
+fn with_symbols<T>(
+    cx: &Context<State, Args>,
+    module: &str,
+    f: impl FnOnce(&[(String, Address)]) -> T,
+) -> Result<Result<T, String>> {
+    if let Some(symbols) = cx.associated_data().symbols.borrow().get(module) {
+        return Ok(Ok(f(symbols)));
+    }
+
+    let symbols = {
+        let process_ref = cx.associated_data().process.borrow();
+        let Some(process) = process_ref.as_ref() else {
+            note_no_process(cx.associated_data(), "getSymbolAddress");
+            return Ok(Err("no process attached".to_owned()));
+        };
+        let Some((_, module_address)) = resolve_module(cx.associated_data(), &**process, module)
+        else {
+            return Ok(Err(format!("module {module} not found")));
+        };
+        let Some(process) = process.as_process() else {
+            return Ok(Err(
+                "symbols can only be read from a real process".to_owned()
+            ));
+        };
+        match read_symbols(process, module_address) {
+            Some(symbols) => symbols,
+            None => return Ok(Err(format!("{module} has no export table"))),
+        }
+    };
+
+    let mut cache = cx.associated_data().symbols.borrow_mut();
+    let symbols = cache.entry(module.to_owned()).or_insert(symbols);
+    Ok(Ok(f(symbols)))
+}
+
+fn read_symbols(process: &Process, module_address: Address) -> Option<Vec<(String, Address)>> {
+    let magic = process.read::<[u8; 4]>(module_address).ok()?;
+
+    let mut symbols = Vec::new();
+    if magic.starts_with(b"MZ") {
+        for symbol in pe::symbols(process, module_address) {
+            if let Ok(name) = symbol.get_name::<256>(process)
+                && let Ok(name) = name.validate_utf8()
+            {
+                symbols.push((name.to_owned(), symbol.address));
+            }
+        }
+    } else if magic == *b"\x7fELF" {
+        for symbol in elf::symbols(process, module_address) {
+            if let Ok(name) = symbol.get_name::<256>(process)
+                && let Ok(name) = name.validate_utf8()
+            {
+                symbols.push((name.to_owned(), symbol.address));
+            }
+        }
+    } else {
+        return None;
+    }
+
+    if symbols.is_empty() {
+        return None;
+    }
+    Some(symbols)
+}
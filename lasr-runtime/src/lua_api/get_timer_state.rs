@@ -0,0 +1,23 @@
+use asr::timer::TimerState;
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::{
+    host,
+    state::{Result, State},
+};
+
+pub fn get_timer_state(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let state = match host::get().timer_state() {
+        TimerState::NotRunning => "NotRunning",
+        TimerState::Running => "Running",
+        TimerState::Paused => "Paused",
+        TimerState::Ended => "Ended",
+        _ => "Unknown",
+    };
+
+    cx.push(Value::Str(cx.create_str(state)))?;
+    Ok(cx.into())
+}
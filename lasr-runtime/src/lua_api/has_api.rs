@@ -0,0 +1,25 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::{
+    state::{Result, State},
+    utils::Globals,
+};
+
+pub fn has_api(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let name = cx.arg(1);
+    let name = name
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| name.error("name is not valid UTF-8"))?;
+
+    // Only native functions count, a script defining a fallback under the
+    // same name doesn't make the API available.
+    let globals = cx.registry::<Globals>().unwrap();
+    let available = matches!(globals.get_str_key(name), Value::Fp(_) | Value::AsyncFp(_));
+
+    cx.push(available)?;
+    Ok(cx.into())
+}
@@ -0,0 +1,26 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::state::{Result, State};
+
+/// `is64Bit()`, whether the attached process is a 64-bit one, as read from its
+/// main module's header when attaching. `nil` without a process or if the
+/// header couldn't be read. Unlike `getArch`, this is about the game itself.
+pub fn is_64_bit(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let state = cx.associated_data();
+    let is_64_bit = match state.pointer_size.get() {
+        Some(size) if state.process.borrow().is_some() => {
+            if size == 8 {
+                Value::True
+            } else {
+                Value::False
+            }
+        }
+        _ => Value::Nil,
+    };
+
+    cx.push(is_64_bit)?;
+    Ok(cx.into())
+}
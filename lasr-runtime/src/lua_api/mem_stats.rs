@@ -0,0 +1,30 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::{
+    heap, host,
+    state::{Result, State},
+};
+
+pub fn mem_stats(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let publish = matches!(cx.arg(1).get(), Some(Value::True));
+
+    let stats = heap::stats();
+    let pages = heap::wasm_pages();
+
+    let table = cx.create_table();
+    table.set_str_key("heapBytes", Value::Int(stats.allocated_bytes as i64));
+    table.set_str_key("wasmPages", Value::Int(pages as i64));
+    table.set_str_key("allocations", Value::Int(stats.allocations as i64));
+    table.set_str_key("deallocations", Value::Int(stats.deallocations as i64));
+
+    if publish {
+        host::get().set_variable("Heap (bytes)", &stats.allocated_bytes.to_string());
+        host::get().set_variable("WASM pages", &pages.to_string());
+    }
+
+    cx.push(Value::Table(table))?;
+    Ok(cx.into())
+}
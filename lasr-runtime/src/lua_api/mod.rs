@@ -1,25 +1,87 @@
+mod autosplit;
+mod buffer;
+mod cooldown;
+mod detect_version;
+mod fastpairs;
+mod format_address;
+mod format_time;
+mod frame_counter;
+mod game_time;
+mod get_arch;
 mod get_base_address;
 mod get_maps;
 mod get_module_size;
+mod get_os;
 mod get_pid;
+mod get_symbol_address;
+mod get_timer_state;
+mod has_api;
+mod is_64_bit;
+mod mem_stats;
+mod module_name;
+mod perf_stats;
 mod print;
 mod print_tbl;
 mod process;
 mod read_address;
+mod read_pointer_path;
+mod rebind_callbacks;
+mod request_burst;
+mod runtime_uses;
+mod scans;
+mod set_pointer_size;
 mod set_variable;
 mod shallow_copy_tbl;
 mod sig_scan;
 mod size_of;
+mod snapshot;
+mod split_once;
+mod value_type;
+mod wait_for_module;
+mod watcher;
 
-pub use get_base_address::get_base_address;
-pub use get_maps::get_maps;
-pub use get_module_size::get_module_size;
+pub use autosplit::{AutoSplit, autosplit, check_autosplits};
+pub use buffer::{create_buffer, read_into_buffer, register_buffer};
+pub use cooldown::{set_split_cooldown, set_start_cooldown};
+pub use detect_version::detect_version;
+pub use fastpairs::fastpairs;
+pub use format_address::{describe_address, format_address};
+pub use format_time::{format_time, set_time_variable};
+pub use frame_counter::{FrameCounter, create_frame_counter, reset_frame_counters};
+pub use game_time::{
+    apply_game_time_pause, is_game_time_paused, pause_game_time, resume_game_time,
+};
+pub use get_arch::get_arch;
+pub use get_base_address::{get_base_address, try_get_base_address};
+pub use get_maps::{get_maps, get_memory_info, map_ranges, maps_ready, warm_maps};
+pub use get_module_size::{get_module_size, try_get_module_size};
+pub use get_os::get_os;
 pub use get_pid::get_pid;
+pub use get_symbol_address::{get_symbol_address, get_symbols};
+pub use get_timer_state::get_timer_state;
+pub use has_api::has_api;
+pub use is_64_bit::is_64_bit;
+pub use mem_stats::mem_stats;
+pub use perf_stats::{ReadStats, get_perf_stats};
 pub use print::print;
 pub use print_tbl::print_tbl;
-pub use process::process;
-pub use read_address::read_address;
-pub use set_variable::set_variable;
+#[cfg(test)]
+pub use process::attached;
+pub use process::{process, process_info, try_attach};
+pub use read_address::{HeldReads, read_address};
+pub use read_pointer_path::read_pointer_path;
+pub use rebind_callbacks::rebind_callbacks;
+pub use request_burst::request_burst;
+pub use runtime_uses::{Capability, HostFeatures, runtime_uses};
+pub use scans::{Scan, invalidate_scans, register_scan, rescan_stale, scanned};
+pub use set_pointer_size::set_pointer_size;
+pub use set_variable::{
+    clear_variable, set_variable, set_variable_throttled, set_variable_with_ttl,
+};
 pub use shallow_copy_tbl::shallow_copy_tbl;
-pub use sig_scan::sig_scan;
+pub use sig_scan::{find_pattern, find_pattern_all, sig_scan};
 pub use size_of::size_of;
+pub use snapshot::snapshot;
+pub use split_once::{reset_split_once, split_once};
+pub use wait_for_module::wait_for_module;
+pub use watcher::{Watcher, create_watcher, reset_watchers, update_watchers};
@@ -0,0 +1,91 @@
+use asr::Address;
+
+use crate::{log, process_memory::ProcessMemory, state::State};
+
+const EXTENSIONS: [&str; 3] = [".exe", ".dll", ".so"];
+
+/// Looks up a module, tolerating a different case or a missing or extra
+/// extension, as the original LASR did. An exact match always wins. Returns
+/// the name the module was found under along with its address.
+pub fn resolve_module(
+    state: &State,
+    process: &dyn ProcessMemory,
+    name: &str,
+) -> Option<(String, Address)> {
+    if let Ok(address) = process.get_module_address(name) {
+        return Some((name.to_owned(), address));
+    }
+
+    if let Some(alias) = state.module_aliases.borrow().get(name) {
+        return process
+            .get_module_address(alias)
+            .ok()
+            .map(|address| (alias.clone(), address));
+    }
+
+    let stem = EXTENSIONS
+        .iter()
+        .find_map(|extension| {
+            let split = name.len().checked_sub(extension.len())?;
+            (name.is_char_boundary(split) && name[split..].eq_ignore_ascii_case(extension))
+                .then(|| &name[..split])
+        })
+        .unwrap_or(name);
+
+    let mut candidates = vec![stem.to_owned()];
+    candidates.extend(
+        EXTENSIONS
+            .iter()
+            .map(|extension| format!("{stem}{extension}")),
+    );
+
+    // asr can't list the loaded modules, so the main module is the only one
+    // whose real name we know.
+    if let Some(process_name) = &*state.process_name.borrow()
+        && candidates
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(process_name))
+    {
+        candidates.insert(0, process_name.clone());
+    }
+
+    let (alias, address) = candidates
+        .into_iter()
+        .filter(|candidate| candidate.as_str() != name)
+        .find_map(|candidate| {
+            let address = process.get_module_address(&candidate).ok()?;
+            Some((candidate, address))
+        })?;
+
+    log::print_message(&format!(
+        "[module] Module '{name}' not found, using '{alias}' instead"
+    ));
+    state
+        .module_aliases
+        .borrow_mut()
+        .insert(name.to_owned(), alias.clone());
+
+    Some((alias, address))
+}
+
+/// Finds the module `address` lies in among the main module and the ones the
+/// script looked up so far.
+pub fn containing_module(
+    state: &State,
+    process: &dyn ProcessMemory,
+    address: Address,
+) -> Option<(String, Address)> {
+    let process_name = state.process_name.borrow();
+    let main = process_name
+        .as_ref()
+        .map(|name| (name.clone(), state.base_address.get()));
+    let modules = state.module_addresses.borrow();
+
+    main.into_iter()
+        .chain(modules.iter().map(|(name, &base)| (name.clone(), base)))
+        .find(|(name, base)| {
+            process
+                .get_module_size(name)
+                .is_ok_and(|size| (base.value()..base.value() + size).contains(&address.value()))
+        })
+}
@@ -0,0 +1,98 @@
+use std::{cell::Cell, time::Duration};
+
+use tsuki::{
+    Float, Value,
+    context::{Args, Context, Ret},
+};
+
+use crate::{
+    log,
+    state::{Result, State},
+};
+
+/// How many process reads a tick may take by default before the user is
+/// warned that the script is probably slowing down the game.
+pub const DEFAULT_READ_BUDGET: u32 = 200;
+
+/// Counts the process reads done for the script, per tick.
+pub struct ReadStats {
+    reads: Cell<u32>,
+    /// Reads of memory chunks by signature scans, which are counted separately
+    /// as a single scan takes a lot of them.
+    scan_reads: Cell<u32>,
+    last_reads: Cell<u32>,
+    last_scan_reads: Cell<u32>,
+    /// Time spent in `onTickStart` and `onTickEnd` this tick and the last.
+    tick_hooks: Cell<Duration>,
+    last_tick_hooks: Cell<Duration>,
+    /// Set by `readBudget` in `startup`, `0` disables the warning.
+    pub budget: Cell<u32>,
+    warned: Cell<bool>,
+}
+
+impl ReadStats {
+    pub fn new() -> Self {
+        Self {
+            reads: Cell::new(0),
+            scan_reads: Cell::new(0),
+            last_reads: Cell::new(0),
+            last_scan_reads: Cell::new(0),
+            tick_hooks: Cell::new(Duration::ZERO),
+            last_tick_hooks: Cell::new(Duration::ZERO),
+            budget: Cell::new(DEFAULT_READ_BUDGET),
+            warned: Cell::new(false),
+        }
+    }
+
+    pub fn count_read(&self) {
+        self.reads.set(self.reads.get() + 1);
+    }
+
+    pub fn count_scan_read(&self) {
+        self.scan_reads.set(self.scan_reads.get() + 1);
+    }
+
+    pub fn count_tick_hook(&self, time: Duration) {
+        self.tick_hooks.set(self.tick_hooks.get() + time);
+    }
+
+    /// Keeps the time the tick hooks took, once `onTickEnd` returned.
+    pub fn end_tick_hooks(&self) {
+        self.last_tick_hooks
+            .set(self.tick_hooks.replace(Duration::ZERO));
+    }
+
+    /// Starts counting the next tick, warning once if this one went over the
+    /// budget. Called at the end of every tick.
+    pub fn end_tick(&self) {
+        let reads = self.reads.replace(0);
+        self.last_reads.set(reads);
+        self.last_scan_reads.set(self.scan_reads.replace(0));
+
+        let budget = self.budget.get();
+        if budget != 0 && reads > budget && !self.warned.replace(true) {
+            log::warn(&format!(
+                "[perf] The script read process memory {reads} times in a single tick, more \
+                 than the budget of {budget}, which may slow down the game. Reading a struct \
+                 at once with snapshot instead of field by field helps."
+            ));
+        }
+    }
+}
+
+pub fn get_perf_stats(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let stats = &cx.associated_data().read_stats;
+
+    let table = cx.create_table();
+    table.set_str_key("reads", Value::Int(stats.last_reads.get() as i64));
+    table.set_str_key("scanReads", Value::Int(stats.last_scan_reads.get() as i64));
+    table.set_str_key("readsThisTick", Value::Int(stats.reads.get() as i64));
+    table.set_str_key("readBudget", Value::Int(stats.budget.get() as i64));
+    table.set_str_key(
+        "tickHooksMs",
+        Value::Float(Float(stats.last_tick_hooks.get().as_secs_f64() * 1000.0)),
+    );
+
+    cx.push(Value::Table(table))?;
+    Ok(cx.into())
+}
@@ -2,19 +2,24 @@ use std::string::String;
 
 use tsuki::context::{Args, Context, Ret};
 
-use crate::state::{Result, State};
+use crate::{
+    log,
+    state::{Result, State},
+    utils::print_long,
+};
 
 pub fn print(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     let mut output = match cx.args() {
         0 => {
-            asr::print_message("");
+            log::print_message("");
 
             return Ok(cx.into());
         }
         1 => {
             let arg = cx.arg(1);
             let v = arg.display()?;
-            asr::print_message(
+            print_long(
+                cx.associated_data(),
                 v.as_utf8()
                     .ok_or_else(|| arg.error("value is not valid UTF-8"))?,
             );
@@ -36,7 +41,7 @@ pub fn print(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
         );
     }
 
-    asr::print_message(&output);
+    print_long(cx.associated_data(), &output);
 
     Ok(cx.into())
 }
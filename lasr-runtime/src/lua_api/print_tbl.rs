@@ -1,49 +1,113 @@
-use std::fmt::Write;
+use std::{cmp::Ordering, fmt::Write};
 
 use tsuki::{
     Value,
     context::{Args, Context, Ret},
-    fp,
 };
 
 use crate::{
+    log,
     state::{Result, State},
-    utils::{DisplayValue, next_pair},
+    utils::{DisplayValue, PairsBatch, TablePairs, print_long, type_name},
 };
 
 pub fn print_tbl(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     if cx.args() == 0 || cx.arg(1).as_table().is_none() {
-        asr::print_message("[print_tbl] Argument is not a table or no argument passed.");
+        log::warn("[print_tbl] Argument is not a table or no argument passed.");
         return Ok(cx.into());
     }
 
-    if cx.args() > 1 {
-        asr::print_message("[print_tbl] Too many arguments passed, only pass a single table");
+    if cx.args() > 2 || (cx.args() == 2 && cx.arg(2).as_table().is_none()) {
+        log::warn(
+            "[print_tbl] Too many arguments passed, only pass a single table and an options table",
+        );
         return Ok(cx.into());
     }
 
-    let table = cx.arg(1).get_table()?;
-    let td = cx.create_thread();
+    let sorted = match cx.arg(2).as_table() {
+        Some(options) => matches!(options.get_str_key("sorted"), Value::True),
+        None => false,
+    };
 
-    let mut key = Value::Nil;
-    let mut buf = String::new();
-    loop {
-        let mut pair: Vec<Value<State>> = td.call(fp!(next_pair), (table, &key))?;
-        if pair.len() != 2 {
-            break;
-        }
+    // The pairs borrow `cx`, so they have to be gone before it's returned.
+    {
+        let table = cx.arg(1).get_table()?;
+        let td = cx.create_thread();
+        let batch = cx.registry::<PairsBatch>().unwrap();
+
+        let mut buf = String::new();
+        let mut pairs = Vec::new();
+        let mut truncated = false;
+        for pair in TablePairs::new(&td, &batch, table) {
+            let (next_key, next_value) = pair?;
 
-        let next_value = pair.pop().unwrap();
-        let next_key = pair.pop().unwrap();
+            if sorted {
+                if pairs.len() == MAX_SORTED_PAIRS {
+                    truncated = true;
+                    break;
+                }
+                pairs.push((next_key, next_value));
+                continue;
+            }
 
-        let key_text = DisplayValue(&next_key);
-        let value_text = DisplayValue(&next_value);
+            print_pair(cx.associated_data(), &mut buf, &next_key, &next_value);
+        }
 
-        buf.clear();
-        let _ = write!(&mut buf, "{key_text}: {value_text}");
-        asr::print_message(&buf);
-        key = next_key;
+        if sorted {
+            pairs.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+            for (key, value) in &pairs {
+                print_pair(cx.associated_data(), &mut buf, key, value);
+            }
+            if truncated {
+                log::print_message(&format!(
+                    "[print_tbl] Only the first {MAX_SORTED_PAIRS} entries were printed"
+                ));
+            }
+        }
     }
 
     Ok(cx.into())
 }
+
+// Sorting needs all pairs in memory, so this keeps a pathological table from
+// using up all of it.
+const MAX_SORTED_PAIRS: usize = 10_000;
+
+fn print_pair(state: &State, buf: &mut String, key: &Value<State>, value: &Value<State>) {
+    let key_text = DisplayValue(key);
+    let value_text = DisplayValue(value);
+
+    buf.clear();
+    let _ = write!(buf, "{key_text}: {value_text}");
+    print_long(state, buf);
+}
+
+/// Numbers come first in ascending order, followed by strings in
+/// lexicographic order and then everything else ordered by type name.
+fn compare_keys(a: &Value<State>, b: &Value<State>) -> Ordering {
+    match (a, b) {
+        (Value::Str(a), Value::Str(b)) => a.as_bytes().cmp(b.as_bytes()),
+        _ => match (as_number(a), as_number(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => key_rank(a)
+                .cmp(&key_rank(b))
+                .then_with(|| type_name(a).cmp(type_name(b))),
+        },
+    }
+}
+
+fn as_number(value: &Value<State>) -> Option<f64> {
+    match value {
+        Value::Int(v) => Some(*v as f64),
+        Value::Float(v) => Some(v.0),
+        _ => None,
+    }
+}
+
+fn key_rank(value: &Value<State>) -> u8 {
+    match value {
+        Value::Int(_) | Value::Float(_) => 0,
+        Value::Str(_) => 1,
+        _ => 2,
+    }
+}
@@ -1,7 +1,29 @@
-use asr::Process;
-use tsuki::context::{Args, Context, Ret};
+use std::rc::Rc;
 
-use crate::state::{Result, State};
+use asr::{Address, future::next_tick, timer::TimerState};
+use tsuki::{
+    Table, Value,
+    context::{Args, Context, Ret},
+};
+
+use super::{get_arch::host_arch, get_maps::warm_maps, get_os::host_os};
+use crate::{
+    host, log,
+    process_memory::ProcessMemory,
+    state::{Result, State},
+    utils::Globals,
+};
+
+/// Notes that `api` was called before `process` attached to anything, like
+/// from `startup`, so it returns nothing. Only once per function, as scripts
+/// commonly keep calling it every tick until the game starts.
+pub fn note_no_process(state: &State, api: &'static str) {
+    if state.no_process_noted.borrow_mut().insert(api) {
+        log::debug(&format!(
+            "[{api}] No process attached yet, so there's nothing to read"
+        ));
+    }
+}
 
 pub async fn process<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, State, Ret>> {
     let arg = cx.arg(1);
@@ -19,24 +41,318 @@ pub async fn process<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, Sta
             .ok_or_else(|| sort_value.error("sort is not valid UTF-8"))?;
 
         if sort != "first" && sort != "last" {
-            asr::print_message(
+            log::warn(
                 "[process] Invalid sort argument. Use 'first' or 'last'. Falling back to first",
             );
         }
     }
 
-    let process = Process::wait_attach(process_name).await;
-
-    let base_address = process
-        .get_module_address(process_name)
-        .map_err(|_| "failed to get process base address")?;
+    attach(cx.associated_data(), process_name).await?;
+    let eager_maps = matches!(
+        cx.registry::<Globals>().unwrap().get_str_key("eagerMaps"),
+        Value::True
+    );
+    if eager_maps {
+        warm_maps(cx.associated_data()).await;
+    }
 
-    *cx.associated_data().process.borrow_mut() = Some(process);
-    cx.associated_data().base_address.set(base_address);
-    *cx.associated_data().process_name.borrow_mut() = Some(process_name.to_owned());
-    *cx.associated_data().maps_cache.borrow_mut() = None;
-    cx.associated_data().maps_cache_cycles.set(1);
-    cx.associated_data().maps_cache_cycles_value.set(1);
+    let info = cx.create_table();
+    process_info(
+        cx.associated_data(),
+        &info,
+        Value::Str(cx.create_str(process_name)),
+    );
 
+    cx.push(Value::Table(info))?;
     Ok(cx.into())
 }
+
+/// The size of the attached process' main module, if it can be queried.
+pub fn main_module_size(state: &State) -> Option<u64> {
+    state
+        .process
+        .borrow()
+        .as_ref()
+        .zip(state.process_name.borrow().as_ref())
+        .and_then(|(process, name)| process.get_module_size(name).ok())
+}
+
+/// Fills `info` with what's known about the attached process, for `process`
+/// and `onAttach`. `name` is the process name as a Lua string. The pid is
+/// left out, as asr can't tell it.
+pub fn process_info(state: &State, info: &Table<State>, name: Value<State>) {
+    info.set_str_key("name", name);
+    info.set_str_key("base", Value::Int(state.base_address.get().value() as i64));
+
+    if let Some(size) = main_module_size(state) {
+        info.set_str_key("mainModuleSize", Value::Int(size as i64));
+    }
+
+    if let Some(pointer_size) = state.pointer_size.get() {
+        info.set_str_key(
+            "is64bit",
+            if pointer_size == 8 {
+                Value::True
+            } else {
+                Value::False
+            },
+        );
+    }
+}
+
+/// Waits for the process to start and resets all the state tied to the
+/// previously attached one.
+pub async fn attach(state: &State, process_name: &str) -> Result<()> {
+    while !try_attach(state, process_name)? {
+        next_tick().await;
+    }
+    Ok(())
+}
+
+/// Attaches to `process_name` if it's running, returning whether it was.
+pub fn try_attach(state: &State, process_name: &str) -> Result<bool> {
+    let Some((process, base_address)) = host::get().attach(process_name) else {
+        return Ok(false);
+    };
+
+    let base_address = base_address.ok_or("failed to get process base address")?;
+
+    attached(state, process, process_name, base_address);
+    Ok(true)
+}
+
+/// Switches over to the newly attached `process`, resetting all the state
+/// tied to the previous one.
+pub fn attached(
+    state: &State,
+    process: Rc<dyn ProcessMemory>,
+    process_name: &str,
+    base_address: Address,
+) {
+    let executable = read_executable(&*process, base_address);
+    let pointer_size = executable
+        .as_ref()
+        .and_then(|executable| executable.pointer_size);
+    match pointer_size {
+        Some(size) => {
+            log::print_message(&format!("[process] Following pointers as {}-bit", size * 8))
+        }
+        None => log::print_message(
+            "[process] Unknown executable format, guessing the pointer size from each address",
+        ),
+    }
+
+    *state.process.borrow_mut() = Some(process);
+    state.base_address.set(base_address);
+    state.pointer_size.set(pointer_size);
+    *state.process_name.borrow_mut() = Some(process_name.to_owned());
+    state.module_addresses.borrow_mut().clear();
+    state.missing_modules_warned.borrow_mut().clear();
+    state.module_aliases.borrow_mut().clear();
+    state.process_died.set(false);
+    super::invalidate_scans(state);
+    super::reset_frame_counters(state);
+    super::reset_watchers(state);
+    state.held_reads.borrow_mut().clear();
+    state.no_process_noted.borrow_mut().clear();
+    state.detected_versions.borrow_mut().clear();
+    state.symbols.borrow_mut().clear();
+    #[cfg(feature = "unity")]
+    state.unity.take();
+    #[cfg(feature = "unreal")]
+    state.unreal.take();
+    *state.maps_cache.borrow_mut() = None;
+    // `mapsCacheCycles` is only applied once, in `startup`, so it has to
+    // survive attaching again.
+    state
+        .maps_cache_cycles_value
+        .set(state.maps_cache_cycles.get());
+    // Without a header we know, the host is the best guess left.
+    match executable {
+        Some(executable) => {
+            state.os.set(Some(executable.os));
+            state.arch.set(Some(executable.arch));
+        }
+        None => {
+            state.os.set(Some(host_os()));
+            state.arch.set(Some(host_arch()));
+        }
+    }
+    state.attach_pending.set(true);
+
+    if state.reset_on_game_start.get()
+        && let TimerState::Running | TimerState::Paused = host::get().timer_state()
+    {
+        log::print_message("[resetOnGameStart] The game started, resetting the timer");
+        crate::reset_timer(state);
+    }
+}
+
+/// What the header of the main module says about the process.
+#[derive(Debug, PartialEq)]
+struct Executable {
+    os: &'static str,
+    arch: &'static str,
+    /// The size of a pointer in bytes.
+    pointer_size: Option<u64>,
+}
+
+/// Reads the PE, ELF or Mach-O header of the main module.
+fn read_executable(process: &dyn ProcessMemory, base_address: Address) -> Option<Executable> {
+    let magic: [u8; 4] = process.read_bytes(base_address).ok()?;
+
+    if magic == *b"\x7fELF" {
+        // EI_CLASS
+        let pointer_size = match process.read_bytes::<1>(base_address + 4).ok()? {
+            [1] => Some(4),
+            [2] => Some(8),
+            _ => None,
+        };
+        // e_machine
+        let arch = match u16::from_le_bytes(process.read_bytes(base_address + 0x12).ok()?) {
+            0x03 => "x86",
+            0x3E => "x86_64",
+            0xB7 => "arm64",
+            _ => "unknown",
+        };
+        return Some(Executable {
+            os: "linux",
+            arch,
+            pointer_size,
+        });
+    }
+
+    if magic[..2] == *b"MZ" {
+        let pe_offset = u32::from_le_bytes(process.read_bytes(base_address + 0x3C).ok()?);
+        let pe_header = base_address + pe_offset as u64;
+        if process.read_bytes::<4>(pe_header).ok()? != *b"PE\0\0" {
+            return None;
+        }
+        // IMAGE_FILE_HEADER.Machine
+        let (arch, pointer_size) = match u16::from_le_bytes(process.read_bytes(pe_header + 4).ok()?)
+        {
+            0x014C => ("x86", Some(4)),
+            0x01C4 => ("unknown", Some(4)),
+            0x8664 => ("x86_64", Some(8)),
+            0xAA64 => ("arm64", Some(8)),
+            _ => ("unknown", None),
+        };
+        return Some(Executable {
+            os: "windows",
+            arch,
+            pointer_size,
+        });
+    }
+
+    // MH_MAGIC and MH_MAGIC_64, the cputype follows the magic.
+    let pointer_size = match u32::from_le_bytes(magic) {
+        0xFEEDFACE => Some(4),
+        0xFEEDFACF => Some(8),
+        _ => return None,
+    };
+    let arch = match u32::from_le_bytes(process.read_bytes(base_address + 4).ok()?) {
+        0x0000_0007 => "x86",
+        0x0100_0007 => "x86_64",
+        0x0100_000C => "arm64",
+        _ => "unknown",
+    };
+    Some(Executable {
+        os: "macos",
+        arch,
+        pointer_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
+    };
+
+    const BASE: u64 = 0x40_0000;
+
+    fn game(header: Vec<u8>) -> Rc<MockProcess> {
+        Rc::new(
+            MockProcess::new()
+                .module("game", BASE, 0x1000)
+                .map(BASE, header),
+        )
+    }
+
+    fn pe(machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 0x200];
+        header[..2].copy_from_slice(b"MZ");
+        header[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        header[0x80..0x84].copy_from_slice(b"PE\0\0");
+        header[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+        header
+    }
+
+    fn elf(class: u8, machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 0x40];
+        header[..4].copy_from_slice(b"\x7fELF");
+        header[4] = class;
+        header[0x12..0x14].copy_from_slice(&machine.to_le_bytes());
+        header
+    }
+
+    fn mach_o(magic: u32, cputype: u32) -> Vec<u8> {
+        let mut header = vec![0; 0x20];
+        header[..4].copy_from_slice(&magic.to_le_bytes());
+        header[4..8].copy_from_slice(&cputype.to_le_bytes());
+        header
+    }
+
+    fn check(header: Vec<u8>, os: &str, arch: &str, is_64_bit: &str) {
+        let lua = lua();
+        attach(&lua, &game(header), "game");
+        run(
+            &lua,
+            &format!(
+                r#"
+                assert(getOS() == "{os}", getOS())
+                assert(getArch() == "{arch}", getArch())
+                assert(is64Bit() == {is_64_bit}, tostring(is64Bit()))
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn reads_pe_headers() {
+        check(pe(0x8664), "windows", "x86_64", "true");
+        check(pe(0x014C), "windows", "x86", "false");
+        check(pe(0xAA64), "windows", "arm64", "true");
+        check(pe(0x1234), "windows", "unknown", "nil");
+    }
+
+    #[test]
+    fn reads_elf_headers() {
+        check(elf(2, 0x3E), "linux", "x86_64", "true");
+        check(elf(1, 0x03), "linux", "x86", "false");
+        check(elf(2, 0xB7), "linux", "arm64", "true");
+    }
+
+    #[test]
+    fn reads_mach_o_headers() {
+        check(mach_o(0xFEEDFACF, 0x0100_0007), "macos", "x86_64", "true");
+        check(mach_o(0xFEEDFACF, 0x0100_000C), "macos", "arm64", "true");
+        check(mach_o(0xFEEDFACE, 0x0000_0007), "macos", "x86", "false");
+    }
+
+    #[test]
+    fn needs_a_header() {
+        let lua = lua();
+        attach(&lua, &game(vec![0; 0x40]), "game");
+        run(&lua, "assert(is64Bit() == nil)");
+
+        // A PE offset pointing at something that isn't a PE header.
+        let mut header = pe(0x8664);
+        header[0x80..0x84].copy_from_slice(b"NOPE");
+        attach(&lua, &game(header), "game");
+        run(&lua, "assert(is64Bit() == nil)");
+    }
+}
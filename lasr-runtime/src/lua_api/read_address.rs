@@ -1,196 +1,692 @@
-use std::str;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
-use asr::{Address, Address32, Address64};
+use asr::Address;
 use tsuki::{
-    Float, Value,
+    Value,
     context::{Args, Context, Ret},
 };
 
-use crate::state::{Result, State};
+use super::{
+    format_address::describe_address,
+    module_name::resolve_module,
+    process::note_no_process,
+    scans::{record_read, scan_address},
+    value_type::{MAX_CSTRING_LEN, ValueType},
+};
+use crate::{
+    log,
+    process_memory::ProcessMemory,
+    state::{Result, State},
+};
 
 pub fn read_address(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     let ty_arg = cx.arg(1);
-    let ty = ty_arg
+    let ty_name = ty_arg
         .to_str()?
         .as_utf8()
         .ok_or_else(|| ty_arg.error("type is not valid UTF-8"))?;
+    let ty = match ValueType::parse(ty_name) {
+        Ok(ty) => ty,
+        Err(msg) => {
+            log::warn(&format!(
+                "[readAddress] Invalid value type {ty_name}: {msg}, please read documentation"
+            ));
+            cx.push(Value::Nil)?;
+            return Ok(cx.into());
+        }
+    };
+
+    // A table after the address holds the options, a table in its place is a
+    // handle from `scanned`.
+    let mut last = cx.args();
+    let mut hold = None;
+    if last > 2
+        && let Some(options) = cx.arg(last).as_table()
+    {
+        hold = match options.get_str_key("hold") {
+            Value::True => Some(Duration::from_millis(DEFAULT_HOLD_MS)),
+            Value::Int(ms) => Some(Duration::from_millis(ms.max(0) as u64)),
+            Value::False | Value::Nil => None,
+            _ => {
+                return Err(cx
+                    .arg(last)
+                    .error("hold must be a boolean or a number of milliseconds"));
+            }
+        };
+        last -= 1;
+    }
+
+    let Some(path) = AddressPath::parse(&cx, "readAddress", 2, last)? else {
+        cx.push(Value::Nil)?;
+        return Ok(cx.into());
+    };
+    let hold = hold.map(|max_age| ((ty_name.to_owned(), path.clone()), max_age));
 
-    let value = {
+    // Where the path got to, even if it couldn't be followed all the way.
+    let mut resolved = None;
+    let value = 'read: {
         let process = &*cx.associated_data().process.borrow();
-        let process = process.as_ref().ok_or("no process attached")?;
+        let Some(process) = process.as_ref() else {
+            note_no_process(cx.associated_data(), "readAddress");
+            break 'read Value::Nil;
+        };
+
+        if cx.associated_data().process_died.get() {
+            break 'read Value::Nil;
+        }
+
+        let address = match path.follow_partially(cx.associated_data(), &**process, "readAddress") {
+            Ok(address) => address,
+            Err(last) => {
+                resolved = last;
+                break 'read Value::Nil;
+            }
+        };
+        resolved = Some(address);
+
+        let buf = read_value(cx.associated_data(), &**process, ty, address);
+        let value = match &buf {
+            Some(buf) => ty.decode(&cx, buf),
+            None => Value::Nil,
+        };
+
+        if matches!(value, Value::Nil) {
+            report_read_failure(cx.associated_data(), &**process, "readAddress", address);
+        } else if let Some((key, _)) = &hold {
+            cx.associated_data()
+                .held_reads
+                .borrow_mut()
+                .store(key.clone(), buf.unwrap());
+        }
+
+        value
+    };
+
+    if let Some(name) = path.scan_name() {
+        record_read(cx.associated_data(), name, !matches!(value, Value::Nil));
+    }
+
+    let resolved = match resolved {
+        Some(address) => Value::Int(address.value() as i64),
+        None => Value::Nil,
+    };
+
+    let Some((key, max_age)) = hold else {
+        cx.push(value)?;
+        cx.push(resolved)?;
+        return Ok(cx.into());
+    };
+
+    // Whether the value is an older one standing in for a failed read.
+    let mut stale = false;
+    let value = match value {
+        Value::Nil => match cx.associated_data().held_reads.borrow().get(&key, max_age) {
+            Some(bytes) => {
+                stale = true;
+                ty.decode(&cx, bytes)
+            }
+            None => Value::Nil,
+        },
+        value => value,
+    };
+
+    cx.push(value)?;
+    cx.push(resolved)?;
+    cx.push(stale)?;
+    Ok(cx.into())
+}
+
+/// How many bytes a `"string"` is read in at a time, until there's a NUL byte.
+const CSTRING_CHUNK_LEN: usize = 32;
 
-        let module_or_addr = cx.arg(2);
+/// Reads the bytes of a value of type `ty` at `address`. A `"string"` is read
+/// in chunks until the first NUL byte, which is left out. If a later chunk
+/// can't be read, like at the end of the memory's last page, the string ends
+/// with the chunks read so far.
+pub(super) fn read_value(
+    state: &State,
+    process: &dyn ProcessMemory,
+    ty: ValueType,
+    address: Address,
+) -> Option<Vec<u8>> {
+    if let Some(size) = ty.size() {
+        let mut buf = vec![0; size];
+        state.read_stats.count_read();
+        return process.read_into_buf(address, &mut buf).ok().map(|()| buf);
+    }
+
+    let mut bytes = Vec::new();
+    while bytes.len() < MAX_CSTRING_LEN {
+        let chunk_address = address + bytes.len() as u64;
+        // Chunks end at multiples of their length, so none of them straddles
+        // a page boundary.
+        let len = CSTRING_CHUNK_LEN - (chunk_address.value() as usize % CSTRING_CHUNK_LEN);
+        let mut chunk = [0; CSTRING_CHUNK_LEN];
+        let chunk = &mut chunk[..len.min(MAX_CSTRING_LEN - bytes.len())];
+
+        state.read_stats.count_read();
+        if process.read_into_buf(chunk_address, chunk).is_err() {
+            if bytes.is_empty() {
+                return None;
+            }
+            break;
+        }
+
+        if let Some(nul) = chunk.iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..nul]);
+            break;
+        }
+        bytes.extend_from_slice(chunk);
+    }
+    Some(bytes)
+}
+
+/// Follows the address given by the arguments `first` to `last`. Also returns
+/// the scan the address is based on, if any. The address is `None` if it can't
+/// be read, after telling the user why.
+pub(super) fn resolve_address(
+    cx: &Context<State, Args>,
+    process: &dyn ProcessMemory,
+    api: &str,
+    first: usize,
+    last: usize,
+) -> Result<(Option<Address>, Option<String>)> {
+    let Some(path) = AddressPath::parse(cx, api, first, last)? else {
+        return Ok((None, None));
+    };
+    let address = path.follow(cx.associated_data(), process, api);
+    Ok((address, path.scan_name().map(str::to_owned)))
+}
+
+/// How long `{hold = true}` keeps returning the last value read.
+const DEFAULT_HOLD_MS: u64 = 5000;
+
+/// The bytes last read with `hold`, by type name and address, for reads
+/// failing briefly, like during loading screens.
+#[derive(Default)]
+pub struct HeldReads(HashMap<(String, AddressPath), (Vec<u8>, Instant)>);
+
+impl HeldReads {
+    fn store(&mut self, key: (String, AddressPath), bytes: Vec<u8>) {
+        self.0.insert(key, (bytes, Instant::now()));
+    }
+
+    /// The bytes last read for `key`, unless they're older than `max_age`.
+    fn get(&self, key: &(String, AddressPath), max_age: Duration) -> Option<&[u8]> {
+        let (bytes, read_at) = self.0.get(key)?;
+        (read_at.elapsed() <= max_age).then_some(bytes.as_slice())
+    }
+
+    /// Forgets all values, as they're meaningless in a newly attached process.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Where a pointer path starts.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum PathBase {
+    /// A module name and an offset into it.
+    Module(String, i64),
+    /// A handle from `scanned`, with the named offset it's for, if any.
+    Scan(String, Option<String>),
+    /// An offset from the main module.
+    MainModule(i64),
+}
+
+/// An address as given to `readAddress`, kept around by APIs that read the
+/// same address again later.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(super) struct AddressPath {
+    base: PathBase,
+    /// Added after dereferencing the address so far, one after another.
+    offsets: Vec<i64>,
+}
+
+impl AddressPath {
+    /// Parses the arguments `first` to `last`: a module name and an offset, a
+    /// handle from `scanned` or an offset from the main module, then the
+    /// offsets of a pointer path. `None` for a `nil` address, after telling
+    /// the user.
+    pub(super) fn parse(
+        cx: &Context<State, Args>,
+        api: &str,
+        first: usize,
+        last: usize,
+    ) -> Result<Option<Self>> {
+        let module_or_addr = cx.arg(first);
 
         if matches!(module_or_addr.get(), Some(Value::Nil)) {
-            asr::print_message(
-                "[readAddress] The address argument cannot be nil. Check your auto splitter code.",
-            );
-            Value::Nil
+            log::warn(&format!(
+                "[{api}] The address argument cannot be nil. Check your auto splitter code."
+            ));
+            return Ok(None);
+        }
+
+        let (start_offsets, base) = if let Some(module) = module_or_addr.as_str(false) {
+            let module = module
+                .as_utf8()
+                .ok_or_else(|| module_or_addr.error("module name is not valid UTF-8"))?;
+            (
+                first + 2,
+                PathBase::Module(module.to_owned(), cx.arg(first + 1).to_int()?),
+            )
+        } else if let Some(handle) = module_or_addr.as_table() {
+            let Value::Str(name) = handle.get_str_key("scan") else {
+                return Err(module_or_addr.error("address table is not a handle from scanned"));
+            };
+            let name = name
+                .as_utf8()
+                .ok_or_else(|| module_or_addr.error("scan name is not valid UTF-8"))?;
+            let field = match handle.get_str_key("field") {
+                Value::Str(field) => Some(
+                    field
+                        .as_utf8()
+                        .ok_or_else(|| module_or_addr.error("offset name is not valid UTF-8"))?
+                        .to_owned(),
+                ),
+                _ => None,
+            };
+            (first + 1, PathBase::Scan(name.to_owned(), field))
         } else {
-            let (start_offsets, mut address) = if let Some(module) = module_or_addr.as_str(false) {
-                let module = module
-                    .as_utf8()
-                    .ok_or_else(|| module_or_addr.error("module name is not valid UTF-8"))?;
+            (first + 1, PathBase::MainModule(module_or_addr.to_int()?))
+        };
 
-                let base = process.get_module_address(module).unwrap_or(Address::NULL);
+        let offsets = (start_offsets..=last)
+            .map(|i| cx.arg(i).to_int())
+            .collect::<Result<_, _>>()?;
 
-                (4, base + cx.arg(3).to_int()? as u64)
-            } else {
-                (
-                    3,
-                    cx.associated_data().base_address.get() + module_or_addr.to_int()? as u64,
-                )
-            };
+        Ok(Some(Self { base, offsets }))
+    }
 
-            let mut memory_error = false;
+    /// A path starting at `offset` into `module`, or into the main module if
+    /// it's `None`.
+    pub(super) fn new(module: Option<String>, offset: i64, offsets: Vec<i64>) -> Self {
+        let base = match module {
+            Some(module) => PathBase::Module(module, offset),
+            None => PathBase::MainModule(offset),
+        };
+        Self { base, offsets }
+    }
 
-            for i in start_offsets..=cx.args() {
-                if address.value() <= u32::MAX as u64 {
-                    address = match process.read::<Address32>(address) {
-                        Ok(next) => next.into(),
-                        Err(_) => {
-                            memory_error = true;
-                            break;
-                        }
-                    };
-                } else {
-                    address = match process.read::<Address64>(address) {
-                        Ok(next) => next.into(),
-                        Err(_) => {
-                            memory_error = true;
-                            break;
-                        }
-                    };
-                }
-                address = address + cx.arg(i).to_int()? as u64;
-            }
+    /// The scan the address is based on, if it came from `scanned`.
+    pub(super) fn scan_name(&self) -> Option<&str> {
+        match &self.base {
+            PathBase::Scan(name, _) => Some(name),
+            _ => None,
+        }
+    }
 
-            if memory_error {
-                asr::print_message("[readAddress] Failed to read process memory");
-                Value::Nil
-            } else {
-                let mut suppress_memory_error = false;
-                let value = match ty {
-                    "sbyte" => match process.read::<i8>(address) {
-                        Ok(v) => Value::Int(v as _),
-                        Err(_) => Value::Nil,
-                    },
-                    "byte" => match process.read::<u8>(address) {
-                        Ok(v) => Value::Int(v as _),
-                        Err(_) => Value::Nil,
-                    },
-                    "short" => match process.read::<i16>(address) {
-                        Ok(v) => Value::Int(v as _),
-                        Err(_) => Value::Nil,
-                    },
-                    "ushort" => match process.read::<u16>(address) {
-                        Ok(v) => Value::Int(v as _),
-                        Err(_) => Value::Nil,
-                    },
-                    "int" => match process.read::<i32>(address) {
-                        Ok(v) => Value::Int(v as _),
-                        Err(_) => Value::Nil,
-                    },
-                    "uint" => match process.read::<u32>(address) {
-                        Ok(v) => Value::Int(v as _),
-                        Err(_) => Value::Nil,
-                    },
-                    "long" => match process.read::<i64>(address) {
-                        Ok(v) => Value::Int(v),
-                        Err(_) => Value::Nil,
-                    },
-                    "ulong" => match process.read::<u64>(address) {
-                        Ok(v) => Value::Int(v as _),
-                        Err(_) => Value::Nil,
-                    },
-                    "float" => match process.read::<f32>(address) {
-                        Ok(v) => Value::Float(Float(v as _)),
-                        Err(_) => Value::Nil,
-                    },
-                    "double" => match process.read::<f64>(address) {
-                        Ok(v) => Value::Float(Float(v)),
-                        Err(_) => Value::Nil,
-                    },
-                    "bool" => match process.read::<u8>(address) {
-                        Ok(v) => {
-                            if v == 0 {
-                                Value::False
-                            } else {
-                                Value::True
-                            }
+    /// Follows the pointer path. `None` if it can't be followed, after telling
+    /// the user why.
+    pub(super) fn follow(
+        &self,
+        state: &State,
+        process: &dyn ProcessMemory,
+        api: &str,
+    ) -> Option<Address> {
+        self.follow_partially(state, process, api).ok()
+    }
+
+    /// Like [`follow`](Self::follow), but if a pointer can't be read, the error
+    /// is the address it was to be read from. `Err(None)` if not even the base
+    /// could be found.
+    pub(super) fn follow_partially(
+        &self,
+        state: &State,
+        process: &dyn ProcessMemory,
+        api: &str,
+    ) -> Result<Address, Option<Address>> {
+        let mut address = match &self.base {
+            PathBase::Module(module, offset) => {
+                let cached = state.module_addresses.borrow().get(module).copied();
+                let base = match cached {
+                    Some(base) => base,
+                    None => match resolve_module(state, process, module) {
+                        Some((_, base)) => {
+                            state
+                                .module_addresses
+                                .borrow_mut()
+                                .insert(module.clone(), base);
+                            base
                         }
-                        Err(_) => Value::Nil,
-                    },
-                    _ => {
-                        if let Some(rem) = ty.strip_prefix("string") {
-                            match rem.parse::<usize>() {
-                                Ok(byte_count) if byte_count >= 2 => {
-                                    let mut buf = vec![0; byte_count];
-                                    if process.read_into_buf(address, &mut buf).is_err() {
-                                        asr::print_message(
-                                            "[readAddress] Failed to read process memory",
-                                        );
-                                        Value::Nil
-                                    } else {
-                                        let len =
-                                            buf.iter().position(|&b| b == 0).unwrap_or(byte_count);
-                                        match str::from_utf8(&buf[..len]) {
-                                            Ok(s) => Value::Str(cx.create_str(s)),
-                                            Err(_) => Value::Nil,
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    asr::print_message(
-                                        "[readAddress] Invalid string size, please read documentation",
-                                    );
-                                    suppress_memory_error = true;
-                                    Value::Nil
-                                }
-                            }
-                        } else if let Some(rem) = ty.strip_prefix("byte") {
-                            match rem.parse::<usize>() {
-                                Ok(byte_count) if byte_count >= 1 => {
-                                    let mut buf = vec![0u8; byte_count];
-                                    if process.read_into_buf(address, &mut buf).is_err() {
-                                        asr::print_message(
-                                            "[readAddress] Failed to read process memory",
-                                        );
-                                        Value::Nil
-                                    } else {
-                                        let table = cx.create_table();
-                                        for (i, byte) in buf.into_iter().enumerate() {
-                                            table.set((i + 1) as i64, byte as i64).unwrap();
-                                        }
-
-                                        Value::Table(table)
-                                    }
-                                }
-                                _ => {
-                                    asr::print_message(
-                                        "[readAddress] Invalid byte array size, please read documentation",
-                                    );
-                                    suppress_memory_error = true;
-                                    Value::Nil
-                                }
+                        None => {
+                            // Reading relative to address 0 instead would only
+                            // ever produce garbage.
+                            if state
+                                .missing_modules_warned
+                                .borrow_mut()
+                                .insert(module.clone())
+                            {
+                                log::warn(&format!("[{api}] module '{module}' not found"));
                             }
-                        } else {
-                            asr::print_message(&format!("[readAddress] Invalid value type: {ty}"));
-                            suppress_memory_error = true;
-                            Value::Nil
+                            return Err(None);
                         }
-                    }
+                    },
                 };
-
-                if matches!(value, Value::Nil) && !suppress_memory_error {
-                    asr::print_message("[readAddress] Failed to read process memory");
-                }
-
-                value
+                base + *offset as u64
+            }
+            PathBase::Scan(name, field) => {
+                scan_address(state, name, field.as_deref()).ok_or(None)?
             }
+            PathBase::MainModule(offset) => state.base_address.get() + *offset as u64,
+        };
+
+        for &offset in &self.offsets {
+            // Without knowing the pointer size, guess it from the address.
+            let pointer_size = state
+                .pointer_size_override
+                .get()
+                .or(state.pointer_size.get())
+                .unwrap_or(if address.value() <= u32::MAX as u64 {
+                    4
+                } else {
+                    8
+                });
+            state.read_stats.count_read();
+            let next = if pointer_size == 4 {
+                process
+                    .read_bytes(address)
+                    .map(|bytes| u64::from(u32::from_le_bytes(bytes)))
+            } else {
+                process.read_bytes(address).map(u64::from_le_bytes)
+            };
+            let Ok(next) = next else {
+                report_read_failure(state, process, api, address);
+                return Err(Some(address));
+            };
+            address = Address::new(next) + offset as u64;
         }
+
+        Ok(address)
+    }
+}
+
+/// Reads failing because the game just closed aren't worth a message each,
+/// the main loop notices and goes on to `onExit` instead.
+pub(super) fn report_read_failure(
+    state: &State,
+    process: &dyn ProcessMemory,
+    api: &str,
+    address: Address,
+) {
+    if process.is_open() {
+        log::print_message(&format!(
+            "[{api}] Failed to read process memory at {}",
+            describe_address(state, Some(process), address)
+        ));
+    } else {
+        state.process_died.set(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
     };
 
-    cx.push(value)?;
-    Ok(cx.into())
+    fn put(memory: &mut [u8], offset: usize, bytes: &[u8]) {
+        memory[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// `game.exe` at 0x10000 with a pointer at +0x10 to a struct at 0x20000,
+    /// whose pointer at +0x8 leads to the values at 0x30000. The same chain
+    /// exists with 64-bit pointers at +0x20.
+    fn game() -> Rc<MockProcess> {
+        let mut module = vec![0; 0x100];
+        put(&mut module, 0x10, &0x20000u32.to_le_bytes());
+        put(&mut module, 0x20, &0x2_0000_0000u64.to_le_bytes());
+
+        let mut object = vec![0; 0x100];
+        put(&mut object, 0x8, &0x30000u32.to_le_bytes());
+
+        let mut object_64 = vec![0; 0x100];
+        put(&mut object_64, 0x8, &0x3_0000_0000u64.to_le_bytes());
+        put(&mut object_64, 0x10, &0x40000u64.to_le_bytes());
+
+        let mut values = vec![0; 0x100];
+        put(&mut values, 0x4, &42i32.to_le_bytes());
+        put(&mut values, 0x8, &(-2i32).to_le_bytes());
+        put(&mut values, 0x10, &1.5f32.to_le_bytes());
+        put(&mut values, 0x18, &(-0.25f64).to_le_bytes());
+        put(&mut values, 0x20, &[1]);
+        put(&mut values, 0x30, b"hello\0world");
+        put(&mut values, 0x40, &[b'h', 0, b'i', 0, 0, 0]);
+        put(&mut values, 0x50, &[0xDE, 0xAD, 0xBE, 0xEF]);
+        put(
+            &mut values,
+            0x60,
+            &[1.0f32, 2.0, 3.0].map(f32::to_le_bytes).concat(),
+        );
+        put(
+            &mut values,
+            0x70,
+            &[7i32, -8, 9].map(i32::to_le_bytes).concat(),
+        );
+
+        Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x10000, module)
+                .map(0x20000, object)
+                .map(0x2_0000_0000, object_64)
+                .map(0x30000, values.clone())
+                .map(0x3_0000_0000, values),
+        )
+    }
+
+    #[test]
+    fn follows_pointer_paths() {
+        let lua = lua();
+        attach(&lua, &game(), "game.exe");
+        run(
+            &lua,
+            r#"
+            local value, at = readAddress("int", 0x10, 0x8, 0x4)
+            assert(value == 42 and at == 0x30004)
+            assert(readAddress("int", "game.exe", 0x10, 0x8, 0x4) == 42)
+            assert(readAddress("uint", 0x10) == 0x20000)
+
+            -- The pointer at 0x20000 + 0x10 is null, so the path leads to
+            -- unmapped memory, and the address is where it got to.
+            local value, at = readAddress("int", 0x10, 0x10, 0x4)
+            assert(value == nil and at == 0x4)
+            local value, at = readAddress("int", 0x10, 0x10, 0x4, 0x8)
+            assert(value == nil and at == 0x4)
+
+            assert(readAddress("int", "missing.dll", 0x10) == nil)
+            "#,
+        );
+    }
+
+    #[test]
+    fn follows_64_bit_pointers() {
+        let lua = lua();
+        attach(&lua, &game(), "game.exe");
+        run(
+            &lua,
+            r#"
+            setPointerSize(8)
+            local value, at = readAddress("int", 0x20, 0x8, 0x4)
+            assert(value == 42 and at == 0x300000004)
+            assert(readAddress("int", 0x20, 0x10, 0x4) == nil)
+            "#,
+        );
+    }
+
+    #[test]
+    fn decodes_every_type() {
+        let lua = lua();
+        attach(&lua, &game(), "game.exe");
+        run(
+            &lua,
+            r#"
+            local function read(ty, offset)
+                return readAddress(ty, 0x10, 0x8, offset)
+            end
+
+            assert(read("int", 0x8) == -2)
+            assert(read("uint", 0x8) == 0xFFFFFFFE)
+            assert(read("sbyte", 0x8) == -2)
+            assert(read("byte", 0x8) == 0xFE)
+            assert(read("short", 0x8) == -2)
+            assert(read("ushort", 0x8) == 0xFFFE)
+            assert(read("long", 0x4) == 0xFFFFFFFE0000002A)
+            assert(read("float", 0x10) == 1.5)
+            assert(read("double", 0x18) == -0.25)
+            assert(read("bool", 0x20) == true and read("bool", 0x21) == false)
+
+            assert(read("string", 0x30) == "hello")
+            assert(read("cstring", 0x36) == "world")
+            assert(read("string4", 0x30) == "hell")
+            assert(read("wstring3", 0x40) == "hi")
+
+            local bytes = read("byte4", 0x50)
+            assert(#bytes == 4 and bytes[1] == 0xDE and bytes[4] == 0xEF)
+            local vec = read("vec3", 0x60)
+            assert(vec.x == 1 and vec.y == 2 and vec.z == 3 and vec.w == nil)
+            local ints = read("int[3]", 0x70)
+            assert(#ints == 3 and ints[1] == 7 and ints[2] == -8 and ints[3] == 9)
+
+            assert(read("nonsense", 0x0) == nil)
+            -- Too large to read, instead of taking all of the memory.
+            assert(read("long[2305843009213693952]", 0x70) == nil)
+            assert(read("int[4194305]", 0x70) == nil)
+            "#,
+        );
+    }
+
+    #[test]
+    fn reads_at_odd_addresses() {
+        // Every value starts at an odd address, the pointers included.
+        let mut module = vec![0; 0x100];
+        put(&mut module, 0x11, &0x20003u32.to_le_bytes());
+        put(&mut module, 0x21, &(-123_456i32).to_le_bytes());
+        put(&mut module, 0x27, &0x1234_5678_9ABC_DEF0u64.to_le_bytes());
+        put(&mut module, 0x33, &(-1.75f32).to_le_bytes());
+        put(&mut module, 0x39, &1e300f64.to_le_bytes());
+        put(&mut module, 0x45, &(-3i16).to_le_bytes());
+        put(
+            &mut module,
+            0x4B,
+            &[4.0f32, 5.5, -6.0].map(f32::to_le_bytes).concat(),
+        );
+
+        let mut object = vec![0; 0x100];
+        put(&mut object, 0x5, &0x3_0000_0001u64.to_le_bytes());
+        put(&mut object, 0xF, &0x7FFFu16.to_le_bytes());
+
+        let mut values = vec![0; 0x10];
+        put(&mut values, 0x1, &2.5f64.to_le_bytes());
+
+        let game = Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x10000, module)
+                .map(0x20000, object)
+                .map(0x3_0000_0000, values),
+        );
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            assert(readAddress("int", 0x21) == -123456)
+            assert(readAddress("long", 0x27) == 0x123456789ABCDEF0)
+            assert(readAddress("float", 0x33) == -1.75)
+            assert(readAddress("double", 0x39) == 1e300)
+            assert(readAddress("short", 0x45) == -3)
+            local vec = readAddress("vec3", 0x4B)
+            assert(vec.x == 4 and vec.y == 5.5 and vec.z == -6)
+
+            local value, at = readAddress("ushort", 0x11, 0xC)
+            assert(value == 0x7FFF and at == 0x2000F)
+
+            setPointerSize(8)
+            local value, at = readAddress("double", 0x11, 0x2, 0x0)
+            assert(value == 2.5 and at == 0x300000001)
+            "#,
+        );
+    }
+
+    #[test]
+    fn holds_values_while_reads_fail() {
+        let game = game();
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        let read = r#"
+            local function read()
+                return readAddress("int", 0x10, 0x8, 0x4, { hold = true })
+            end
+        "#;
+        run(
+            &lua,
+            &format!(
+                r#"{read}
+                local value, at, stale = read()
+                assert(value == 42 and at == 0x30004 and stale == false)
+                "#
+            ),
+        );
+
+        // The struct now points to unmapped memory.
+        game.write(0x20008, &0x50000u32.to_le_bytes());
+        run(
+            &lua,
+            &format!(
+                r#"{read}
+                local value, at, stale = read()
+                assert(value == 42 and at == 0x50004 and stale == true)
+                local value, at, stale = readAddress("int", 0x10, 0x8, 0x4)
+                assert(value == nil and at == 0x50004 and stale == nil)
+                "#
+            ),
+        );
+
+        // The path can't be followed all the way anymore.
+        game.write(0x10010, &0u32.to_le_bytes());
+        run(
+            &lua,
+            &format!(
+                r#"{read}
+                local value, at, stale = read()
+                assert(value == 42 and at == 0x8 and stale == true)
+                "#
+            ),
+        );
+    }
+
+    #[test]
+    fn stops_reading_once_the_game_closed() {
+        let game = game();
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(&lua, r#"assert(readAddress("int", 0x10, 0x8, 0x4) == 42)"#);
+        assert!(!lua.associated_data().process_died.get());
+
+        // The game exits between two reads of the same tick.
+        game.close();
+        run(&lua, r#"assert(readAddress("int", 0x10, 0x8, 0x4) == nil)"#);
+        assert!(lua.associated_data().process_died.get());
+
+        // The rest of the tick doesn't touch the process anymore.
+        let reads = game.reads.get();
+        run(
+            &lua,
+            r#"
+            assert(readAddress("int", 0x10, 0x8, 0x4) == nil)
+            assert(readAddress("float", 0x10, 0x8, 0x10) == nil)
+            assert(readPointerPath("int", nil, 0x10, { 0x8, 0x4 }) == nil)
+            assert(snapshot(0x10, 0x10) == nil)
+            "#,
+        );
+        assert_eq!(game.reads.get(), reads);
+    }
 }
@@ -0,0 +1,228 @@
+use tsuki::{
+    Table, Value,
+    context::{Args, Context, Ret},
+};
+
+use super::{
+    process::note_no_process,
+    read_address::{AddressPath, read_value, report_read_failure},
+    value_type::ValueType,
+};
+use crate::{
+    process_memory::ProcessMemory,
+    state::{Result, State},
+};
+
+/// `readPointerPath(type, module, baseOffset, {offsets...})` reads like
+/// `readAddress`, with the offsets in a table and `nil` as the module for the
+/// main module. `readPointerPath({{type, module, baseOffset, {offsets...}},
+/// ...})` reads all of the paths in a single call and returns a table of their
+/// values by index, without the ones that couldn't be read. That only batches
+/// the calls from Lua, every pointer and value is still read on its own.
+pub fn read_pointer_path(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    if cx.args() == 1
+        && let Some(paths) = cx.arg(1).as_table()
+    {
+        let mut parsed = Vec::new();
+        for i in 1.. {
+            match paths.get(Value::Int(i)) {
+                Value::Table(path) => parsed.push(
+                    parse_path_table(&path)
+                        .map_err(|(_, msg)| cx.arg(1).error(format!("path {i}: {msg}")))?,
+                ),
+                Value::Nil => break,
+                _ => {
+                    return Err(cx.arg(1).error(format!(
+                        "path {i} must be a table of type, module, offset and offsets"
+                    )));
+                }
+            }
+        }
+
+        let values = cx.create_table();
+        let process = cx.associated_data().process.borrow();
+        if let Some(process) = attached(cx.associated_data(), process.as_deref()) {
+            for (i, (ty, path)) in parsed.iter().enumerate() {
+                if let Some(bytes) = read(cx.associated_data(), process, *ty, path) {
+                    values.set(Value::Int(i as i64 + 1), ty.decode(&cx, &bytes))?;
+                }
+            }
+        }
+        drop(process);
+
+        cx.push(Value::Table(values))?;
+        return Ok(cx.into());
+    }
+
+    let (ty, path) = parse_path(
+        cx.arg(1).get().unwrap_or(Value::Nil),
+        cx.arg(2).get().unwrap_or(Value::Nil),
+        cx.arg(3).get().unwrap_or(Value::Nil),
+        cx.arg(4).get().unwrap_or(Value::Nil),
+    )
+    .map_err(|(n, msg)| cx.arg(n).error(msg))?;
+
+    let value = {
+        let process = cx.associated_data().process.borrow();
+        attached(cx.associated_data(), process.as_deref())
+            .and_then(|process| read(cx.associated_data(), process, ty, &path))
+            .map_or(Value::Nil, |bytes| ty.decode(&cx, &bytes))
+    };
+
+    cx.push(value)?;
+    Ok(cx.into())
+}
+
+/// Why a path is invalid, along with which of its four parts is to blame.
+type PathError = (usize, &'static str);
+
+fn parse_path_table(
+    path: &Table<State>,
+) -> std::result::Result<(ValueType, AddressPath), PathError> {
+    parse_path(
+        path.get(Value::Int(1)),
+        path.get(Value::Int(2)),
+        path.get(Value::Int(3)),
+        path.get(Value::Int(4)),
+    )
+}
+
+fn parse_path(
+    ty: Value<State>,
+    module: Value<State>,
+    offset: Value<State>,
+    offsets: Value<State>,
+) -> std::result::Result<(ValueType, AddressPath), PathError> {
+    let Value::Str(ty) = ty else {
+        return Err((1, "type must be a string"));
+    };
+    let ty = ty.as_utf8().ok_or((1, "type is not valid UTF-8"))?;
+    let ty = ValueType::parse(ty).map_err(|msg| (1, msg))?;
+
+    let module = match module {
+        Value::Str(module) => Some(
+            module
+                .as_utf8()
+                .ok_or((2, "module name is not valid UTF-8"))?
+                .to_owned(),
+        ),
+        Value::Nil => None,
+        _ => return Err((2, "module must be a string, or nil for the main module")),
+    };
+
+    let Value::Int(offset) = offset else {
+        return Err((3, "baseOffset must be an integer"));
+    };
+
+    let mut path_offsets = Vec::new();
+    match offsets {
+        Value::Table(offsets) => {
+            for i in 1.. {
+                match offsets.get(Value::Int(i)) {
+                    Value::Int(offset) => path_offsets.push(offset),
+                    Value::Nil => break,
+                    _ => return Err((4, "offsets must be integers")),
+                }
+            }
+        }
+        Value::Nil => {}
+        _ => return Err((4, "offsets must be a table")),
+    }
+
+    Ok((ty, AddressPath::new(module, offset, path_offsets)))
+}
+
+/// The process to read from, unless there's none or it just closed.
+fn attached<'a>(
+    state: &State,
+    process: Option<&'a dyn ProcessMemory>,
+) -> Option<&'a dyn ProcessMemory> {
+    let Some(process) = process else {
+        note_no_process(state, "readPointerPath");
+        return None;
+    };
+    (!state.process_died.get()).then_some(process)
+}
+
+fn read(
+    state: &State,
+    process: &dyn ProcessMemory,
+    ty: ValueType,
+    path: &AddressPath,
+) -> Option<Vec<u8>> {
+    let address = path.follow(state, process, "readPointerPath")?;
+    let bytes = read_value(state, process, ty, address);
+    if bytes.is_none() {
+        report_read_failure(state, process, "readPointerPath", address);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
+    };
+
+    #[test]
+    fn reads_paths_in_batches() {
+        let mut module = vec![0; 0x100];
+        module[0x10..0x14].copy_from_slice(&0x20000u32.to_le_bytes());
+        let mut values = vec![0; 0x100];
+        values[0x8..0xC].copy_from_slice(&42i32.to_le_bytes());
+        let game = Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x10000, module)
+                .map(0x20000, values),
+        );
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            assert(readPointerPath("int", nil, 0x10, { 0x8 }) == 42)
+            assert(readPointerPath("uint", "game.exe", 0x10) == 0x20000)
+
+            local values = readPointerPath({
+                { "int", nil, 0x10, { 0x8 } },
+                { "int", "missing.dll", 0x10 },
+                { "byte", nil, 0x10 },
+            })
+            assert(values[1] == 42 and values[2] == nil and values[3] == 0)
+            "#,
+        );
+        let reads = game.reads.get();
+        run(&lua, r#"readPointerPath({ { "int", nil, 0x10, { 0x8 } } })"#);
+        // The pointer and the value, batching doesn't merge any reads.
+        assert_eq!(game.reads.get() - reads, 2);
+    }
+
+    #[test]
+    fn blames_the_argument_at_fault() {
+        let lua = lua();
+        run(
+            &lua,
+            r##"
+            local function fails(expected, ...)
+                local ok, err = pcall(readPointerPath, ...)
+                assert(not ok and err:find(expected, 1, true), err)
+            end
+
+            fails("#1", 42, nil, 0x10)
+            fails("#2", "int", 42, 0x10)
+            fails("#3", "int", nil, "0x10")
+            fails("#4", "int", nil, 0x10, 8)
+            fails("#1", "unknown", nil, 0x10)
+            fails("path 2: offsets must be integers", {
+                { "int", nil, 0x10 },
+                { "int", nil, 0x10, { "8" } },
+            })
+            fails("path 1 must be a table", { "int" })
+            "##,
+        );
+    }
+}
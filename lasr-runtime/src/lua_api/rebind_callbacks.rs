@@ -0,0 +1,9 @@
+use tsuki::context::{Args, Context, Ret};
+
+use crate::state::{Result, State};
+
+pub fn rebind_callbacks(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    // The main loop picks this up before running the callbacks again.
+    cx.associated_data().rebind_callbacks.set(true);
+    Ok(cx.into())
+}
@@ -0,0 +1,15 @@
+use tsuki::context::{Args, Context, Ret};
+
+use crate::state::{Result, State};
+
+/// Keeps a script from spinning the loop without ever yielding to the host.
+const MAX_BURST: i64 = 10;
+
+pub fn request_burst(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let passes = cx.arg(1).to_int()?.clamp(0, MAX_BURST) as u32;
+
+    let burst = &cx.associated_data().burst;
+    burst.set(burst.get().max(passes));
+
+    Ok(cx.into())
+}
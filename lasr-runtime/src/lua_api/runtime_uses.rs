@@ -0,0 +1,133 @@
+use std::cell::Cell;
+
+use tsuki::context::{Args, Context, Ret};
+
+use crate::{
+    API_LEVEL, log,
+    state::{Result, State},
+};
+
+/// The optional host functions the runtime knows about, along with the API
+/// level from which on it calls them. `None` for those it doesn't call at
+/// all. Hosts can't be asked what they implement, calling a missing function
+/// traps, so this only says what the runtime uses, not what the host has.
+const CAPABILITIES: [(&str, Option<i64>); 4] = [
+    // `setVariable` and friends always pass the value as text.
+    ("typed_variables", None),
+    // `process` attaches by name, without listing the running processes.
+    ("process_lists", None),
+    ("tick_rate", Some(1)),
+    ("settings_maps", Some(15)),
+];
+
+/// One of the `CAPABILITIES`, by its index.
+#[derive(Clone, Copy)]
+pub enum Capability {
+    TickRate = 2,
+    SettingsMaps = 3,
+}
+
+/// Which of the `CAPABILITIES` the runtime uses, and which ones it already
+/// warned about not using.
+pub struct HostFeatures {
+    used: [Cell<bool>; CAPABILITIES.len()],
+    warned: [Cell<bool>; CAPABILITIES.len()],
+}
+
+impl HostFeatures {
+    pub fn new() -> Self {
+        Self {
+            used: CAPABILITIES
+                .map(|(_, since)| Cell::new(since.is_some_and(|level| level <= API_LEVEL))),
+            warned: Default::default(),
+        }
+    }
+
+    /// Whether the runtime uses `capability`. If it doesn't, `api` warns once
+    /// that it does nothing instead.
+    pub fn check(&self, capability: Capability, api: &str) -> bool {
+        let index = capability as usize;
+        if self.used[index].get() {
+            return true;
+        }
+        if !self.warned[index].replace(true) {
+            log::warn(&format!(
+                "[{api}] The runtime doesn't use the host's {}, so this does nothing",
+                CAPABILITIES[index].0
+            ));
+        }
+        false
+    }
+
+    #[cfg(test)]
+    pub fn set(&self, capability: Capability, used: bool) {
+        self.used[capability as usize].set(used);
+    }
+}
+
+/// `runtimeUses(name)`, whether the runtime calls the host for one of the
+/// `CAPABILITIES`. It doesn't ask the host, which can't tell. Unknown names are an error, so a typo doesn't look like
+/// a missing capability.
+pub fn runtime_uses(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let name = cx.arg(1);
+    let name = name
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| name.error("name is not valid UTF-8"))?;
+
+    let Some(index) = CAPABILITIES.iter().position(|(known, _)| *known == name) else {
+        let known = CAPABILITIES.map(|(known, _)| known).join(", ");
+        return Err(cx
+            .arg(1)
+            .error(format!("unknown capability, expected one of {known}")));
+    };
+
+    let used = cx.associated_data().host_features.used[index].get();
+    cx.push(used)?;
+    Ok(cx.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Capability;
+    use crate::testing::{lua, run};
+
+    #[test]
+    fn reports_what_the_runtime_counts_on() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(runtimeUses("tick_rate") == true)
+            assert(runtimeUses("settings_maps") == true)
+            assert(runtimeUses("typed_variables") == false)
+            assert(runtimeUses("process_lists") == false)
+            local ok, err = pcall(runtimeUses, "tickrate")
+            assert(not ok and err:find("typed_variables, process_lists", 1, true))
+            "#,
+        );
+
+        lua.associated_data()
+            .host_features
+            .set(Capability::SettingsMaps, false);
+        run(&lua, r#"assert(runtimeUses("settings_maps") == false)"#);
+    }
+
+    #[test]
+    fn ignores_unused_settings_maps() {
+        let lua = lua();
+        lua.associated_data()
+            .host_features
+            .set(Capability::SettingsMaps, false);
+        run(
+            &lua,
+            r#"
+            settings.add("fast", true, "Fast")
+            for _ = 1, 3 do
+                assert(settings.get("fast") == nil)
+            end
+            "#,
+        );
+        assert!(lua.associated_data().settings_map.borrow().is_none());
+    }
+}
@@ -0,0 +1,297 @@
+use std::time::{Duration, Instant};
+
+use asr::Address;
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use super::{
+    module_name::resolve_module,
+    process::note_no_process,
+    sig_scan::{ScanOffsets, scan_offsets},
+};
+use crate::{
+    log,
+    sigscan::{SigByte, parse_signature, scan_signature, signature_matches},
+    state::{Result, State},
+};
+
+/// How many reads in a row through a scan may fail before it's run again.
+/// Reads fail for a tick or two all the time, during loading screens for
+/// example, so this waits for a while first.
+const RESCAN_AFTER_FAILURES: u32 = 120;
+
+/// A signature scan registered through `registerScan`.
+#[derive(Clone)]
+pub struct Scan {
+    signature: Vec<SigByte>,
+    offsets: ScanOffsets,
+    /// Restricts the scan to this module, if set.
+    module: Option<String>,
+    /// Where the signature was found last, if it was.
+    address: Option<Address>,
+    failed_reads: u32,
+    /// How often to check that the signature is still at `address`, if at
+    /// all.
+    revalidate: Option<Duration>,
+    revalidated: Instant,
+}
+
+impl Scan {
+    /// The named offset `field` passed to `registerScan`, if there is one.
+    fn field_offset(&self, field: &str) -> Option<i64> {
+        match &self.offsets {
+            ScanOffsets::Named(named) => named
+                .iter()
+                .find(|(name, _)| name == field)
+                .map(|&(_, offset)| offset),
+            ScanOffsets::Single(_) => None,
+        }
+    }
+}
+
+pub async fn register_scan<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, State, Ret>> {
+    let name_arg = cx.arg(1);
+    let name = name_arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| name_arg.error("name is not valid UTF-8"))?
+        .to_owned();
+
+    let pattern_arg = cx.arg(2);
+    let signature = parse_signature(
+        pattern_arg
+            .to_str()?
+            .as_utf8()
+            .ok_or_else(|| pattern_arg.error("signature is not valid UTF-8"))?,
+    )
+    .map_err(|msg| pattern_arg.error(msg))?;
+
+    let offsets = scan_offsets(&cx, 3)?;
+
+    let module_arg = cx.arg(4);
+    let module = match module_arg.to_nilable_str(false)? {
+        Some(module) => Some(
+            module
+                .as_utf8()
+                .ok_or_else(|| module_arg.error("module name is not valid UTF-8"))?
+                .to_owned(),
+        ),
+        None => None,
+    };
+
+    let mut revalidate = None;
+    if let Some(options) = cx.arg(5).as_table() {
+        revalidate = match options.get_str_key("revalidateSecs") {
+            Value::Int(secs) if secs > 0 => Some(Duration::from_secs(secs as u64)),
+            Value::Float(secs) if secs.0 > 0.0 => Some(Duration::from_secs_f64(secs.0)),
+            Value::Nil => None,
+            _ => {
+                return Err(cx.arg(5).error("revalidateSecs must be a positive number"));
+            }
+        };
+    }
+
+    let mut scan = Scan {
+        signature,
+        offsets,
+        module,
+        address: None,
+        failed_reads: 0,
+        revalidate,
+        revalidated: Instant::now(),
+    };
+    scan.address = run_scan(cx.associated_data(), &scan).await?;
+
+    cx.push(match (&scan.offsets, scan.address) {
+        (ScanOffsets::Single(_), Some(address)) => Value::Int(address.value() as i64),
+        (ScanOffsets::Single(_), None) => Value::Nil,
+        (ScanOffsets::Named(named), address) => {
+            let table = cx.create_table();
+            if let Some(address) = address {
+                for (name, offset) in named {
+                    table.set_str_key(
+                        name.as_str(),
+                        Value::Int((address + *offset as u64).value() as i64),
+                    );
+                }
+            }
+            Value::Table(table)
+        }
+    })?;
+    cx.associated_data().scans.borrow_mut().insert(name, scan);
+
+    Ok(cx.into())
+}
+
+pub fn scanned(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let name_arg = cx.arg(1);
+    let name = name_arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| name_arg.error("name is not valid UTF-8"))?;
+
+    let field_arg = cx.arg(2);
+    let field = match field_arg.to_nilable_str(false)? {
+        Some(field) => Some(
+            field
+                .as_utf8()
+                .ok_or_else(|| field_arg.error("offset name is not valid UTF-8"))?,
+        ),
+        None => None,
+    };
+
+    match cx.associated_data().scans.borrow().get(name) {
+        None => {
+            return Err(name_arg.error("no scan with this name, call registerScan first"));
+        }
+        Some(scan) => {
+            if let Some(field) = field
+                && scan.field_offset(field).is_none()
+            {
+                return Err(field_arg.error("the scan has no offset with this name"));
+            }
+        }
+    }
+
+    let handle = cx.create_table();
+    handle.set_str_key("scan", Value::Str(cx.create_str(name)));
+    if let Some(field) = field {
+        handle.set_str_key("field", Value::Str(cx.create_str(field)));
+    }
+
+    cx.push(Value::Table(handle))?;
+    Ok(cx.into())
+}
+
+/// Where the scan `name` found its signature, plus the named offset `field`,
+/// for reads through a handle from `scanned`.
+pub fn scan_address(state: &State, name: &str, field: Option<&str>) -> Option<Address> {
+    let scans = state.scans.borrow();
+    let scan = scans.get(name)?;
+    let offset = match field {
+        Some(field) => scan.field_offset(field)?,
+        None => 0,
+    };
+    Some(scan.address? + offset as u64)
+}
+
+/// Keeps track of whether reads through the scan `name` work, so it can be run
+/// again once they don't anymore.
+pub fn record_read(state: &State, name: &str, success: bool) {
+    // The game closing isn't the scan's fault.
+    if state.process_died.get() {
+        return;
+    }
+    if let Some(scan) = state.scans.borrow_mut().get_mut(name) {
+        scan.failed_reads = if success { 0 } else { scan.failed_reads + 1 };
+    }
+}
+
+/// Runs all scans again on the next tick, as their addresses are meaningless
+/// in a newly attached process.
+pub fn invalidate_scans(state: &State) {
+    for scan in state.scans.borrow_mut().values_mut() {
+        scan.address = None;
+        scan.failed_reads = RESCAN_AFTER_FAILURES;
+    }
+}
+
+/// Checks whether the scans registered with `revalidateSecs` that are due
+/// still find their signature where they found it, and marks them stale if
+/// not. Code the game relocates, like JIT compiled code, reads just fine from
+/// its old address, so reads failing wouldn't notice.
+fn revalidate(state: &State) {
+    if state.process_died.get() {
+        return;
+    }
+    let process = state.process.borrow().clone();
+    let Some(process) = process else {
+        return;
+    };
+
+    let now = Instant::now();
+    for (name, scan) in state.scans.borrow_mut().iter_mut() {
+        let (Some(interval), Some(address)) = (scan.revalidate, scan.address) else {
+            continue;
+        };
+        if now.duration_since(scan.revalidated) < interval {
+            continue;
+        }
+        scan.revalidated = now;
+
+        // The signature's start, without the offset the address includes.
+        let start = Address::new(address.value().wrapping_sub(scan.offsets.offset() as u64));
+        let mut bytes = vec![0; scan.signature.len()];
+        state.read_stats.count_scan_read();
+        // Failed reads are up to `record_read`.
+        if process.read_into_buf(start, &mut bytes).is_ok()
+            && !signature_matches(&scan.signature, &bytes)
+        {
+            log::print_message(&format!(
+                "[registerScan] The signature of {name} moved, scanning again"
+            ));
+            scan.failed_reads = RESCAN_AFTER_FAILURES;
+        }
+    }
+}
+
+/// Runs the scans whose reads kept failing, or whose signature moved, again.
+/// Called once per tick.
+pub async fn rescan_stale(state: &State) {
+    revalidate(state);
+
+    let stale: Vec<(String, Scan)> = state
+        .scans
+        .borrow()
+        .iter()
+        .filter(|(_, scan)| scan.failed_reads >= RESCAN_AFTER_FAILURES)
+        .map(|(name, scan)| (name.clone(), scan.clone()))
+        .collect();
+
+    for (name, scan) in stale {
+        let Ok(address) = run_scan(state, &scan).await else {
+            continue;
+        };
+        if address != scan.address {
+            log::print_message(&format!("[registerScan] Found {name} at a new address"));
+        }
+        if let Some(scan) = state.scans.borrow_mut().get_mut(&name) {
+            scan.address = address;
+            scan.failed_reads = 0;
+        }
+    }
+}
+
+async fn run_scan(state: &State, scan: &Scan) -> Result<Option<Address>> {
+    let process = state.process.borrow().clone();
+    let Some(process) = process else {
+        // The scan runs again once a process is attached.
+        note_no_process(state, "registerScan");
+        return Ok(None);
+    };
+
+    let range = match &scan.module {
+        Some(module) => {
+            let Some((module, base)) = resolve_module(state, &*process, module) else {
+                return Ok(None);
+            };
+            let Ok(size) = process.get_module_size(&module) else {
+                return Ok(None);
+            };
+            Some((base, size))
+        }
+        None => None,
+    };
+
+    let found = scan_signature(
+        &*process,
+        &state.read_stats,
+        &scan.signature,
+        scan.offsets.offset(),
+        range,
+    )
+    .await?;
+    Ok(found.map(|address| Address::new(address as u64)))
+}
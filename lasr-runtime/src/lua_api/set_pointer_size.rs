@@ -0,0 +1,19 @@
+use tsuki::context::{Args, Context, Ret};
+
+use crate::state::{Result, State};
+
+/// `setPointerSize(4 | 8 | nil)`, follows pointer paths with pointers of the
+/// given size from now on, instead of the size read from the main module's
+/// header, or guessed from each address if that failed. `nil` goes back to
+/// that. Kept across attaches.
+pub fn set_pointer_size(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let size_arg = cx.arg(1);
+    let size = match size_arg.to_nilable_int(false)? {
+        Some(size @ (4 | 8)) => Some(size as u64),
+        None => None,
+        Some(_) => return Err(size_arg.error("pointer size must be 4, 8 or nil")),
+    };
+
+    cx.associated_data().pointer_size_override.set(size);
+    Ok(cx.into())
+}
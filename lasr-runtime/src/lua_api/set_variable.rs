@@ -1,7 +1,12 @@
-use asr::timer;
+use std::time::Duration;
+
 use tsuki::context::{Args, Context, Ret};
 
-use crate::state::{Result, State};
+use crate::{
+    host,
+    state::{Result, State},
+    variables,
+};
 
 pub fn set_variable(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     let key = cx.arg(1);
@@ -16,7 +21,62 @@ pub fn set_variable(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
         .as_utf8()
         .ok_or_else(|| value.error("value is not valid UTF-8"))?;
 
-    timer::set_variable(key, value);
+    variables::forget(cx.associated_data(), key);
+    host::get().set_variable(key, value);
+
+    Ok(cx.into())
+}
+
+pub fn set_variable_throttled(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let key = cx.arg(1);
+    let key = key
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| key.error("key is not valid UTF-8"))?;
+
+    let value = cx.arg(2);
+    let value = value
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| value.error("value is not valid UTF-8"))?;
+
+    let interval = Duration::from_millis(cx.arg(3).to_int()?.max(0) as u64);
+
+    variables::set_throttled(cx.associated_data(), key, value, interval);
+
+    Ok(cx.into())
+}
+
+pub fn set_variable_with_ttl(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let key = cx.arg(1);
+    let key = key
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| key.error("key is not valid UTF-8"))?;
+
+    let value = cx.arg(2);
+    let value = value
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| value.error("value is not valid UTF-8"))?;
+
+    let ttl = Duration::from_millis(cx.arg(3).to_int()?.max(0) as u64);
+
+    variables::set_expiring(cx.associated_data(), key, value, ttl);
+
+    Ok(cx.into())
+}
+
+pub fn clear_variable(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let key = cx.arg(1);
+    let key = key
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| key.error("key is not valid UTF-8"))?;
+
+    // The host has no way to remove a variable, so blank it instead.
+    variables::forget(cx.associated_data(), key);
+    host::get().set_variable(key, "");
 
     Ok(cx.into())
 }
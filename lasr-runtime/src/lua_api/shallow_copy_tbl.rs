@@ -1,46 +1,116 @@
 use tsuki::{
-    Value,
+    Ref, Table, Thread, Value,
     context::{Args, Context, Ret},
-    fp,
 };
 
 use crate::{
+    log,
     state::{Result, State},
-    utils::next_pair,
+    utils::{PairsBatch, TablePairs},
 };
 
 pub fn shallow_copy_tbl(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     if cx.args() == 0 || cx.arg(1).as_table().is_none() {
-        asr::print_message("[shallow_copy_tbl] Argument is not a table or no argument passed.");
+        log::warn("[shallow_copy_tbl] Argument is not a table or no argument passed.");
         cx.push(Value::Nil)?;
         return Ok(cx.into());
     }
 
-    if cx.args() > 1 {
-        asr::print_message(
-            "[shallow_copy_tbl] Too many arguments passed, only pass a single table",
+    if cx.args() > 2 || (cx.args() == 2 && cx.arg(2).as_table().is_none()) {
+        log::warn(
+            "[shallow_copy_tbl] Too many arguments passed, only pass a single table and an \
+             options table",
         );
         cx.push(Value::Nil)?;
         return Ok(cx.into());
     }
 
+    let mut options = CopyOptions {
+        metatable: false,
+        depth: 1,
+    };
+    if let Some(table) = cx.arg(2).as_table() {
+        options.metatable = matches!(table.get_str_key("metatable"), Value::True);
+        match table.get_str_key("depth") {
+            Value::Nil => {}
+            Value::Int(depth) if depth >= 1 => options.depth = depth,
+            _ => {
+                return Err(cx
+                    .arg(2)
+                    .error("depth needs to be an integer of at least 1"));
+            }
+        }
+    }
+
     let source = cx.arg(1).get_table()?;
-    let out = cx.create_table();
     let td = cx.create_thread();
+    // Maps every table copied so far to its copy, so cycles and tables
+    // referenced multiple times keep their shape.
+    let copies = cx.create_table();
+
+    let source_value = cx.arg(1).get().unwrap_or(Value::Nil);
+    let out = copy_table(
+        &cx,
+        &td,
+        source,
+        &source_value,
+        &options,
+        options.depth,
+        &copies,
+    )?;
 
-    let mut key = Value::Nil;
-    loop {
-        let mut pair: Vec<Value<State>> = td.call(fp!(next_pair), (source, &key))?;
-        if pair.len() != 2 {
-            break;
+    cx.push(Value::Table(out))?;
+    Ok(cx.into())
+}
+
+struct CopyOptions {
+    metatable: bool,
+    depth: i64,
+}
+
+fn copy_table<'a>(
+    cx: &Context<'a, State, Args>,
+    td: &Ref<'a, Thread<State>>,
+    source: &Table<State>,
+    source_value: &Value<'a, State>,
+    options: &CopyOptions,
+    depth: i64,
+    copies: &Ref<'a, Table<State>>,
+) -> Result<Ref<'a, Table<State>>> {
+    let out = cx.create_table();
+    if options.depth > 1 {
+        copies.set(source_value, Value::Table(out.clone()))?;
+    }
+
+    let batch = cx.registry::<PairsBatch>().unwrap();
+    for pair in TablePairs::new(td, &batch, source) {
+        let (next_key, mut next_value) = pair?;
+
+        if depth > 1
+            && let Value::Table(nested) = &next_value
+        {
+            next_value = match copies.get(&next_value) {
+                Value::Table(copy) => Value::Table(copy),
+                _ => Value::Table(copy_table(
+                    cx,
+                    td,
+                    nested,
+                    &next_value,
+                    options,
+                    depth - 1,
+                    copies,
+                )?),
+            };
         }
 
-        let next_value = pair.pop().unwrap();
-        let next_key = pair.pop().unwrap();
         out.set(&next_key, &next_value)?;
-        key = next_key;
     }
 
-    cx.push(Value::Table(out))?;
-    Ok(cx.into())
+    if options.metatable
+        && let Some(metatable) = source.metatable()
+    {
+        out.set_metatable(&metatable)?;
+    }
+
+    Ok(out)
 }
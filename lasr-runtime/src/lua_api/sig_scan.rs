@@ -1,61 +1,16 @@
-use asr::{Process, future::next_tick};
+use asr::Address;
 use tsuki::{
     Value,
     context::{Args, Context, Ret},
 };
 
-use crate::state::{Result, State};
-
-#[derive(Copy, Clone)]
-struct SigByte {
-    value: u8,
-    mask: u8,
-}
-
-fn parse_sig_token(token: &str) -> Result<SigByte, &'static str> {
-    if token == "?" || token == "??" {
-        return Ok(SigByte { value: 0, mask: 0 });
-    }
-
-    let bytes = token.as_bytes();
-    if bytes.len() != 2 {
-        return Err("signature token must be 2 hex chars or '?' wildcards");
-    }
-
-    let (hi_val, hi_mask) = match bytes[0] {
-        b'?' => (0, 0),
-        c => (hex_nibble(c)?, 0xF),
-    };
-    let (lo_val, lo_mask) = match bytes[1] {
-        b'?' => (0, 0),
-        c => (hex_nibble(c)?, 0xF),
-    };
-
-    Ok(SigByte {
-        value: (hi_val << 4) | lo_val,
-        mask: (hi_mask << 4) | lo_mask,
-    })
-}
-
-fn hex_nibble(c: u8) -> Result<u8, &'static str> {
-    match c {
-        b'0'..=b'9' => Ok(c - b'0'),
-        b'a'..=b'f' => Ok(c - b'a' + 10),
-        b'A'..=b'F' => Ok(c - b'A' + 10),
-        _ => Err("signature contains non-hex characters"),
-    }
-}
-
-fn parse_signature(pattern: &str) -> Result<Vec<SigByte>, &'static str> {
-    let mut out = Vec::new();
-    for token in pattern.split_whitespace() {
-        out.push(parse_sig_token(token)?);
-    }
-    if out.is_empty() {
-        return Err("signature is empty");
-    }
-    Ok(out)
-}
+use super::{module_name::containing_module, process::note_no_process};
+use crate::{
+    log,
+    sigscan::{SigByte, find_matches, parse_signature, scan_signature},
+    state::{Result, State},
+    utils::{PairsBatch, TablePairs},
+};
 
 pub async fn sig_scan<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, State, Ret>> {
     let signature = {
@@ -68,102 +23,395 @@ pub async fn sig_scan<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, St
         parse_signature(&pattern).map_err(|msg| pattern_arg.error(msg))?
     };
 
-    let offset = {
-        let offset_arg = cx.arg(2);
-        offset_arg.to_int()?
+    let offsets = scan_offsets(&cx, 2)?;
+
+    let relative_to = match cx.arg(3).as_table() {
+        Some(options) => match options.get_str_key("relativeTo") {
+            Value::Nil => None,
+            Value::Str(mode) => match mode.as_utf8() {
+                Some("module") => Some(RelativeTo::Module),
+                Some("main") => Some(RelativeTo::Main),
+                Some("absolute") => Some(RelativeTo::Absolute),
+                _ => {
+                    return Err(cx
+                        .arg(3)
+                        .error("relativeTo must be 'module', 'main' or 'absolute'"));
+                }
+            },
+            _ => return Err(cx.arg(3).error("relativeTo must be a string")),
+        },
+        None => None,
     };
 
-    let found = {
-        let process_ref = cx.associated_data().process.borrow();
-        let process = process_ref.as_ref().ok_or("no process attached")?;
+    let (found, module) = 'scan: {
+        let process = cx.associated_data().process.borrow().clone();
+        let Some(process) = process else {
+            note_no_process(cx.associated_data(), "sig_scan");
+            break 'scan (None, None);
+        };
 
-        scan_signature(process, &signature, offset).await?
+        let found = scan_signature(
+            &*process,
+            &cx.associated_data().read_stats,
+            &signature,
+            offsets.offset(),
+            None,
+        )
+        .await?;
+        let module = found.and_then(|address| {
+            containing_module(
+                cx.associated_data(),
+                &*process,
+                Address::new(address as u64),
+            )
+        });
+        (found, module)
     };
 
     let base_address = cx.associated_data().base_address.get().value() as i64;
 
-    cx.push(if let Some(address) = found {
-        Value::Int(address.wrapping_sub(base_address))
-    } else {
-        Value::Nil
+    let Some(address) = found else {
+        // A table without any of the names, so they're all `nil` and scripts
+        // don't have to check the table itself.
+        cx.push(match offsets {
+            ScanOffsets::Single(_) => Value::Nil,
+            ScanOffsets::Named(_) => Value::Table(cx.create_table()),
+        })?;
+        return Ok(cx.into());
+    };
+
+    let value = match relative_to {
+        Some(RelativeTo::Module) => match &module {
+            Some((_, module_base)) => address.wrapping_sub(module_base.value() as i64),
+            None => address,
+        },
+        Some(RelativeTo::Absolute) => address,
+        Some(RelativeTo::Main) => address.wrapping_sub(base_address),
+        None => {
+            let outside_main = module
+                .as_ref()
+                .is_none_or(|(_, module_base)| module_base.value() as i64 != base_address);
+            if outside_main && !cx.associated_data().sig_scan_legacy_warned.replace(true) {
+                log::warn(
+                    "[sig_scan] The signature was found outside the main module, but the result \
+                     is still relative to the main module. Pass {relativeTo = \"module\"} or \
+                     {relativeTo = \"absolute\"} to get a usable address.",
+                );
+            }
+            address.wrapping_sub(base_address)
+        }
+    };
+
+    cx.push(match offsets {
+        ScanOffsets::Single(_) => Value::Int(value),
+        ScanOffsets::Named(named) => {
+            let table = cx.create_table();
+            for (name, offset) in &named {
+                table.set_str_key(name.as_str(), Value::Int(value.wrapping_add(*offset)));
+            }
+            Value::Table(table)
+        }
+    })?;
+    cx.push(match module {
+        Some((name, _)) => Value::Str(cx.create_str(name)),
+        None => Value::Nil,
     })?;
     Ok(cx.into())
 }
 
-async fn scan_signature(
-    process: &Process,
-    signature: &[SigByte],
-    offset: i64,
-) -> Result<Option<i64>, &'static str> {
-    let sig_len = signature.len();
-    let chunk_size: usize = 0x10000;
-    let mut buf = vec![0u8; chunk_size];
-    let lps = build_lps(signature);
-
-    let mut chunk_counter: u32 = 0;
-    for range in process.memory_ranges() {
-        let (base, range_size) = range.range().map_err(|_| "failed to query memory range")?;
-
-        if range_size == 0 {
-            continue;
+/// What `sig_scan` and `registerScan` accept as the offset to add to where
+/// the signature was found.
+#[derive(Clone)]
+pub enum ScanOffsets {
+    Single(i64),
+    /// Named offsets, each giving its own address from the same scan.
+    Named(Vec<(String, i64)>),
+}
+
+impl ScanOffsets {
+    /// The offset to scan with, the named ones get added afterwards.
+    pub fn offset(&self) -> i64 {
+        match self {
+            Self::Single(offset) => *offset,
+            Self::Named(_) => 0,
         }
-        let mut offset_bytes: u64 = 0;
-        let mut matched: usize = 0;
-        while offset_bytes < range_size {
-            let remaining = (range_size - offset_bytes) as usize;
-            let read_len = remaining.min(chunk_size);
-            let buf_slice = &mut buf[..read_len];
-
-            if process.read_into_buf(base + offset_bytes, buf_slice).is_err() {
-                break;
-            }
+    }
+}
 
-            for (i, &byte) in buf_slice.iter().enumerate() {
-                while matched > 0 && !sig_byte_matches(signature[matched], byte) {
-                    matched = lps[matched - 1];
-                }
-                if sig_byte_matches(signature[matched], byte) {
-                    matched += 1;
-                    if matched == sig_len {
-                        let found = offset_bytes + i as u64 + 1 - sig_len as u64;
-                        let address = base.value() as i64 + found as i64 + offset;
-                        return Ok(Some(address));
-                    }
-                }
-            }
+/// Parses argument `index` as either an offset or a table of named offsets.
+pub fn scan_offsets(cx: &Context<State, Args>, index: usize) -> Result<ScanOffsets> {
+    let arg = cx.arg(index);
+    let Some(table) = arg.as_table() else {
+        return Ok(ScanOffsets::Single(arg.to_int()?));
+    };
 
-            offset_bytes += read_len as u64;
-            chunk_counter = chunk_counter.wrapping_add(1);
-            if chunk_counter.is_multiple_of(64) {
-                next_tick().await;
-            }
-        }
+    let td = cx.create_thread();
+    let batch = cx.registry::<PairsBatch>().unwrap();
+    let mut named = Vec::new();
+    for pair in TablePairs::new(&td, &batch, table) {
+        let (Value::Str(name), Value::Int(offset)) = pair? else {
+            return Err(arg.error("named offsets must map names to integer offsets"));
+        };
+        let name = name
+            .as_utf8()
+            .ok_or_else(|| arg.error("offset name is not valid UTF-8"))?;
+        named.push((name.to_owned(), offset));
     }
 
-    Ok(None)
+    Ok(ScanOffsets::Named(named))
+}
+
+#[derive(Copy, Clone)]
+enum RelativeTo {
+    Module,
+    Main,
+    Absolute,
 }
 
-fn build_lps(signature: &[SigByte]) -> Vec<usize> {
-    let mut lps = vec![0usize; signature.len()];
-    let mut len = 0;
+pub fn find_pattern(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let (haystack, signature, start) = pattern_args(&cx)?;
 
-    for i in 1..signature.len() {
-        while len > 0 && !sig_byte_eq(signature[i], signature[len]) {
-            len = lps[len - 1];
-        }
-        if sig_byte_eq(signature[i], signature[len]) {
-            len += 1;
-            lps[i] = len;
-        }
+    let found = find_matches(&haystack, &signature, start).next();
+
+    cx.push(match found {
+        Some(index) => Value::Int(index as i64 + 1),
+        None => Value::Nil,
+    })?;
+    Ok(cx.into())
+}
+
+pub fn find_pattern_all(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let (haystack, signature, start) = pattern_args(&cx)?;
+
+    let table = cx.create_table();
+    for (i, index) in find_matches(&haystack, &signature, start).enumerate() {
+        table.set(Value::Int(i as i64 + 1), Value::Int(index as i64 + 1))?;
     }
 
-    lps
+    cx.push(Value::Table(table))?;
+    Ok(cx.into())
 }
 
-fn sig_byte_matches(sig: SigByte, byte: u8) -> bool {
-    (byte & sig.mask) == sig.value
+/// The haystack as bytes, the parsed signature and the 0-based index to start
+/// searching at.
+fn pattern_args(cx: &Context<State, Args>) -> Result<(Vec<u8>, Vec<SigByte>, usize)> {
+    let haystack_arg = cx.arg(1);
+    let haystack = match haystack_arg.get() {
+        Some(Value::Str(haystack)) => haystack.as_bytes().to_vec(),
+        Some(Value::Table(table)) => {
+            let mut bytes = Vec::new();
+            for i in 1.. {
+                match table.get(Value::Int(i)) {
+                    Value::Int(byte @ 0..=255) => bytes.push(byte as u8),
+                    Value::Nil => break,
+                    _ => {
+                        return Err(haystack_arg.error("haystack table may only contain bytes"));
+                    }
+                }
+            }
+            bytes
+        }
+        _ => {
+            return Err(haystack_arg.error("haystack must be a string or a table of bytes"));
+        }
+    };
+
+    let pattern_arg = cx.arg(2);
+    let pattern = pattern_arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| pattern_arg.error("signature is not valid UTF-8"))?
+        .to_owned();
+    let signature = parse_signature(&pattern).map_err(|msg| pattern_arg.error(msg))?;
+
+    let start = cx.arg(3).to_nilable_int(false)?.unwrap_or(1).max(1) as usize - 1;
+
+    Ok((haystack, signature, start))
 }
 
-fn sig_byte_eq(left: SigByte, right: SigByte) -> bool {
-    left.value == right.value && left.mask == right.mask
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        rc::Rc,
+        task::{Context, Poll, Waker},
+    };
+
+    use tsuki::{Value, fp};
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, block_on, lua, run},
+        utils::resume,
+    };
+
+    /// `game.exe` at 0x10000, followed by a region of twice the size memory
+    /// is scanned in at a time, so matches can straddle two chunks.
+    fn game() -> Rc<MockProcess> {
+        Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x1000)
+                .map(0x10000, vec![0; 0x1000])
+                .map(0x100000, vec![0; 0x20000]),
+        )
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let game = game();
+        game.write(0x10100, &[0x48, 0x8B, 0x05, 0x12, 0x34]);
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            assert(sig_scan("48 8B 05 12 34", 0) == 0x100)
+            assert(sig_scan("48 ?? 05 ? 34", 0) == 0x100)
+            assert(sig_scan("4? 8B ?5 1? ?4", 0) == 0x100)
+            assert(sig_scan("48 8B 05 12 35", 0) == nil)
+            assert(sig_scan("4? 8B ?6", 0) == nil)
+
+            -- Offsets are added to where the signature starts.
+            assert(sig_scan("8B 05", 3) == 0x104)
+            assert(sig_scan("8B 05", -1) == 0x100)
+            local named = sig_scan("48 8B", { first = 0, last = 4 })
+            assert(named.first == 0x100 and named.last == 0x104)
+            "#,
+        );
+    }
+
+    #[test]
+    fn matches_across_chunks() {
+        let game = game();
+        // The first chunk ends at 0x110000.
+        game.write(0x10FFFE, &[0xDE, 0xAD, 0xBE, 0xEF]);
+        game.write(0x11FFFC, &[0x13, 0x37, 0x13, 0x37]);
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            local absolute = { relativeTo = "absolute" }
+            assert(sig_scan("DE AD ?? EF", 0, absolute) == 0x10FFFE)
+            assert(sig_scan("AD BE", 0, absolute) == 0x10FFFF)
+            -- Right at the end of the last range.
+            assert(sig_scan("13 37 13 37", 0, absolute) == 0x11FFFC)
+            assert(sig_scan("13 37 13 37 00", 0, absolute) == nil)
+            "#,
+        );
+    }
+
+    #[test]
+    fn doesnt_match_across_ranges() {
+        // Adjacent here, but two ranges of a real process needn't be.
+        let game = Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x10)
+                .map(0x10000, [0, 0, 0xAA, 0xBB])
+                .map(0x10004, [0xCC, 0xDD, 0, 0]),
+        );
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            assert(sig_scan("AA BB", 0) == 2)
+            assert(sig_scan("CC DD", 0) == 4)
+            assert(sig_scan("BB CC", 0) == nil)
+            "#,
+        );
+    }
+
+    #[test]
+    fn scans_from_two_tasks_at_once() {
+        // Large enough for a scan to wait for the next tick halfway through.
+        let game = Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x1000)
+                .map(0x10000, vec![0; 0x1000])
+                .map(0x100000, vec![0; 0x500000]),
+        );
+        game.write(0x5FFFF0, &[0xAB, 0xCD, 0xEF]);
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+
+        let td = lua.create_thread();
+        let run_tasks = lua.load("tasks.lua", include_str!("../tasks.lua")).unwrap();
+        let Value::LuaFn(run_tasks) =
+            block_on(td.async_call(&*run_tasks, fp!(resume as async))).unwrap()
+        else {
+            unreachable!("tasks.lua returns the task runner");
+        };
+        run(
+            &lua,
+            r#"
+            found = {}
+            for i = 1, 2 do
+                spawn(function()
+                    found[i] = sig_scan("AB CD EF", 0, { relativeTo = "absolute" })
+                end)
+            end
+            spawn(function()
+                nextTick()
+                found[3] = sig_scan("AB CD EF", 0, { relativeTo = "absolute" })
+            end)
+            "#,
+        );
+
+        let mut ticks = pin!(td.async_call(&run_tasks, 0));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(ticks.as_mut().poll(&mut cx).is_pending());
+        // Nothing may be borrowed while a scan waits, attaching needs to
+        // replace the process.
+        attach(&lua, &game, "game.exe");
+        let () = loop {
+            if let Poll::Ready(result) = ticks.as_mut().poll(&mut cx) {
+                break result.unwrap();
+            }
+        };
+
+        run(
+            &lua,
+            "assert(found[1] == 0x5FFFF0 and found[2] == 0x5FFFF0 and found[3] == nil)",
+        );
+
+        // The task that waited for the next tick scans now.
+        let () = block_on(td.async_call(&run_tasks, 0)).unwrap();
+        run(&lua, "assert(found[3] == 0x5FFFF0)");
+    }
+
+    #[test]
+    fn finds_patterns_in_buffers() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            local bytes = "\x01\x02\xAA\x01\x02\xAA\x01"
+            assert(findPattern(bytes, "01 02") == 1)
+            assert(findPattern(bytes, "01 02", 2) == 4)
+            assert(findPattern(bytes, "01 02", 5) == nil)
+            assert(findPattern(bytes, "01 02", 100) == nil)
+            assert(findPattern(bytes, "?A 01") == 3)
+            assert(findPattern(bytes, "AA 01 02 AA 01 02") == nil)
+
+            local all = findPatternAll(bytes, "01 ?? AA")
+            assert(#all == 2 and all[1] == 1 and all[2] == 4)
+            all = findPatternAll(bytes, "AA ?? ?? AA")
+            assert(#all == 1 and all[1] == 3)
+            assert(#findPatternAll(bytes, "FF") == 0)
+
+            local table = { 0x10, 0x20, 0x10, 0x20, 0x10 }
+            assert(findPattern(table, "10 20 10") == 1)
+            all = findPatternAll(table, "10 20 10")
+            assert(#all == 2 and all[2] == 3)
+            assert(findPattern({}, "10") == nil)
+
+            assert(not pcall(findPattern, bytes, "1"))
+            assert(not pcall(findPattern, bytes, ""))
+            assert(not pcall(findPattern, { 1, 256 }, "01"))
+            assert(not pcall(findPattern, { 1, "2" }, "01"))
+            assert(not pcall(findPattern, 12, "01"))
+            "#,
+        );
+    }
 }
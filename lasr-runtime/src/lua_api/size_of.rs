@@ -1,7 +1,14 @@
-use tsuki::{Value, context::{Args, Context, Ret}};
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
 
+use super::value_type::ValueType;
 use crate::state::{Result, State};
 
+/// `sizeOf(type)`, how many bytes `readAddress` reads for `type`. Both parse
+/// the type through `ValueType::parse`, but `"string"` and `"cstring"` have
+/// no fixed size, so only `readAddress` accepts them.
 pub fn size_of(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     let ty_arg = cx.arg(1);
     let ty = ty_arg
@@ -9,27 +16,66 @@ pub fn size_of(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
         .as_utf8()
         .ok_or_else(|| ty_arg.error("type is not valid UTF-8"))?;
 
-    let size = match ty {
-        "sbyte" | "byte" | "bool" => 1,
-        "short" | "ushort" => 2,
-        "int" | "uint" | "float" => 4,
-        "long" | "ulong" | "double" => 8,
-        _ => {
-            if let Some(rem) = ty.strip_prefix("string")
-                && let Ok(byte_count) = rem.parse::<usize>()
-            {
-                byte_count
-            } else if let Some(rem) = ty.strip_prefix("byte")
-                && !rem.is_empty()
-                && let Ok(byte_count) = rem.parse::<usize>()
-            {
-                byte_count
-            } else {
-                return Err(ty_arg.error("unsupported type"));
-            }
-        }
-    };
+    let size = ValueType::parse(ty)
+        .map_err(|msg| ty_arg.error(msg))?
+        .size()
+        .ok_or_else(|| ty_arg.error("string has no fixed size, use string<n> instead"))?;
 
     cx.push(Value::Int(size as i64))?;
     Ok(cx.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
+    };
+
+    #[test]
+    fn accepts_what_read_address_reads() {
+        let lua = lua();
+        let game = MockProcess::new()
+            .module("game.exe", 0x10000, 0x1000)
+            .map(0x10000, vec![0x41; 0x1000]);
+        attach(&lua, &Rc::new(game), "game.exe");
+        run(
+            &lua,
+            r#"
+            local sizes = {
+                sbyte = 1, byte = 1, bool = 1, short = 2, ushort = 2,
+                int = 4, uint = 4, float = 4, long = 8, ulong = 8, double = 8,
+                string2 = 2, string64 = 64, wstring2 = 4, byte1 = 1, byte16 = 16,
+                vec2 = 8, vec3 = 12, vec4 = 16,
+                ["int[16]"] = 64, ["bool[3]"] = 3, ["double[2]"] = 16,
+            }
+            for ty, size in pairs(sizes) do
+                assert(sizeOf(ty) == size, ty)
+                assert(readAddress(ty, 0x10) ~= nil, ty)
+            end
+
+            -- These read up to the NUL byte, so they have no fixed size.
+            for _, ty in ipairs({ "string", "cstring" }) do
+                assert(readAddress(ty, 0x10) ~= nil, ty)
+                assert(not pcall(sizeOf, ty), ty)
+            end
+
+            -- Both reject the same invalid types, `readAddress` by warning
+            -- and returning nil.
+            local invalid = {
+                "unknown", "string1", "wstring1", "byte0", "vec5", "int[0]",
+                "string2[2]", "vec2[2]",
+                -- Larger than 16 MiB, or even than the address space.
+                "byte16777217", "string16777217", "wstring8388609", "bool[16777217]",
+                "int[4194305]", "long[2305843009213693952]", "int[99999999999999999999]",
+            }
+            for _, ty in ipairs(invalid) do
+                assert(not pcall(sizeOf, ty), ty)
+                assert(readAddress(ty, 0x10) == nil, ty)
+            end
+            "#,
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use super::{
+    process::note_no_process,
+    read_address::{report_read_failure, resolve_address},
+    scans::record_read,
+    value_type::{MAX_CSTRING_LEN, MAX_READ_LEN, ValueType},
+};
+use crate::{
+    log,
+    state::{Result, State},
+};
+
+/// Reads `length` bytes in one go, so fields read from the snapshot afterwards
+/// are all from the same moment, instead of possibly straddling an update by
+/// the game like separate `readAddress` calls can.
+pub fn snapshot(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    if cx.args() < 2 {
+        return Err("snapshot expects an address and a length".into());
+    }
+
+    let length_arg = cx.arg(cx.args());
+    let length = length_arg.to_int()?;
+    if length < 1 || length as u64 > MAX_READ_LEN as u64 {
+        return Err(length_arg.error(format!("length must be between 1 and {MAX_READ_LEN}")));
+    }
+
+    let mut scan_name = None;
+
+    let bytes = 'read: {
+        let process = &*cx.associated_data().process.borrow();
+        let Some(process) = process.as_ref() else {
+            note_no_process(cx.associated_data(), "snapshot");
+            break 'read None;
+        };
+
+        if cx.associated_data().process_died.get() {
+            break 'read None;
+        }
+
+        let (address, scan) = resolve_address(&cx, &**process, "snapshot", 1, cx.args() - 1)?;
+        scan_name = scan;
+        let Some(address) = address else {
+            break 'read None;
+        };
+
+        let mut buf = vec![0; length as usize];
+        cx.associated_data().read_stats.count_read();
+        if process.read_into_buf(address, &mut buf).is_err() {
+            report_read_failure(cx.associated_data(), &**process, "snapshot", address);
+            break 'read None;
+        }
+
+        Some(buf)
+    };
+
+    if let Some(name) = scan_name {
+        record_read(cx.associated_data(), &name, bytes.is_some());
+    }
+
+    cx.push(match bytes {
+        Some(bytes) => {
+            let snapshot = cx.create_table();
+            snapshot.set_str_key("bytes", Value::Str(cx.create_bytes(bytes)));
+            snapshot.set_str_key("read", fp!(read));
+            Value::Table(snapshot)
+        }
+        None => Value::Nil,
+    })?;
+    Ok(cx.into())
+}
+
+/// `snapshot:read(type, offset)`, decodes a value from the captured bytes
+/// without touching the process again.
+fn read(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let ty_arg = cx.arg(2);
+    let ty = ty_arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| ty_arg.error("type is not valid UTF-8"))?;
+    let ty = match ValueType::parse(ty) {
+        Ok(ty) => ty,
+        Err(msg) => {
+            log::warn(&format!(
+                "[snapshot] Invalid value type {ty}: {msg}, please read documentation"
+            ));
+            cx.push(Value::Nil)?;
+            return Ok(cx.into());
+        }
+    };
+
+    let offset = cx.arg(3).to_int()?;
+
+    // The bytes are only borrowed in here, so that they are released before
+    // `cx` is turned into the return value.
+    let value = {
+        let snapshot = cx.arg(1).get_table()?;
+        let Value::Str(bytes) = snapshot.get_str_key("bytes") else {
+            return Err(cx.arg(1).error("expected a snapshot returned by snapshot"));
+        };
+        let bytes = bytes.as_bytes();
+
+        // A `"string"` goes up to the end of the snapshot at most.
+        let size = ty.size().unwrap_or(1);
+        match usize::try_from(offset)
+            .ok()
+            .and_then(|offset| bytes.get(offset..offset.checked_add(size)?))
+        {
+            Some(_) if ty.size().is_none() => {
+                let end = bytes.len().min(offset as usize + MAX_CSTRING_LEN);
+                ty.decode(&cx, &bytes[offset as usize..end])
+            }
+            Some(bytes) => ty.decode(&cx, bytes),
+            None => {
+                log::warn(&format!(
+                    "[snapshot] Reading {size} bytes at offset {offset} is outside of the snapshot of {} bytes",
+                    bytes.len(),
+                ));
+                Value::Nil
+            }
+        }
+    };
+
+    cx.push(value)?;
+    Ok(cx.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
+    };
+
+    #[test]
+    fn caps_the_length() {
+        let lua = lua();
+        let game = Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x10000, (0..=255).collect::<Vec<u8>>()),
+        );
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            local snap = snapshot(0x10, 4)
+            assert(snap:read("byte", 0) == 0x10 and snap:read("byte", 3) == 0x13)
+            assert(snap:read("byte", 4) == nil)
+
+            assert(not pcall(snapshot, 0x10, 0))
+            -- A typo in the length can't take all of the memory.
+            assert(not pcall(snapshot, 0x10, 0x7FFFFFFF))
+            assert(not pcall(snapshot, 0x10, math.maxinteger))
+            "#,
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use tsuki::context::{Args, Context, Ret};
+
+use crate::state::{Result, State};
+
+pub fn split_once(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let arg = cx.arg(1);
+    let id = arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("id is not valid UTF-8"))?;
+
+    // The main loop forgets the ids whenever the timer starts, resets or
+    // isn't running.
+    let first = cx
+        .associated_data()
+        .split_once_fired
+        .borrow_mut()
+        .insert(id.to_owned());
+
+    cx.push(first)?;
+    Ok(cx.into())
+}
+
+pub fn reset_split_once(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let arg = cx.arg(1);
+    let id = arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("id is not valid UTF-8"))?;
+
+    cx.associated_data()
+        .split_once_fired
+        .borrow_mut()
+        .remove(id);
+
+    Ok(cx.into())
+}
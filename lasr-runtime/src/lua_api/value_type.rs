@@ -0,0 +1,219 @@
+use std::str;
+
+use tsuki::{
+    Float, Value,
+    context::{Args, Context},
+};
+
+use crate::state::State;
+
+/// How many bytes a bare `"string"` reads at most, if there's no NUL byte
+/// before.
+pub const MAX_CSTRING_LEN: usize = 4096;
+
+/// How many bytes a buffer, snapshot or value may take at most, so a typo in
+/// a length can't make the runtime run out of memory. 16 MiB.
+pub const MAX_READ_LEN: usize = 16 << 20;
+
+/// A type name accepted by `readAddress` and `sizeOf`.
+#[derive(Copy, Clone)]
+pub enum ValueType {
+    SByte,
+    Byte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double,
+    Bool,
+    /// `"string<n>"`, a NUL terminated string of at most `n` bytes.
+    String(usize),
+    /// `"string"` or `"cstring"`, a NUL terminated string of any length, up
+    /// to `MAX_CSTRING_LEN` bytes.
+    CString,
+    /// `"wstring<n>"`, a NUL terminated UTF-16 string of at most `n` code
+    /// units, so `2 * n` bytes.
+    WString(usize),
+    /// `"byte<n>"`, an array of `n` bytes.
+    ByteArray(usize),
+    /// `"vec2"`, `"vec3"` or `"vec4"`, that many floats, read as a table with
+    /// `x`, `y`, `z` and `w` fields.
+    Vector(usize),
+    /// `"<type>[n]"`, `n` values of one of the `SCALARS` in a row, read as a
+    /// sequence.
+    Array(&'static ValueType, usize),
+}
+
+/// The types of a fixed size that stand for a single value, which are the
+/// ones arrays can be made of.
+static SCALARS: [(&str, ValueType); 11] = [
+    ("sbyte", ValueType::SByte),
+    ("byte", ValueType::Byte),
+    ("short", ValueType::Short),
+    ("ushort", ValueType::UShort),
+    ("int", ValueType::Int),
+    ("uint", ValueType::UInt),
+    ("long", ValueType::Long),
+    ("ulong", ValueType::ULong),
+    ("float", ValueType::Float),
+    ("double", ValueType::Double),
+    ("bool", ValueType::Bool),
+];
+
+/// The fields of a `"vec<n>"` table, in order.
+const VECTOR_FIELDS: [&str; 4] = ["x", "y", "z", "w"];
+
+impl ValueType {
+    pub fn parse(ty: &str) -> Result<Self, &'static str> {
+        if let Some((_, scalar)) = SCALARS.iter().find(|(name, _)| *name == ty) {
+            return Ok(*scalar);
+        }
+
+        let ty = match ty {
+            "string" | "cstring" => Self::CString,
+            "vec2" => Self::Vector(2),
+            "vec3" => Self::Vector(3),
+            "vec4" => Self::Vector(4),
+            _ => {
+                if let Some((element, len)) = ty.strip_suffix(']').and_then(|ty| ty.split_once('['))
+                {
+                    let Some((_, element)) = SCALARS.iter().find(|(name, _)| *name == element)
+                    else {
+                        return Err("arrays can only hold numbers or bools, like int[16]");
+                    };
+                    match len.parse::<usize>() {
+                        Ok(len) if len >= 1 => Self::Array(element, len),
+                        _ => return Err("invalid array length, it must be at least 1"),
+                    }
+                } else if let Some(rem) = ty.strip_prefix("wstring") {
+                    match rem.parse::<usize>() {
+                        Ok(len) if len >= 2 => Self::WString(len),
+                        _ => return Err("invalid wstring size, it must be at least 2"),
+                    }
+                } else if let Some(rem) = ty.strip_prefix("string") {
+                    match rem.parse::<usize>() {
+                        Ok(byte_count) if byte_count >= 2 => Self::String(byte_count),
+                        _ => return Err("invalid string size, it must be at least 2"),
+                    }
+                } else if let Some(rem) = ty.strip_prefix("byte") {
+                    match rem.parse::<usize>() {
+                        Ok(byte_count) if byte_count >= 1 => Self::ByteArray(byte_count),
+                        _ => return Err("invalid byte array size, it must be at least 1"),
+                    }
+                } else {
+                    return Err("unknown type");
+                }
+            }
+        };
+
+        // Checked once here, so a typo in a length can't make a read take all
+        // of the memory.
+        if !matches!(ty, Self::CString) && ty.size().is_none_or(|size| size > MAX_READ_LEN) {
+            return Err("the type is larger than 16 MiB");
+        }
+
+        Ok(ty)
+    }
+
+    /// How many bytes a value takes, `None` for `"string"`, which goes up to
+    /// the first NUL byte, however far away that is, and for arrays too large
+    /// to count their bytes.
+    pub fn size(self) -> Option<usize> {
+        Some(match self {
+            Self::SByte | Self::Byte | Self::Bool => 1,
+            Self::Short | Self::UShort => 2,
+            Self::Int | Self::UInt | Self::Float => 4,
+            Self::Long | Self::ULong | Self::Double => 8,
+            Self::String(byte_count) | Self::ByteArray(byte_count) => byte_count,
+            Self::WString(len) => len * 2,
+            Self::Vector(len) => len * 4,
+            Self::Array(element, len) => element.size()?.checked_mul(len)?,
+            Self::CString => return None,
+        })
+    }
+
+    /// Turns `size()` little endian bytes into a Lua value. Strings that aren't
+    /// valid UTF-8 are `nil`.
+    ///
+    /// All reads of typed values go through this on bytes copied out of the
+    /// process, rather than through typed reads, so values at unaligned
+    /// addresses read just like aligned ones.
+    pub fn decode<'a>(self, cx: &Context<'a, State, Args>, bytes: &[u8]) -> Value<'a, State> {
+        macro_rules! int {
+            ($ty:ty) => {
+                Value::Int(<$ty>::from_le_bytes(bytes.try_into().unwrap()) as i64)
+            };
+        }
+
+        match self {
+            Self::SByte => int!(i8),
+            Self::Byte => int!(u8),
+            Self::Short => int!(i16),
+            Self::UShort => int!(u16),
+            Self::Int => int!(i32),
+            Self::UInt => int!(u32),
+            Self::Long => int!(i64),
+            Self::ULong => int!(u64),
+            Self::Float => Value::Float(Float(f32::from_le_bytes(bytes.try_into().unwrap()) as _)),
+            Self::Double => Value::Float(Float(f64::from_le_bytes(bytes.try_into().unwrap()))),
+            Self::Bool => {
+                if bytes[0] == 0 {
+                    Value::False
+                } else {
+                    Value::True
+                }
+            }
+            Self::String(_) | Self::CString => {
+                let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                match str::from_utf8(&bytes[..len]) {
+                    Ok(s) => Value::Str(cx.create_str(s)),
+                    Err(_) => Value::Nil,
+                }
+            }
+            Self::WString(_) => Value::Str(cx.create_str(decode_utf16(bytes).as_str())),
+            Self::ByteArray(_) => {
+                let table = cx.create_table();
+                for (i, &byte) in bytes.iter().enumerate() {
+                    table.set((i + 1) as i64, byte as i64).unwrap();
+                }
+
+                Value::Table(table)
+            }
+            Self::Vector(_) => {
+                let table = cx.create_table();
+                for (field, bytes) in VECTOR_FIELDS.into_iter().zip(bytes.chunks_exact(4)) {
+                    table.set_str_key(field, Self::Float.decode(cx, bytes));
+                }
+
+                Value::Table(table)
+            }
+            Self::Array(element, _) => {
+                let table = cx.create_table();
+                let element_size = element.size().unwrap();
+                for (i, bytes) in bytes.chunks_exact(element_size).enumerate() {
+                    table
+                        .set(Value::Int((i + 1) as i64), element.decode(cx, bytes))
+                        .unwrap();
+                }
+
+                Value::Table(table)
+            }
+        }
+    }
+}
+
+/// Decodes UTF-16LE up to the first NUL code unit. Unpaired surrogates become
+/// U+FFFD, as games often leave garbage in the buffer that would otherwise
+/// make the whole string unreadable.
+pub fn decode_utf16(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0);
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
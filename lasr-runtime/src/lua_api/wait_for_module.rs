@@ -0,0 +1,55 @@
+use std::time::{Duration, Instant};
+
+use asr::future::next_tick;
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+};
+
+use super::{module_name::resolve_module, process::note_no_process};
+use crate::state::{Result, State};
+
+pub async fn wait_for_module<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, State, Ret>> {
+    let arg = cx.arg(1);
+    let module = arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("module name is not valid UTF-8"))?;
+
+    let deadline = cx
+        .arg(2)
+        .to_nilable_int(false)?
+        .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64));
+
+    // Waiting wouldn't help, nothing attaches a process while `startup` is
+    // stuck in here.
+    if cx.associated_data().process.borrow().is_none() {
+        note_no_process(cx.associated_data(), "waitForModule");
+        cx.push(Value::Nil)?;
+        return Ok(cx.into());
+    }
+
+    loop {
+        let address = {
+            let process_ref = cx.associated_data().process.borrow();
+            let process = process_ref.as_ref().ok_or("no process attached")?;
+            resolve_module(cx.associated_data(), &**process, module).map(|(_, address)| address)
+        };
+
+        if let Some(address) = address {
+            cx.associated_data()
+                .module_addresses
+                .borrow_mut()
+                .insert(module.to_owned(), address);
+            cx.push(Value::Int(address.value() as i64))?;
+            return Ok(cx.into());
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            cx.push(Value::Nil)?;
+            return Ok(cx.into());
+        }
+
+        next_tick().await;
+    }
+}
@@ -0,0 +1,351 @@
+use std::{cmp::Ordering, str};
+
+use tsuki::{
+    Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use super::{
+    read_address::{AddressPath, read_value, report_read_failure},
+    scans::record_read,
+    value_type::{ValueType, decode_utf16},
+};
+use crate::state::{Result, State};
+
+/// A watcher created through `createWatcher`. The main loop reads it once at
+/// the start of every tick, so all of its methods agree within a tick.
+pub struct Watcher {
+    ty: ValueType,
+    path: AddressPath,
+    /// The bytes read last tick and this tick, `None` where the read failed.
+    old: Option<Vec<u8>>,
+    current: Option<Vec<u8>>,
+    changed: bool,
+    increased: bool,
+    decreased: bool,
+}
+
+/// A watched value, compared by what it means rather than by its bytes, so
+/// `0.0` equals `-0.0` and a string ends at its NUL byte.
+#[derive(PartialEq, PartialOrd)]
+enum Watched<'a> {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Bytes(&'a [u8]),
+    Text(String),
+}
+
+impl<'a> Watched<'a> {
+    fn new(ty: ValueType, bytes: &'a [u8]) -> Option<Self> {
+        macro_rules! int {
+            ($ty:ty) => {
+                Self::Int(<$ty>::from_le_bytes(bytes.try_into().unwrap()) as i128)
+            };
+        }
+
+        Some(match ty {
+            ValueType::SByte => int!(i8),
+            ValueType::Byte => int!(u8),
+            ValueType::Short => int!(i16),
+            ValueType::UShort => int!(u16),
+            ValueType::Int => int!(i32),
+            ValueType::UInt => int!(u32),
+            ValueType::Long => int!(i64),
+            ValueType::ULong => int!(u64),
+            ValueType::Float => Self::Float(f32::from_le_bytes(bytes.try_into().unwrap()) as f64),
+            ValueType::Double => Self::Float(f64::from_le_bytes(bytes.try_into().unwrap())),
+            ValueType::Bool => Self::Bool(bytes[0] != 0),
+            ValueType::String(_) | ValueType::CString => {
+                let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                // `readAddress` returns `nil` for these, so they can't be
+                // compared either.
+                str::from_utf8(&bytes[..len]).ok()?;
+                Self::Bytes(&bytes[..len])
+            }
+            ValueType::WString(_) => Self::Text(decode_utf16(bytes)),
+            // Compared as a whole, which is enough to tell whether any of the
+            // values changed.
+            ValueType::ByteArray(_) | ValueType::Vector(_) | ValueType::Array(..) => {
+                Self::Bytes(bytes)
+            }
+        })
+    }
+
+    /// Whether this is `value`. Integers and floats compare by their numeric
+    /// value.
+    fn is(&self, value: &Expected) -> bool {
+        match (self, value) {
+            (Self::Int(a), Expected::Int(b)) => *a == *b as i128,
+            (Self::Int(a), Expected::Float(b)) => *a as f64 == *b,
+            (Self::Float(a), Expected::Int(b)) => *a == *b as f64,
+            (Self::Float(a), Expected::Float(b)) => *a == *b,
+            (Self::Bool(a), Expected::Bool(b)) => a == b,
+            (Self::Bytes(a), Expected::Str(b)) => *a == b.as_bytes(),
+            (Self::Text(a), Expected::Str(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A value from Lua that a watched value is compared to.
+pub enum Expected {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl Expected {
+    /// `None` for values no watched value can be, like tables.
+    pub fn from_value(value: &Value<State>) -> Option<Self> {
+        Some(match value {
+            Value::Int(value) => Self::Int(*value),
+            Value::Float(value) => Self::Float(value.0),
+            Value::True => Self::Bool(true),
+            Value::False => Self::Bool(false),
+            Value::Str(value) => Self::Str(value.as_utf8()?.to_owned()),
+            _ => return None,
+        })
+    }
+}
+
+/// What a watcher can be checked for, through its methods or `autosplit`.
+pub enum Condition {
+    Changed,
+    Increased,
+    Decreased,
+    ChangedTo(Expected),
+    ChangedFrom(Expected),
+}
+
+impl Watcher {
+    /// Moves the current value to the old one and stores what was just read,
+    /// working out the predicates for the tick.
+    fn advance(&mut self, bytes: Option<Vec<u8>>) {
+        self.old = std::mem::replace(&mut self.current, bytes);
+
+        let old = self
+            .old
+            .as_deref()
+            .and_then(|bytes| Watched::new(self.ty, bytes));
+        let current = self
+            .current
+            .as_deref()
+            .and_then(|bytes| Watched::new(self.ty, bytes));
+        let (Some(old), Some(current)) = (old, current) else {
+            self.changed = false;
+            self.increased = false;
+            self.decreased = false;
+            return;
+        };
+
+        self.changed = current != old;
+        let numeric = matches!(current, Watched::Int(_) | Watched::Float(_));
+        let ordering = current.partial_cmp(&old).filter(|_| numeric);
+        self.increased = ordering == Some(Ordering::Greater);
+        self.decreased = ordering == Some(Ordering::Less);
+    }
+
+    fn check(&self, condition: &Condition) -> bool {
+        let is = |bytes: &Option<Vec<u8>>, value: &Expected| {
+            bytes
+                .as_deref()
+                .and_then(|bytes| Watched::new(self.ty, bytes))
+                .is_some_and(|watched| watched.is(value))
+        };
+
+        match condition {
+            Condition::Changed => self.changed,
+            Condition::Increased => self.increased,
+            Condition::Decreased => self.decreased,
+            Condition::ChangedTo(value) => self.changed && is(&self.current, value),
+            Condition::ChangedFrom(value) => self.changed && is(&self.old, value),
+        }
+    }
+}
+
+/// The id of a watcher returned by `createWatcher`, if `value` is one.
+pub fn watcher_id(state: &State, value: &Value<State>) -> Option<usize> {
+    let Value::Table(table) = value else {
+        return None;
+    };
+    let Value::Int(id) = table.get_str_key("id") else {
+        return None;
+    };
+    (id >= 0 && (id as usize) < state.watchers.borrow().len()).then_some(id as usize)
+}
+
+/// Checks the watcher `id` for `condition`, as of this tick.
+pub fn check_watcher(state: &State, id: usize, condition: &Condition) -> bool {
+    state
+        .watchers
+        .borrow()
+        .get(id)
+        .is_some_and(|watcher| watcher.check(condition))
+}
+
+pub fn create_watcher(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let ty_arg = cx.arg(1);
+    let ty = ValueType::parse(
+        ty_arg
+            .to_str()?
+            .as_utf8()
+            .ok_or_else(|| ty_arg.error("type is not valid UTF-8"))?,
+    )
+    .map_err(|msg| ty_arg.error(msg))?;
+
+    let Some(path) = AddressPath::parse(&cx, "createWatcher", 2, cx.args())? else {
+        cx.push(Value::Nil)?;
+        return Ok(cx.into());
+    };
+
+    let id = {
+        let mut watchers = cx.associated_data().watchers.borrow_mut();
+        watchers.push(Watcher {
+            ty,
+            path,
+            old: None,
+            current: None,
+            changed: false,
+            increased: false,
+            decreased: false,
+        });
+        watchers.len() - 1
+    };
+
+    let watcher = cx.create_table();
+    watcher.set_str_key("id", Value::Int(id as i64));
+    watcher.set_str_key("current", fp!(current));
+    watcher.set_str_key("old", fp!(old));
+    watcher.set_str_key("changed", fp!(changed));
+    watcher.set_str_key("increased", fp!(increased));
+    watcher.set_str_key("decreased", fp!(decreased));
+    watcher.set_str_key("changedTo", fp!(changed_to));
+    watcher.set_str_key("changedFrom", fp!(changed_from));
+
+    cx.push(Value::Table(watcher))?;
+    Ok(cx.into())
+}
+
+/// Runs `f` on the watcher passed as `self`.
+fn with_watcher<T>(cx: &Context<State, Args>, f: impl FnOnce(&Watcher) -> T) -> Result<T> {
+    let Value::Int(id) = cx.arg(1).get_table()?.get_str_key("id") else {
+        return Err(cx
+            .arg(1)
+            .error("expected a watcher returned by createWatcher"));
+    };
+
+    let watchers = cx.associated_data().watchers.borrow();
+    let watcher = watchers
+        .get(id as usize)
+        .ok_or("the watcher doesn't exist")?;
+    Ok(f(watcher))
+}
+
+/// `watcher:current()`, the value read this tick, or `nil` if it couldn't be
+/// read.
+fn current(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let value = with_watcher(&cx, |watcher| {
+        watcher
+            .current
+            .as_ref()
+            .map_or(Value::Nil, |bytes| watcher.ty.decode(&cx, bytes))
+    })?;
+    cx.push(value)?;
+    Ok(cx.into())
+}
+
+/// `watcher:old()`, the value read last tick, or `nil` if it couldn't be read.
+fn old(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let value = with_watcher(&cx, |watcher| {
+        watcher
+            .old
+            .as_ref()
+            .map_or(Value::Nil, |bytes| watcher.ty.decode(&cx, bytes))
+    })?;
+    cx.push(value)?;
+    Ok(cx.into())
+}
+
+fn check<'a>(
+    cx: Context<'a, State, Args>,
+    condition: Condition,
+) -> Result<Context<'a, State, Ret>> {
+    let result = with_watcher(&cx, |watcher| watcher.check(&condition))?;
+    cx.push(result)?;
+    Ok(cx.into())
+}
+
+fn changed(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    check(cx, Condition::Changed)
+}
+
+fn increased(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    check(cx, Condition::Increased)
+}
+
+fn decreased(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    check(cx, Condition::Decreased)
+}
+
+/// `watcher:changedTo(value)`, whether it changed this tick and is now
+/// `value`.
+fn changed_to(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let Some(value) = Expected::from_value(&cx.arg(2).get().unwrap_or(Value::Nil)) else {
+        cx.push(false)?;
+        return Ok(cx.into());
+    };
+    check(cx, Condition::ChangedTo(value))
+}
+
+/// `watcher:changedFrom(value)`, whether it changed this tick and was `value`
+/// before.
+fn changed_from(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let Some(value) = Expected::from_value(&cx.arg(2).get().unwrap_or(Value::Nil)) else {
+        cx.push(false)?;
+        return Ok(cx.into());
+    };
+    check(cx, Condition::ChangedFrom(value))
+}
+
+/// Reads every watcher. Called once at the start of every tick, before any
+/// callback.
+pub fn update_watchers(state: &State) {
+    let process = state.process.borrow();
+    let mut watchers = state.watchers.borrow_mut();
+    for watcher in watchers.iter_mut() {
+        let bytes = match &*process {
+            Some(process) if !state.process_died.get() => watcher
+                .path
+                .follow(state, &**process, "createWatcher")
+                .and_then(|address| {
+                    let bytes = read_value(state, &**process, watcher.ty, address);
+                    if bytes.is_none() {
+                        report_read_failure(state, &**process, "createWatcher", address);
+                    }
+                    bytes
+                }),
+            _ => None,
+        };
+
+        if let Some(name) = watcher.path.scan_name() {
+            record_read(state, name, bytes.is_some());
+        }
+
+        watcher.advance(bytes);
+    }
+}
+
+/// Forgets what the watchers read, as it's meaningless in a newly attached
+/// process.
+pub fn reset_watchers(state: &State) {
+    for watcher in state.watchers.borrow_mut().iter_mut() {
+        watcher.old = None;
+        watcher.current = None;
+        watcher.changed = false;
+        watcher.increased = false;
+        watcher.decreased = false;
+    }
+}
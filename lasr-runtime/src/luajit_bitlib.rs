@@ -1,5 +1,5 @@
 use tsuki::{
-    Lua, Module, Ref, Table, Value,
+    Float, Lua, Module, Ref, Table, Value,
     context::{Args, Context, Ret},
     fp,
 };
@@ -36,12 +36,24 @@ impl Module<State> for LuaJitBitLib {
     }
 }
 
+/// Normalizes a number the way LuaJIT does, so `bit.tobit(2^40 + 3) == 3` and
+/// `bit.tobit(2.5) == 2`: round to the nearest integer, ties to even, then wrap
+/// modulo 2^32.
 fn arg_i32(cx: &Context<State, Args>, index: usize) -> Result<i32> {
-    Ok(cx.arg(index).to_int()? as i32)
+    Ok(match cx.arg(index).get() {
+        Some(Value::Int(x)) => x as i32,
+        Some(Value::Float(Float(x))) => {
+            if !x.is_finite() {
+                return Err(cx.arg(index).error("number has no integer representation"));
+            }
+            x.round_ties_even().rem_euclid(4294967296.0) as u32 as i32
+        }
+        _ => cx.arg(index).to_int()? as i32,
+    })
 }
 
 fn arg_shift(cx: &Context<State, Args>, index: usize) -> Result<u32> {
-    Ok((cx.arg(index).to_int()? as u32) & 31)
+    Ok(arg_i32(cx, index)? as u32 & 31)
 }
 
 pub fn tobit(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
@@ -123,7 +135,8 @@ pub fn tohex(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     let digits = cx.arg(2).to_nilable_int(false)?.unwrap_or(8);
 
     let uppercase = digits < 0;
-    let width = (digits.unsigned_abs() as usize).clamp(1, 8);
+    // Like LuaJIT 2.0, which turns a width of 0 into an empty string.
+    let width = (digits.unsigned_abs() as usize).min(8);
 
     let full = if uppercase {
         format!("{x:08X}")
@@ -141,3 +154,95 @@ pub fn bswap(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     cx.push((x.swap_bytes() as i32) as i64)?;
     Ok(cx.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{lua, run};
+
+    #[test]
+    fn normalizes_numbers() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(bit.tobit(0xffffffff) == -1)
+            assert(bit.tobit(0xffffffff + 1) == 0)
+            assert(bit.tobit(2^40 + 0x1234) == 0x1234)
+            assert(bit.tobit(2^40 + 3) == 3)
+            assert(bit.tobit(-2^31) == -2^31 and bit.tobit(2^31) == -2^31)
+            assert(bit.band(2^32 + 5, 0xFF) == 5)
+            assert(bit.tobit(2.5) == 2 and bit.tobit(3.5) == 4 and bit.tobit(-2.5) == -2)
+            assert(bit.tobit(1e300) == 0)
+            assert(not pcall(bit.tobit, 0/0))
+            assert(not pcall(bit.tobit, math.huge))
+            assert(not pcall(bit.tobit, "nope"))
+            "#,
+        );
+    }
+
+    #[test]
+    fn combines_bits() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(bit.bnot(0) == -1 and bit.bnot(-1) == 0)
+            assert(bit.bor(1, 2, 4, 8) == 15)
+            assert(bit.band(0x12345678, 0xff) == 0x78)
+            assert(bit.bxor(0xa5a5f0f0, 0xaa55ff00) == 0x0ff00ff0)
+            assert(bit.band(-1, 0xffffffff) == -1)
+            assert(bit.bswap(0x12345678) == 0x78563412)
+            assert(bit.bswap(0x78563412) == 0x12345678)
+            "#,
+        );
+    }
+
+    #[test]
+    fn shifts_by_the_lowest_5_bits() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(bit.lshift(1, 0) == 1 and bit.lshift(1, 8) == 256)
+            assert(bit.lshift(1, 31) == -2^31)
+            assert(bit.lshift(1, 32) == 1 and bit.lshift(1, 40) == 256)
+            assert(bit.lshift(1, -1) == -2^31)
+            assert(bit.lshift(0x87654321, 12) == 0x54321000)
+
+            assert(bit.rshift(256, 8) == 1 and bit.rshift(-256, 8) == 16777215)
+            assert(bit.rshift(0x87654321, 12) == 0x00087654)
+            assert(bit.rshift(2, 33) == 1 and bit.rshift(1, 33) == 0)
+            assert(bit.rshift(-1, -1) == 1)
+
+            assert(bit.arshift(-256, 8) == -1)
+            assert(bit.arshift(0x87654321, 12) == bit.tobit(0xfff87654))
+            assert(bit.arshift(-2^31, 63) == -1)
+
+            assert(bit.rol(0x12345678, 12) == 0x45678123)
+            assert(bit.ror(0x12345678, 12) == 0x67812345)
+            assert(bit.rol(0x12345678, 44) == 0x45678123)
+            assert(bit.ror(0x12345678, -20) == 0x67812345)
+            "#,
+        );
+    }
+
+    #[test]
+    fn formats_hex() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            assert(bit.tohex(1) == "00000001")
+            assert(bit.tohex(-1) == "ffffffff")
+            assert(bit.tohex(0xffffffff) == "ffffffff")
+            assert(bit.tohex(-1, -8) == "FFFFFFFF")
+            assert(bit.tohex(0x21, 4) == "0021")
+            assert(bit.tohex(0x87654321, 4) == "4321")
+            assert(bit.tohex(0x87654321, -2) == "21")
+            assert(bit.tohex(0xabcdef, 1) == "f" and bit.tohex(0xabcdef, -1) == "F")
+            assert(bit.tohex(0x87654321, 12) == "87654321")
+            assert(bit.tohex(0x87654321, 0) == "")
+            "#,
+        );
+    }
+}
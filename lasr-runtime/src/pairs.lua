@@ -0,0 +1,24 @@
+-- Walks tables for `TablePairs` in batches, so Rust calls into the VM once per
+-- batch instead of once per pair. Returns up to `BATCH` pairs flattened into
+-- key, value, key, value, ..., followed by the key to continue from. The key is
+-- missing once the end of the table is reached.
+
+local next_pair = ...
+local unpack = table.unpack
+
+local BATCH = 256
+
+return function(t, k)
+    local batch, n = {}, 0
+    for _ = 1, BATCH do
+        local v
+        k, v = next_pair(t, k)
+        if k == nil then
+            return unpack(batch, 1, n)
+        end
+        batch[n + 1], batch[n + 2] = k, v
+        n = n + 2
+    end
+    batch[n + 1] = k
+    return unpack(batch, 1, n + 1)
+end
@@ -0,0 +1,173 @@
+use asr::{Address, Process};
+
+/// A read or query that failed. Like asr's own error, it doesn't say why.
+#[derive(Debug, Clone, Copy)]
+pub struct Error;
+
+/// The subset of [`Process`] the Lua API needs, so the API doesn't depend on
+/// a real process being attached.
+pub trait ProcessMemory {
+    fn is_open(&self) -> bool;
+
+    fn read_into_buf(&self, address: Address, buf: &mut [u8]) -> Result<(), Error>;
+
+    fn get_module_address(&self, name: &str) -> Result<Address, Error>;
+
+    fn get_module_size(&self, name: &str) -> Result<u64, Error>;
+
+    fn memory_ranges(&self) -> Box<dyn Iterator<Item = Result<(Address, u64), Error>> + '_>;
+
+    /// The underlying [`Process`], which asr's file format and game engine
+    /// support need.
+    fn as_process(&self) -> Option<&Process> {
+        None
+    }
+}
+
+impl dyn ProcessMemory + '_ {
+    /// Reads `N` bytes into a buffer on the stack, to decode with
+    /// `from_le_bytes`, which works at any alignment.
+    pub fn read_bytes<const N: usize>(&self, address: Address) -> Result<[u8; N], Error> {
+        let mut buf = [0; N];
+        self.read_into_buf(address, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl ProcessMemory for Process {
+    fn is_open(&self) -> bool {
+        Process::is_open(self)
+    }
+
+    fn read_into_buf(&self, address: Address, buf: &mut [u8]) -> Result<(), Error> {
+        let result = Process::read_into_buf(self, address, buf).map_err(|_| Error);
+        crate::record::read(address, buf, result.is_ok());
+        result
+    }
+
+    fn get_module_address(&self, name: &str) -> Result<Address, Error> {
+        Process::get_module_address(self, name).map_err(|_| Error)
+    }
+
+    fn get_module_size(&self, name: &str) -> Result<u64, Error> {
+        Process::get_module_size(self, name).map_err(|_| Error)
+    }
+
+    fn memory_ranges(&self) -> Box<dyn Iterator<Item = Result<(Address, u64), Error>> + '_> {
+        Box::new(Process::memory_ranges(self).map(|range| range.range().map_err(|_| Error)))
+    }
+
+    fn as_process(&self) -> Option<&Process> {
+        Some(self)
+    }
+}
+
+/// A process that's nothing but the memory and modules it's given, for
+/// testing the Lua API without a game.
+#[cfg(test)]
+pub mod mock {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::{BTreeMap, HashMap},
+    };
+
+    use asr::Address;
+
+    use super::{Error, ProcessMemory};
+
+    #[derive(Default)]
+    pub struct MockProcess {
+        /// The mapped memory, by start address. Reads can't span two regions,
+        /// just like they can't span two unrelated pages of a real process.
+        regions: RefCell<BTreeMap<u64, Vec<u8>>>,
+        modules: HashMap<String, (u64, u64)>,
+        closed: Cell<bool>,
+        /// How often the memory ranges were queried.
+        pub range_queries: Cell<usize>,
+        /// How many reads there were, including failed ones.
+        pub reads: Cell<usize>,
+    }
+
+    impl MockProcess {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Maps `bytes` at `address`.
+        pub fn map(self, address: u64, bytes: impl Into<Vec<u8>>) -> Self {
+            self.regions.borrow_mut().insert(address, bytes.into());
+            self
+        }
+
+        /// Adds a module. Its memory needs to be mapped separately.
+        pub fn module(mut self, name: &str, address: u64, size: u64) -> Self {
+            self.modules.insert(name.to_owned(), (address, size));
+            self
+        }
+
+        /// Closes the process, like the game exiting. Reads fail from then on.
+        pub fn close(&self) {
+            self.closed.set(true);
+        }
+
+        /// Overwrites mapped memory, like the game would.
+        pub fn write(&self, address: u64, bytes: &[u8]) {
+            let mut regions = self.regions.borrow_mut();
+            let region = Self::region(&mut regions, address, bytes.len())
+                .unwrap_or_else(|| panic!("{address:#X} isn't mapped"));
+            region.copy_from_slice(bytes);
+        }
+
+        fn region(
+            regions: &mut BTreeMap<u64, Vec<u8>>,
+            address: u64,
+            len: usize,
+        ) -> Option<&mut [u8]> {
+            let (&start, bytes) = regions.range_mut(..=address).next_back()?;
+            let offset = (address - start) as usize;
+            bytes.get_mut(offset..offset.checked_add(len)?)
+        }
+    }
+
+    impl ProcessMemory for MockProcess {
+        fn is_open(&self) -> bool {
+            !self.closed.get()
+        }
+
+        fn read_into_buf(&self, address: Address, buf: &mut [u8]) -> Result<(), Error> {
+            self.reads.set(self.reads.get() + 1);
+            if self.closed.get() {
+                return Err(Error);
+            }
+            let mut regions = self.regions.borrow_mut();
+            let bytes = Self::region(&mut regions, address.value(), buf.len()).ok_or(Error)?;
+            buf.copy_from_slice(bytes);
+            Ok(())
+        }
+
+        fn get_module_address(&self, name: &str) -> Result<Address, Error> {
+            match self.modules.get(name) {
+                Some(&(address, _)) if !self.closed.get() => Ok(Address::new(address)),
+                _ => Err(Error),
+            }
+        }
+
+        fn get_module_size(&self, name: &str) -> Result<u64, Error> {
+            match self.modules.get(name) {
+                Some(&(_, size)) if !self.closed.get() => Ok(size),
+                _ => Err(Error),
+            }
+        }
+
+        fn memory_ranges(&self) -> Box<dyn Iterator<Item = Result<(Address, u64), Error>> + '_> {
+            self.range_queries.set(self.range_queries.get() + 1);
+            let ranges: Vec<_> = self
+                .regions
+                .borrow()
+                .iter()
+                .map(|(&start, bytes)| Ok((Address::new(start), bytes.len() as u64)))
+                .collect();
+            Box::new(ranges.into_iter())
+        }
+    }
+}
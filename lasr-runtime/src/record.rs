@@ -0,0 +1,200 @@
+//! Records the process reads and callback results of the last few ticks, so
+//! `dumpTrace` can show what led up to a wrong split after the fact. Enabled
+//! by `recordTicks` in `startup`.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    fmt::Write,
+};
+
+use asr::Address;
+use tsuki::context::{Args, Context, Ret};
+
+use crate::{
+    log,
+    lua_api::describe_address,
+    state::{Result, State},
+    timer_policy::TickResults,
+    utils::fnv1a,
+};
+
+/// Reads beyond this many in a tick are only counted, a signature scan alone
+/// would fill the recording otherwise.
+const MAX_READS_PER_TICK: usize = 256;
+
+/// Reads up to this many bytes are kept as is with `recordRawReads`, longer
+/// ones are hashed either way.
+const MAX_RAW_LEN: usize = 64;
+
+enum ReadResult {
+    Failed,
+    Raw(Vec<u8>),
+    Hash(u64),
+}
+
+struct ReadRecord {
+    address: Address,
+    len: usize,
+    result: ReadResult,
+}
+
+#[derive(Default)]
+struct TickRecord {
+    /// Set once the tick is over.
+    tick: u64,
+    reads: Vec<ReadRecord>,
+    dropped_reads: usize,
+    results: Option<String>,
+}
+
+thread_local! {
+    /// How many ticks to keep, `0` while not recording.
+    static MAX_TICKS: Cell<usize> = const { Cell::new(0) };
+    static RAW: Cell<bool> = const { Cell::new(false) };
+    static TICK: Cell<u64> = const { Cell::new(0) };
+    static CURRENT: RefCell<TickRecord> = RefCell::default();
+    static TICKS: RefCell<VecDeque<TickRecord>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Starts recording the last `ticks` ticks, keeping the bytes of short reads
+/// if `raw` is set instead of only their hash.
+pub fn enable(ticks: usize, raw: bool) {
+    MAX_TICKS.set(ticks);
+    RAW.set(raw);
+}
+
+/// Records a read of `buf.len()` bytes at `address`, `buf` holding the bytes
+/// read if it `succeeded`.
+pub fn read(address: Address, buf: &[u8], succeeded: bool) {
+    if MAX_TICKS.get() == 0 {
+        return;
+    }
+
+    CURRENT.with_borrow_mut(|current| {
+        if current.reads.len() == MAX_READS_PER_TICK {
+            current.dropped_reads += 1;
+            return;
+        }
+
+        let result = if !succeeded {
+            ReadResult::Failed
+        } else if RAW.get() && buf.len() <= MAX_RAW_LEN {
+            ReadResult::Raw(buf.to_vec())
+        } else {
+            ReadResult::Hash(fnv1a(buf))
+        };
+        current.reads.push(ReadRecord {
+            address,
+            len: buf.len(),
+            result,
+        });
+    });
+}
+
+/// Records what the callbacks returned this tick.
+pub fn results(results: &TickResults) {
+    if MAX_TICKS.get() == 0 {
+        return;
+    }
+
+    fn opt(value: Option<bool>) -> &'static str {
+        match value {
+            Some(true) => "true",
+            Some(false) => "false",
+            None => "nil",
+        }
+    }
+
+    let mut line = format!(
+        "start={} split={} isLoading={} reset={} gameTime=",
+        opt(results.start),
+        opt(results.split),
+        opt(results.is_loading),
+        opt(results.reset),
+    );
+    match results.game_time {
+        Some(game_time) => {
+            let _ = write!(line, "{:.3}s", game_time.as_seconds_f64());
+        }
+        None => line.push_str("nil"),
+    }
+
+    CURRENT.with_borrow_mut(|current| current.results = Some(line));
+}
+
+/// Finishes the tick's record. Called at the end of every tick.
+pub fn end_tick() {
+    let tick = TICK.get() + 1;
+    TICK.set(tick);
+
+    let max_ticks = MAX_TICKS.get();
+    if max_ticks == 0 {
+        return;
+    }
+
+    let mut record = CURRENT.take();
+    record.tick = tick;
+    TICKS.with_borrow_mut(|ticks| {
+        if ticks.len() == max_ticks {
+            ticks.pop_front();
+        }
+        ticks.push_back(record);
+    });
+}
+
+pub fn dump_trace(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    if MAX_TICKS.get() == 0 {
+        log::warn("[dumpTrace] Nothing was recorded, set recordTicks in startup first");
+        return Ok(cx.into());
+    }
+
+    let count = cx.arg(1).to_nilable_int(false)?;
+
+    let process = cx.associated_data().process.borrow();
+    TICKS.with_borrow(|ticks| {
+        let skip = match count {
+            Some(count) => ticks.len().saturating_sub(count.max(0) as usize),
+            None => 0,
+        };
+
+        for record in ticks.iter().skip(skip) {
+            log::print_message(&format!(
+                "[dumpTrace] tick {}: {}",
+                record.tick,
+                record.results.as_deref().unwrap_or("no callbacks"),
+            ));
+
+            for read in &record.reads {
+                let mut line = format!(
+                    "[dumpTrace]   {} bytes at {}: ",
+                    read.len,
+                    describe_address(cx.associated_data(), process.as_deref(), read.address),
+                );
+                match &read.result {
+                    ReadResult::Failed => line.push_str("failed"),
+                    ReadResult::Raw(bytes) => {
+                        for byte in bytes {
+                            let _ = write!(line, "{byte:02X} ");
+                        }
+                        line.pop();
+                    }
+                    ReadResult::Hash(hash) => {
+                        let _ = write!(line, "hash {hash:016X}");
+                    }
+                }
+                log::print_message(&line);
+            }
+
+            if record.dropped_reads != 0 {
+                log::print_message(&format!(
+                    "[dumpTrace]   {} more reads",
+                    record.dropped_reads
+                ));
+            }
+        }
+    });
+    drop(process);
+
+    Ok(cx.into())
+}
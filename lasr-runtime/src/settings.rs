@@ -0,0 +1,332 @@
+//! The `settings` module, which registers settings the runner can change in
+//! LiveSplit's auto splitter settings.
+
+use std::collections::HashMap;
+
+use asr::settings::{Map, gui};
+use tsuki::{
+    Float, Lua, Module, Ref, Table, Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use crate::{
+    log,
+    lua_api::Capability,
+    state::{Result, State},
+};
+
+pub struct SettingsLib;
+
+/// How a setting is represented, so `settings.get` can hand it back the way it
+/// was added.
+#[derive(Clone, Copy)]
+pub enum SettingKind {
+    Bool,
+    Choice,
+    /// asr has no numeric settings, so numbers are a choice between all the
+    /// values in the range.
+    Number,
+}
+
+/// Numbers are offered as a dropdown, which gets unusable beyond this many
+/// entries.
+const MAX_NUMBER_OPTIONS: i64 = 256;
+
+impl Module<State> for SettingsLib {
+    const NAME: &str = "settings";
+
+    type Inst<'a>
+        = Ref<'a, Table<State>>
+    where
+        State: 'a;
+
+    fn open(self, lua: &Lua<State>) -> Result<Self::Inst<'_>> {
+        let m = lua.create_table();
+
+        m.set_str_key("add", fp!(add));
+        m.set_str_key("addTitle", fp!(add_title));
+        m.set_str_key("addChoice", fp!(add_choice));
+        m.set_str_key("addNumber", fp!(add_number));
+        m.set_str_key("beginGroup", fp!(begin_group));
+        m.set_str_key("endGroup", fp!(end_group));
+        m.set_str_key("setTooltip", fp!(set_tooltip));
+        m.set_str_key("get", fp!(get));
+
+        Ok(m)
+    }
+}
+
+/// Settings are meant to be registered from `startup`, the host may not pick
+/// up ones registered later on.
+fn warn_if_late(cx: &Context<State, Args>, function: &str) {
+    if cx.associated_data().startup_finished.get() {
+        log::warn(&format!(
+            "[settings] {function} was called after startup, the setting may not show up"
+        ));
+    }
+}
+
+fn str_arg(cx: &Context<State, Args>, index: usize, utf8_error: &'static str) -> Result<String> {
+    let arg = cx.arg(index);
+    Ok(arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error(utf8_error))?
+        .to_owned())
+}
+
+fn add(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    warn_if_late(&cx, "settings.add");
+
+    let key = str_arg(&cx, 1, "key is not valid UTF-8")?;
+    let default = matches!(cx.arg(2).get(), Some(Value::True));
+    let description = str_arg(&cx, 3, "description is not valid UTF-8")?;
+
+    gui::add_bool(&key, &description, default);
+    if let Some(tooltip) = cx.arg(4).to_nilable_str(false)? {
+        let tooltip = tooltip
+            .as_utf8()
+            .ok_or_else(|| cx.arg(4).error("tooltip is not valid UTF-8"))?;
+        gui::set_tooltip(&key, tooltip);
+    }
+    cx.associated_data()
+        .settings_keys
+        .borrow_mut()
+        .insert(key, SettingKind::Bool);
+
+    Ok(cx.into())
+}
+
+fn add_title(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    warn_if_late(&cx, "settings.addTitle");
+
+    let description = str_arg(&cx, 1, "description is not valid UTF-8")?;
+    let heading_level = match cx.arg(2).to_nilable_int(false)? {
+        Some(level) => level.max(0) as u32,
+        None => cx.associated_data().settings_depth.get(),
+    };
+
+    // Titles need a key like any other setting, but scripts never read them.
+    let title_index = cx.associated_data().settings_titles.get();
+    cx.associated_data().settings_titles.set(title_index + 1);
+    gui::add_title(
+        &format!("__title{title_index}"),
+        &description,
+        heading_level,
+    );
+
+    Ok(cx.into())
+}
+
+fn begin_group(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    warn_if_late(&cx, "settings.beginGroup");
+
+    let key = str_arg(&cx, 1, "key is not valid UTF-8")?;
+    let title = str_arg(&cx, 2, "title is not valid UTF-8")?;
+
+    // The host has no real nesting, so groups are titles with increasing
+    // heading levels.
+    let depth = &cx.associated_data().settings_depth;
+    gui::add_title(&key, &title, depth.get());
+    depth.set(depth.get() + 1);
+
+    Ok(cx.into())
+}
+
+fn end_group(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let depth = &cx.associated_data().settings_depth;
+    if depth.get() == 0 {
+        return Err("settings.endGroup was called without a matching settings.beginGroup".into());
+    }
+    depth.set(depth.get() - 1);
+
+    Ok(cx.into())
+}
+
+fn set_tooltip(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let key = str_arg(&cx, 1, "key is not valid UTF-8")?;
+    let tooltip = str_arg(&cx, 2, "tooltip is not valid UTF-8")?;
+
+    gui::set_tooltip(&key, &tooltip);
+
+    Ok(cx.into())
+}
+
+fn add_choice(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    warn_if_late(&cx, "settings.addChoice");
+
+    let key = str_arg(&cx, 1, "key is not valid UTF-8")?;
+    let default = str_arg(&cx, 2, "default is not valid UTF-8")?;
+    let description = str_arg(&cx, 3, "description is not valid UTF-8")?;
+
+    let options_arg = cx.arg(4);
+    let options_table = options_arg.get_table()?;
+    let mut options = Vec::new();
+    for i in 1.. {
+        match options_table.get(Value::Int(i)) {
+            Value::Str(option) => options.push(
+                option
+                    .as_utf8()
+                    .ok_or_else(|| options_arg.error("option is not valid UTF-8"))?
+                    .to_owned(),
+            ),
+            Value::Nil => break,
+            _ => return Err(options_arg.error("options need to be strings")),
+        }
+    }
+
+    if !options.contains(&default) {
+        return Err(cx.arg(2).error("default needs to be one of the options"));
+    }
+
+    gui::add_choice(&key, &description, &default);
+    for option in &options {
+        gui::add_choice_option(&key, option, option);
+    }
+    cx.associated_data()
+        .settings_keys
+        .borrow_mut()
+        .insert(key, SettingKind::Choice);
+
+    Ok(cx.into())
+}
+
+fn add_number(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    warn_if_late(&cx, "settings.addNumber");
+
+    let key = str_arg(&cx, 1, "key is not valid UTF-8")?;
+    let default = cx.arg(2).to_int()?;
+    let description = str_arg(&cx, 3, "description is not valid UTF-8")?;
+    let min = cx.arg(4).to_int()?;
+    let max = cx.arg(5).to_int()?;
+
+    if min > max || max - min >= MAX_NUMBER_OPTIONS {
+        return Err(format!(
+            "settings.addNumber needs min <= max and at most {MAX_NUMBER_OPTIONS} values in between"
+        )
+        .into());
+    }
+    if !(min..=max).contains(&default) {
+        return Err(cx.arg(2).error("default needs to be between min and max"));
+    }
+
+    gui::add_choice(&key, &description, &default.to_string());
+    for value in min..=max {
+        let value = value.to_string();
+        gui::add_choice_option(&key, &value, &value);
+    }
+    cx.associated_data()
+        .settings_keys
+        .borrow_mut()
+        .insert(key, SettingKind::Number);
+
+    Ok(cx.into())
+}
+
+fn get(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let key = str_arg(&cx, 1, "key is not valid UTF-8")?;
+
+    let state = cx.associated_data();
+    let Some(kind) = state.settings_keys.borrow().get(&key).copied() else {
+        // Most likely a typo, so point it out once instead of every tick.
+        if state.settings_warned.borrow_mut().insert(key.clone()) {
+            log::warn(&format!(
+                "[settings] settings.get(\"{key}\") refers to a setting that was never added"
+            ));
+        }
+        cx.push(Value::Nil)?;
+        return Ok(cx.into());
+    };
+
+    let value = read(state, &key, kind).to_value(|value| Value::Str(cx.create_str(value)));
+
+    cx.push(value)?;
+    Ok(cx.into())
+}
+
+/// A setting's value, detached from the host's settings map so it can be
+/// compared across ticks.
+#[derive(Clone, PartialEq)]
+pub enum SettingValue {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl SettingValue {
+    pub fn to_value<'a>(
+        &self,
+        create_str: impl FnOnce(&str) -> Value<'a, State>,
+    ) -> Value<'a, State> {
+        match self {
+            SettingValue::Nil => Value::Nil,
+            SettingValue::Bool(true) => Value::True,
+            SettingValue::Bool(false) => Value::False,
+            SettingValue::Int(value) => Value::Int(*value),
+            SettingValue::Float(value) => Value::Float(Float(*value)),
+            SettingValue::Str(value) => create_str(value),
+        }
+    }
+}
+
+pub fn read(state: &State, key: &str, kind: SettingKind) -> SettingValue {
+    if !state
+        .host_features
+        .check(Capability::SettingsMaps, "settings.get")
+    {
+        return SettingValue::Nil;
+    }
+
+    let mut map = state.settings_map.borrow_mut();
+    // Loaded at most once per tick, the main loop clears it.
+    let map = map.get_or_insert_with(Map::load);
+    let Some(value) = map.get(key) else {
+        return SettingValue::Nil;
+    };
+
+    if let Some(value) = value.get_bool() {
+        SettingValue::Bool(value)
+    } else if let Some(value) = value.get_i64() {
+        SettingValue::Int(value)
+    } else if let Some(value) = value.get_f64() {
+        SettingValue::Float(value)
+    } else if let Some(value) = value.get_string() {
+        match kind {
+            SettingKind::Number => match value.parse() {
+                Ok(value) => SettingValue::Int(value),
+                Err(_) => SettingValue::Nil,
+            },
+            SettingKind::Bool | SettingKind::Choice => SettingValue::Str(value),
+        }
+    } else {
+        SettingValue::Nil
+    }
+}
+
+/// Compares the registered settings against the previous call and returns
+/// the key, old and new value of each one that changed. The first call only
+/// takes the snapshot.
+pub fn changes(state: &State) -> Vec<(String, SettingValue, SettingValue)> {
+    let current: HashMap<String, SettingValue> = state
+        .settings_keys
+        .borrow()
+        .iter()
+        .map(|(key, &kind)| (key.clone(), read(state, key, kind)))
+        .collect();
+
+    let mut snapshot = state.settings_snapshot.borrow_mut();
+    let mut changes = Vec::new();
+    if let Some(previous) = &*snapshot {
+        for (key, new) in &current {
+            let old = previous.get(key).unwrap_or(&SettingValue::Nil);
+            if old != new {
+                changes.push((key.clone(), old.clone(), new.clone()));
+            }
+        }
+    }
+    *snapshot = Some(current);
+    changes
+}
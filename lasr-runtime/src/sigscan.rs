@@ -0,0 +1,413 @@
+//! Signatures like `"48 8B ?? 05"`, and searching for them in process memory
+//! and in plain bytes. `sig_scan`, `registerScan`, `detectVersion` and
+//! `findPattern` all match through here.
+
+use std::iter;
+
+use asr::{Address, future::next_tick};
+
+use crate::{
+    lua_api::ReadStats,
+    process_memory::{self, ProcessMemory},
+};
+
+/// How many bytes of process memory are read at once.
+const CHUNK_SIZE: usize = 0x10000;
+
+/// A byte of a signature. Wildcard nibbles are masked out.
+#[derive(Copy, Clone)]
+pub struct SigByte {
+    value: u8,
+    mask: u8,
+}
+
+impl SigByte {
+    fn matches(self, byte: u8) -> bool {
+        (byte & self.mask) == self.value
+    }
+}
+
+pub fn parse_signature(pattern: &str) -> Result<Vec<SigByte>, &'static str> {
+    let mut out = Vec::new();
+    for token in pattern.split_whitespace() {
+        out.push(parse_sig_token(token)?);
+    }
+    if out.is_empty() {
+        return Err("signature is empty");
+    }
+    Ok(out)
+}
+
+fn parse_sig_token(token: &str) -> Result<SigByte, &'static str> {
+    if token == "?" || token == "??" {
+        return Ok(SigByte { value: 0, mask: 0 });
+    }
+
+    let bytes = token.as_bytes();
+    if bytes.len() != 2 {
+        return Err("signature token must be 2 hex chars or '?' wildcards");
+    }
+
+    let (hi_val, hi_mask) = match bytes[0] {
+        b'?' => (0, 0),
+        c => (hex_nibble(c)?, 0xF),
+    };
+    let (lo_val, lo_mask) = match bytes[1] {
+        b'?' => (0, 0),
+        c => (hex_nibble(c)?, 0xF),
+    };
+
+    Ok(SigByte {
+        value: (hi_val << 4) | lo_val,
+        mask: (hi_mask << 4) | lo_mask,
+    })
+}
+
+fn hex_nibble(c: u8) -> Result<u8, &'static str> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err("signature contains non-hex characters"),
+    }
+}
+
+/// Whether `bytes` are the `signature`'s bytes.
+pub fn signature_matches(signature: &[SigByte], bytes: &[u8]) -> bool {
+    signature.len() == bytes.len()
+        && signature
+            .iter()
+            .zip(bytes)
+            .all(|(&sig, &byte)| sig.matches(byte))
+}
+
+/// Looks for a signature in bytes fed to it one at a time, so a match can
+/// span the chunks process memory is read in. It keeps track of every prefix
+/// of the signature that matches the bytes fed last, one bit per prefix, so
+/// wildcards can't make it skip over a match.
+pub struct Matcher<'a> {
+    signature: &'a [SigByte],
+    /// For every byte value, which of the signature's bytes it matches, in
+    /// `words` words per byte value.
+    masks: Vec<u64>,
+    /// Bit `i` is set if the last `i + 1` bytes fed match the start of the
+    /// signature.
+    state: Vec<u64>,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(signature: &'a [SigByte]) -> Self {
+        let words = signature.len().div_ceil(64);
+        let mut masks = vec![0; 256 * words];
+        for byte in 0..=255u8 {
+            let mask = &mut masks[byte as usize * words..][..words];
+            for (i, sig) in signature.iter().enumerate() {
+                if sig.matches(byte) {
+                    mask[i / 64] |= 1 << (i % 64);
+                }
+            }
+        }
+        Self {
+            signature,
+            masks,
+            state: vec![0; words],
+        }
+    }
+
+    /// Feeds the next byte, returning whether it completes a match. The
+    /// match started `signature.len() - 1` bytes before it. Matches may
+    /// overlap.
+    pub fn feed(&mut self, byte: u8) -> bool {
+        let words = self.state.len();
+        let mask = &self.masks[byte as usize * words..][..words];
+        // Every prefix that matched grows by a byte, and a new one starts.
+        let mut carry = 1;
+        for (word, &mask) in self.state.iter_mut().zip(mask) {
+            let next_carry = *word >> 63;
+            *word = ((*word << 1) | carry) & mask;
+            carry = next_carry;
+        }
+
+        let last = self.signature.len() - 1;
+        self.state[last / 64] & (1 << (last % 64)) != 0
+    }
+
+    /// Forgets the bytes fed so far, for when the next byte doesn't follow
+    /// them in memory.
+    pub fn reset(&mut self) {
+        self.state.fill(0);
+    }
+}
+
+/// The 0-based indices of all, possibly overlapping, matches in `haystack`.
+pub fn find_matches<'a>(
+    haystack: &'a [u8],
+    signature: &'a [SigByte],
+    start: usize,
+) -> impl Iterator<Item = usize> + 'a {
+    let mut matcher = Matcher::new(signature);
+
+    haystack
+        .iter()
+        .enumerate()
+        .skip(start)
+        .filter(move |&(_, &byte)| matcher.feed(byte))
+        .map(|(i, _)| i + 1 - signature.len())
+}
+
+/// Scans `range`, or all of the process' memory if it's `None`. Returns where
+/// the signature was found first, plus `offset`.
+pub async fn scan_signature(
+    process: &dyn ProcessMemory,
+    stats: &ReadStats,
+    signature: &[SigByte],
+    offset: i64,
+    range: Option<(Address, u64)>,
+) -> Result<Option<i64>, &'static str> {
+    let found = scan_signatures(process, stats, &[signature], range).await?[0];
+    Ok(found.map(|address| address.value() as i64 + offset))
+}
+
+/// Scans `range`, or all of the process' memory if it's `None`, for all of
+/// the `signatures` in a single pass. Returns where each of them was found
+/// first.
+pub async fn scan_signatures(
+    process: &dyn ProcessMemory,
+    stats: &ReadStats,
+    signatures: &[impl AsRef<[SigByte]>],
+    range: Option<(Address, u64)>,
+) -> Result<Vec<Option<Address>>, &'static str> {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut matchers: Vec<Matcher> = signatures
+        .iter()
+        .map(|signature| Matcher::new(signature.as_ref()))
+        .collect();
+    let mut found = vec![None; signatures.len()];
+    let mut remaining_signatures = signatures.len();
+
+    let mut chunk_counter: u32 = 0;
+    let ranges: Box<dyn Iterator<Item = Result<(Address, u64), process_memory::Error>>> =
+        match range {
+            Some(range) => Box::new(iter::once(Ok(range))),
+            None => process.memory_ranges(),
+        };
+    for range in ranges {
+        let (base, range_size) = range.map_err(|_| "failed to query memory range")?;
+
+        if range_size == 0 {
+            continue;
+        }
+        // A match can't span two ranges, they needn't be adjacent.
+        for matcher in &mut matchers {
+            matcher.reset();
+        }
+        let mut offset_bytes: u64 = 0;
+        while offset_bytes < range_size {
+            let remaining = (range_size - offset_bytes) as usize;
+            let read_len = remaining.min(CHUNK_SIZE);
+            let buf_slice = &mut buf[..read_len];
+
+            stats.count_scan_read();
+
+            if process
+                .read_into_buf(base + offset_bytes, buf_slice)
+                .is_err()
+            {
+                break;
+            }
+
+            for (i, &byte) in buf_slice.iter().enumerate() {
+                for (sig, matcher) in matchers.iter_mut().enumerate() {
+                    if found[sig].is_some() || !matcher.feed(byte) {
+                        continue;
+                    }
+                    let start = offset_bytes + i as u64 + 1 - matcher.signature.len() as u64;
+                    found[sig] = Some(base + start);
+                    remaining_signatures -= 1;
+                }
+                if remaining_signatures == 0 {
+                    return Ok(found);
+                }
+            }
+
+            offset_bytes += read_len as u64;
+            chunk_counter = chunk_counter.wrapping_add(1);
+            if chunk_counter.is_multiple_of(64) {
+                next_tick().await;
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use asr::Address;
+
+    use super::{CHUNK_SIZE, find_matches, parse_signature, scan_signatures, signature_matches};
+    use crate::{lua_api::ReadStats, process_memory::mock::MockProcess, testing::block_on};
+
+    fn matches(haystack: &[u8], signature: &str) -> Vec<usize> {
+        let signature = parse_signature(signature).unwrap();
+        find_matches(haystack, &signature, 0).collect()
+    }
+
+    #[test]
+    fn parses_signatures() {
+        assert!(parse_signature("48 8b ?? ? 4? ?F").is_ok());
+        assert!(parse_signature("").is_err());
+        assert!(parse_signature("  ").is_err());
+        assert!(parse_signature("488B").is_err());
+        assert!(parse_signature("4").is_err());
+        assert!(parse_signature("GG").is_err());
+        assert!(parse_signature("???").is_err());
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        // A failed match mustn't skip over one that starts inside it.
+        assert_eq!(matches(&[1, 1, 1, 2], "01 ?? 02"), [1]);
+        assert_eq!(matches(&[1, 2, 1, 3, 2], "01 ?? 02"), [2]);
+        assert_eq!(matches(&[0xA1, 0xB2, 0x3C], "?1 B? 3C"), [0]);
+        assert_eq!(matches(&[0xA1, 0xB2, 0x3D], "?1 B? 3C"), []);
+        assert_eq!(matches(&[0x1F, 0x2F], "?? ?F"), [0]);
+        assert_eq!(matches(&[5, 6, 7], "??"), [0, 1, 2]);
+    }
+
+    #[test]
+    fn finds_overlapping_matches() {
+        assert_eq!(matches(&[0xAA; 5], "AA AA"), [0, 1, 2, 3]);
+        assert_eq!(matches(&[1, 2, 1, 2, 1, 2], "01 02 01 02"), [0, 2]);
+        assert_eq!(matches(&[1, 2], "01 02 03"), []);
+    }
+
+    #[test]
+    fn finds_matches_from_start() {
+        let signature = parse_signature("01 02").unwrap();
+        let haystack = [1, 2, 0, 1, 2];
+        let found: Vec<_> = find_matches(&haystack, &signature, 1).collect();
+        assert_eq!(found, [3]);
+    }
+
+    #[test]
+    fn matches_long_signatures() {
+        // Longer than a word of prefix bits, with the wildcards on either
+        // side of the word boundaries.
+        for len in [63, 64, 65, 128, 129, 200] {
+            let mut haystack = vec![0xEE; 7];
+            haystack.extend((0..len).map(|i| i as u8));
+            haystack.extend([0xEE; 3]);
+            let signature: Vec<_> = (0..len)
+                .map(|i| match i {
+                    63 | 64 | 127 => "??".to_owned(),
+                    _ => format!("{:02X}", i as u8),
+                })
+                .collect();
+            assert_eq!(matches(&haystack, &signature.join(" ")), [7], "{len}");
+
+            haystack[7 + 62] ^= 1;
+            assert_eq!(matches(&haystack, &signature.join(" ")), [], "{len}");
+        }
+    }
+
+    #[test]
+    fn scans_across_chunk_boundaries() {
+        let base = 0x100000u64;
+        let process = MockProcess::new().map(base, vec![0; 3 * CHUNK_SIZE]);
+        let boundary = base + CHUNK_SIZE as u64;
+        // Ends right before, straddles and starts right at a boundary.
+        process.write(boundary - 3, &[0x11, 0x22, 0x33]);
+        process.write(boundary + CHUNK_SIZE as u64 - 2, &[0x44, 0x55, 0x66, 0x77]);
+        process.write(boundary + CHUNK_SIZE as u64 + 2, &[0x88, 0x99]);
+
+        let signatures = [
+            parse_signature("11 22 33").unwrap(),
+            parse_signature("44 ?? 66 77").unwrap(),
+            parse_signature("88 99").unwrap(),
+            parse_signature("33 44").unwrap(),
+        ];
+        let found = block_on(scan_signatures(
+            &process,
+            &ReadStats::new(),
+            &signatures,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(
+            found,
+            [
+                Some(Address::new(boundary - 3)),
+                Some(Address::new(boundary + CHUNK_SIZE as u64 - 2)),
+                Some(Address::new(boundary + CHUNK_SIZE as u64 + 2)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_a_range() {
+        let base = 0x100000u64;
+        let process = MockProcess::new().map(base, vec![0; 0x100]);
+        process.write(base + 0x10, &[0xAB, 0xCD]);
+        process.write(base + 0x80, &[0xAB, 0xCD]);
+
+        let signatures = [parse_signature("AB CD").unwrap()];
+        let scan = |start: u64, len: u64| {
+            block_on(scan_signatures(
+                &process,
+                &ReadStats::new(),
+                &signatures,
+                Some((Address::new(start), len)),
+            ))
+            .unwrap()[0]
+        };
+        assert_eq!(scan(base, 0x100), Some(Address::new(base + 0x10)));
+        assert_eq!(scan(base + 0x11, 0xEF), Some(Address::new(base + 0x80)));
+        // The match has to end inside the range.
+        assert_eq!(scan(base + 0x11, 0x70), None);
+    }
+
+    /// A small xorshift, the tests only need some bytes that aren't all the
+    /// same.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u8 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as u8
+        }
+    }
+
+    #[test]
+    fn agrees_with_checking_every_index() {
+        let mut random = Random(0x2545_F491_4F6C_DD1D);
+        for _ in 0..2000 {
+            // Few distinct bytes, so there are plenty of partial matches.
+            let haystack: Vec<u8> = (0..random.next() % 64).map(|_| random.next() % 4).collect();
+            let tokens: Vec<_> = (0..1 + random.next() % 6)
+                .map(|_| match random.next() % 4 {
+                    0 => "??".to_owned(),
+                    1 => format!("?{:X}", random.next() % 4),
+                    2 => format!("{:X}?", random.next() % 2),
+                    _ => format!("{:02X}", random.next() % 4),
+                })
+                .collect();
+            let pattern = tokens.join(" ");
+            let signature = parse_signature(&pattern).unwrap();
+            let start = (random.next() % 8) as usize;
+
+            let expected: Vec<_> = haystack
+                .windows(signature.len())
+                .enumerate()
+                .skip(start)
+                .filter(|(_, window)| signature_matches(&signature, window))
+                .map(|(i, _)| i)
+                .collect();
+            let found: Vec<_> = find_matches(&haystack, &signature, start).collect();
+            assert_eq!(found, expected, "{pattern} in {haystack:02X?} from {start}");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! The `splits` module, which registers a checkbox per split and keeps track
+//! of which splits already happened in the current run.
+
+use asr::settings::gui;
+use tsuki::{
+    Lua, Module, Ref, Table, Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use crate::{
+    log,
+    settings::{self, SettingKind, SettingValue},
+    state::{Result, State},
+};
+
+pub struct SplitsLib;
+
+impl Module<State> for SplitsLib {
+    const NAME: &str = "splits";
+
+    type Inst<'a>
+        = Ref<'a, Table<State>>
+    where
+        State: 'a;
+
+    fn open(self, lua: &Lua<State>) -> Result<Self::Inst<'_>> {
+        let m = lua.create_table();
+
+        m.set_str_key("define", fp!(define));
+        m.set_str_key("trigger", fp!(trigger));
+
+        Ok(m)
+    }
+}
+
+fn entry_str(entry: &Table<State>, field: &str) -> Result<String> {
+    match entry.get_str_key(field) {
+        Value::Str(value) => Ok(value
+            .as_utf8()
+            .ok_or_else(|| format!("split {field} is not valid UTF-8"))?
+            .to_owned()),
+        _ => Err(format!("every split needs a string {field}").into()),
+    }
+}
+
+fn define(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let list = cx.arg(1).get_table()?;
+
+    let mut splits = Vec::new();
+    for i in 1.. {
+        match list.get(Value::Int(i)) {
+            Value::Table(entry) => {
+                let id = entry_str(&entry, "id")?;
+                let label = entry_str(&entry, "label")?;
+                let enabled = !matches!(entry.get_str_key("default"), Value::False);
+                splits.push((id, label, enabled));
+            }
+            Value::Nil => break,
+            _ => return Err(cx.arg(1).error("every split needs to be a table")),
+        }
+    }
+
+    if cx.associated_data().startup_finished.get() {
+        log::warn("[splits] splits.define was called after startup, the splits may not show up");
+    }
+
+    let depth = cx.associated_data().settings_depth.get();
+    gui::add_title("__splits", "Splits", depth);
+
+    {
+        let mut keys = cx.associated_data().settings_keys.borrow_mut();
+        let mut split_ids = cx.associated_data().split_ids.borrow_mut();
+        for (id, label, enabled) in splits {
+            gui::add_bool(&id, &label, enabled);
+            keys.insert(id.clone(), SettingKind::Bool);
+            split_ids.insert(id);
+        }
+    }
+
+    Ok(cx.into())
+}
+
+fn trigger(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let arg = cx.arg(1);
+    let id = arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("split id is not valid UTF-8"))?;
+
+    let state = cx.associated_data();
+    if !state.split_ids.borrow().contains(id) {
+        return Err(arg.error("unknown split id, add it with splits.define first"));
+    }
+
+    cx.push(fire(state, id))?;
+    Ok(cx.into())
+}
+
+/// Whether the split `id` from `splits.define` happens now, which it does if
+/// its setting is enabled and it didn't happen yet in this run.
+pub fn fire(state: &State, id: &str) -> bool {
+    let enabled = settings::read(state, id, SettingKind::Bool) == SettingValue::Bool(true);
+    // Fires at most once per run, the main loop clears this whenever the
+    // timer isn't running.
+    enabled && state.splits_fired.borrow_mut().insert(id.to_owned())
+}
@@ -1,20 +1,216 @@
 use core::error::Error;
 use std::{
     cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
     string::String,
 };
 
-use asr::{Address, Process};
+use asr::Address;
+
+use crate::process_memory::ProcessMemory;
 
 pub type Result<T, E = Box<dyn Error>> = std::result::Result<T, E>;
 
 pub struct State {
-    pub process: RefCell<Option<Process>>,
+    /// An `Rc`, so async natives can keep the process without keeping this
+    /// borrowed across an await, during which another coroutine may attach.
+    pub process: RefCell<Option<Rc<dyn ProcessMemory>>>,
     pub base_address: Cell<Address>,
+    /// The size of the attached process' pointers in bytes, if its main
+    /// module's header could be read.
+    pub pointer_size: Cell<Option<u64>>,
+    /// Set by `setPointerSize`, takes precedence over `pointer_size`.
+    pub pointer_size_override: Cell<Option<u64>>,
     pub process_name: RefCell<Option<String>>,
+    /// Set by every attach, so the main loop calls `onAttach` before the next
+    /// tick's callbacks.
+    pub attach_pending: Cell<bool>,
+    /// Module addresses found so far, cleared when attaching.
+    pub module_addresses: RefCell<HashMap<String, Address>>,
+    /// Modules `readAddress` already warned about not finding, cleared when
+    /// attaching.
+    pub missing_modules_warned: RefCell<HashSet<String>>,
+    /// The names modules were found under when a script's name for them
+    /// didn't match exactly, cleared when attaching.
+    pub module_aliases: RefCell<HashMap<String, String>>,
+    /// Set once a read notices the process closed, after which reads return
+    /// `nil` without printing anything. Cleared when attaching.
+    pub process_died: Cell<bool>,
+    /// The scans registered through `registerScan`, by name.
+    pub scans: RefCell<HashMap<String, crate::lua_api::Scan>>,
+    /// What `readAddress` with `hold` read last. Cleared when attaching.
+    pub held_reads: RefCell<crate::lua_api::HeldReads>,
+    /// The process reads done for the script this tick and the last one.
+    pub read_stats: crate::lua_api::ReadStats,
+    /// The counters created through `createFrameCounter`, by id.
+    pub frame_counters: RefCell<Vec<crate::lua_api::FrameCounter>>,
+    /// The watchers created through `createWatcher`, by id.
+    pub watchers: RefCell<Vec<crate::lua_api::Watcher>>,
+    /// The split conditions registered through `autosplit`.
+    pub autosplits: RefCell<Vec<crate::lua_api::AutoSplit>>,
+    /// Whether `sig_scan` already warned about a main module relative result
+    /// outside the main module.
+    pub sig_scan_legacy_warned: Cell<bool>,
+    /// The exported symbols of each module parsed so far, cleared when
+    /// attaching.
+    pub symbols: RefCell<HashMap<String, Vec<(String, Address)>>>,
     pub maps_cache: RefCell<Option<Vec<MapRange>>>,
     pub maps_cache_cycles: Cell<i64>,
     pub maps_cache_cycles_value: Cell<i64>,
+    /// The platform of the attached process, cached when attaching.
+    pub os: Cell<Option<&'static str>>,
+    pub arch: Cell<Option<&'static str>>,
+    pub rebind_callbacks: Cell<bool>,
+    /// Extra `state` / `update` passes to run before waiting for the next tick.
+    pub burst: Cell<u32>,
+    pub startup_finished: Cell<bool>,
+    pub settings_titles: Cell<u32>,
+    /// How many `settings.beginGroup` calls are still open.
+    pub settings_depth: Cell<u32>,
+    /// The keys registered through the `settings` module.
+    pub settings_keys: RefCell<HashMap<String, crate::settings::SettingKind>>,
+    /// Unregistered keys `settings.get` already warned about.
+    pub settings_warned: RefCell<HashSet<String>>,
+    /// Which optional host functions the runtime uses.
+    pub host_features: crate::lua_api::HostFeatures,
+    /// The host's settings, loaded on first use in each tick.
+    pub settings_map: RefCell<Option<asr::settings::Map>>,
+    /// The settings' values as of the last tick, for `onSettingsChanged`.
+    pub settings_snapshot: RefCell<Option<HashMap<String, crate::settings::SettingValue>>>,
+    /// The ids registered through `splits.define`.
+    pub split_ids: RefCell<HashSet<String>>,
+    /// The splits `splits.trigger` fired for in the current run.
+    pub splits_fired: RefCell<HashSet<String>>,
+    /// The ids `splitOnce` returned `true` for since the timer last started or
+    /// reset. Kept when the game restarts mid-run.
+    pub split_once_fired: RefCell<HashSet<String>>,
+    /// What `detectVersion` found for each set of signatures, as the game
+    /// version doesn't change while attached.
+    pub detected_versions: RefCell<HashMap<String, Vec<String>>>,
+    /// The functions that already noted being called without a process.
+    pub no_process_noted: RefCell<HashSet<&'static str>>,
+    /// The text last shown for each `setTimeVariable` key.
+    pub time_variables: RefCell<HashMap<String, String>>,
+    /// The variables set through `setVariableThrottled`.
+    pub throttled_variables: RefCell<HashMap<String, crate::variables::ThrottledVariable>>,
+    /// The variables set through `setVariableWithTTL` that haven't expired yet.
+    pub expiring_variables: RefCell<HashMap<String, crate::variables::ExpiringVariable>>,
+    /// What `isLoading` returned last.
+    pub loading: Cell<bool>,
+    /// Set by `pauseGameTime`, until `resumeGameTime` releases it.
+    pub game_time_paused_manually: Cell<bool>,
+    /// Whether the host's game time is currently paused.
+    pub game_time_paused: Cell<bool>,
+    /// Decides on the timer actions, along with the cooldowns and the
+    /// settings that affect them.
+    pub timer_policy: RefCell<crate::timer_policy::TimerPolicy>,
+    /// Messages longer than this many bytes get printed in parts. Set by
+    /// `maxMessageLength` in `startup`.
+    pub max_message_len: Cell<usize>,
+    /// Set by `gameTimeUnit` in `startup`.
+    pub game_time_unit: Cell<GameTimeUnit>,
+    /// Set by `resetOnGameExit` in `startup`.
+    pub reset_on_game_exit: Cell<bool>,
+    /// Set by `resetOnGameStart` in `startup`.
+    pub reset_on_game_start: Cell<bool>,
+    /// Set by `idleTickRate` in `startup`.
+    pub idle_tick_rate: Cell<Option<f64>>,
+    /// Whether the runtime currently ticks at `idle_tick_rate`.
+    pub idling: Cell<bool>,
+    #[cfg(feature = "unity")]
+    pub unity: RefCell<crate::unity::UnityState>,
+    #[cfg(feature = "unreal")]
+    pub unreal: RefCell<Option<asr::game_engine::unreal::Module>>,
+    /// Attaches on its own, independent of the `process` the script uses.
+    #[cfg(feature = "emulator")]
+    pub emulator: RefCell<Option<crate::emulator::Emulator>>,
+}
+
+impl State {
+    /// The state of a freshly started runtime, before the script ran.
+    pub fn new() -> Self {
+        Self {
+            process: RefCell::new(None),
+            base_address: Cell::new(Address::NULL),
+            pointer_size: Cell::new(None),
+            pointer_size_override: Cell::new(None),
+            process_name: RefCell::new(None),
+            attach_pending: Cell::new(false),
+            module_addresses: RefCell::new(HashMap::new()),
+            missing_modules_warned: RefCell::new(HashSet::new()),
+            module_aliases: RefCell::new(HashMap::new()),
+            sig_scan_legacy_warned: Cell::new(false),
+            process_died: Cell::new(false),
+            scans: RefCell::new(HashMap::new()),
+            held_reads: RefCell::new(crate::lua_api::HeldReads::default()),
+            read_stats: crate::lua_api::ReadStats::new(),
+            frame_counters: RefCell::new(Vec::new()),
+            watchers: RefCell::new(Vec::new()),
+            autosplits: RefCell::new(Vec::new()),
+            symbols: RefCell::new(HashMap::new()),
+            maps_cache: RefCell::new(None),
+            maps_cache_cycles: Cell::new(1),
+            maps_cache_cycles_value: Cell::new(1),
+            os: Cell::new(None),
+            arch: Cell::new(None),
+            rebind_callbacks: Cell::new(false),
+            burst: Cell::new(0),
+            startup_finished: Cell::new(false),
+            settings_titles: Cell::new(0),
+            settings_depth: Cell::new(0),
+            settings_keys: RefCell::new(HashMap::new()),
+            settings_warned: RefCell::new(HashSet::new()),
+            host_features: crate::lua_api::HostFeatures::new(),
+            settings_map: RefCell::new(None),
+            settings_snapshot: RefCell::new(None),
+            split_ids: RefCell::new(HashSet::new()),
+            splits_fired: RefCell::new(HashSet::new()),
+            split_once_fired: RefCell::new(HashSet::new()),
+            detected_versions: RefCell::new(HashMap::new()),
+            no_process_noted: RefCell::new(HashSet::new()),
+            time_variables: RefCell::new(HashMap::new()),
+            throttled_variables: RefCell::new(HashMap::new()),
+            expiring_variables: RefCell::new(HashMap::new()),
+            loading: Cell::new(false),
+            game_time_paused_manually: Cell::new(false),
+            game_time_paused: Cell::new(false),
+            timer_policy: RefCell::new(crate::timer_policy::TimerPolicy::new()),
+            max_message_len: Cell::new(4096),
+            game_time_unit: Cell::new(GameTimeUnit::Milliseconds),
+            reset_on_game_exit: Cell::new(false),
+            idle_tick_rate: Cell::new(None),
+            idling: Cell::new(false),
+            reset_on_game_start: Cell::new(false),
+            #[cfg(feature = "unity")]
+            unity: RefCell::default(),
+            #[cfg(feature = "unreal")]
+            unreal: RefCell::new(None),
+            #[cfg(feature = "emulator")]
+            emulator: RefCell::new(None),
+        }
+    }
+}
+
+/// The unit `gameTime` returns its value in.
+#[derive(Clone, Copy)]
+pub enum GameTimeUnit {
+    Milliseconds,
+    Microseconds,
+    Seconds,
+}
+
+impl GameTimeUnit {
+    pub fn duration(self, time: f64) -> asr::time::Duration {
+        // Going through whole nanoseconds keeps the fractions of a millisecond
+        // that frame based times are made of.
+        let nanos = match self {
+            Self::Milliseconds => time * 1e6,
+            Self::Microseconds => time * 1e3,
+            Self::Seconds => time * 1e9,
+        };
+        asr::time::Duration::nanoseconds(nanos.round() as i64)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -23,3 +219,40 @@ pub struct MapRange {
     pub end: u64,
     pub size: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use asr::time::Duration;
+
+    use super::GameTimeUnit;
+
+    #[test]
+    fn keeps_frame_times_precise() {
+        // An hour at 60 fps, added up frame by frame like a script would.
+        for (unit, frame) in [
+            (GameTimeUnit::Milliseconds, 1000.0 / 60.0),
+            (GameTimeUnit::Microseconds, 1e6 / 60.0),
+            (GameTimeUnit::Seconds, 1.0 / 60.0),
+        ] {
+            let time = (0..216_000).fold(0.0, |time, _| time + frame);
+            let error = (unit.duration(time) - Duration::hours(1)).abs();
+            assert!(error < Duration::milliseconds(1), "{error}");
+        }
+    }
+
+    #[test]
+    fn keeps_fractions_of_a_millisecond() {
+        assert_eq!(
+            GameTimeUnit::Milliseconds.duration(16.6667),
+            Duration::nanoseconds(16_666_700)
+        );
+        assert_eq!(
+            GameTimeUnit::Microseconds.duration(1.5),
+            Duration::nanoseconds(1_500)
+        );
+        assert_eq!(
+            GameTimeUnit::Seconds.duration(-0.25),
+            Duration::milliseconds(-250)
+        );
+    }
+}
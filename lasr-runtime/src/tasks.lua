@@ -0,0 +1,78 @@
+-- Background tasks started with `spawn`. Every live task is resumed once per
+-- tick after the regular callbacks. Also provides `nextTick` and `waitMs`,
+-- which work in both tasks and callbacks, as both can yield.
+
+-- `resume` resumes like `coroutine.resume`, but lets the task call async
+-- natives like `sig_scan`. `log_error` prints at error severity.
+local resume, log_error = ...
+local create, status, yield = coroutine.create, coroutine.status, coroutine.yield
+
+-- Spawning tasks in a loop by accident would otherwise slowly bring the
+-- runtime to a crawl.
+local MAX_TASKS = 64
+
+local tasks = {}
+local count = 0
+local now = 0
+
+function nextTick()
+    yield()
+end
+
+function waitMs(ms)
+    local deadline = now + ms
+    repeat
+        yield()
+    until now >= deadline
+end
+
+function spawn(fn)
+    if count >= MAX_TASKS then
+        error("too many tasks running, at most " .. MAX_TASKS .. " are allowed", 2)
+    end
+
+    local co = create(fn)
+    tasks[co] = true
+    count = count + 1
+
+    local handle = {}
+
+    function handle:cancel()
+        if tasks[co] then
+            tasks[co] = nil
+            count = count - 1
+        end
+    end
+
+    function handle:isRunning()
+        return tasks[co] == true
+    end
+
+    return handle
+end
+
+return function(elapsed_ms)
+    now = elapsed_ms
+
+    -- Tasks spawned while iterating only start running on the next tick.
+    local running = {}
+    for co in pairs(tasks) do
+        running[#running + 1] = co
+    end
+
+    for _, co in ipairs(running) do
+        -- Another task may have cancelled this one in the meantime.
+        if tasks[co] then
+            local ok, err = resume(co)
+            if not ok then
+                -- There's no debug library for a traceback, the error says
+                -- where in the script it was raised.
+                log_error("[spawn] task failed: " .. tostring(err))
+            end
+            if status(co) == "dead" then
+                tasks[co] = nil
+                count = count - 1
+            end
+        end
+    end
+end
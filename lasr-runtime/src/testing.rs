@@ -0,0 +1,72 @@
+//! Runs Lua against the API with a [`MockProcess`] attached, so tests don't
+//! need LiveSplit or a game. Tests do their checks in Lua through `assert`,
+//! which keeps the values on the Lua side.
+
+use std::{
+    future::Future,
+    pin::{Pin, pin},
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use asr::Address;
+use tsuki::{Lua, Value};
+
+use crate::{
+    lua_api::attached,
+    process_memory::{ProcessMemory, mock::MockProcess},
+    register_api,
+    state::State,
+    utils::error_message,
+};
+
+/// A fresh Lua with the whole API registered.
+pub fn lua() -> Pin<Rc<Lua<State>>> {
+    let lua = Lua::new(State::new());
+    register_api(&lua);
+    lua
+}
+
+/// Attaches `process` with `main_module` as its main module, like `process`
+/// does for a real one.
+pub fn attach(lua: &Lua<State>, process: &Rc<MockProcess>, main_module: &str) {
+    let base_address = process
+        .get_module_address(main_module)
+        .unwrap_or(Address::NULL);
+    attached(
+        lua.associated_data(),
+        process.clone(),
+        main_module,
+        base_address,
+    );
+}
+
+/// Runs `source`, panicking with the Lua error if it fails.
+pub fn run(lua: &Lua<State>, source: &str) {
+    if let Err(err) = try_run(lua, source) {
+        panic!("{err}");
+    }
+}
+
+/// Runs `source`, returning the Lua error if it fails.
+pub fn try_run(lua: &Lua<State>, source: &str) -> Result<(), String> {
+    let chunk = lua
+        .load("test.lua", source)
+        .map_err(|err| err.to_string())?;
+    let td = lua.create_thread();
+    let _: Value<State> =
+        block_on(td.async_call(&chunk, ())).map_err(|err| error_message(&*err))?;
+    Ok(())
+}
+
+/// Polls `future` until it's done. The runtime's futures only ever wait for
+/// the next tick, so there's nothing to wait for in between.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
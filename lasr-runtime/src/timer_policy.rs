@@ -0,0 +1,352 @@
+//! Decides what happens to the timer each tick based on the callbacks'
+//! results, without touching asr, so the main loop only carries the decisions
+//! out.
+
+use std::time::{Duration, Instant};
+
+use asr::timer::TimerState;
+
+pub enum TimerAction {
+    SetGameTime(asr::time::Duration),
+    Start,
+    Split,
+    /// What `isLoading` returned.
+    SetLoading(bool),
+    Reset,
+}
+
+/// What the callbacks returned this tick. `None` for callbacks that aren't
+/// defined, didn't decide or weren't called.
+#[derive(Default)]
+pub struct TickResults {
+    pub game_time: Option<asr::time::Duration>,
+    pub start: Option<bool>,
+    pub split: Option<bool>,
+    pub is_loading: Option<bool>,
+    pub reset: Option<bool>,
+}
+
+pub struct TimerPolicy {
+    /// How long `start` is ignored after starting or resetting the timer.
+    pub start_cooldown: Duration,
+    /// How long `split` is ignored after splitting.
+    pub split_cooldown: Duration,
+    /// Set by `gameTimeWhenEnded` in `startup`.
+    pub game_time_when_ended: bool,
+    /// Set by `isLoadingWhenEnded` in `startup`.
+    pub is_loading_when_ended: bool,
+    /// Cleared by `resetWhenEnded = false` in `startup`.
+    pub reset_when_ended: bool,
+    start_blocked_until: Option<Instant>,
+    split_blocked_until: Option<Instant>,
+}
+
+impl TimerPolicy {
+    pub fn new() -> Self {
+        Self {
+            start_cooldown: Duration::ZERO,
+            split_cooldown: Duration::ZERO,
+            game_time_when_ended: false,
+            is_loading_when_ended: false,
+            reset_when_ended: true,
+            start_blocked_until: None,
+            split_blocked_until: None,
+        }
+    }
+
+    pub fn calls_game_time(&self, timer_state: TimerState) -> bool {
+        // A finished run's game time stays as it was at the final split.
+        match timer_state {
+            TimerState::Running | TimerState::Paused => true,
+            TimerState::Ended => self.game_time_when_ended,
+            _ => false,
+        }
+    }
+
+    pub fn calls_start(&self, timer_state: TimerState) -> bool {
+        matches!(timer_state, TimerState::NotRunning)
+    }
+
+    pub fn calls_split(&self, timer_state: TimerState) -> bool {
+        matches!(timer_state, TimerState::Running | TimerState::Paused)
+    }
+
+    pub fn calls_is_loading(&self, timer_state: TimerState) -> bool {
+        !matches!(timer_state, TimerState::Ended) || self.is_loading_when_ended
+    }
+
+    pub fn calls_reset(&self, timer_state: TimerState) -> bool {
+        // There's nothing to reset before a run started.
+        match timer_state {
+            TimerState::Running | TimerState::Paused => true,
+            TimerState::Ended => self.reset_when_ended,
+            _ => false,
+        }
+    }
+
+    /// Turns the tick's results into the actions to carry out, in order.
+    pub fn decide(
+        &mut self,
+        timer_state: TimerState,
+        now: Instant,
+        results: TickResults,
+    ) -> Vec<TimerAction> {
+        let mut actions = Vec::new();
+
+        if let Some(game_time) = results.game_time
+            && self.calls_game_time(timer_state)
+        {
+            actions.push(TimerAction::SetGameTime(game_time));
+        }
+
+        if let Some(true) = results.start
+            && self.calls_start(timer_state)
+            && !in_cooldown(self.start_blocked_until, now)
+        {
+            actions.push(TimerAction::Start);
+            self.started_or_reset(now);
+        }
+
+        if let Some(true) = results.split
+            && self.calls_split(timer_state)
+            && !in_cooldown(self.split_blocked_until, now)
+        {
+            actions.push(TimerAction::Split);
+            self.split_blocked_until = cooldown_end(now, self.split_cooldown);
+        }
+
+        if let Some(loading) = results.is_loading
+            && self.calls_is_loading(timer_state)
+        {
+            actions.push(TimerAction::SetLoading(loading));
+        }
+
+        if let Some(true) = results.reset
+            && self.calls_reset(timer_state)
+        {
+            actions.push(TimerAction::Reset);
+            self.started_or_reset(now);
+        }
+
+        actions
+    }
+
+    /// Starts the `start` cooldown, also for resets the policy didn't decide
+    /// on, like `resetOnGameExit`.
+    pub fn started_or_reset(&mut self, now: Instant) {
+        self.start_blocked_until = cooldown_end(now, self.start_cooldown);
+    }
+}
+
+fn cooldown_end(now: Instant, cooldown: Duration) -> Option<Instant> {
+    (!cooldown.is_zero()).then(|| now + cooldown)
+}
+
+fn in_cooldown(until: Option<Instant>, now: Instant) -> bool {
+    until.is_some_and(|until| now < until)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use asr::timer::TimerState;
+
+    use super::{TickResults, TimerAction, TimerPolicy};
+
+    /// The actions as short names, which are easier to compare.
+    fn decide(
+        policy: &mut TimerPolicy,
+        timer_state: TimerState,
+        now: Instant,
+        results: TickResults,
+    ) -> Vec<&'static str> {
+        policy
+            .decide(timer_state, now, results)
+            .iter()
+            .map(|action| match action {
+                TimerAction::SetGameTime(_) => "game time",
+                TimerAction::Start => "start",
+                TimerAction::Split => "split",
+                TimerAction::SetLoading(true) => "loading",
+                TimerAction::SetLoading(false) => "not loading",
+                TimerAction::Reset => "reset",
+            })
+            .collect()
+    }
+
+    fn start() -> TickResults {
+        TickResults {
+            start: Some(true),
+            ..Default::default()
+        }
+    }
+
+    fn split() -> TickResults {
+        TickResults {
+            split: Some(true),
+            ..Default::default()
+        }
+    }
+
+    fn reset() -> TickResults {
+        TickResults {
+            reset: Some(true),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn starts_only_while_not_running() {
+        let mut policy = TimerPolicy::new();
+        let now = Instant::now();
+        assert_eq!(
+            decide(&mut policy, TimerState::NotRunning, now, start()),
+            ["start"]
+        );
+        for timer_state in [TimerState::Running, TimerState::Paused, TimerState::Ended] {
+            assert!(decide(&mut policy, timer_state, now, start()).is_empty());
+        }
+        let results = TickResults {
+            start: Some(false),
+            ..Default::default()
+        };
+        assert!(decide(&mut policy, TimerState::NotRunning, now, results).is_empty());
+    }
+
+    #[test]
+    fn resets_only_a_run() {
+        let mut policy = TimerPolicy::new();
+        policy.start_cooldown = Duration::from_secs(5);
+        let now = Instant::now();
+
+        // Nothing to reset, so the start cooldown doesn't begin either.
+        assert!(decide(&mut policy, TimerState::NotRunning, now, reset()).is_empty());
+        assert_eq!(
+            decide(&mut policy, TimerState::NotRunning, now, start()),
+            ["start"]
+        );
+
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, now, reset()),
+            ["reset"]
+        );
+        assert_eq!(
+            decide(&mut policy, TimerState::Paused, now, reset()),
+            ["reset"]
+        );
+        assert_eq!(
+            decide(&mut policy, TimerState::Ended, now, reset()),
+            ["reset"]
+        );
+
+        policy.reset_when_ended = false;
+        assert!(decide(&mut policy, TimerState::Ended, now, reset()).is_empty());
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, now, reset()),
+            ["reset"]
+        );
+    }
+
+    #[test]
+    fn splits_every_time_without_a_cooldown() {
+        let mut policy = TimerPolicy::new();
+        let now = Instant::now();
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, now, split()),
+            ["split"]
+        );
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, now, split()),
+            ["split"]
+        );
+        assert_eq!(
+            decide(&mut policy, TimerState::Paused, now, split()),
+            ["split"]
+        );
+        assert!(decide(&mut policy, TimerState::NotRunning, now, split()).is_empty());
+        assert!(decide(&mut policy, TimerState::Ended, now, split()).is_empty());
+    }
+
+    #[test]
+    fn keeps_a_split_from_firing_twice() {
+        let mut policy = TimerPolicy::new();
+        policy.split_cooldown = Duration::from_millis(500);
+        let now = Instant::now();
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, now, split()),
+            ["split"]
+        );
+        let soon = now + Duration::from_millis(16);
+        assert!(decide(&mut policy, TimerState::Running, soon, split()).is_empty());
+        let later = now + Duration::from_millis(500);
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, later, split()),
+            ["split"]
+        );
+    }
+
+    #[test]
+    fn waits_after_starting_or_resetting() {
+        let mut policy = TimerPolicy::new();
+        policy.start_cooldown = Duration::from_secs(1);
+        let now = Instant::now();
+        assert_eq!(
+            decide(&mut policy, TimerState::NotRunning, now, start()),
+            ["start"]
+        );
+        // Say the runner reset the timer right away.
+        let soon = now + Duration::from_millis(100);
+        assert!(decide(&mut policy, TimerState::NotRunning, soon, start()).is_empty());
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            decide(&mut policy, TimerState::NotRunning, later, start()),
+            ["start"]
+        );
+
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, later, reset()),
+            ["reset"]
+        );
+        let soon = later + Duration::from_millis(100);
+        assert!(decide(&mut policy, TimerState::NotRunning, soon, start()).is_empty());
+
+        // Resets the policy didn't decide on, like `resetOnGameExit`.
+        let later = later + Duration::from_secs(1);
+        policy.started_or_reset(later);
+        assert!(decide(&mut policy, TimerState::NotRunning, later, start()).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_order_of_a_tick() {
+        let mut policy = TimerPolicy::new();
+        let now = Instant::now();
+        let results = || TickResults {
+            game_time: Some(asr::time::Duration::seconds(1)),
+            start: Some(true),
+            split: Some(true),
+            is_loading: Some(true),
+            reset: Some(true),
+        };
+        assert_eq!(
+            decide(&mut policy, TimerState::Running, now, results()),
+            ["game time", "split", "loading", "reset"]
+        );
+        assert_eq!(
+            decide(&mut policy, TimerState::NotRunning, now, results()),
+            ["start", "loading"]
+        );
+
+        // A finished run keeps its game time and loading state by default.
+        assert_eq!(
+            decide(&mut policy, TimerState::Ended, now, results()),
+            ["reset"]
+        );
+        policy.game_time_when_ended = true;
+        policy.is_loading_when_ended = true;
+        assert_eq!(
+            decide(&mut policy, TimerState::Ended, now, results()),
+            ["game time", "loading", "reset"]
+        );
+    }
+}
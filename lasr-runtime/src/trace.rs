@@ -0,0 +1,110 @@
+// Tracing of every Lua API call, enabled through the `trace-api` feature.
+// Without the feature, all of this compiles down to nothing.
+
+#[cfg(feature = "trace-api")]
+mod enabled {
+    use std::{cell::Cell, error::Error, fmt::Write};
+
+    use tsuki::{
+        Value,
+        context::{Args, Context},
+    };
+
+    use crate::{state::State, utils::DisplayValue};
+
+    // A single tick can call the API in a loop, so we only print the first
+    // few calls of each tick to not flood the log.
+    const MAX_LINES_PER_TICK: u32 = 64;
+
+    thread_local! {
+        static TICK: Cell<u64> = const { Cell::new(0) };
+        static LINES: Cell<u32> = const { Cell::new(0) };
+    }
+
+    pub fn tick() {
+        let suppressed = LINES.replace(0).saturating_sub(MAX_LINES_PER_TICK);
+        if suppressed > 0 {
+            crate::log::debug(&format!("[trace] {suppressed} more calls suppressed"));
+        }
+
+        let tick = TICK.get() + 1;
+        TICK.set(tick);
+        crate::log::debug(&format!("[trace] tick {tick}"));
+    }
+
+    pub fn format_call_args(cx: &Context<State, Args>) -> String {
+        let mut out = String::new();
+        for i in 1..=cx.args() {
+            if i > 1 {
+                out.push_str(", ");
+            }
+            let value = cx.arg(i).get().unwrap_or(Value::Nil);
+            let _ = write!(out, "{}", DisplayValue(&value));
+        }
+        out
+    }
+
+    pub fn finish_call(name: &str, args: &str, error: Option<&dyn Error>) {
+        let lines = LINES.get();
+        LINES.set(lines + 1);
+        if lines >= MAX_LINES_PER_TICK {
+            return;
+        }
+
+        match error {
+            Some(err) => crate::log::debug(&format!("[trace] {name}({args}) -> error: {err}")),
+            None => crate::log::debug(&format!("[trace] {name}({args}) -> ok")),
+        }
+    }
+}
+
+#[cfg(feature = "trace-api")]
+pub use enabled::{finish_call, format_call_args, tick};
+
+#[cfg(not(feature = "trace-api"))]
+#[inline(always)]
+pub fn tick() {}
+
+/// Wraps an API function like `fp!` does, but logs each call when the
+/// `trace-api` feature is enabled.
+#[cfg(feature = "trace-api")]
+macro_rules! traced {
+    ($name:literal, $f:path as async) => {{
+        async fn traced<'a>(
+            cx: tsuki::context::Context<'a, crate::state::State, tsuki::context::Args>,
+        ) -> crate::state::Result<
+            tsuki::context::Context<'a, crate::state::State, tsuki::context::Ret>,
+        > {
+            let args = crate::trace::format_call_args(&cx);
+            let result = $f(cx).await;
+            crate::trace::finish_call($name, &args, result.as_ref().err().map(|err| &**err));
+            result
+        }
+        tsuki::fp!(traced as async)
+    }};
+    ($name:literal, $f:path) => {{
+        fn traced(
+            cx: tsuki::context::Context<crate::state::State, tsuki::context::Args>,
+        ) -> crate::state::Result<
+            tsuki::context::Context<crate::state::State, tsuki::context::Ret>,
+        > {
+            let args = crate::trace::format_call_args(&cx);
+            let result = $f(cx);
+            crate::trace::finish_call($name, &args, result.as_ref().err().map(|err| &**err));
+            result
+        }
+        tsuki::fp!(traced)
+    }};
+}
+
+#[cfg(not(feature = "trace-api"))]
+macro_rules! traced {
+    ($name:literal, $f:path as async) => {
+        tsuki::fp!($f as async)
+    };
+    ($name:literal, $f:path) => {
+        tsuki::fp!($f)
+    };
+}
+
+pub(crate) use traced;
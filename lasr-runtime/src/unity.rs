@@ -0,0 +1,386 @@
+//! The `unity` module, exposing asr's Mono and IL2CPP support. Version
+//! mismatches are routine, so lookups return `nil` and a message instead of
+//! raising errors.
+
+use std::{cell::Cell, collections::HashMap};
+
+use asr::{
+    Address, Process,
+    game_engine::unity::{il2cpp, mono},
+};
+use tsuki::{
+    Lua, Module, Ref, Table, Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use crate::state::{Result, State};
+
+pub struct UnityLib;
+
+impl Module<State> for UnityLib {
+    const NAME: &str = "unity";
+
+    type Inst<'a>
+        = Ref<'a, Table<State>>
+    where
+        State: 'a;
+
+    fn open(self, lua: &Lua<State>) -> Result<Self::Inst<'_>> {
+        let m = lua.create_table();
+
+        m.set_str_key("attach", fp!(attach));
+        m.set_str_key("findClass", fp!(find_class));
+
+        Ok(m)
+    }
+}
+
+thread_local! {
+    /// The generation of the next [`UnityState`]. Class handles remember the
+    /// generation they were found in, so one from before attaching again
+    /// can't end up referring to a class found afterwards.
+    static NEXT_GENERATION: Cell<i64> = const { Cell::new(0) };
+}
+
+/// Everything looked up so far, dropped whenever the runtime attaches to a
+/// process.
+pub struct UnityState {
+    generation: i64,
+    module: Option<UnityModule>,
+    class_ids: HashMap<(String, String, String), usize>,
+    classes: Vec<UnityClass>,
+}
+
+impl Default for UnityState {
+    fn default() -> Self {
+        let generation = NEXT_GENERATION.get();
+        NEXT_GENERATION.set(generation + 1);
+        Self {
+            generation,
+            module: None,
+            class_ids: HashMap::new(),
+            classes: Vec::new(),
+        }
+    }
+}
+
+enum UnityModule {
+    Mono(mono::Module),
+    Il2Cpp(il2cpp::Module),
+}
+
+enum ClassHandle {
+    Mono(mono::Class),
+    Il2Cpp(il2cpp::Class),
+}
+
+struct UnityClass {
+    handle: ClassHandle,
+    field_offsets: HashMap<String, Option<u32>>,
+    static_table: Option<Address>,
+}
+
+fn push_failure<'a>(
+    cx: Context<'a, State, Args>,
+    message: &str,
+) -> Result<Context<'a, State, Ret>> {
+    cx.push(Value::Nil)?;
+    cx.push(Value::Str(cx.create_str(message)))?;
+    Ok(cx.into())
+}
+
+/// Calls `f` with the attached process, `None` if there is none.
+fn with_process<T>(cx: &Context<State, Args>, f: impl FnOnce(&Process) -> T) -> Option<T> {
+    let process_ref = cx.associated_data().process.borrow();
+    let process = process_ref.as_ref()?.as_process()?;
+    Some(f(process))
+}
+
+const NO_PROCESS: &str = "no process attached";
+const NOT_ATTACHED: &str = "unity.attach needs to be called first";
+const STALE_CLASS: &str = "the class is no longer valid, find it again after attaching";
+
+fn attach(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let arg = cx.arg(1);
+    let kind = match arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("backend is not valid UTF-8"))?
+    {
+        "mono" => "mono",
+        "il2cpp" => "il2cpp",
+        _ => {
+            return Err(arg.error("backend needs to be either 'mono' or 'il2cpp'"));
+        }
+    };
+
+    let module = with_process(&cx, |process| match kind {
+        "mono" => mono::Module::attach_auto_detect(process).map(UnityModule::Mono),
+        _ => il2cpp::Module::attach_auto_detect(process).map(UnityModule::Il2Cpp),
+    });
+    let Some(module) = module else {
+        return push_failure(cx, NO_PROCESS);
+    };
+    let Some(module) = module else {
+        return push_failure(cx, &format!("failed to find the {kind} module"));
+    };
+
+    *cx.associated_data().unity.borrow_mut() = UnityState {
+        module: Some(module),
+        ..UnityState::default()
+    };
+
+    cx.push(Value::True)?;
+    Ok(cx.into())
+}
+
+fn find_class(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let mut names = [const { String::new() }; 3];
+    for (i, name) in names.iter_mut().enumerate() {
+        let arg = cx.arg(i + 1);
+        *name = arg
+            .to_str()?
+            .as_utf8()
+            .ok_or_else(|| arg.error("name is not valid UTF-8"))?
+            .to_owned();
+    }
+    let [assembly, namespace, class] = names;
+
+    let key = (assembly, namespace, class);
+    let (generation, cached) = {
+        let unity = cx.associated_data().unity.borrow();
+        (unity.generation, unity.class_ids.get(&key).copied())
+    };
+    let id = match cached {
+        Some(id) => id,
+        None => {
+            let (assembly, _, class) = &key;
+            let handle = {
+                let unity = cx.associated_data().unity.borrow();
+                let Some(module) = &unity.module else {
+                    drop(unity);
+                    return push_failure(cx, NOT_ATTACHED);
+                };
+                // asr finds classes by name only, the namespace just keeps
+                // the cache apart.
+                with_process(&cx, |process| match module {
+                    UnityModule::Mono(module) => module
+                        .get_image(process, assembly)
+                        .and_then(|image| image.get_class(process, module, class))
+                        .map(ClassHandle::Mono),
+                    UnityModule::Il2Cpp(module) => module
+                        .get_image(process, assembly)
+                        .and_then(|image| image.get_class(process, module, class))
+                        .map(ClassHandle::Il2Cpp),
+                })
+            };
+            let Some(handle) = handle else {
+                return push_failure(cx, NO_PROCESS);
+            };
+
+            let Some(handle) = handle else {
+                let message = format!("class {class} not found in {assembly}");
+                return push_failure(cx, &message);
+            };
+
+            let mut unity = cx.associated_data().unity.borrow_mut();
+            let id = unity.classes.len();
+            unity.classes.push(UnityClass {
+                handle,
+                field_offsets: HashMap::new(),
+                static_table: None,
+            });
+            unity.class_ids.insert(key, id);
+            id
+        }
+    };
+
+    let table = cx.create_table();
+    table.set_str_key("id", Value::Int(id as i64));
+    table.set_str_key("generation", Value::Int(generation));
+    table.set_str_key("getField", fp!(get_field));
+    table.set_str_key("getStaticAddress", fp!(get_static_address));
+
+    cx.push(Value::Table(table))?;
+    Ok(cx.into())
+}
+
+/// The class passed as the first argument, `None` if it's from before the
+/// last attach, and the field name.
+fn class_arg(cx: &Context<State, Args>) -> Result<(Option<usize>, String)> {
+    let class = cx.arg(1).get_table()?;
+    let (Value::Int(id), Value::Int(generation)) =
+        (class.get_str_key("id"), class.get_str_key("generation"))
+    else {
+        return Err(cx
+            .arg(1)
+            .error("expected a class returned by unity.findClass"));
+    };
+    let current = cx.associated_data().unity.borrow().generation == generation;
+
+    let arg = cx.arg(2);
+    let name = arg
+        .to_str()?
+        .as_utf8()
+        .ok_or_else(|| arg.error("field name is not valid UTF-8"))?
+        .to_owned();
+
+    Ok((current.then_some(id as usize), name))
+}
+
+/// The offset of `field` in the class, `Err` with the message to return
+/// instead if it can't be looked up at all.
+fn field_offset(
+    cx: &Context<State, Args>,
+    id: Option<usize>,
+    field: &str,
+) -> Result<Option<u32>, &'static str> {
+    let mut unity = cx.associated_data().unity.borrow_mut();
+    let UnityState {
+        module, classes, ..
+    } = &mut *unity;
+    let (Some(module), Some(class)) = (module.as_ref(), id.and_then(|id| classes.get_mut(id)))
+    else {
+        return Err(STALE_CLASS);
+    };
+
+    if let Some(&offset) = class.field_offsets.get(field) {
+        return Ok(offset);
+    }
+
+    let offset = with_process(cx, |process| match (module, &class.handle) {
+        (UnityModule::Mono(module), ClassHandle::Mono(handle)) => {
+            handle.get_field_offset(process, module, field)
+        }
+        (UnityModule::Il2Cpp(module), ClassHandle::Il2Cpp(handle)) => {
+            handle.get_field_offset(process, module, field)
+        }
+        _ => None,
+    })
+    .ok_or(NO_PROCESS)?;
+    class.field_offsets.insert(field.to_owned(), offset);
+    Ok(offset)
+}
+
+fn get_field(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let (id, field) = class_arg(&cx)?;
+
+    match field_offset(&cx, id, &field) {
+        Ok(Some(offset)) => {
+            cx.push(Value::Int(offset as i64))?;
+            Ok(cx.into())
+        }
+        Ok(None) => push_failure(cx, &format!("field {field} not found")),
+        Err(message) => push_failure(cx, message),
+    }
+}
+
+fn get_static_address(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let (id, field) = class_arg(&cx)?;
+
+    let offset = match field_offset(&cx, id, &field) {
+        Ok(Some(offset)) => offset,
+        Ok(None) => return push_failure(cx, &format!("field {field} not found")),
+        Err(message) => return push_failure(cx, message),
+    };
+
+    let static_table = 'table: {
+        let mut unity = cx.associated_data().unity.borrow_mut();
+        let UnityState {
+            module, classes, ..
+        } = &mut *unity;
+        let (Some(module), Some(class)) = (module.as_ref(), id.and_then(|id| classes.get_mut(id)))
+        else {
+            break 'table Err(STALE_CLASS);
+        };
+
+        if class.static_table.is_none() {
+            let Some(static_table) = with_process(&cx, |process| match (module, &class.handle) {
+                (UnityModule::Mono(module), ClassHandle::Mono(handle)) => {
+                    handle.get_static_table(process, module)
+                }
+                (UnityModule::Il2Cpp(module), ClassHandle::Il2Cpp(handle)) => {
+                    handle.get_static_table(process, module)
+                }
+                _ => None,
+            }) else {
+                break 'table Err(NO_PROCESS);
+            };
+            class.static_table = static_table;
+        }
+        Ok(class.static_table)
+    };
+    let static_table = match static_table {
+        Ok(static_table) => static_table,
+        Err(message) => return push_failure(cx, message),
+    };
+
+    match static_table {
+        Some(table) => {
+            cx.push(Value::Int((table + offset as u64).value() as i64))?;
+            Ok(cx.into())
+        }
+        None => push_failure(cx, "the class has no static fields yet"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use tsuki::fp;
+
+    use super::{get_field, get_static_address};
+    use crate::{
+        process_memory::mock::MockProcess,
+        testing::{attach, lua, run},
+    };
+
+    #[test]
+    fn fails_without_a_process() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            local ok, err = unity.attach("mono")
+            assert(ok == nil and err == "no process attached")
+            local class, err = unity.findClass("Assembly-CSharp", "", "GameManager")
+            assert(class == nil and err == "unity.attach needs to be called first")
+            "#,
+        );
+
+        // The mock only stands in for memory reads, asr needs a real process.
+        attach(&lua, &Rc::new(MockProcess::new()), "game.exe");
+        run(
+            &lua,
+            r#"
+            local ok, err = unity.attach("il2cpp")
+            assert(ok == nil and err == "no process attached")
+            "#,
+        );
+    }
+
+    #[test]
+    fn rejects_classes_from_before_attaching() {
+        let lua = lua();
+        let generation = lua.associated_data().unity.borrow().generation;
+        lua.associated_data().unity.take();
+        lua.global().set_str_key("getField", fp!(get_field));
+        lua.global()
+            .set_str_key("getStaticAddress", fp!(get_static_address));
+        run(
+            &lua,
+            &format!(
+                r#"
+                local class = {{ id = 0, generation = {generation} }}
+                local stale = "the class is no longer valid, find it again after attaching"
+                local offset, err = getField(class, "isLoading")
+                assert(offset == nil and err == stale)
+                local address, err = getStaticAddress(class, "instance")
+                assert(address == nil and err == stale)
+                "#
+            ),
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! The `unreal` module, exposing asr's Unreal Engine support. Like the
+//! `unity` module, failures return `nil` and a message instead of raising
+//! errors.
+
+use asr::{
+    Process,
+    game_engine::unreal::{Module as UnrealModule, Version},
+    string::ArrayCString,
+};
+use tsuki::{
+    Lua, Module, Ref, Table, Value,
+    context::{Args, Context, Ret},
+    fp,
+};
+
+use crate::state::{Result, State};
+
+pub struct UnrealLib;
+
+impl Module<State> for UnrealLib {
+    const NAME: &str = "unreal";
+
+    type Inst<'a>
+        = Ref<'a, Table<State>>
+    where
+        State: 'a;
+
+    fn open(self, lua: &Lua<State>) -> Result<Self::Inst<'_>> {
+        let m = lua.create_table();
+
+        m.set_str_key("attach", fp!(attach));
+        m.set_str_key("getGWorld", fp!(get_g_world));
+        m.set_str_key("fnameToString", fp!(fname_to_string));
+        m.set_str_key("findObject", fp!(find_object));
+
+        Ok(m)
+    }
+}
+
+fn push_failure<'a>(
+    cx: Context<'a, State, Args>,
+    message: &str,
+) -> Result<Context<'a, State, Ret>> {
+    cx.push(Value::Nil)?;
+    cx.push(Value::Str(cx.create_str(message)))?;
+    Ok(cx.into())
+}
+
+fn with_process<T>(cx: &Context<State, Args>, f: impl FnOnce(&Process) -> T) -> Result<T> {
+    let process_ref = cx.associated_data().process.borrow();
+    let process = process_ref
+        .as_ref()
+        .and_then(|process| process.as_process())
+        .ok_or("no process attached")?;
+    Ok(f(process))
+}
+
+fn parse_version(hint: &str) -> Option<Version> {
+    Some(match hint {
+        "4.23" => Version::V4_23,
+        "4.25" => Version::V4_25,
+        "4.26" => Version::V4_26,
+        "4.27" => Version::V4_27,
+        "5.0" => Version::V5_0,
+        "5.1" => Version::V5_1,
+        "5.2" => Version::V5_2,
+        "5.3" => Version::V5_3,
+        _ => return None,
+    })
+}
+
+fn attach(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let arg = cx.arg(1);
+    let version = arg
+        .to_str()?
+        .as_utf8()
+        .and_then(parse_version)
+        .ok_or_else(|| {
+            arg.error(
+                "unsupported version, use one of 4.23, 4.25, 4.26, 4.27, 5.0, 5.1, 5.2 or 5.3",
+            )
+        })?;
+
+    let main_module = cx.associated_data().base_address.get();
+    if main_module.is_null() {
+        return push_failure(
+            cx,
+            "attach failed at the module step: main module not found",
+        );
+    }
+
+    let module = with_process(&cx, |process| {
+        UnrealModule::attach(process, version, main_module)
+    })?;
+
+    // asr's discovery doesn't tell GNames and GObjects failures apart.
+    let Some(module) = module else {
+        return push_failure(
+            cx,
+            "attach failed at the GNames / GObjects step: the signatures didn't match, \
+             the version hint may be wrong",
+        );
+    };
+
+    *cx.associated_data().unreal.borrow_mut() = Some(module);
+
+    cx.push(Value::True)?;
+    Ok(cx.into())
+}
+
+fn get_g_world(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let g_world = cx
+        .associated_data()
+        .unreal
+        .borrow()
+        .as_ref()
+        .map(|module| module.g_world())
+        .ok_or("unreal.attach needs to be called first")?;
+
+    cx.push(Value::Int(g_world.value() as i64))?;
+    Ok(cx.into())
+}
+
+fn fname_to_string(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    let index = cx.arg(1).to_int()?;
+
+    let name = {
+        let unreal = cx.associated_data().unreal.borrow();
+        let module = unreal
+            .as_ref()
+            .ok_or("unreal.attach needs to be called first")?;
+        with_process(&cx, |process| {
+            module.get_fname::<128>(process, index.to_le_bytes())
+        })?
+    };
+
+    match name.as_ref().map(ArrayCString::validate_utf8) {
+        Ok(Ok(name)) => {
+            cx.push(Value::Str(cx.create_str(name)))?;
+            Ok(cx.into())
+        }
+        _ => push_failure(cx, "failed to resolve the FName"),
+    }
+}
+
+fn find_object(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
+    // FIXME: asr doesn't locate GObjects, so there's no object array to
+    // search through yet.
+    push_failure(
+        cx,
+        "findObject is not supported yet, asr doesn't expose GObjects",
+    )
+}
@@ -1,22 +1,141 @@
-use std::{fmt, pin::Pin, rc::Rc};
+use std::{error::Error, fmt};
 
 use tsuki::{
-    Lua, Ref, Thread, Value,
+    CallError, Coroutine, Lua, LuaFn, Ref, RegKey, Table, Thread, Value,
     context::{Args, Context, Ret},
+    fp,
 };
 
-use crate::state::{Result, State};
+use crate::{
+    budget::{self, Running},
+    callbacks::Callback,
+    log,
+    state::{Result, State},
+};
+
+/// Registry key of the global table. Natives don't get direct access to the
+/// globals, so `register_api` stores them here.
+pub struct Globals;
+
+impl<A> RegKey<A> for Globals {
+    type Value<'a>
+        = Table<A>
+    where
+        A: 'a;
+}
 
 pub fn next_pair(cx: Context<State, Args>) -> Result<Context<State, Ret>> {
     let table = cx.arg(1).get_table()?;
-    let key = cx.arg(2).get().unwrap_or(Value::Nil);
 
     // FIXME: Tsuki doesn't expose a direct public table iterator on `Table`, so we must route
     // iteration through `Context::push_next` inside a Rust callback and call it via a thread.
-    cx.push_next(table, key)?;
+    cx.push_next(table, cx.arg(2))?;
+    Ok(cx.into())
+}
+
+/// `resume(co)` for the Lua side of the runtime, like `coroutine.resume`
+/// but able to run async natives like `sig_scan` inside the coroutine.
+/// `coroutine.resume` runs it outside of the async context, where they fail.
+/// Returns whether it ran without an error, followed by the error or the
+/// first value the coroutine returned. What it yielded is dropped.
+pub async fn resume<'a>(cx: Context<'a, State, Args>) -> Result<Context<'a, State, Ret>> {
+    let co = cx.arg(1).get_thread()?;
+
+    budget::restart();
+    match co.async_resume::<Value<State>>(()).await {
+        Ok(Coroutine::Finished(value)) => {
+            cx.push(true)?;
+            cx.push(value)?;
+        }
+        Ok(Coroutine::Suspended(_)) => cx.push(true)?,
+        Err(err) => {
+            cx.push(false)?;
+            cx.push_str(error_message(&*err))?;
+        }
+    }
     Ok(cx.into())
 }
 
+/// The error of a failed Lua call, with where in the script it happened if
+/// it's known.
+pub fn error_message(err: &(dyn Error + 'static)) -> String {
+    match err
+        .downcast_ref::<CallError>()
+        .and_then(CallError::location)
+    {
+        Some((chunk, line)) => format!("{chunk}:{line}: {err}"),
+        None => err.to_string(),
+    }
+}
+
+/// Registry key of the function in `pairs.lua` that returns the next batch of
+/// pairs for [`TablePairs`].
+pub struct PairsBatch;
+
+impl<A> RegKey<A> for PairsBatch {
+    type Value<'a>
+        = LuaFn<A>
+    where
+        A: 'a;
+}
+
+/// Loads `pairs.lua` and stores its batch function in the registry.
+pub fn register_pairs_batch(lua: &Lua<State>) {
+    let chunk = lua.load("pairs.lua", include_str!("pairs.lua")).unwrap();
+    let td = lua.create_thread();
+    let Value::LuaFn(batch) = td.call(&*chunk, fp!(next_pair)).unwrap() else {
+        unreachable!("pairs.lua returns the batch function");
+    };
+    lua.set_registry::<PairsBatch>(&batch);
+}
+
+/// Iterates over the pairs of a table in `next` order. All table walking in
+/// the runtime goes through this. The pairs are fetched through `pairs.lua` a
+/// batch at a time, as Tsuki doesn't expose a direct iterator on `Table`.
+pub struct TablePairs<'a> {
+    td: &'a Thread<State>,
+    batch_fn: &'a LuaFn<State>,
+    table: &'a Table<State>,
+    /// The rest of the current batch, in reverse so pairs pop off the end.
+    batch: Vec<Value<'a, State>>,
+    /// The key to fetch the next batch from, `None` once the end is reached.
+    key: Option<Value<'a, State>>,
+}
+
+impl<'a> TablePairs<'a> {
+    pub fn new(td: &'a Thread<State>, batch_fn: &'a LuaFn<State>, table: &'a Table<State>) -> Self {
+        Self {
+            td,
+            batch_fn,
+            table,
+            batch: Vec::new(),
+            key: Some(Value::Nil),
+        }
+    }
+}
+
+impl<'a> Iterator for TablePairs<'a> {
+    type Item = Result<(Value<'a, State>, Value<'a, State>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.batch.is_empty() {
+            let key = self.key.take()?;
+            self.batch = match self.td.call(self.batch_fn, (self.table, &key)) {
+                Ok(batch) => batch,
+                Err(err) => return Some(Err(err)),
+            };
+            if self.batch.len() % 2 == 1 {
+                self.key = self.batch.pop();
+            }
+            self.batch.reverse();
+        }
+
+        let key = self.batch.pop()?;
+        let value = self.batch.pop()?;
+        Some(Ok((key, value)))
+    }
+}
+
 pub struct DisplayValue<'a, 'b, S>(pub &'a Value<'b, S>);
 
 impl fmt::Display for DisplayValue<'_, '_, State> {
@@ -33,26 +152,321 @@ impl fmt::Display for DisplayValue<'_, '_, State> {
     }
 }
 
-pub async fn call_maybe(lua: &Pin<Rc<Lua<State>>>, td: &Ref<'_, Thread<State>>, name: &str) {
-    let func = lua.global().get_str_key(name);
-    if let Value::LuaFn(func) = func {
-        () = td.async_call(&func, ()).await.unwrap();
+/// Calls `callback` through the driver in `callbacks.lua`, which runs it as a
+/// coroutine. A callback that fails, like by running out of budget, starts
+/// over the next time.
+pub async fn call_driver<'a>(
+    td: &'a Ref<'_, Thread<State>>,
+    driver: &Ref<'_, LuaFn<State>>,
+    callback: &Callback<'_>,
+) -> Result<Value<'a, State>> {
+    budget::start(Running::Callback(callback.name));
+    let results: Vec<Value<State>> = td.async_call(driver, &*callback.func).await?;
+    let mut results = results.into_iter();
+    match (results.next(), results.next()) {
+        (Some(Value::False), err) => {
+            let err = err.unwrap_or(Value::Nil);
+            Err(DisplayValue(&err).to_string().into())
+        }
+        (_, value) => Ok(value.unwrap_or(Value::Nil)),
+    }
+}
+
+/// Calls `callback` through the driver like [`call_driver`], logging an error
+/// instead of returning it. A failed callback makes no decision.
+pub async fn call_logged<'a>(
+    td: &'a Ref<'_, Thread<State>>,
+    driver: &Ref<'_, LuaFn<State>>,
+    callback: &Callback<'_>,
+) -> Value<'a, State> {
+    call_driver(td, driver, callback)
+        .await
+        .unwrap_or_else(|err| {
+            log::error(&format!("[{}] {}", callback.name, error_message(&*err)));
+            Value::Nil
+        })
+}
+
+pub async fn call_maybe(
+    td: &Ref<'_, Thread<State>>,
+    driver: &Ref<'_, LuaFn<State>>,
+    callback: Option<&Callback<'_>>,
+) {
+    if let Some(callback) = callback {
+        call_logged(td, driver, callback).await;
     }
 }
 
 pub async fn call_maybe_bool(
-    lua: &Pin<Rc<Lua<State>>>,
     td: &Ref<'_, Thread<State>>,
-    name: &str,
+    driver: &Ref<'_, LuaFn<State>>,
+    callback: Option<&Callback<'_>>,
 ) -> Option<bool> {
-    let func = lua.global().get_str_key(name);
-    if let Value::LuaFn(func) = func {
-        match td.async_call(&func, ()).await.unwrap() {
-            Value::True => Some(true),
-            Value::False => Some(false),
-            _ => None,
-        }
-    } else {
-        None
+    let callback = callback?;
+    match call_logged(td, driver, callback).await {
+        Value::True => Some(true),
+        Value::False => Some(false),
+        _ => None,
+    }
+}
+
+/// 64-bit FNV-1a, a fast hash that's good enough to tell byte strings apart
+/// in the log.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// Prints `message`, splitting it into numbered parts if it's longer than
+/// `maxMessageLength` so the host doesn't cut off the end. Multi-line
+/// messages are split between lines where possible.
+pub fn print_long(state: &State, message: &str) {
+    let max_len = state.max_message_len.get();
+    if message.len() <= max_len {
+        log::print_message(message);
+        return;
+    }
+
+    // Leaves room for the ` …[i/n]` suffix.
+    let max_len = max_len.saturating_sub(16).max(16);
+
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for line in message.split_inclusive('\n') {
+        if chunk.len() + line.len() > max_len && !chunk.is_empty() {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+
+        let mut line = line;
+        while line.len() > max_len {
+            let mut split = max_len;
+            while !line.is_char_boundary(split) {
+                split -= 1;
+            }
+            chunks.push(line[..split].to_owned());
+            line = &line[split..];
+        }
+        chunk.push_str(line);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    let count = chunks.len();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let chunk = chunk.strip_suffix('\n').unwrap_or(chunk);
+        log::print_message(&format!("{chunk} …[{}/{count}]", i + 1));
+    }
+}
+
+/// The name Lua's `type` returns for `value`.
+pub fn type_name(value: &Value<State>) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::True | Value::False => "boolean",
+        Value::Int(_) | Value::Float(_) => "number",
+        Value::Str(_) => "string",
+        Value::Table(_) => "table",
+        Value::LuaFn(_) | Value::Fp(_) | Value::AsyncFp(_) => "function",
+        _ => "userdata",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use tsuki::{Thread, Value, fp};
+
+    use super::{
+        DisplayValue, PairsBatch, TablePairs, call_driver, call_logged, error_message, next_pair,
+        resume,
+    };
+    use crate::{
+        callbacks::lookup,
+        host::{self, mock::MockHost},
+        process_memory::mock::MockProcess,
+        state::State,
+        testing::{attach, block_on, lua, run},
+    };
+
+    /// What `TablePairs` replaced, a call into the VM for every pair. Returns
+    /// the pairs as `key=value` strings.
+    fn per_pair<'a>(td: &'a Thread<State>, table: &Value<'a, State>) -> Vec<String> {
+        let mut pairs = Vec::new();
+        let mut key = Value::Nil;
+        loop {
+            let mut pair: Vec<Value<State>> = td.call(fp!(next_pair), (table, &key)).unwrap();
+            if pair.is_empty() || matches!(pair[0], Value::Nil) {
+                break;
+            }
+            let value = pair.pop().unwrap();
+            key = pair.pop().unwrap();
+            pairs.push(format!("{}={}", DisplayValue(&key), DisplayValue(&value)));
+        }
+        pairs
+    }
+
+    #[test]
+    fn matches_per_pair_iteration() {
+        let lua = lua();
+        let chunk = lua
+            .load(
+                "test.lua",
+                r#"
+                local t = {}
+                for i = 1, 600 do
+                    t[i] = i * 2
+                end
+                for i = 1, 600 do
+                    t["key" .. i] = i % 3 == 0
+                end
+                t[1.5], t[-7], t[true] = "float", "negative", "boolean"
+                -- Holes and removed keys.
+                t[300], t.key10, t[10000] = nil, nil, "sparse"
+                return t
+                "#,
+            )
+            .unwrap();
+        let td = lua.create_thread();
+        let table: Value<State> = td.call(&*chunk, ()).unwrap();
+        let Value::Table(t) = &table else {
+            unreachable!("the chunk returns a table");
+        };
+
+        let batch_fn = lua.registry::<PairsBatch>().unwrap();
+        let batched: Vec<_> = TablePairs::new(&td, &batch_fn, t)
+            .map(|pair| {
+                let (key, value) = pair.unwrap();
+                format!("{}={}", DisplayValue(&key), DisplayValue(&value))
+            })
+            .collect();
+        let expected = per_pair(&td, &table);
+
+        assert_eq!(batched.len(), 1202);
+        assert_eq!(batched, expected);
+    }
+
+    #[test]
+    fn runs_callbacks_as_coroutines() {
+        let host = Rc::new(MockHost::new());
+        host::set(host.clone());
+        let game = Rc::new(
+            MockProcess::new()
+                .module("game.exe", 0x10000, 0x100)
+                .map(0x10000, vec![0; 0x100]),
+        );
+        game.write(0x10010, &[0xDE, 0xAD]);
+        let lua = lua();
+        attach(&lua, &game, "game.exe");
+        run(
+            &lua,
+            r#"
+            calls = 0
+            function plain()
+                calls = calls + 1
+                return calls
+            end
+            function yielding()
+                calls = calls + 1
+                coroutine.yield()
+                return sig_scan("DE AD", 0)
+            end
+            function failing()
+                calls = calls + 1
+                error("broken")
+            end
+            "#,
+        );
+
+        let td = lua.create_thread();
+        let driver = lua
+            .load("callbacks.lua", include_str!("callbacks.lua"))
+            .unwrap();
+        let Value::LuaFn(driver) = td.call(&*driver, fp!(resume as async)).unwrap() else {
+            unreachable!("callbacks.lua returns the driver function");
+        };
+        let plain = lookup(&lua, "plain").unwrap();
+        let yielding = lookup(&lua, "yielding").unwrap();
+        let failing = lookup(&lua, "failing").unwrap();
+
+        block_on(async {
+            let call = async |callback| call_driver(&td, &driver, callback).await.unwrap();
+            assert!(matches!(call(&plain).await, Value::Int(1)));
+            assert!(matches!(call(&plain).await, Value::Int(2)));
+
+            // Nothing runs twice when a callback yields.
+            assert!(matches!(call(&yielding).await, Value::Nil));
+            run(&lua, "assert(calls == 3)");
+            // `sig_scan` is async, which a coroutine can only call when it's
+            // resumed by the runtime.
+            assert!(matches!(call(&yielding).await, Value::Int(0x10)));
+            assert!(matches!(call(&yielding).await, Value::Nil));
+            run(&lua, "assert(calls == 4)");
+
+            // Errors are returned, or logged, and the callback runs again
+            // next time.
+            let Err(err) = call_driver(&td, &driver, &failing).await else {
+                panic!("the callback failed");
+            };
+            assert_eq!(error_message(&*err), "test.lua:14: broken");
+            assert!(matches!(
+                call_logged(&td, &driver, &failing).await,
+                Value::Nil
+            ));
+            run(&lua, "assert(calls == 6)");
+        });
+        let messages = host.messages.take();
+        let errors: Vec<_> = messages
+            .iter()
+            .filter(|m| m.starts_with("ERROR "))
+            .collect();
+        assert_eq!(errors, ["ERROR [failing] test.lua:14: broken"]);
+    }
+
+    #[test]
+    fn logs_where_tasks_failed() {
+        let lua = lua();
+        run(
+            &lua,
+            r#"
+            logged = {}
+            function capture(message)
+                logged[#logged + 1] = message
+            end
+            "#,
+        );
+
+        let td = lua.create_thread();
+        let run_tasks = lua.load("tasks.lua", include_str!("tasks.lua")).unwrap();
+        let capture = lookup(&lua, "capture").unwrap();
+        let Value::LuaFn(run_tasks) = td
+            .call(&*run_tasks, (fp!(resume as async), &*capture.func))
+            .unwrap()
+        else {
+            unreachable!("tasks.lua returns the task runner");
+        };
+
+        run(
+            &lua,
+            r#"
+            spawn(function()
+                nextTick()
+                local t = nil
+                return t.field
+            end)
+            "#,
+        );
+        for elapsed_ms in 0..3 {
+            let () = block_on(td.async_call(&run_tasks, elapsed_ms)).unwrap();
+        }
+        run(
+            &lua,
+            r#"
+            assert(#logged == 1)
+            assert(logged[1] == "[spawn] task failed: test.lua:5: attempt to index a nil value (local 't')")
+            "#,
+        );
     }
 }
@@ -0,0 +1,123 @@
+//! Bookkeeping for variables whose updates are throttled, so scripts can
+//! update them every tick without a host call each time, and for variables
+//! that get blanked once the script stops refreshing them.
+
+use std::time::{Duration, Instant};
+
+use crate::{host, state::State};
+
+pub struct ThrottledVariable {
+    /// The value the host currently shows.
+    sent: String,
+    sent_at: Instant,
+    /// A newer value still held back by the interval.
+    pending: Option<String>,
+    interval: Duration,
+}
+
+pub struct ExpiringVariable {
+    expires_at: Instant,
+    /// Whether the script refreshed the variable since the last `expire`.
+    refreshed: bool,
+}
+
+/// Shows `value` for `key` unless the last update happened less than
+/// `interval` ago, in which case it's held back until the interval elapsed.
+pub fn set_throttled(state: &State, key: &str, value: &str, interval: Duration) {
+    state.expiring_variables.borrow_mut().remove(key);
+    state.time_variables.borrow_mut().remove(key);
+
+    let mut variables = state.throttled_variables.borrow_mut();
+    let now = Instant::now();
+
+    match variables.get_mut(key) {
+        Some(variable) => {
+            variable.interval = interval;
+            if variable.sent == value {
+                variable.pending = None;
+            } else if now.duration_since(variable.sent_at) >= interval {
+                host::get().set_variable(key, value);
+                variable.sent = value.to_owned();
+                variable.sent_at = now;
+                variable.pending = None;
+            } else {
+                variable.pending = Some(value.to_owned());
+            }
+        }
+        None => {
+            host::get().set_variable(key, value);
+            variables.insert(
+                key.to_owned(),
+                ThrottledVariable {
+                    sent: value.to_owned(),
+                    sent_at: now,
+                    pending: None,
+                    interval,
+                },
+            );
+        }
+    }
+}
+
+/// Shows `value` for `key` until it isn't refreshed for `ttl`.
+pub fn set_expiring(state: &State, key: &str, value: &str, ttl: Duration) {
+    state.throttled_variables.borrow_mut().remove(key);
+    state.time_variables.borrow_mut().remove(key);
+    host::get().set_variable(key, value);
+
+    state.expiring_variables.borrow_mut().insert(
+        key.to_owned(),
+        ExpiringVariable {
+            expires_at: Instant::now() + ttl,
+            refreshed: true,
+        },
+    );
+}
+
+/// Forgets about `key`, so a value held back for it doesn't overwrite one set
+/// in another way, and it doesn't get blanked when it was set without a TTL.
+pub fn forget(state: &State, key: &str) {
+    state.throttled_variables.borrow_mut().remove(key);
+    state.expiring_variables.borrow_mut().remove(key);
+    state.time_variables.borrow_mut().remove(key);
+}
+
+/// Sends the values held back for longer than their interval, or all of them
+/// if `force` is set.
+pub fn flush(state: &State, force: bool) {
+    let mut variables = state.throttled_variables.borrow_mut();
+    let now = Instant::now();
+
+    for (key, variable) in variables.iter_mut() {
+        if !force && now.duration_since(variable.sent_at) < variable.interval {
+            continue;
+        }
+        if let Some(value) = variable.pending.take() {
+            host::get().set_variable(key, &value);
+            variable.sent = value;
+            variable.sent_at = now;
+        }
+    }
+}
+
+/// Blanks the variables whose TTL ran out. Called once per tick, a variable
+/// refreshed during the tick never expires, even if ticks are further apart
+/// than its TTL.
+pub fn expire(state: &State) {
+    let now = Instant::now();
+
+    state
+        .expiring_variables
+        .borrow_mut()
+        .retain(|key, variable| {
+            if variable.refreshed {
+                variable.refreshed = false;
+                true
+            } else if now >= variable.expires_at {
+                host::get().set_variable(key, "");
+                false
+            } else {
+                true
+            }
+        });
+}